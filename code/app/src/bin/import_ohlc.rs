@@ -0,0 +1,160 @@
+//! Imports one minute candles from a CSV archive through the import workers of the app service.
+//!
+//! Usage: `import_ohlc <exchange> <pair> <file> [options]`, e.g.
+//! `import_ohlc bitfinex BTC:USD btcusd.csv --time-column timestamp --time-unit ms`
+use common::prelude::*;
+use common::import::CsvOptions;
+use common::msgs::IngestUpdate;
+use common::types::{Exchange, TradePair, OhlcSpec, OhlcPeriod};
+use std::process;
+
+const USAGE: &str = "Usage: import_ohlc <exchange> <pair> <file> [options]
+
+Options:
+    --delimiter <char>        Field delimiter, ',' by default
+    --no-header               The file has no header, columns are given by their index
+    --time-column <column>    Name or index of the time column, 'time' by default
+    --open-column <column>    'open' by default, likewise for high, low and close
+    --high-column <column>
+    --low-column <column>
+    --close-column <column>
+    --volume-column <column>  'volume' by default
+    --time-unit <unit>        s, ms, us, ns or text, s by default
+    --time-format <format>    Format of textual times, '%Y-%m-%d %H:%M:%S' by default
+    --timezone <offset>       Offset of textual times from UTC, e.g. +02:00, UTC by default
+    --batch <count>           Candles sent in a single request, 5000 by default
+    --dry-run                 Only validate the file";
+
+const DEFAULT_BATCH: usize = 5000;
+/// Saving a batch taking longer fails the import
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Args {
+    spec: OhlcSpec,
+    file: String,
+    options: CsvOptions,
+    batch: usize,
+    dry_run: bool,
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args> {
+    let mut positional = vec![];
+    let mut options = CsvOptions::default();
+    let (mut batch, mut dry_run) = (DEFAULT_BATCH, false);
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format_err!("Missing value of {}", arg));
+        match arg.as_str() {
+            "--delimiter" => {
+                let delimiter = value()?;
+                let mut chars = delimiter.chars();
+                options.delimiter = match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => bail!("Delimiter must be a single character"),
+                };
+            }
+            "--no-header" => options.header = false,
+            "--time-column" => options.time_column = value()?,
+            "--open-column" => options.open_column = value()?,
+            "--high-column" => options.high_column = value()?,
+            "--low-column" => options.low_column = value()?,
+            "--close-column" => options.close_column = value()?,
+            "--volume-column" => options.volume_column = value()?,
+            "--time-unit" => options.time_unit = value()?.parse()?,
+            "--time-format" => options.time_format = value()?,
+            "--timezone" => options.timezone = value()?,
+            "--batch" => batch = value()?.parse().map_err(|_| format_err!("Batch must be a positive number"))?,
+            "--dry-run" => dry_run = true,
+            flag if flag.starts_with("--") => bail!("Unknown option {}", flag),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    if positional.len() != 3 || batch == 0 {
+        bail!("Expected an exchange, a pair and a file");
+    }
+    let exchange = Exchange::from_str(&positional[0]).map_err(|_| format_err!("Unknown exchange {}", positional[0]))?;
+    let pair = TradePair::from_str(&positional[1]).map_err(|_| format_err!("Invalid pair {}, expected a format like BTC:USD", positional[1]))?;
+
+    Ok(Args {
+        spec: OhlcSpec::new(exchange, pair, OhlcPeriod::Min1),
+        file: positional[2].clone(),
+        options,
+        batch,
+        dry_run,
+    })
+}
+
+fn main() {
+    common::init();
+    let args = match parse_args(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let data = match std::fs::read_to_string(&args.file) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Could not read {} : {}", args.file, e);
+            process::exit(1);
+        }
+    };
+    let parsed = match args.options.parse(&data) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Could not parse {} : {}", args.file, e);
+            process::exit(1);
+        }
+    };
+
+    for error in parsed.errors.iter() {
+        eprintln!("Line {} : {}", error.line, error.message);
+    }
+    println!("{} rows, {} valid candles, {} duplicates, {} rejected",
+             parsed.rows, parsed.candles.len(), parsed.duplicates, parsed.rejected);
+    if args.dry_run || parsed.candles.is_empty() {
+        return;
+    }
+
+    let (spec, batch, candles) = (args.spec, args.batch, parsed.candles);
+    let code = actix::System::run(move || {
+        let fut = async move {
+            let client = anats::Client::new(common::config().nats.url.clone()).await;
+            let total = candles.len();
+            let mut imported = 0;
+
+            for chunk in candles.chunks(batch) {
+                let update = IngestUpdate::new(spec.clone(), chunk.to_vec());
+                let saved = client.request(common::CHANNEL_OHLC_IMPORT, update)
+                    .timeout(REQUEST_TIMEOUT)
+                    .compat()
+                    .await;
+
+                match saved {
+                    Ok(Ok(())) => {
+                        imported += chunk.len();
+                        println!("Imported {}/{} candles of {} ({:.1}%)", imported, total, spec.pair_id(),
+                                 imported as f64 * 100.0 / total as f64);
+                    }
+                    Ok(Err(())) => {
+                        eprintln!("Import workers could not save candles starting at {}", chunk[0].time);
+                        System::current().stop_with_code(1);
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        eprintln!("Could not import candles starting at {} : {}", chunk[0].time, e);
+                        System::current().stop_with_code(1);
+                        return Ok(());
+                    }
+                }
+            }
+            System::current().stop();
+            Ok::<_, ()>(())
+        };
+        actix::spawn(fut.boxed_local().compat());
+    });
+    process::exit(code);
+}
@@ -2,30 +2,39 @@ use crate::prelude::*;
 
 use std::time::Duration;
 use chrono::NaiveDateTime;
-use common::msgs::{EvalRequest, PositionRequest, OhlcUpdate};
-use db::Evaluation;
+use common::msgs::{EvalRequest, PositionRequest, OhlcUpdate, AssignmentsChanged, EvaluationLogged};
+use db::{Evaluation, Signal};
 
 
 use multimap::MultiMap;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AssignmentSpec {
+    pub id: i32,
     pub pair_id: i32,
     pub period: OhlcPeriod,
 
     pub user_id: i32,
     pub strat_id: i32,
+    pub revision_id: i32,
+    pub params: ParamValues,
     pub trader: Option<db::Trader>,
+    /// Webhook receiving the decisions
+    pub signal_url: Option<String>,
 }
 
 impl AssignmentSpec {
     pub fn from_db(d: &db::Assignment, t: Option<db::Trader>) -> Self {
         AssignmentSpec {
+            id: d.id,
             pair_id: d.pair_id,
             period: OhlcPeriod::from_str(&d.period).unwrap(),
             user_id: d.user_id,
             strat_id: d.strategy_id,
+            revision_id: d.revision_id,
+            params: d.param_values(),
             trader: t,
+            signal_url: d.signal_url.clone(),
         }
     }
     pub fn search_prefix(&self) -> String {
@@ -35,57 +44,117 @@ impl AssignmentSpec {
 
 impl_invoke!(Decider);
 
+/// Assignments are updated by change events, this full reload only catches up on lost ones
+const RESYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
 pub struct Decider {
     client: anats::Client,
     db: Database,
-    requests: MultiMap<OhlcSpec, AssignmentSpec>
+    requests: MultiMap<OhlcSpec, AssignmentSpec>,
+    /// Strategy, pair and period of evaluations whose last run failed, users are notified only about the first failure
+    failing: HashSet<(i32, i32, String)>,
+    /// Sequence number of the last started reload, reloads can finish out of order
+    reload_seq: u64,
+    /// Sequence numbers of the last applied full reload, and of user reloads applied after it
+    applied_all: u64,
+    applied_users: HashMap<i32, u64>,
 }
 
 impl Decider {
     pub async fn new(client: anats::Client, db: db::Database) -> Result<Addr<Self>, failure::Error> {
         Ok(Arbiter::start(move |ctx: &mut Context<Self>| {
             client.subscribe(crate::CHANNEL_OHLC_RESCALED, None, ctx.address().recipient::<OhlcUpdate>());
+            client.subscribe(crate::CHANNEL_ASSIGNMENT_UPDATES, None, ctx.address().recipient::<AssignmentsChanged>());
 
-            ctx.run_interval(Duration::from_secs(5), |this, ctx| {
-                this.reload(ctx);
+            ctx.run_later(Duration::from_secs(0), |this, ctx| {
+                this.reload(None, ctx);
+            });
+            ctx.run_interval(RESYNC_INTERVAL, |this, ctx| {
+                this.reload(None, ctx);
             });
             Decider {
                 client,
                 db,
                 requests: MultiMap::new(),
+                failing: HashSet::new(),
+                reload_seq: 0,
+                applied_all: 0,
+                applied_users: HashMap::new(),
             }
         }))
     }
 
-    pub fn reload(&mut self, ctx: &mut Context<Self>) {
-        let addr = ctx.address().clone();
-
-
-        let fut = wrap_future(self.db.all_assignments_with_traders().boxed_local().compat())
-            .map(|res, this: &mut Self, ctx| {
-                warn!("Eval requests reloaded : {:?}", res);
-                this.requests = MultiMap::new();
-                for (r, t) in res.iter() {
-                    let spec = AssignmentSpec::from_db(r, t.clone());
-                    let assign = wrap_future(self.db.pair_data(spec.pair_id).boxed_local().compat())
-                        .map(move| pair, this : &mut Self, ctx| {
-                            this.requests.insert(OhlcSpec::new(pair.exch().clone(), pair.pair(), spec.period), spec);
-                        });
+    /// Replaces assignments of the user, or all of them when no user is given.
+    /// Results of a reload that started before an already applied one are ignored.
+    pub fn reload(&mut self, uid: Option<i32>, ctx: &mut Context<Self>) {
+        self.reload_seq += 1;
+        let seq = self.reload_seq;
+
+        let fut = wrap_future(self.db.active_assignments(uid).boxed_local().compat())
+            .map(move |res, this: &mut Self, ctx| {
+                let newer = match uid {
+                    Some(uid) => {
+                        if seq < this.applied_all.max(this.applied_users.get(&uid).cloned().unwrap_or(0)) {
+                            debug!("Ignoring outdated reload of assignments of {}", uid);
+                            return;
+                        }
+                        this.applied_users.insert(uid, seq);
+                        for (_, specs) in this.requests.iter_all_mut() {
+                            specs.retain(|s| s.user_id != uid);
+                        }
+                        HashSet::new()
+                    }
+                    None => {
+                        if seq < this.applied_all {
+                            debug!("Ignoring outdated reload of all assignments");
+                            return;
+                        }
+                        // Users reloaded after this reload started keep their newer assignments
+                        this.applied_all = seq;
+                        this.applied_users.retain(|_, applied| *applied > seq);
+                        let newer = this.applied_users.keys().cloned().collect::<HashSet<_>>();
+                        for (_, specs) in this.requests.iter_all_mut() {
+                            specs.retain(|s| newer.contains(&s.user_id));
+                        }
+                        newer
+                    }
+                };
+                info!("Reloaded {} assignments of {:?}", res.len(), uid);
 
-                    ctx.spawn(assign.map(|_, _, _| ()).drop_err());
+                for (a, t, p) in res.into_iter().filter(|(a, _, _)| !newer.contains(&a.user_id)) {
+                    let spec = AssignmentSpec::from_db(&a, t);
+                    let pair: PairId = p.into();
+                    this.requests.insert(OhlcSpec::new(pair.exch().clone(), pair.pair(), spec.period), spec);
                 }
-            });
-        ctx.spawn(fut.map(|_, _, _| ()).drop_err());
+            })
+            .map_err(move |e, _, _| error!("Could not reload assignments of {:?} : {:?}", uid, e));
+        ctx.spawn(fut);
     }
 }
 
 impl Actor for Decider { type Context = Context<Self>; }
 
+/// Signal target of the evaluated assignment
 #[derive(Debug)]
-struct MakeEvalRequest(EvalRequest, Option<db::Trader>);
+struct SignalTarget {
+    assignment_id: i32,
+    url: String,
+}
+
+#[derive(Debug)]
+struct MakeEvalRequest(EvalRequest, Option<db::Trader>, i32, Option<SignalTarget>);
 
 impl Message for MakeEvalRequest { type Result = (); }
 
+impl Handler<AssignmentsChanged> for Decider {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, msg: AssignmentsChanged, ctx: &mut Self::Context) -> Self::Result {
+        self.reload(Some(msg.user_id), ctx);
+        Ok(())
+    }
+}
+
 impl Handler<OhlcUpdate> for Decider {
     type Result = ();
 
@@ -101,8 +170,9 @@ impl Handler<OhlcUpdate> for Decider {
 
                 error!("Should eval {:?} on {:?}", spec, msg.clone().spec);
 
-                let req = EvalRequest::new(spec.strat_id, spec.pair_id, spec.period.clone(), msg.ohlc.time);
-                ctx.address().do_send(MakeEvalRequest(req, spec.trader.clone()));
+                let req = EvalRequest::new(spec.strat_id, spec.revision_id, spec.pair_id, spec.period.clone(), msg.ohlc.time, spec.params.clone());
+                let signal = spec.signal_url.clone().map(|url| SignalTarget { assignment_id: spec.id, url });
+                ctx.address().do_send(MakeEvalRequest(req, spec.trader.clone(), spec.user_id, signal));
             }
         }
     }
@@ -114,8 +184,13 @@ impl Handler<MakeEvalRequest> for Decider {
     fn handle(&mut self, msg: MakeEvalRequest, ctx: &mut Self::Context) -> Self::Result {
         let req = msg.0;
         let trader = msg.1;
+        let user_id = msg.2;
+        let signal = msg.3;
+        let (time, ohlc_period) = (req.last, req.period);
 
         let pair_id = req.pair_id;
+        let (strategy_id, revision_id, period) = (req.strat_id, req.revision_id, req.period.to_string());
+        let started = Instant::now();
 
         let pair = wrap_future::<_,Self>(self.db.pair_data(req.pair_id).boxed_local().compat());
         let eval_res = wrap_future::<_,Self>(self.client.request(common::CHANNEL_EVAL_REQUESTS, req));
@@ -123,12 +198,41 @@ impl Handler<MakeEvalRequest> for Decider {
         let fut = pair.drop_err().and_then(move |pair, this : &mut Self, ctx| {
             info!("Eval ?");
             eval_res.and_then(move |eval, this: &mut Self, ctx| {
+                let status = eval.is_ok();
+                this.client.publish(crate::CHANNEL_EVALUATIONS, EvaluationLogged {
+                    user_id,
+                    strategy_id,
+                    revision_id,
+                    pair: pair.clone(),
+                    period: ohlc_period,
+                    time,
+                    decision: eval.as_ref().ok().cloned(),
+                    error: eval.as_ref().err().map(|e| e.to_string()),
+                });
                 let (ok, error) = match eval {
                     Ok(ref decision) => {
+                        if let Some(target) = signal {
+                            let sig = Signal {
+                                id: uuid::Uuid::new_v4(),
+                                assignment_id: target.assignment_id,
+                                strategy_id,
+                                revision_id,
+                                exchange: pair.exch().to_string(),
+                                pair: pair.pair().to_string(),
+                                period: period.clone(),
+                                decision: decision.to_string(),
+                                time,
+                            };
+                            let queued = this.db.enqueue_signal(user_id, target.url, sig);
+                            ctx.spawn(wrap_future(queued.boxed_local().compat())
+                                .map(|_, _, _| ())
+                                .map_err(|e, _, _| error!("Could not queue signal : {:?}", e)));
+                        }
                         if let Some(trader) = trader {
                             info!("Trader available, sending trade request");
-                            let pos = PositionRequest::new(trader.api_key, trader.api_secret, pair.into(), *decision);
-                            this.client.publish(crate::CHANNEL_POSITION_REQUESTS, pos);
+                            let pos = PositionRequest::new(trader.id, pair.into(), *decision, Some(revision_id));
+                            let queued = this.db.enqueue(crate::CHANNEL_POSITION_REQUESTS, &pos);
+                            ctx.spawn(wrap_future(queued.boxed_local().compat()).map(|_, _, _| ()).drop_err());
                         } else {
                             info!("Trader unavailable")
                         }
@@ -139,6 +243,41 @@ impl Handler<MakeEvalRequest> for Decider {
                     }
                 };
 
+                let key = (strategy_id, pair_id, period.clone());
+                match error {
+                    Some(ref e) if this.failing.insert(key) => {
+                        let notified = this.db.notify(
+                            user_id,
+                            db::NotifyEvent::EvaluationError,
+                            format!("Evaluation of strategy {} failed", strategy_id),
+                            format!("Evaluation on pair {} with period {} failed : {}", pair_id, period, e),
+                        );
+                        ctx.spawn(wrap_future(notified.boxed_local().compat())
+                            .map(|_, _, _| ())
+                            .map_err(|e, _, _| warn!("Could not notify about evaluation error : {:?}", e)));
+                    }
+                    Some(_) => {}
+                    None => {
+                        this.failing.remove(&key);
+                    }
+                }
+
+                let evaluation = Evaluation {
+                    id: uuid::Uuid::new_v4(),
+                    pair_id,
+                    period,
+                    user_id,
+                    strategy_id,
+                    time: chrono::Utc::now(),
+                    status,
+                    duration: started.elapsed().as_millis() as _,
+                    ok,
+                    error,
+                    revision_id,
+                };
+                let logged = this.db.log_eval(evaluation);
+                ctx.spawn(wrap_future(logged.boxed_local().compat()).map(|_, _, _| ()).drop_err());
+
                 afut::ok(())
             }).drop_err()
         });
@@ -6,6 +6,13 @@
 pub mod prelude;
 pub mod ingest;
 pub mod trader;
+pub mod outbox;
+pub mod optimizer;
+pub mod risk;
+pub mod signals;
+
+#[cfg(test)]
+mod tests;
 
 use crate::prelude::*;
 
@@ -15,13 +22,18 @@ use std::env;
 fn main() {
     common::init();
     common::launch(|| async {
-        let client = anats::Client::new("nats://nats:4222").await;
+        let client = anats::Client::new(common::config().nats.url.clone()).await;
         let db = db::start();
 
         let decider = ingest::decision::Decider::new(client.clone(), db.clone()).await.unwrap();
         let rescaler = ingest::rescaler::Rescaler::new(client.clone(), db.clone()).await.unwrap();
         let ingest = ingest::Ingest::new(client.clone(), db.clone()).await.unwrap();
         let import = ingest::Import::new(client.clone(), db.clone()).await;
+        let relay = outbox::Relay::new(client.clone(), db.clone()).await;
+        let trader = trader::Trader::new(client.clone(), db.clone()).await.unwrap();
+        let optimizer = optimizer::Optimizer::new(client.clone(), db.clone()).await;
+        let risk = risk::RiskManager::new(client.clone(), db.clone()).await;
+        let signals = signals::SignalSender::new(db.clone()).await;
 
     })
 
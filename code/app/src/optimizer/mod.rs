@@ -0,0 +1,191 @@
+use crate::prelude::*;
+use common::msgs::{BacktestRequest, BacktestResult};
+use common::types::{ParamSearch, ParamSpec, ParamType, ParamValue, validate_walk_forward};
+use db::{CandidateScore, Optimization};
+use common::futures03::StreamExt as _;
+use rand::{Rng, thread_rng};
+
+/// Backtests of one optimization that are waiting for eval workers at the same time
+const PARALLEL_BACKTESTS: usize = 16;
+/// How long a single backtest may take on a worker
+const BACKTEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Component running parameter optimizations requested through the web API, one at a time.
+/// Backtests are distributed to the eval workers, each parameter combination is scored by walk-forward
+/// validation: the data is split into `folds + 1` windows, and every fold is trained on all windows
+/// up to its own and tested on the next one.
+pub struct Optimizer {
+    client: anats::Client,
+    db: Database,
+    busy: bool,
+}
+
+impl Actor for Optimizer { type Context = Context<Self>; }
+
+impl Optimizer {
+    pub async fn new(client: anats::Client, db: Database) -> Addr<Self> {
+        Arbiter::start(|ctx: &mut Context<Self>| {
+            ctx.run_interval(Duration::from_secs(5), |this, ctx| {
+                this.poll(ctx);
+            });
+            Optimizer {
+                client,
+                db,
+                busy: false,
+            }
+        })
+    }
+
+    fn poll(&mut self, ctx: &mut Context<Self>) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+
+        let (client, db) = (self.client.clone(), self.db.clone());
+        let fut = async move {
+            let job = match db.claim_optimization().await? {
+                Some(job) => job,
+                None => return Ok(()),
+            };
+            info!("Running optimization {} of strategy {}", job.id, job.strategy_id);
+
+            match run(&client, &db, &job).await {
+                Ok(scores) => db.finish_optimization(job.id, scores).await?,
+                Err(e) => {
+                    warn!("Optimization {} failed: {}", job.id, e);
+                    db.fail_optimization(job.id, e.to_string()).await?
+                }
+            }
+            Ok::<_, failure::Error>(())
+        };
+
+        let fut = wrap_future(fut.boxed_local().compat()).then(|res, this: &mut Self, ctx| {
+            if let Err(e) = res {
+                error!("Could not process optimization: {}", e);
+            }
+            this.busy = false;
+            afut::ok(())
+        });
+        ctx.spawn(fut);
+    }
+}
+
+async fn run(client: &anats::Client, db: &Database, job: &Optimization) -> Result<Vec<CandidateScore>> {
+    let revision = db.strategy_revision(job.revision_id).await?;
+    let specs = revision.param_specs();
+    let search = job.search()?;
+    let period = OhlcPeriod::from_str(&job.period).map_err(|_| format_err!("Invalid period {}", job.period))?;
+
+    // Jobs are checked when requested, but an empty or degenerate split would score every candidate as NaN
+    validate_walk_forward(period, job.from_time, job.to_time, job.folds).map_err(|e| format_err!("{}", e))?;
+
+    let candidates = candidates(&search, &specs);
+    let windows = walk_forward(job.from_time, job.to_time, job.folds as i64);
+
+    let mut requests = vec![];
+    for (c, params) in candidates.iter().enumerate() {
+        for &(train, test) in windows.iter() {
+            for &(is_test, (from, to)) in [(false, train), (true, test)].iter() {
+                let req = BacktestRequest {
+                    revision_id: job.revision_id,
+                    pair_id: job.pair_id,
+                    period,
+                    from,
+                    to,
+                    params: params.clone(),
+                };
+                requests.push((c, is_test, req));
+            }
+        }
+    }
+
+    let client = client.clone();
+    let results = futures03::stream::iter(requests)
+        .map(move |(c, is_test, req)| {
+            client.deliver(common::CHANNEL_BACKTEST_REQUESTS, req, 1, BACKTEST_TIMEOUT)
+                .compat()
+                .map(move |res| (c, is_test, res))
+        })
+        .buffer_unordered(PARALLEL_BACKTESTS)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut train = vec![vec![]; candidates.len()];
+    let mut test = vec![vec![]; candidates.len()];
+    let mut failed = HashMap::new();
+    for (c, is_test, res) in results {
+        match res {
+            Ok(Ok(res)) => if is_test { test[c].push(res) } else { train[c].push(res) },
+            Ok(Err(e)) => { failed.insert(c, e.to_string()); }
+            Err(e) => { failed.insert(c, format!("Backtest was not completed: {:?}", e)); }
+        }
+    }
+    if failed.len() == candidates.len() {
+        bail!("{}", failed.values().next().cloned().unwrap_or_default());
+    }
+
+    let mean = |results: &[BacktestResult]| results.iter().map(|r| r.ret).sum::<f64>() / results.len() as f64;
+    Ok(candidates.into_iter().enumerate()
+        .filter(|(c, _)| !failed.contains_key(c) && !train[*c].is_empty() && !test[*c].is_empty())
+        .map(|(c, params)| CandidateScore {
+            params,
+            in_sample: mean(&train[c]),
+            out_of_sample: mean(&test[c]),
+            max_drawdown: test[c].iter().map(|r| r.max_drawdown).fold(0.0, f64::max),
+            trades: test[c].iter().map(|r| r.trades as i32).sum(),
+        })
+        .collect())
+}
+
+/// Training and testing windows of the folds, both given as inclusive time ranges
+fn walk_forward(from: i64, to: i64, folds: i64) -> Vec<((i64, i64), (i64, i64))> {
+    let step = (to - from) / (folds + 1);
+    (1..=folds)
+        .map(|k| {
+            let split = from + k * step;
+            ((from, split - 1), (split, split + step - 1))
+        })
+        .collect()
+}
+
+/// Parameter combinations evaluated by the search, with defaults for parameters it does not cover
+fn candidates(search: &ParamSearch, specs: &[ParamSpec]) -> Vec<ParamValues> {
+    let defaults = specs.iter()
+        .map(|s| (s.name.clone(), s.default))
+        .collect::<ParamValues>();
+
+    match search {
+        ParamSearch::Grid { values } => {
+            let mut out = vec![defaults];
+            for (name, values) in values.iter() {
+                out = out.into_iter()
+                    .flat_map(|base| values.iter().map(move |v| {
+                        let mut params = base.clone();
+                        params.insert(name.clone(), *v);
+                        params
+                    }))
+                    .collect();
+            }
+            out
+        }
+        ParamSearch::Random { samples } => {
+            let mut rng = thread_rng();
+            (0..*samples)
+                .map(|_| specs.iter()
+                    .map(|s| {
+                        let value = match (s.kind, s.min, s.max) {
+                            (ParamType::Bool, _, _) => ParamValue::Bool(rng.gen()),
+                            (ParamType::Integer, Some(min), Some(max)) if max.floor() >= min.ceil() => {
+                                ParamValue::Integer(rng.gen_range(min.ceil() as i64, max.floor() as i64 + 1))
+                            }
+                            (ParamType::Float, Some(min), Some(max)) if max > min => ParamValue::Float(rng.gen_range(min, max)),
+                            _ => s.default,
+                        };
+                        (s.name.clone(), value)
+                    })
+                    .collect())
+                .collect()
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::prelude::*;
+use common::msgs::*;
+use db::OutboxMessage;
+use anats::RemoteMessage;
+
+/// How long a single delivery attempt waits for the subscriber to acknowledge the message
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of messages loaded from the outbox in one iteration
+const BATCH_SIZE: i64 = 64;
+/// How long delivered and received messages are kept, a message redelivered after that would be acted on again
+const MESSAGE_RETENTION: i64 = 7 * 24 * 60 * 60;
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Component responsible for relaying messages stored in the outbox table onto NATS.
+/// Message is removed from the outbox only after the subscriber replied to it.
+pub struct Relay {
+    client: anats::Client,
+    db: Database,
+    in_flight: HashSet<Uuid>,
+}
+
+impl Actor for Relay { type Context = Context<Self>; }
+
+impl Relay {
+    pub async fn new(client: anats::Client, db: Database) -> Addr<Self> {
+        Arbiter::start(|ctx: &mut Context<Self>| {
+            ctx.run_interval(Duration::from_secs(1), |this, ctx| {
+                this.flush(ctx);
+            });
+            ctx.run_interval(PURGE_INTERVAL, |this, ctx| {
+                this.purge(ctx);
+            });
+            Relay {
+                client,
+                db,
+                in_flight: HashSet::new(),
+            }
+        })
+    }
+
+    fn flush(&mut self, ctx: &mut Context<Self>) {
+        let pending = wrap_future(self.db.outbox_pending(BATCH_SIZE).boxed_local().compat());
+
+        let fut = pending.map(|msgs: Vec<OutboxMessage>, this: &mut Self, ctx| {
+            for msg in msgs {
+                if this.in_flight.insert(msg.id) {
+                    this.dispatch(msg, ctx);
+                }
+            }
+        });
+        ctx.spawn(fut.drop_err());
+    }
+
+    fn purge(&mut self, ctx: &mut Context<Self>) {
+        let before = chrono::Utc::now() - chrono::Duration::seconds(MESSAGE_RETENTION);
+        let purged = wrap_future(self.db.purge_messages(before).boxed_local().compat());
+
+        ctx.spawn(purged.then(|res, this: &mut Self, ctx| {
+            match res {
+                Ok((sent, received)) => info!("Purged {} delivered and {} received messages", sent, received),
+                Err(e) => warn!("Could not purge old messages : {:?}", e),
+            }
+            afut::ok(())
+        }));
+    }
+
+    fn dispatch(&mut self, msg: OutboxMessage, ctx: &mut Context<Self>) {
+        let ack = match msg.subject.as_str() {
+            common::CHANNEL_POSITION_REQUESTS => self.deliver::<PositionRequest>(&msg),
+            common::CHANNEL_APPROVED_POSITIONS => self.deliver::<PositionRequest>(&msg),
+            common::CHANNEL_TRADE_REQUESTS => self.deliver_trade(&msg),
+            common::CHANNEL_ASSIGNMENT_UPDATES => self.deliver::<AssignmentsChanged>(&msg),
+            common::CHANNEL_TRADES => self.broadcast::<TradeLogged>(&msg),
+            _ => {
+                error!("No relay for outbox subject : {:?}", msg.subject);
+                return;
+            }
+        };
+
+        let fut = wrap_future(ack).then(move |res, this: &mut Self, ctx| {
+            this.in_flight.remove(&msg.id);
+            let done = match res {
+                Ok(_) => this.db.outbox_delivered(msg.id),
+                Err(_) => {
+                    warn!("Outbox message {:?} on {:?} was not acknowledged, attempt {:?}", msg.id, msg.subject, msg.attempts + 1);
+                    this.db.outbox_failed(msg)
+                }
+            };
+            wrap_future(done.boxed_local().compat()).drop_err()
+        });
+        ctx.spawn(fut);
+    }
+
+    fn deliver<T>(&self, msg: &OutboxMessage) -> Box<dyn Future<Item=(), Error=()>>
+        where T: RemoteMessage + Clone
+    {
+        let data: T = match msg.decode() {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Invalid outbox payload for {:?} : {:?}", msg.id, e);
+                return box future::err(());
+            }
+        };
+
+        box self.client.deliver(msg.subject.clone(), data, 1, ACK_TIMEOUT)
+            .map(|_| ())
+            .map_err(|_| ())
+    }
+
+    /// Trade requests are acknowledged only after their result is logged.
+    /// The exchange connector returns the same result for a redelivered request, and it is logged once.
+    fn deliver_trade(&self, msg: &OutboxMessage) -> Box<dyn Future<Item=(), Error=()>> {
+        let req: TradeRequest = match msg.decode() {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Invalid outbox payload for {:?} : {:?}", msg.id, e);
+                return box future::err(());
+            }
+        };
+
+        let db = self.db.clone();
+        box self.client.deliver(msg.subject.clone(), req.clone(), 1, ACK_TIMEOUT)
+            .map_err(|_| ())
+            .and_then(move |res| {
+                let id = req.id;
+                let logged = async move { db.log_trade_result(req, res).await };
+                logged.boxed_local().compat()
+                    .map(|_| ())
+                    .map_err(move |e| error!("Could not log result of trade request {:?} : {:?}", id, e))
+            })
+    }
+
+    /// Publishes an event without waiting for receivers, it's removed from the outbox right away
+    fn broadcast<T>(&self, msg: &OutboxMessage) -> Box<dyn Future<Item=(), Error=()>>
+        where T: RemoteMessage
+    {
+        let data: T = match msg.decode() {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Invalid outbox payload for {:?} : {:?}", msg.id, e);
+                return box future::err(());
+            }
+        };
+
+        self.client.publish(msg.subject.clone(), data);
+        box future::ok(())
+    }
+}
@@ -6,7 +6,7 @@ pub use db;
 pub use db::Database;
 
 pub use common::types::{
-    Ohlc, OhlcSpec, OhlcPeriod, TradePair, PairId, TradingPosition,
+    Ohlc, OhlcSpec, OhlcPeriod, TradePair, PairId, TradingPosition, ParamValues,
 };
 
 
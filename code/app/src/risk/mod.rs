@@ -0,0 +1,379 @@
+use crate::prelude::*;
+use common::msgs::{PositionRequest, PositionResponse, ExchangeError, BalanceRequest};
+use db::{RiskDecision, RiskState};
+
+/// Holdings smaller than this are considered closed
+const DUST: f64 = 1e-8;
+
+/// Component standing between the decider and the trader. Position requests are checked against
+/// limits of the user owning the trader, and passed on through the outbox only if they fit them,
+/// possibly with a lowered size. Every request changing a position is recorded with the reason of the outcome.
+pub struct RiskManager {
+    client: anats::Client,
+    db: Database,
+}
+
+impl Actor for RiskManager { type Context = Context<Self>; }
+
+impl RiskManager {
+    pub async fn new(client: anats::Client, db: Database) -> Addr<Self> {
+        Arbiter::start(move |ctx: &mut Context<Self>| {
+            client.subscribe(crate::CHANNEL_POSITION_REQUESTS, None, ctx.address().recipient::<PositionRequest>());
+            RiskManager {
+                client,
+                db,
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Verdict {
+    /// Requested position is already held, nothing is recorded or sent to the trader
+    Unchanged,
+    /// `amount` is the approved size in the target currency, when the funds of the trader are known
+    Approved { size: f64, amount: Option<f64>, reason: Option<String> },
+    Rejected(String),
+}
+
+impl Handler<PositionRequest> for RiskManager {
+    type Result = Response<PositionResponse, ExchangeError>;
+
+    fn handle(&mut self, req: PositionRequest, _ctx: &mut Self::Context) -> Self::Result {
+        let db = self.db.clone();
+        let client = self.client.clone();
+        Response::r#async(async move {
+            let internal = |e: db::diesel::result::Error| ExchangeError::Internal(e.to_string());
+            let state = db.risk_state(req.trader_id).await.map_err(internal)?;
+            let prices = db.ohlc_lasts().await.map_err(internal)?;
+            let pair_id = db.pair_id(req.pair.clone()).await.map_err(internal)?;
+
+            // Exposure is limited in the target currency, so the size has to be converted using the funds of the trader
+            let funds = match (state.limits.as_ref().and_then(|l| l.max_exposure), prices.get(&req.pair)) {
+                (Some(_), Some(last)) if last.close > 0.0 => {
+                    let balance = client.request(common::CHANNEL_BALANCE_REQUESTS, BalanceRequest::new(req.pair.clone(), req.trader_id))
+                        .compat()
+                        .await;
+                    match balance {
+                        Ok(Ok(balance)) => Some(balance.target + balance.source / last.close),
+                        e => {
+                            warn!("Could not load balance of trader {} : {:?}", req.trader_id, e);
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+
+            let request = req.clone();
+            let reviewed = db.record_risk_decision(req.trader_id, move |state: &RiskState| {
+                let (approved, amount, reason) = match review(state, &prices, funds, &request) {
+                    Verdict::Unchanged => return None,
+                    Verdict::Approved { size, amount, reason } => (Some(size), amount, reason),
+                    Verdict::Rejected(reason) => (None, None, Some(reason)),
+                };
+                let decision = RiskDecision {
+                    id: request.id,
+                    time: chrono::Utc::now(),
+                    user_id: state.user_id,
+                    trader_id: request.trader_id,
+                    pair_id,
+                    position: request.position.to_string(),
+                    requested: request.size,
+                    approved,
+                    reason,
+                    amount,
+                };
+                let forward = approved.map(|size| (common::CHANNEL_APPROVED_POSITIONS, PositionRequest { size, ..request }));
+                Some((decision, forward))
+            }).await.map_err(internal)?;
+
+            let (decision, first) = match reviewed {
+                Some(reviewed) => reviewed,
+                None => return Ok(PositionResponse::Unchanged),
+            };
+            if !first {
+                info!("Position request {:?} was already reviewed", req.id);
+            }
+
+            match (decision.approved, decision.reason) {
+                (Some(size), reason) => {
+                    if let Some(reason) = reason {
+                        info!("Position request {:?} of trader {} : {}", req.id, req.trader_id, reason);
+                    }
+                    Ok(PositionResponse::Approved { size })
+                }
+                (None, reason) => {
+                    let reason = reason.unwrap_or_default();
+                    warn!("Rejected position request {:?} of trader {} : {}", req.id, req.trader_id, reason);
+                    Ok(PositionResponse::Rejected { reason })
+                }
+            }
+        }.boxed_local().compat())
+    }
+}
+
+/// Checks the request against limits of the user. Closing a position is always allowed,
+/// and positions already held after executed trades are left as they are.
+/// `funds` are the funds of the trader available for the pair, in its target currency.
+fn review(state: &RiskState, prices: &BTreeMap<PairId, Ohlc>, funds: Option<f64>, req: &PositionRequest) -> Verdict {
+    let held = state.holdings.iter()
+        .find(|h| h.trader_id == req.trader_id && h.pair == req.pair)
+        .map_or(0.0, |h| h.amount);
+    let current = if held > DUST {
+        TradingPosition::Long
+    } else if held < -DUST {
+        TradingPosition::Short
+    } else {
+        TradingPosition::Indeterminate
+    };
+
+    if current == req.position {
+        return Verdict::Unchanged;
+    }
+    let approve = |size: f64, reason| Verdict::Approved { size, amount: funds.map(|f| size * f), reason };
+    if req.position == TradingPosition::Indeterminate {
+        return approve(req.size, None);
+    }
+
+    let limits = match state.limits {
+        Some(ref limits) => limits,
+        None => return approve(req.size, None),
+    };
+    if limits.kill_switch {
+        return Verdict::Rejected("Kill switch is on, positions can only be closed".to_string());
+    }
+    if let Some(max) = limits.max_orders_per_hour {
+        if state.recent_orders >= max as i64 {
+            return Verdict::Rejected(format!("Limit of {} orders per hour was reached", max));
+        }
+    }
+    if let Some(max) = limits.max_daily_loss {
+        let loss = daily_loss(&state.trades_today, prices);
+        if loss >= max {
+            return Verdict::Rejected(format!("Daily loss {:.2} reached the limit of {:.2}", loss, max));
+        }
+    }
+
+    let mut size = req.size;
+    let mut reason = None;
+    if let Some(max) = limits.max_exposure {
+        let currency = req.pair.tar_currency();
+        let funds = match funds {
+            Some(funds) => funds,
+            None => return Verdict::Rejected(format!("Funds of the trader are unknown, exposure to {} can't be checked", currency)),
+        };
+        // Held amounts are in the target currency, the same unit as the limit and the funds
+        let other: f64 = state.holdings.iter()
+            .filter(|h| !(h.trader_id == req.trader_id && h.pair == req.pair))
+            .filter(|h| h.pair.tar_currency() == currency)
+            .map(|h| h.amount.abs())
+            .filter(|amount| *amount > DUST)
+            .sum();
+
+        let allowed = max - other;
+        if allowed <= 0.0 {
+            return Verdict::Rejected(format!("Exposure to {} is {:.2}, the limit is {:.2}", currency, other, max));
+        }
+        if size * funds > allowed {
+            let lowered = allowed / funds;
+            reason = Some(format!("Size lowered from {:.2} to {:.2}, exposure to {} is limited to {:.2}", size, lowered, currency, max));
+            size = lowered;
+        }
+    }
+    approve(size, reason)
+}
+
+/// Result of trades made today, valued at the latest close prices
+fn daily_loss(trades: &[(db::Trade, PairId)], prices: &BTreeMap<PairId, Ohlc>) -> f64 {
+    let pnl: f64 = trades.iter()
+        .map(|(t, pair)| {
+            let last = prices.get(pair).map(|o| o.close).unwrap_or(t.price);
+            let amount = if t.buy { t.amount } else { -t.amount };
+            amount * (last - t.price)
+        })
+        .sum();
+    -pnl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::Exchange;
+    use db::{RiskLimits, HeldAmount, Trade};
+
+    const TRADER: i32 = 1;
+
+    fn btc_usd() -> PairId {
+        PairId::new(Exchange::Bitfinex, TradePair::new("BTC", "USD"))
+    }
+
+    fn btc_eur() -> PairId {
+        PairId::new(Exchange::Bitfinex, TradePair::new("BTC", "EUR"))
+    }
+
+    fn limits() -> RiskLimits {
+        RiskLimits {
+            user_id: 1,
+            max_exposure: None,
+            max_daily_loss: None,
+            max_orders_per_hour: None,
+            kill_switch: false,
+            updated: chrono::Utc::now(),
+        }
+    }
+
+    fn state(limits: Option<RiskLimits>) -> RiskState {
+        RiskState {
+            user_id: 1,
+            limits,
+            positions: vec![],
+            holdings: vec![],
+            recent_orders: 0,
+            trades_today: vec![],
+        }
+    }
+
+    fn decision(trader_id: i32, position: TradingPosition, amount: Option<f64>) -> RiskDecision {
+        RiskDecision {
+            id: Uuid::new_v4(),
+            time: chrono::Utc::now(),
+            user_id: 1,
+            trader_id,
+            pair_id: 2,
+            position: position.to_string(),
+            requested: 1.0,
+            approved: Some(1.0),
+            reason: None,
+            amount,
+        }
+    }
+
+    fn trade(buy: bool, amount: f64, price: f64) -> (Trade, PairId) {
+        (Trade {
+            id: Uuid::new_v4(),
+            time: chrono::Utc::now(),
+            user_id: 1,
+            trader_id: TRADER,
+            pair_id: 1,
+            buy,
+            amount,
+            price,
+            status: true,
+            ok: None,
+            error: None,
+            revision_id: None,
+            fee: 0.0,
+        }, btc_usd())
+    }
+
+    fn request(position: TradingPosition) -> PositionRequest {
+        PositionRequest::new(TRADER, btc_usd(), position, None)
+    }
+
+    fn prices(close: f64) -> BTreeMap<PairId, Ohlc> {
+        let mut prices = BTreeMap::new();
+        prices.insert(btc_usd(), Ohlc { time: 0, open: close, high: close, low: close, close, vol: 0.0 });
+        prices
+    }
+
+    #[test]
+    fn unchanged_follows_executed_trades() {
+        let mut state = state(None);
+        // An approved but never executed decision doesn't count as a held position
+        state.positions.push((decision(TRADER, TradingPosition::Long, Some(1.0)), btc_usd()));
+        assert!(match review(&state, &prices(10.0), None, &request(TradingPosition::Long)) {
+            Verdict::Approved { .. } => true,
+            _ => false,
+        });
+
+        state.holdings.push(HeldAmount { trader_id: TRADER, pair: btc_usd(), amount: 0.5 });
+        assert_eq!(review(&state, &prices(10.0), None, &request(TradingPosition::Long)), Verdict::Unchanged);
+
+        state.holdings[0].amount = -0.5;
+        assert_eq!(review(&state, &prices(10.0), None, &request(TradingPosition::Short)), Verdict::Unchanged);
+
+        state.holdings[0].amount = 1e-12;
+        assert_eq!(review(&state, &prices(10.0), None, &request(TradingPosition::Indeterminate)), Verdict::Unchanged);
+    }
+
+    #[test]
+    fn closing_is_always_allowed() {
+        let mut limits = limits();
+        limits.kill_switch = true;
+        let mut state = state(Some(limits));
+        state.holdings.push(HeldAmount { trader_id: TRADER, pair: btc_usd(), amount: 1.0 });
+
+        assert_eq!(review(&state, &prices(10.0), None, &request(TradingPosition::Indeterminate)),
+                   Verdict::Approved { size: 1.0, amount: None, reason: None });
+        assert!(match review(&state, &prices(10.0), None, &request(TradingPosition::Short)) {
+            Verdict::Rejected(_) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn order_and_loss_limits() {
+        let mut limits = limits();
+        limits.max_orders_per_hour = Some(3);
+        let mut state = state(Some(limits.clone()));
+        state.recent_orders = 3;
+        assert!(match review(&state, &prices(10.0), None, &request(TradingPosition::Long)) {
+            Verdict::Rejected(_) => true,
+            _ => false,
+        });
+
+        limits.max_orders_per_hour = None;
+        limits.max_daily_loss = Some(5.0);
+        let mut state = self::state(Some(limits));
+        // Bought 1 at 12, now worth 10
+        state.trades_today.push(trade(true, 1.0, 12.0));
+        assert!(match review(&state, &prices(10.0), None, &request(TradingPosition::Long)) {
+            Verdict::Approved { .. } => true,
+            _ => false,
+        });
+        assert!(match review(&state, &prices(6.0), None, &request(TradingPosition::Long)) {
+            Verdict::Rejected(_) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn exposure_is_summed_from_holdings() {
+        let mut limits = limits();
+        limits.max_exposure = Some(2.0);
+        let mut state = state(Some(limits));
+        state.holdings.push(HeldAmount { trader_id: 2, pair: btc_eur(), amount: 0.5 });
+        state.holdings.push(HeldAmount { trader_id: 3, pair: btc_eur(), amount: 1e-12 });
+        // Approved decisions which were not executed don't add to the exposure
+        state.positions.push((decision(5, TradingPosition::Long, Some(5.0)), btc_eur()));
+
+        // Funds of 1 BTC fit within the remaining 1.5
+        assert_eq!(review(&state, &prices(10.0), Some(1.0), &request(TradingPosition::Long)),
+                   Verdict::Approved { size: 1.0, amount: Some(1.0), reason: None });
+
+        // Funds of 3 BTC are lowered to half of them
+        match review(&state, &prices(10.0), Some(3.0), &request(TradingPosition::Long)) {
+            Verdict::Approved { size, amount, reason } => {
+                assert!((size - 0.5).abs() < 1e-9);
+                assert!((amount.unwrap() - 1.5).abs() < 1e-9);
+                assert!(reason.is_some());
+            }
+            v => panic!("Unexpected verdict {:?}", v),
+        }
+
+        // Without the balance the exposure can't be checked
+        assert!(match review(&state, &prices(10.0), None, &request(TradingPosition::Long)) {
+            Verdict::Rejected(_) => true,
+            _ => false,
+        });
+
+        // Short holdings are exposed as well
+        state.holdings.push(HeldAmount { trader_id: 4, pair: btc_eur(), amount: -1.5 });
+        assert!(match review(&state, &prices(10.0), Some(1.0), &request(TradingPosition::Long)) {
+            Verdict::Rejected(_) => true,
+            _ => false,
+        });
+    }
+}
+
@@ -0,0 +1,122 @@
+use crate::prelude::*;
+use db::SignalDelivery;
+
+/// How often the queue is checked for pending signals
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const BATCH_SIZE: i64 = 64;
+/// Requests taking longer are failed attempts
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Header carrying hex encoded HMAC-SHA256 of `<timestamp>.<body>`, keyed by the signal secret of the assignment
+pub const SIGNATURE_HEADER: &str = "X-Signal-Signature";
+/// Header carrying the unix time of the attempt in seconds, receivers can refuse old ones to prevent replays
+pub const TIMESTAMP_HEADER: &str = "X-Signal-Timestamp";
+
+/// Component posting decisions of assignments to their signal targets. Failed deliveries are retried
+/// with increasing delays, and every attempt is recorded on the delivery.
+pub struct SignalSender {
+    db: Database,
+    /// Batch of deliveries is in progress, the next one is claimed after it finishes
+    busy: bool,
+}
+
+impl Actor for SignalSender { type Context = Context<Self>; }
+
+impl SignalSender {
+    pub async fn new(db: Database) -> Addr<Self> {
+        Arbiter::start(|ctx: &mut Context<Self>| {
+            ctx.run_interval(POLL_INTERVAL, |this, ctx| {
+                this.flush(ctx);
+            });
+            SignalSender {
+                db,
+                busy: false,
+            }
+        })
+    }
+
+    fn flush(&mut self, ctx: &mut Context<Self>) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+
+        let fut = wrap_future(self.db.claim_signal_deliveries(BATCH_SIZE).boxed_local().compat())
+            .map_err(|e, this: &mut Self, _| {
+                this.busy = false;
+                error!("Could not claim signal deliveries : {:?}", e);
+            })
+            .and_then(|pending, this: &mut Self, _| {
+                let sends = pending.into_iter().map(|(delivery, secret)| {
+                    let db = this.db.clone();
+                    post_signal(&delivery, secret).then(move |res| {
+                        let recorded = match res {
+                            Ok(status) => db.signal_delivered(delivery.id, status),
+                            Err((status, e)) => {
+                                warn!("Could not deliver signal {} to {} : {}", delivery.id, delivery.url, e);
+                                db.signal_failed(delivery, status, e)
+                            }
+                        };
+                        recorded.compat().then(|res| {
+                            if let Err(e) = res {
+                                error!("Could not record signal delivery : {:?}", e);
+                            }
+                            Ok::<_, ()>(())
+                        })
+                    })
+                }).collect::<Vec<_>>();
+
+                wrap_future(future::join_all(sends)).map(|_, this: &mut Self, _| this.busy = false)
+            });
+        ctx.spawn(fut);
+    }
+}
+
+/// Signature of the payload sent at `timestamp`
+pub fn signature(secret: &str, timestamp: i64, payload: &str) -> String {
+    hex(&hmac_sha256(secret, &format!("{}.{}", timestamp, payload)))
+}
+
+/// Posts the signed payload, resolves to the response status. Failures carry the status if there was a response.
+/// Targets resolving to private addresses are refused, they are checked again on every attempt.
+fn post_signal(delivery: &SignalDelivery, secret: Option<String>) -> Box<dyn Future<Item=i32, Error=(Option<i32>, String)>> {
+    let secret = match secret {
+        Some(secret) => secret,
+        None => return box future::err((None, "Assignment has no signal secret".to_string())),
+    };
+    let (url, payload) = (delivery.url.clone(), delivery.payload.clone());
+
+    box common::net::check_public_url(&url).compat()
+        .map_err(|e| (None, e))
+        .and_then(move |()| {
+            let timestamp = unixtime();
+            actix_web::client::post(&url)
+                .timeout(DELIVERY_TIMEOUT)
+                .header("Content-Type", "application/json")
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(SIGNATURE_HEADER, signature(&secret, timestamp, &payload))
+                .body(payload)
+                .map_err(|e| (None, e.to_string()))
+        })
+        .and_then(|req| req.send().map_err(|e| (None, e.to_string())))
+        .and_then(|resp| {
+            let status = resp.status();
+            if status.is_success() {
+                Ok(status.as_u16() as i32)
+            } else {
+                Err((Some(status.as_u16() as i32), format!("Responded with {}", status)))
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_covers_timestamp() {
+        let payload = r#"{"id":"1"}"#;
+        assert_eq!(signature("secret", 100, payload), hex(&hmac_sha256("secret", r#"100.{"id":"1"}"#)));
+        assert_ne!(signature("secret", 100, payload), signature("secret", 101, payload));
+        assert_ne!(signature("secret", 100, payload), signature("other", 100, payload));
+    }
+}
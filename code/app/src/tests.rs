@@ -0,0 +1,232 @@
+//! Runs the ingest, rescaling, decision, evaluation, risk and trading pipeline inside one process,
+//! with the NATS server replaced by the in-memory broker. Only the exchange connector is replaced by a stub.
+//! The test needs a migrated postgres database, run it with `cargo test -- --ignored`.
+use crate::prelude::*;
+use crate::{ingest, outbox, risk, trader};
+use common::msgs::*;
+use common::types::{Exchange, ParamSpec};
+use db::diesel::{Connection, PgConnection};
+
+use std::sync::Mutex;
+
+/// Longest time the pipeline gets to log a trade
+const PIPELINE_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Evaluates strategies the same way as the eval service, on the candles stored by the pipeline
+struct Evaluator(Database);
+
+impl Actor for Evaluator { type Context = Context<Self>; }
+
+impl Handler<EvalRequest> for Evaluator {
+    type Result = Response<TradingPosition, EvalError>;
+
+    fn handle(&mut self, req: EvalRequest, _ctx: &mut Self::Context) -> Self::Result {
+        let db = self.0.clone();
+        Response::r#async(async move {
+            let revision = db.strategy_revision(req.revision_id).await
+                .map_err(|_| EvalError::MissingData)?;
+            let params = ParamSpec::resolve(&revision.param_specs(), &req.params)
+                .map_err(EvalError::InvalidParams)?;
+            let since = req.last - (req.period.seconds() * 1000);
+            let data = db.ohlc_history_backfilled(req.pair_id, req.period, since).await
+                .map_err(|_| EvalError::MissingData)?;
+
+            strat_eval::eval(data.into_iter().map(|x| (x.time, x)).collect(), revision.body, params)
+        }.boxed_local().compat())
+    }
+}
+
+/// Stands in for the exchange connector, fills every order at the requested amount and records the requests
+struct StubExchange(Arc<Mutex<Vec<TradeRequest>>>);
+
+/// Funds of the stub trader in the quote currency
+const STUB_FUNDS: f64 = 1000.0;
+
+impl Actor for StubExchange { type Context = Context<Self>; }
+
+impl Handler<BalanceRequest> for StubExchange {
+    type Result = Result<BalanceResponse, ExchangeError>;
+
+    fn handle(&mut self, _msg: BalanceRequest, _ctx: &mut Self::Context) -> Self::Result {
+        Ok(BalanceResponse {
+            target: 0.0,
+            source: STUB_FUNDS,
+            min_buy: 0.0,
+            min_sell: 0.0,
+        })
+    }
+}
+
+impl Handler<TradeRequest> for StubExchange {
+    type Result = Result<TradeResponse, ExchangeError>;
+
+    fn handle(&mut self, msg: TradeRequest, _ctx: &mut Self::Context) -> Self::Result {
+        let resp = TradeResponse {
+            amount: msg.amount,
+            price: 1.0,
+            fee: 0.0,
+        };
+        self.0.lock().unwrap().push(msg);
+        Ok(resp)
+    }
+}
+
+async fn delay(duration: Duration) {
+    tokio::timer::Delay::new(Instant::now() + duration).compat().await.unwrap();
+}
+
+fn candle(time: i64) -> Ohlc {
+    Ohlc {
+        time,
+        open: 1.0,
+        high: 1.0,
+        low: 1.0,
+        close: 1.0,
+        vol: 1.0,
+    }
+}
+
+#[derive(Default)]
+struct Outcome {
+    trader_id: i32,
+    pair_id: i32,
+    decisions: Vec<db::RiskDecision>,
+    trades: Vec<db::Trade>,
+}
+
+/// Feeds candles to the pipeline until a trade of the decided position is logged, or the timeout elapses.
+/// A new candle is published on every poll, so the assignment is picked up whenever the decider loads it.
+fn run_pipeline(pair: PairId, orders: Arc<Mutex<Vec<TradeRequest>>>) -> Outcome {
+    let outcome = Arc::new(Mutex::new(Outcome::default()));
+    let result = outcome.clone();
+
+    actix::System::run(move || {
+        let fut = async move {
+            let client = anats::Client::in_memory();
+            let db = db::start();
+
+            let eval = Evaluator(db.clone()).start();
+            client.subscribe(common::CHANNEL_EVAL_REQUESTS, common::GROUP_EVAL_WORKERS.to_string(), eval.recipient::<EvalRequest>());
+            let exchange = StubExchange(orders).start();
+            client.subscribe(common::CHANNEL_BALANCE_REQUESTS, None, exchange.clone().recipient::<BalanceRequest>());
+            client.subscribe(common::CHANNEL_TRADE_REQUESTS, None, exchange.recipient::<TradeRequest>());
+
+            let _decider = ingest::decision::Decider::new(client.clone(), db.clone()).await.unwrap();
+            let _rescaler = ingest::rescaler::Rescaler::new(client.clone(), db.clone()).await.unwrap();
+            let _ingest = ingest::Ingest::new(client.clone(), db.clone()).await.unwrap();
+            let _relay = outbox::Relay::new(client.clone(), db.clone()).await;
+            let _risk = risk::RiskManager::new(client.clone(), db.clone()).await;
+            let _trader = trader::Trader::new(client.clone(), db.clone()).await.unwrap();
+
+            let suffix = Uuid::new_v4().to_string()[..8].to_string();
+            let user = db.new_user(db::UserAuthInfo {
+                email: format!("pipeline-{}@example.com", suffix),
+                password: "".into(),
+            }).await.unwrap();
+            let strategy = db.save_strategy(db::StrategyData {
+                id: None,
+                user_id: user.id,
+                name: "always long".into(),
+                body: "return 'long'".into(),
+                params: vec![],
+            }).await.unwrap();
+            let trader = db.save_trader(db::TraderData {
+                id: None,
+                user_id: user.id,
+                name: "stub".into(),
+                exchange: Exchange::Bitfinex.to_string(),
+                api_key: "key".into(),
+                api_secret: "secret".into(),
+            }).await.unwrap().unwrap();
+            result.lock().unwrap().trader_id = trader.id;
+
+            db.do_save_ohlc(pair.clone(), vec![]).await.unwrap();
+            let pair_id = db.pair_id(pair.clone()).await.unwrap();
+            result.lock().unwrap().pair_id = pair_id;
+            db.create_assignment(db::AssignmentData {
+                pair_id,
+                user_id: user.id,
+                period: OhlcPeriod::Min1.to_string(),
+                strategy_id: strategy.id,
+                trader_id: Some(trader.id),
+                params: Default::default(),
+                enabled: true,
+                signal_url: None,
+                signal_secret: None,
+            }).await.unwrap();
+            db.enqueue(common::CHANNEL_ASSIGNMENT_UPDATES, &AssignmentsChanged { user_id: user.id }).await.unwrap();
+
+            let spec = OhlcSpec::from_pair_1m(pair);
+            let mut time = OhlcPeriod::Min1.clamp_time(unixtime()) - 2 * 60 * 60;
+            let history = (0..8).map(|i| candle(time + i * 60)).collect::<Vec<_>>();
+            client.publish(common::CHANNEL_OHLC_INGEST, IngestUpdate::new(spec.clone(), history));
+            time += 8 * 60;
+
+            let started = Instant::now();
+            while started.elapsed() < PIPELINE_TIMEOUT {
+                delay(POLL_INTERVAL).await;
+                let decisions = db.risk_decisions(user.id, 10).await.unwrap();
+                let trades = db.user_trades(user.id).await.unwrap();
+                let traded = !trades.is_empty();
+                let mut current = result.lock().unwrap();
+                current.decisions = decisions;
+                current.trades = trades;
+                if traded {
+                    break;
+                }
+                drop(current);
+                client.publish(common::CHANNEL_OHLC_INGEST, IngestUpdate::new(spec.clone(), vec![candle(time)]));
+                time += 60;
+            }
+
+            actix::System::current().stop();
+            Ok::<_, ()>(())
+        };
+        actix::spawn(fut.boxed_local().compat());
+    });
+
+    let mut outcome = outcome.lock().unwrap();
+    std::mem::replace(&mut *outcome, Outcome::default())
+}
+
+#[test]
+#[ignore]
+fn stable_candles_result_in_trades() {
+    // Saving a trader encrypts its credentials, the key has to be set before the configuration is loaded
+    if env::var("MASTER_KEY").is_err() {
+        env::set_var("MASTER_KEY", base64::encode(&[7u8; 32]));
+    }
+    PgConnection::establish(&common::config().database.url())
+        .expect("The pipeline test needs a postgres database");
+
+    let target = Uuid::new_v4().to_string()[..4].to_uppercase();
+    let pair = PairId::new(Exchange::Bitfinex, TradePair::new(target, "USD".to_string()));
+    let orders = Arc::new(Mutex::new(vec![]));
+
+    let outcome = run_pipeline(pair.clone(), orders.clone());
+
+    assert!(!outcome.decisions.is_empty(), "Risk manager did not decide on any position within {:?}", PIPELINE_TIMEOUT);
+    for decision in outcome.decisions.iter() {
+        assert_eq!(decision.trader_id, outcome.trader_id);
+        assert_eq!(decision.position, TradingPosition::Long.to_string());
+        assert!(decision.approved.is_some(), "Position was rejected : {:?}", decision.reason);
+    }
+
+    assert!(!outcome.trades.is_empty(), "No trades were logged within {:?}", PIPELINE_TIMEOUT);
+    let orders = orders.lock().unwrap();
+    for trade in outcome.trades.iter() {
+        assert_eq!(trade.trader_id, outcome.trader_id);
+        assert_eq!(trade.pair_id, outcome.pair_id);
+        assert!(trade.buy);
+        assert!(trade.status, "Trade failed : {:?}", trade.error);
+        assert_eq!(trade.amount, STUB_FUNDS);
+        assert_eq!(trade.price, 1.0);
+        // Every logged trade is the result of exactly one order placed on the exchange
+        assert_eq!(orders.iter().filter(|o| o.id == trade.id).count(), 1);
+    }
+    for order in orders.iter() {
+        assert_eq!(order.pair, *pair.pair());
+        assert!(order.buy);
+    }
+}
@@ -1,22 +1,25 @@
 use crate::prelude::*;
 use common::prelude::*;
-use db::NewTradeData;
 
 use common::msgs::*;
 
-/// Component responsible for executing actual trades on the exchange
+/// Component turning approved positions into trade requests for the exchange connector.
+/// Trade requests are enqueued in the outbox, and their results are logged by the relay.
 pub struct Trader {
     client: anats::Client,
     db: db::Database,
+    /// Position requests being handled, a request redelivered meanwhile is refused
+    handling: HashSet<Uuid>,
 }
 
 impl Trader {
     pub async fn new(client: anats::Client, db: db::Database) -> Result<Addr<Self>> {
         Ok(Actor::create(|ctx| {
-            client.subscribe(crate::CHANNEL_POSITION_REQUESTS, None, ctx.address().recipient());
+            client.subscribe(crate::CHANNEL_APPROVED_POSITIONS, None, ctx.address().recipient());
             Self {
                 client,
                 db,
+                handling: HashSet::new(),
             }
         }))
     }
@@ -29,18 +32,110 @@ impl Handler<PositionRequest> for Trader {
     type Result = ResponseActFuture<Self, PositionResponse, ExchangeError>;
 
     fn handle(&mut self, msg: PositionRequest, ctx: &mut Self::Context) -> Self::Result {
-        let balance = BalanceRequest::new(msg.pair, msg.api_key, msg.api_secret);
-        let balance = self.client.request(common::CHANNEL_BALANCE_REQUESTS, balance);
+        if !self.handling.insert(msg.id) {
+            return box afut::err(ExchangeError::Internal(format!("Position request {} is already being handled", msg.id)));
+        }
+        let id = msg.id;
+        let fut = execute(self.client.clone(), self.db.clone(), msg);
 
-        let balance = wrap_future(balance);
+        box wrap_future(fut.boxed_local().compat()).then(move |res, this: &mut Self, ctx| {
+            this.handling.remove(&id);
+            afut::result(res)
+        })
+    }
+}
 
-        Box::new(balance.then(move |balance, this: &mut Self, ctx| {
-            let balance = balance.unwrap();
-            panic!("Balance : {:?}", balance);
+/// Requests are relayed from the outbox, and can arrive more than once. The response is recorded
+/// with the trade request in one transaction, and returned again for a redelivered request.
+async fn execute(client: anats::Client, db: db::Database, req: PositionRequest) -> StdResult<PositionResponse, ExchangeError> {
+    let internal = |e: db::diesel::result::Error| ExchangeError::Internal(e.to_string());
+    if let Some(resp) = db.received_response::<PositionResponse>(req.id).await.map_err(internal)? {
+        info!("Position request {:?} was already handled", req.id);
+        return Ok(resp);
+    }
 
-            afut::err(ExchangeError::InvalidFunds("".to_string()))
-        }))
+    let prices = db.ohlc_lasts().await.map_err(internal)?;
+    let price = match prices.get(&req.pair) {
+        Some(last) if last.close > 0.0 => last.close,
+        _ => return Err(ExchangeError::Internal(format!("No price of {:?} is known", req.pair))),
+    };
+    let balance = client.request(common::CHANNEL_BALANCE_REQUESTS, BalanceRequest::new(req.pair.clone(), req.trader_id))
+        .compat()
+        .await
+        .map_err(|e| ExchangeError::Internal(e.to_string()))??;
+
+    let (resp, first) = match order(&req, &balance, price) {
+        Some((amount, buy)) => {
+            let trade = TradeRequest {
+                revision_id: req.revision_id,
+                ..TradeRequest::new(req.pair.exchange().to_string(), req.trader_id, req.pair.pair().clone(), amount, buy)
+            };
+            let adjusted = PositionResponse::Adjusted { amout: if buy { amount } else { -amount } };
+            db.respond_once_with(req.id, common::CHANNEL_APPROVED_POSITIONS, adjusted, (common::CHANNEL_TRADE_REQUESTS, trade)).await
+        }
+        None => db.respond_once(req.id, common::CHANNEL_APPROVED_POSITIONS, PositionResponse::Unchanged).await,
+    }.map_err(internal)?;
+
+    if first {
+        info!("Position request {:?} of trader {} : {:?}", req.id, req.trader_id, resp);
     }
+    Ok(resp)
 }
 
+/// Order moving the funds of the trader into the requested position, as the amount of the target currency
+/// and whether it is bought. `None` when the order would be smaller than the exchange allows.
+fn order(req: &PositionRequest, balance: &BalanceResponse, price: f64) -> Option<(f64, bool)> {
+    let (amount, buy, min) = match req.position {
+        TradingPosition::Long => (req.size * balance.source / price, true, balance.min_buy),
+        // Spot funds can not go below zero, so both close the held target currency
+        TradingPosition::Short | TradingPosition::Indeterminate => (req.size * balance.target, false, balance.min_sell),
+    };
+    if amount > 0.0 && amount >= min {
+        Some((amount, buy))
+    } else {
+        None
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::Exchange;
+
+    fn request(position: TradingPosition, size: f64) -> PositionRequest {
+        PositionRequest {
+            id: Uuid::new_v4(),
+            trader_id: 1,
+            pair: PairId::new(Exchange::Bitfinex, TradePair::new("BTC", "USD")),
+            position,
+            revision_id: None,
+            size,
+        }
+    }
+
+    fn balance(target: f64, source: f64) -> BalanceResponse {
+        BalanceResponse {
+            target,
+            source,
+            min_buy: 0.01,
+            min_sell: 0.01,
+        }
+    }
+
+    #[test]
+    fn long_buys_with_the_approved_part_of_source_funds() {
+        assert_eq!(order(&request(TradingPosition::Long, 0.5), &balance(0.0, 1000.0), 100.0), Some((5.0, true)));
+    }
+
+    #[test]
+    fn short_and_neutral_sell_held_target() {
+        assert_eq!(order(&request(TradingPosition::Short, 1.0), &balance(2.0, 0.0), 100.0), Some((2.0, false)));
+        assert_eq!(order(&request(TradingPosition::Indeterminate, 0.5), &balance(2.0, 0.0), 100.0), Some((1.0, false)));
+    }
+
+    #[test]
+    fn orders_below_minimum_are_skipped() {
+        assert_eq!(order(&request(TradingPosition::Long, 1.0), &balance(0.0, 0.5), 100.0), None);
+        assert_eq!(order(&request(TradingPosition::Short, 1.0), &balance(0.0, 1000.0), 100.0), None);
+    }
+}
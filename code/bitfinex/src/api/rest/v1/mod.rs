@@ -80,6 +80,20 @@ pub async fn wallet_info(auth: AuthInfo) -> Result<Vec<WalletInfo>, actix_web::E
 }
 
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountFees {
+    /// Percentage of the traded value charged for orders taking liquidity, market orders always do
+    #[serde(deserialize_with = "f64_from_str")]
+    pub taker_fees: f64,
+}
+
+pub async fn account_fees(auth: AuthInfo) -> Result<AccountFees, actix_web::Error> {
+    let resp = req_v1(&auth, "/v1/account_infos", json!({})).await?;
+    let mut infos: Vec<AccountFees> = resp.json().compat().await?;
+    infos.pop().ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing account info"))
+}
+
+
 #[derive(Debug, Clone, )]
 pub struct NewOrderPayload {
     pub symbol: TradePair,
@@ -143,6 +157,9 @@ pub struct OrderStatus {
     #[serde(deserialize_with = "f64_from_str")]
     pub executed_amount: f64,
 
+    /// Average price of the executed part, `price` of market orders is only a placeholder
+    #[serde(default, deserialize_with = "f64_from_str_opt")]
+    pub avg_execution_price: Option<f64>,
 }
 
 pub async fn new_order(auth: AuthInfo, amount: f64, pair: TradePair, buy: bool) -> Result<OrderStatus, actix_web::Error> {
@@ -9,9 +9,13 @@ pub mod trade;
 fn main() {
     common::init();
     common::launch(|| async {
-        let client = anats::Client::new("nats://nats:4222").await;
+        // Candle snapshots and history dumps are large, use the binary format for them
+        let client = anats::Client::new(common::config().nats.url.clone()).await
+            .with_codec(anats::Codec::Binary);
 
-        let _ = trade::BitfinexClient::new(client.clone()).await.unwrap();
+        let db = db::start();
+
+        let _ = trade::BitfinexClient::new(client.clone(), db).await.unwrap();
         let _ = dump::BitfinexDumper::new(client.clone()).await.unwrap();
     });
 }
@@ -6,7 +6,6 @@ use crate::{
 use actix_web::ws;
 
 use common::msgs::*;
-use common::types::auth::AuthInfo;
 
 
 pub struct ActixWsClient {
@@ -186,9 +185,11 @@ impl StreamHandler<ws::Message, ws::ProtocolError> for ActixWsClient {
 
 pub struct BitfinexClient {
     client: anats::Client,
+    db: db::Database,
     ws_clients: Vec<Addr<ActixWsClient>>,
     pairs: BTreeMap<TradePair, SymbolDetail>,
-
+    /// Trade requests being executed, a request redelivered meanwhile is refused
+    executing: HashSet<Uuid>,
 }
 
 
@@ -212,7 +213,7 @@ impl Actor for BitfinexClient {
 
 
 impl BitfinexClient {
-    pub async fn new(client: anats::Client) -> Result<Addr<Self>, actix_web::Error> {
+    pub async fn new(client: anats::Client, db: db::Database) -> Result<Addr<Self>, actix_web::Error> {
         info!("Connecting to websocket");
         let symbols = crate::api::rest::v1::get_available_symbols().await?;
 
@@ -236,9 +237,10 @@ impl BitfinexClient {
             client.subscribe(crate::CHANNEL_TRADE_REQUESTS, None, ctx.address().recipient::<TradeRequest>());
             BitfinexClient {
                 client,
+                db,
                 ws_clients: clients,
                 pairs: pairs.into_iter().collect(),
-
+                executing: HashSet::new(),
             }
         }))
     }
@@ -251,12 +253,17 @@ impl Handler<BalanceRequest> for BitfinexClient {
         info!("Serving BalanceRequest");
 
         let pairs = self.pairs.clone();
+        let db = self.db.clone();
         let fut = async move {
-            let info = AuthInfo::new(&req.api_key, &req.api_secret);
+            let info = db.trader_credentials(req.trader_id).await
+                .map_err(|e| ExchangeError::InvalidInfo(e.to_string()))?;
             let w = crate::api::rest::v1::wallet_info(info).await;
 
             println!("BalanceRequest RES: {:?}", w);
-            let w = w.map_err(|e| ExchangeError::InvalidInfo(e.to_string()));
+            let w = match w {
+                Ok(w) => Ok(w),
+                Err(e) => Err(request_failed(db, req.trader_id, e).await),
+            };
 
             let pair = pairs.get(&req.pair_id.pair());
             let min_amount = pair.map(|s| s.minimum_order_size).unwrap_or(0.0);
@@ -300,20 +307,69 @@ impl Handler<TradeRequest> for BitfinexClient {
     fn handle(&mut self, req: TradeRequest, ctx: &mut Self::Context) -> Self::Result {
         info!("Serving TradeRequest");
 
-        let info = AuthInfo::new(&req.api_key, &req.api_secret);
-        let fut = crate::api::rest::v1::new_order(info, req.amount, req.pair, req.buy);
+        if !self.executing.insert(req.id) {
+            return Box::new(afut::err(ExchangeError::Internal(format!("Trade request {} is already being executed", req.id))));
+        }
+        let id = req.id;
+        let db = self.db.clone();
+        let fut = async move {
+            // Requests can arrive more than once, the result of the placed order is recorded
+            // and returned again for a redelivered request
+            let stored = db.received_response::<StdResult<TradeResponse, ExchangeError>>(req.id).await
+                .map_err(|e| ExchangeError::Internal(e.to_string()))?;
+            if let Some(result) = stored {
+                info!("Trade request {:?} was already executed", req.id);
+                return result;
+            }
+            let info = db.trader_credentials(req.trader_id).await
+                .map_err(|e| ExchangeError::InvalidInfo(e.to_string()))?;
+            // Fees are loaded before the order is placed, an order whose fee is unknown would be booked wrong
+            let fees = match crate::api::rest::v1::account_fees(info.clone()).await {
+                Ok(fees) => fees,
+                Err(e) => return Err(request_failed(db, req.trader_id, e).await),
+            };
+            let result = match crate::api::rest::v1::new_order(info, req.amount, req.pair, req.buy).await {
+                Ok(order) => {
+                    let price = order.avg_execution_price.unwrap_or(order.price);
+                    Ok(TradeResponse {
+                        amount: order.executed_amount,
+                        price,
+                        fee: order.executed_amount * price * fees.taker_fees / 100.,
+                    })
+                }
+                Err(e) => Err(request_failed(db.clone(), req.trader_id, e).await),
+            };
+
+            let (result, _) = db.respond_once(req.id, crate::CHANNEL_TRADE_REQUESTS, result).await
+                .map_err(|e| ExchangeError::Internal(e.to_string()))?;
+            result
+        };
         let fut = wrap_future(fut.boxed_local().compat());
 
-        let fut = fut.map_err(|e, this, ctx| {
-            let err = ExchangeError::InvalidInfo(e.to_string());
-            println!("TradeRequest MapErr: {:?}", err);
-            err
-        }).and_then(|_, _, _| afut::result(Ok(TradeResponse {
-            amount: 0.,
-            price: 0.,
-        })));
+        let fut = fut.then(move |res, this: &mut Self, ctx| {
+            this.executing.remove(&id);
+            if let Err(ref err) = res {
+                println!("TradeRequest MapErr: {:?}", err);
+            }
+            afut::result(res)
+        });
 
         return Box::new(fut);
     }
 }
 
+/// Notifies the owner of the trader when bitfinex rejected its credentials
+async fn request_failed(db: db::Database, tid: i32, e: actix_web::Error) -> ExchangeError {
+    if e.as_response_error().error_response().status() == actix_web::http::StatusCode::UNAUTHORIZED {
+        let notified = db.notify_trader_owner(
+            tid,
+            db::NotifyEvent::TraderAuthFailed,
+            "Bitfinex rejected credentials of a trader",
+            format!("Request of trader {} was rejected : {}", tid, e),
+        ).await;
+        if let Err(err) = notified {
+            warn!("Could not notify about rejected credentials of trader {} : {:?}", tid, err);
+        }
+    }
+    ExchangeError::InvalidInfo(e.to_string())
+}
@@ -0,0 +1,48 @@
+#![feature(test)]
+extern crate test;
+
+use test::Bencher;
+
+use common::anats::Codec;
+use common::msgs::IngestUpdate;
+use common::types::{Ohlc, OhlcSpec, OhlcPeriod, Exchange, TradePair};
+
+/// Same size as a single history request of the bitfinex dumper
+const DUMP_SIZE: usize = 4000;
+
+fn ingest_update(count: usize) -> IngestUpdate {
+    let ohlc = (0..count as i64).map(|i| Ohlc {
+        time: 1_500_000_000 + i * 60,
+        open: 8000.0 + i as f64,
+        high: 8010.5 + i as f64,
+        low: 7990.25 + i as f64,
+        close: 8005.125 + i as f64,
+        vol: 12.345678 * i as f64,
+    }).collect::<Vec<_>>();
+
+    IngestUpdate::new(OhlcSpec::new(Exchange::Bitfinex, TradePair::new("BTC", "USD"), OhlcPeriod::Min1), ohlc)
+}
+
+fn bench_encode(b: &mut Bencher, codec: Codec) {
+    let update = ingest_update(DUMP_SIZE);
+    b.bytes = codec.encode(&update).len() as u64;
+    b.iter(|| codec.encode(&update));
+}
+
+fn bench_decode(b: &mut Bencher, codec: Codec) {
+    let payload = codec.encode(&ingest_update(DUMP_SIZE));
+    b.bytes = payload.len() as u64;
+    b.iter(|| codec.decode::<IngestUpdate>(&payload).unwrap());
+}
+
+#[bench]
+fn encode_json(b: &mut Bencher) { bench_encode(b, Codec::Json) }
+
+#[bench]
+fn encode_binary(b: &mut Bencher) { bench_encode(b, Codec::Binary) }
+
+#[bench]
+fn decode_json(b: &mut Bencher) { bench_decode(b, Codec::Json) }
+
+#[bench]
+fn decode_binary(b: &mut Bencher) { bench_decode(b, Codec::Binary) }
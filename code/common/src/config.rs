@@ -0,0 +1,336 @@
+use crate::prelude::*;
+use std::path::Path;
+
+/// Path of the configuration file, unless overridden by `CONFIG_FILE`.
+/// Missing file is not an error, every value has a default or an environment override.
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+lazy_static! {
+    static ref CONFIG: Config = {
+        match Config::load() {
+            Ok(c) => c,
+            Err(e) => panic!("Invalid configuration: {}", e),
+        }
+    };
+}
+
+/// Configuration shared by all services, loaded on first access
+pub fn config() -> &'static Config {
+    &CONFIG
+}
+
+/// Value that should never end up in logs. Can be provided directly,
+/// or through a `<NAME>_FILE` environment variable pointing to a file containing it.
+#[derive(Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NatsConfig {
+    pub url: String,
+}
+
+impl Default for NatsConfig {
+    fn default() -> Self {
+        NatsConfig {
+            url: "nats://nats:4222".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub host: String,
+    pub port: u16,
+    pub name: String,
+    pub user: String,
+    pub password: Secret,
+    pub pool_size: usize,
+    /// Base64 encoded 32 byte key, encrypting exchange credentials of traders
+    pub master_key: Secret,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            host: "postgres.default.svc".into(),
+            port: 5432,
+            name: "trader".into(),
+            user: "trader".into(),
+            password: Secret::new("trader"),
+            pool_size: 8,
+            master_key: Secret::default(),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn url(&self) -> String {
+        format!("postgres://{}:{}@{}:{}/{}", self.user, self.password.expose(), self.host, self.port, self.name)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebConfig {
+    pub bind: String,
+    /// Address the web application is reachable at, used in links sent by email
+    pub public_url: String,
+    pub webapp_root: String,
+    /// Key used to encrypt session cookies, at least 32 bytes long
+    pub session_key: Secret,
+    /// Addresses of reverse proxies whose `X-Forwarded-For` headers are honoured
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        WebConfig {
+            bind: "0.0.0.0:8000".into(),
+            public_url: "http://localhost:8000".into(),
+            webapp_root: "./code/web/app/dist".into(),
+            session_key: Secret::default(),
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    /// Mails are only logged, for local development
+    Log,
+    /// Every mail is written into a separate file in `mail.directory`
+    File,
+    Smtp,
+}
+
+impl FromStr for MailTransport {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "log" => Ok(MailTransport::Log),
+            "file" => Ok(MailTransport::File),
+            "smtp" => Ok(MailTransport::Smtp),
+            _ => bail!("expected log, file or smtp, got {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub from: String,
+    pub directory: String,
+    pub smtp_host: String,
+    pub smtp_user: String,
+    pub smtp_password: Secret,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            transport: MailTransport::Log,
+            from: "trader@localhost".into(),
+            directory: "./mail".into(),
+            smtp_host: "".into(),
+            smtp_user: "".into(),
+            smtp_password: Secret::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// Base of the Telegram style bot API used by bot channels
+    pub bot_api_url: String,
+    /// Seconds without a new candle after which the feed of a pair is reported as stale
+    pub stale_after: u64,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        NotifyConfig {
+            bot_api_url: "https://api.telegram.org".into(),
+            stale_after: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub bind: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            bind: "0.0.0.0:9000".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub nats: NatsConfig,
+    pub database: DatabaseConfig,
+    pub web: WebConfig,
+    pub mail: MailConfig,
+    pub notify: NotifyConfig,
+    pub metrics: MetricsConfig,
+}
+
+impl Config {
+    /// Reads the configuration file, applies environment overrides and validates the result
+    pub fn load() -> Result<Config> {
+        let path = env::var("CONFIG_FILE").unwrap_or(DEFAULT_CONFIG_FILE.to_string());
+        let mut config = if Path::new(&path).exists() {
+            let text = std::fs::read_to_string(&path)?;
+            toml::from_str(&text).map_err(|e| format_err!("{}: {}", path, e))?
+        } else {
+            Config::default()
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        override_var("NATS_URL", &mut self.nats.url)?;
+
+        override_var("DATABASE_HOST", &mut self.database.host)?;
+        override_parsed("DATABASE_PORT", &mut self.database.port)?;
+        override_var("DATABASE_NAME", &mut self.database.name)?;
+        override_var("DATABASE_USER", &mut self.database.user)?;
+        override_secret("DATABASE_PASSWORD", &mut self.database.password)?;
+        override_parsed("DATABASE_POOL_SIZE", &mut self.database.pool_size)?;
+        override_secret("MASTER_KEY", &mut self.database.master_key)?;
+
+        override_var("WEB_BIND", &mut self.web.bind)?;
+        override_var("WEB_PUBLIC_URL", &mut self.web.public_url)?;
+        override_var("WEBAPP_ROOT", &mut self.web.webapp_root)?;
+        override_secret("SESSION_KEY", &mut self.web.session_key)?;
+        override_list("WEB_TRUSTED_PROXIES", &mut self.web.trusted_proxies)?;
+
+        override_parsed("MAIL_TRANSPORT", &mut self.mail.transport)?;
+        override_var("MAIL_FROM", &mut self.mail.from)?;
+        override_var("MAIL_DIRECTORY", &mut self.mail.directory)?;
+        override_var("SMTP_HOST", &mut self.mail.smtp_host)?;
+        override_var("SMTP_USER", &mut self.mail.smtp_user)?;
+        override_secret("SMTP_PASSWORD", &mut self.mail.smtp_password)?;
+
+        override_var("NOTIFY_BOT_API_URL", &mut self.notify.bot_api_url)?;
+        override_parsed("NOTIFY_STALE_AFTER", &mut self.notify.stale_after)?;
+
+        override_var("METRICS_BIND", &mut self.metrics.bind)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        let nats = Url::parse(&self.nats.url).map_err(|e| format_err!("nats.url: {}", e))?;
+        if nats.scheme() != "nats" {
+            bail!("nats.url: expected nats:// scheme, got {}", nats.scheme());
+        }
+        if self.database.host.is_empty() || self.database.name.is_empty() || self.database.user.is_empty() {
+            bail!("database: host, name and user are required");
+        }
+        if self.database.pool_size == 0 {
+            bail!("database.pool_size: must be at least 1");
+        }
+        if !self.database.master_key.is_empty() {
+            let key = base64::decode(self.database.master_key.expose()).map_err(|e| format_err!("database.master_key: {}", e))?;
+            if key.len() != 32 {
+                bail!("database.master_key: expected 32 bytes, got {}", key.len());
+            }
+        }
+        for (name, addr) in &[("web.bind", &self.web.bind), ("metrics.bind", &self.metrics.bind)] {
+            addr.parse::<std::net::SocketAddr>().map_err(|e| format_err!("{}: {}", name, e))?;
+        }
+        if !self.web.session_key.is_empty() && self.web.session_key.expose().len() < 32 {
+            bail!("web.session_key: must be at least 32 bytes long");
+        }
+        Url::parse(&self.web.public_url).map_err(|e| format_err!("web.public_url: {}", e))?;
+        if self.mail.transport == MailTransport::Smtp && self.mail.smtp_host.is_empty() {
+            bail!("mail.smtp_host: required by the smtp transport");
+        }
+        Url::parse(&self.notify.bot_api_url).map_err(|e| format_err!("notify.bot_api_url: {}", e))?;
+        if self.notify.stale_after == 0 {
+            bail!("notify.stale_after: must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+fn env_value(name: &str) -> Result<Option<String>> {
+    if let Ok(value) = env::var(name) {
+        return Ok(Some(value));
+    }
+    if let Ok(path) = env::var(format!("{}_FILE", name)) {
+        let value = std::fs::read_to_string(&path).map_err(|e| format_err!("{}_FILE: {}", name, e))?;
+        return Ok(Some(value.trim_end().to_string()));
+    }
+    Ok(None)
+}
+
+fn override_var(name: &str, target: &mut String) -> Result<()> {
+    if let Some(value) = env_value(name)? {
+        *target = value;
+    }
+    Ok(())
+}
+
+fn override_secret(name: &str, target: &mut Secret) -> Result<()> {
+    if let Some(value) = env_value(name)? {
+        *target = Secret::new(value);
+    }
+    Ok(())
+}
+
+fn override_parsed<T: FromStr>(name: &str, target: &mut T) -> Result<()>
+    where T::Err: fmt::Display
+{
+    if let Some(value) = env_value(name)? {
+        *target = value.parse().map_err(|e| format_err!("{}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// Comma separated list of values
+fn override_list<T: FromStr>(name: &str, target: &mut Vec<T>) -> Result<()>
+    where T::Err: fmt::Display
+{
+    if let Some(value) = env_value(name)? {
+        *target = value.split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse().map_err(|e| format_err!("{}: {}", name, e)))
+            .collect::<Result<_>>()?;
+    }
+    Ok(())
+}
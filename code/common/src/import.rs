@@ -0,0 +1,412 @@
+//! Parsing of historical candle archives in CSV files, shared by the import CLI and the upload endpoint.
+use crate::prelude::*;
+use crate::types::Ohlc;
+use chrono::{FixedOffset, NaiveDateTime, TimeZone};
+
+/// Rejected rows reported in detail, the rest is only counted
+pub const MAX_REPORTED_ERRORS: usize = 100;
+
+/// Unit of the values in the time column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeUnit {
+    #[serde(rename = "s")]
+    Seconds,
+    #[serde(rename = "ms")]
+    Millis,
+    #[serde(rename = "us")]
+    Micros,
+    #[serde(rename = "ns")]
+    Nanos,
+    /// Date and time formatted according to `time_format`
+    #[serde(rename = "text")]
+    Text,
+}
+
+impl FromStr for TimeUnit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(json::from_value(json::Value::String(s.to_string()))
+            .map_err(|_| format_err!("Unknown time unit {}, supported are s, ms, us, ns and text", s))?)
+    }
+}
+
+/// Layout of a CSV archive. Columns are referred to by their name in the header,
+/// or by their index when the file has no header.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub header: bool,
+    pub time_column: String,
+    pub open_column: String,
+    pub high_column: String,
+    pub low_column: String,
+    pub close_column: String,
+    pub volume_column: String,
+    pub time_unit: TimeUnit,
+    /// Format of textual times, as accepted by `chrono`
+    pub time_format: String,
+    /// Offset of textual times from UTC, e.g. `+02:00`
+    pub timezone: String,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            header: true,
+            time_column: "time".into(),
+            open_column: "open".into(),
+            high_column: "high".into(),
+            low_column: "low".into(),
+            close_column: "close".into(),
+            volume_column: "volume".into(),
+            time_unit: TimeUnit::Seconds,
+            time_format: "%Y-%m-%d %H:%M:%S".into(),
+            timezone: "+00:00".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RowError {
+    /// Line of the file, starting at 1
+    pub line: usize,
+    pub message: String,
+}
+
+/// Valid candles of an archive ordered by time, and what happened to the other rows
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CsvImport {
+    #[serde(skip)]
+    pub candles: Vec<Ohlc>,
+    /// Data rows in the file
+    pub rows: usize,
+    /// Rows with the time of an earlier row, the last of them is kept
+    pub duplicates: usize,
+    pub rejected: usize,
+    /// First rejected rows, up to `MAX_REPORTED_ERRORS`
+    pub errors: Vec<RowError>,
+}
+
+fn parse_offset(tz: &str) -> Result<FixedOffset> {
+    if tz.eq_ignore_ascii_case("utc") || tz == "Z" {
+        return Ok(FixedOffset::east(0));
+    }
+    let (sign, rest) = match tz.chars().next() {
+        Some('+') => (1, &tz[1..]),
+        Some('-') => (-1, &tz[1..]),
+        _ => bail!("Timezone must be UTC or an offset like +02:00"),
+    };
+    let mut parts = rest.splitn(2, ':');
+    let hours: i32 = parts.next().unwrap_or("").parse().map_err(|_| format_err!("Invalid timezone offset {}", tz))?;
+    let minutes: i32 = parts.next().unwrap_or("0").parse().map_err(|_| format_err!("Invalid timezone offset {}", tz))?;
+    if hours > 23 || minutes > 59 {
+        bail!("Invalid timezone offset {}", tz);
+    }
+    Ok(FixedOffset::east(sign * (hours * 3600 + minutes * 60)))
+}
+
+/// Splits a line into fields, fields can be quoted with `"` and quotes inside them doubled
+fn split_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::replace(&mut field, String::new())),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+struct Columns {
+    time: usize,
+    open: usize,
+    high: usize,
+    low: usize,
+    close: usize,
+    volume: usize,
+}
+
+impl Columns {
+    fn resolve(options: &CsvOptions, header: Option<&[String]>) -> Result<Self> {
+        let find = |column: &str| -> Result<usize> {
+            match header {
+                Some(header) => header.iter().position(|h| h.trim().eq_ignore_ascii_case(column))
+                    .ok_or_else(|| format_err!("No column named {} in the header", column)),
+                None => column.parse().map_err(|_| format_err!("Column {} must be an index, the file has no header", column)),
+            }
+        };
+        Ok(Columns {
+            time: find(&options.time_column)?,
+            open: find(&options.open_column)?,
+            high: find(&options.high_column)?,
+            low: find(&options.low_column)?,
+            close: find(&options.close_column)?,
+            volume: find(&options.volume_column)?,
+        })
+    }
+}
+
+impl CsvOptions {
+    fn parse_time(&self, value: &str, offset: &FixedOffset) -> Result<i64> {
+        let value = value.trim();
+        let seconds = match self.time_unit {
+            TimeUnit::Text => {
+                let time = NaiveDateTime::parse_from_str(value, &self.time_format)
+                    .map_err(|e| format_err!("Invalid time {} : {}", value, e))?;
+                return match offset.from_local_datetime(&time).single() {
+                    Some(time) => Ok(time.timestamp()),
+                    None => bail!("Invalid time {}", value),
+                };
+            }
+            unit => {
+                let raw: i64 = value.parse().map_err(|_| format_err!("Invalid timestamp {}", value))?;
+                match unit {
+                    TimeUnit::Millis => raw.div_euclid(1_000),
+                    TimeUnit::Micros => raw.div_euclid(1_000_000),
+                    TimeUnit::Nanos => raw.div_euclid(1_000_000_000),
+                    _ => raw,
+                }
+            }
+        };
+        Ok(seconds)
+    }
+
+    fn parse_row(&self, fields: &[String], columns: &Columns, offset: &FixedOffset) -> Result<Ohlc> {
+        let field = |idx: usize| -> Result<&str> {
+            fields.get(idx).map(|f| f.trim()).ok_or_else(|| format_err!("Missing column {}", idx))
+        };
+        let number = |idx: usize| -> Result<f64> {
+            let value = field(idx)?;
+            match value.parse::<f64>() {
+                Ok(v) if v.is_finite() => Ok(v),
+                _ => bail!("Invalid number {}", value),
+            }
+        };
+
+        let candle = Ohlc {
+            time: self.parse_time(field(columns.time)?, offset)?,
+            open: number(columns.open)?,
+            high: number(columns.high)?,
+            low: number(columns.low)?,
+            close: number(columns.close)?,
+            vol: number(columns.volume)?,
+        };
+        if candle.time % 60 != 0 {
+            bail!("Time {} is not at the start of a minute, only one minute candles can be imported", candle.time);
+        }
+        if candle.low <= 0.0 || candle.vol < 0.0 {
+            bail!("Prices must be positive and volume must not be negative");
+        }
+        if candle.high < candle.low || candle.high < f64::max(candle.open, candle.close) || candle.low > f64::min(candle.open, candle.close) {
+            bail!("High and low don't enclose the open and close");
+        }
+        Ok(candle)
+    }
+
+    /// Parses the archive. Invalid rows are skipped and reported, only an unusable layout fails the whole file.
+    pub fn parse(&self, data: &str) -> Result<CsvImport> {
+        let offset = parse_offset(&self.timezone)?;
+        let mut lines = data.lines().enumerate()
+            .map(|(i, line)| (i + 1, line.trim_end_matches('\r')))
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        let header = if self.header {
+            match lines.next() {
+                Some((_, line)) => Some(split_line(line, self.delimiter)),
+                None => bail!("The file is empty"),
+            }
+        } else {
+            None
+        };
+        let columns = Columns::resolve(self, header.as_ref().map(|h| h.as_slice()))?;
+
+        let mut result = CsvImport::default();
+        let mut candles = BTreeMap::new();
+        for (line, text) in lines {
+            result.rows += 1;
+            match self.parse_row(&split_line(text, self.delimiter), &columns, &offset) {
+                Ok(candle) => {
+                    if candles.insert(candle.time, candle).is_some() {
+                        result.duplicates += 1;
+                    }
+                }
+                Err(e) => {
+                    result.rejected += 1;
+                    if result.errors.len() < MAX_REPORTED_ERRORS {
+                        result.errors.push(RowError { line, message: e.to_string() });
+                    }
+                }
+            }
+        }
+        result.candles = candles.into_iter().map(|(_, c)| c).collect();
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset(tz: &str) -> FixedOffset {
+        parse_offset(tz).unwrap()
+    }
+
+    fn with_unit(time_unit: TimeUnit) -> CsvOptions {
+        CsvOptions { time_unit, ..CsvOptions::default() }
+    }
+
+    #[test]
+    fn split_line_handles_quotes() {
+        assert_eq!(split_line("1,2,3", ','), vec!["1", "2", "3"]);
+        assert_eq!(split_line("1;2;;", ';'), vec!["1", "2", "", ""]);
+        assert_eq!(split_line(r#""a,b",c"#, ','), vec!["a,b", "c"]);
+        assert_eq!(split_line(r#""say ""hi""",x"#, ','), vec![r#"say "hi""#, "x"]);
+        assert_eq!(split_line("", ','), vec![""]);
+    }
+
+    #[test]
+    fn parse_offset_accepts_utc_and_offsets() {
+        assert_eq!(offset("UTC"), FixedOffset::east(0));
+        assert_eq!(offset("Z"), FixedOffset::east(0));
+        assert_eq!(offset("+02:00"), FixedOffset::east(2 * 3600));
+        assert_eq!(offset("-05:30"), FixedOffset::west(5 * 3600 + 30 * 60));
+        assert_eq!(offset("+3"), FixedOffset::east(3 * 3600));
+
+        assert!(parse_offset("02:00").is_err());
+        assert!(parse_offset("+24:00").is_err());
+        assert!(parse_offset("+01:60").is_err());
+        assert!(parse_offset("Europe/Prague").is_err());
+    }
+
+    #[test]
+    fn parse_time_converts_units() {
+        let utc = offset("UTC");
+        assert_eq!(with_unit(TimeUnit::Seconds).parse_time("1546300800", &utc).unwrap(), 1546300800);
+        assert_eq!(with_unit(TimeUnit::Millis).parse_time("1546300800123", &utc).unwrap(), 1546300800);
+        assert_eq!(with_unit(TimeUnit::Micros).parse_time(" 1546300800123456 ", &utc).unwrap(), 1546300800);
+        assert_eq!(with_unit(TimeUnit::Nanos).parse_time("1546300800123456789", &utc).unwrap(), 1546300800);
+        // Times before the epoch round down
+        assert_eq!(with_unit(TimeUnit::Millis).parse_time("-1", &utc).unwrap(), -1);
+
+        assert!(with_unit(TimeUnit::Seconds).parse_time("1.5", &utc).is_err());
+        assert!(with_unit(TimeUnit::Millis).parse_time("abc", &utc).is_err());
+    }
+
+    #[test]
+    fn parse_time_applies_format_and_timezone() {
+        let text = with_unit(TimeUnit::Text);
+        assert_eq!(text.parse_time("2019-01-01 00:00:00", &offset("UTC")).unwrap(), 1546300800);
+        assert_eq!(text.parse_time("2019-01-01 02:00:00", &offset("+02:00")).unwrap(), 1546300800);
+        assert_eq!(text.parse_time("2018-12-31 19:00:00", &offset("-05:00")).unwrap(), 1546300800);
+
+        let iso = CsvOptions { time_format: "%Y-%m-%dT%H:%M".into(), ..text.clone() };
+        assert_eq!(iso.parse_time("2019-01-01T00:01", &offset("UTC")).unwrap(), 1546300860);
+
+        assert!(text.parse_time("2019-01-01", &offset("UTC")).is_err());
+        assert!(text.parse_time("1546300800", &offset("UTC")).is_err());
+    }
+
+    #[test]
+    fn parse_reads_columns_by_name() {
+        let data = "Close,Time,Open,High,Low,Volume\r\n\
+                    1.5,1546300860,1.0,2.0,0.5,10\r\n\
+                    \r\n\
+                    1.0,1546300800,1.0,1.0,1.0,0\r\n";
+        let parsed = CsvOptions::default().parse(data).unwrap();
+        assert_eq!(parsed.rows, 2);
+        assert_eq!(parsed.rejected, 0);
+        assert_eq!(parsed.candles.iter().map(|c| c.time).collect::<Vec<_>>(), vec![1546300800, 1546300860]);
+        let last = &parsed.candles[1];
+        assert_eq!((last.open, last.high, last.low, last.close, last.vol), (1.0, 2.0, 0.5, 1.5, 10.0));
+    }
+
+    #[test]
+    fn parse_reads_columns_by_index() {
+        let options = CsvOptions {
+            delimiter: ';',
+            header: false,
+            time_column: "0".into(),
+            open_column: "1".into(),
+            high_column: "2".into(),
+            low_column: "3".into(),
+            close_column: "4".into(),
+            volume_column: "5".into(),
+            time_unit: TimeUnit::Millis,
+            ..CsvOptions::default()
+        };
+        let parsed = options.parse("1546300800000;1;2;0.5;1.5;3\n").unwrap();
+        assert_eq!(parsed.candles.len(), 1);
+        assert_eq!(parsed.candles[0].time, 1546300800);
+
+        assert!(CsvOptions { header: false, ..CsvOptions::default() }.parse("1,2,3").is_err());
+    }
+
+    #[test]
+    fn parse_keeps_last_duplicate() {
+        let data = "time,open,high,low,close,volume\n\
+                    60,1,1,1,1,1\n\
+                    60,2,2,2,2,2\n\
+                    120,1,1,1,1,1\n";
+        let parsed = CsvOptions::default().parse(data).unwrap();
+        assert_eq!(parsed.rows, 3);
+        assert_eq!(parsed.duplicates, 1);
+        assert_eq!(parsed.candles.len(), 2);
+        assert_eq!(parsed.candles[0].open, 2.0);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_rows() {
+        let data = "time,open,high,low,close,volume\n\
+                    61,1,1,1,1,1\n\
+                    120,1,1,1,1\n\
+                    180,1,1,1,x,1\n\
+                    240,1,1,1,1,-1\n\
+                    300,1,1,0,1,1\n\
+                    360,1,0.5,1,1,1\n\
+                    420,1,1,1,1,NaN\n\
+                    480,1,1,1,1,1\n";
+        let parsed = CsvOptions::default().parse(data).unwrap();
+        assert_eq!(parsed.rows, 8);
+        assert_eq!(parsed.rejected, 7);
+        assert_eq!(parsed.errors.iter().map(|e| e.line).collect::<Vec<_>>(), vec![2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(parsed.candles.len(), 1);
+        assert_eq!(parsed.candles[0].time, 480);
+    }
+
+    #[test]
+    fn parse_limits_reported_errors() {
+        let mut data = "time,open,high,low,close,volume\n".to_string();
+        for i in 0..MAX_REPORTED_ERRORS + 10 {
+            data.push_str(&format!("{},x,1,1,1,1\n", i * 60));
+        }
+        let parsed = CsvOptions::default().parse(&data).unwrap();
+        assert_eq!(parsed.rejected, MAX_REPORTED_ERRORS + 10);
+        assert_eq!(parsed.errors.len(), MAX_REPORTED_ERRORS);
+    }
+
+    #[test]
+    fn parse_fails_on_unusable_layout() {
+        assert!(CsvOptions::default().parse("").is_err());
+        assert!(CsvOptions::default().parse("time,open,high,low,close\n60,1,1,1,1\n").is_err());
+        assert!(CsvOptions { timezone: "CET".into(), ..CsvOptions::default() }.parse("time\n").is_err());
+    }
+
+    #[test]
+    fn time_unit_from_str() {
+        assert_eq!(TimeUnit::from_str("ms").unwrap(), TimeUnit::Millis);
+        assert_eq!(TimeUnit::from_str("text").unwrap(), TimeUnit::Text);
+        assert!(TimeUnit::from_str("minutes").is_err());
+    }
+}
@@ -5,11 +5,15 @@ pub mod msgs;
 pub mod types;
 pub mod prelude;
 pub mod metrics;
+pub mod config;
+pub mod import;
+pub mod net;
 
 pub use futures01;
 pub use log;
 pub use serde;
 pub use crate::prelude::*;
+pub use crate::config::config;
 
 
 pub const BODY_LIMIT: usize = 4 * 1024 * 1024;
@@ -22,11 +26,19 @@ pub const GROUP_IMPORT_WORKERS: &str = "workers";
 pub const CHANNEL_OHLC_IMPORT: &str = "ohlc.histimport";
 
 pub const CHANNEL_EVAL_REQUESTS: &str = "eval";
+pub const CHANNEL_BACKTEST_REQUESTS: &str = "backtest";
 pub const CHANNEL_POSITION_REQUESTS: &str = "decision";
+/// Position requests which passed the risk manager
+pub const CHANNEL_APPROVED_POSITIONS: &str = "decision.approved";
 
 pub const CHANNEL_TRADE_REQUESTS: &str = "trade";
 pub const CHANNEL_BALANCE_REQUESTS: &str = "balance";
 
+pub const CHANNEL_ASSIGNMENT_UPDATES: &str = "assignments.updated";
+/// Events streamed to browsers of the users, published without waiting for a receiver
+pub const CHANNEL_EVALUATIONS: &str = "evaluations.logged";
+pub const CHANNEL_TRADES: &str = "trades.logged";
+
 pub const GROUP_EVAL_WORKERS: &str = "workers";
 
 
@@ -34,6 +46,7 @@ pub fn init() {
     dotenv::dotenv();
     env_logger::init();
     env::set_var("RUST_BACKTRACE", "full");
+    info!("Loaded configuration : {:?}", config());
 }
 
 pub fn launch<F, Fut>(f: F)
@@ -43,7 +56,7 @@ pub fn launch<F, Fut>(f: F)
     actix::System::run(move || {
         let _server = actix_web::server::new(|| {
             metrics::make_exporting_app()
-        }).bind("0.0.0.0:9000").unwrap().start();
+        }).bind(&config().metrics.bind).unwrap().start();
 
         let fut = f();
         let fut = async {
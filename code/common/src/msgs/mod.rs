@@ -1,23 +1,41 @@
 use crate::prelude::*;
 use crate::types::*;
+use uuid::Uuid;
 
 pub use actix::msgs::StopArbiter;
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PositionRequest {
-    pub api_key: String,
-    pub api_secret: String,
+    /// Idempotency key, stays the same when the request is redelivered
+    pub id: Uuid,
+    /// Credentials are loaded by the exchange connector, and never travel over NATS
+    pub trader_id: i32,
     pub pair: PairId,
     pub position: TradingPosition,
+    /// Strategy revision which decided on the position
+    pub revision_id: Option<i32>,
+    /// Fraction of the funds available for the pair which the position may use, lowered by the risk manager
+    #[serde(default = "full_size")]
+    pub size: f64,
 }
 
+fn full_size() -> f64 { 1.0 }
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PositionResponse {
     Adjusted {
         amout: f64,
     },
     Unchanged,
+    /// Passed to the trader by the risk manager, possibly with a lowered size
+    Approved {
+        size: f64,
+    },
+    /// Stopped by a risk limit of the user
+    Rejected {
+        reason: String,
+    },
 }
 
 impl Message for PositionRequest {
@@ -25,12 +43,14 @@ impl Message for PositionRequest {
 }
 
 impl PositionRequest {
-    pub fn new(api_key: impl Into<String>, api_secret: impl Into<String>, pair: PairId, position: TradingPosition) -> Self {
+    pub fn new(trader_id: i32, pair: PairId, position: TradingPosition, revision_id: Option<i32>) -> Self {
         Self {
-            api_key: api_key.into(),
-            api_secret: api_secret.into(),
+            id: Uuid::new_v4(),
+            trader_id,
             pair,
             position,
+            revision_id,
+            size: 1.0,
         }
     }
 }
@@ -39,30 +59,63 @@ impl PositionRequest {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EvalRequest {
     pub strat_id: i32,
+    /// Revision of the strategy whose code is evaluated
+    pub revision_id: i32,
     pub pair_id : i32,
     pub period : OhlcPeriod,
     pub last: i64,
+    /// Parameter values of the assignment, defaults of the revision are used for missing ones
+    pub params: ParamValues,
 }
 
 impl Message for EvalRequest { type Result = Result<TradingPosition, EvalError>; }
 
 impl EvalRequest {
-    pub fn new(strat_id: i32, pair_id : i32, period : OhlcPeriod, last: i64) -> Self {
+    pub fn new(strat_id: i32, revision_id: i32, pair_id : i32, period : OhlcPeriod, last: i64, params: ParamValues) -> Self {
         EvalRequest {
             strat_id,
+            revision_id,
             pair_id,
             period,
             last,
+            params,
         }
     }
 }
 
+/// Replays a strategy revision over stored candles, only positions taken in `from..=to` are scored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestRequest {
+    pub revision_id: i32,
+    pub pair_id: i32,
+    pub period: OhlcPeriod,
+    pub from: i64,
+    pub to: i64,
+    pub params: ParamValues,
+}
+
+impl Message for BacktestRequest { type Result = Result<BacktestResult, EvalError>; }
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BacktestResult {
+    /// Relative return over the scored candles, `0.1` is a 10% gain
+    pub ret: f64,
+    /// Largest relative drop from a previous peak
+    pub max_drawdown: f64,
+    /// Number of position changes
+    pub trades: u32,
+}
+
 #[derive(Debug, Fail, Serialize, Deserialize)]
 pub enum EvalError {
     #[fail(display = "Missing strategy required data")]
     MissingData,
     #[fail(display = "Invalid strategy source code : {}", 0)]
     InvalidStrategy(String),
+    #[fail(display = "Strategy failed : {}", 0)]
+    RuntimeError(String),
+    #[fail(display = "Invalid strategy parameters : {}", 0)]
+    InvalidParams(String),
 }
 
 
@@ -79,8 +132,7 @@ pub enum ExchangeError {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BalanceRequest {
     pub pair_id: PairId,
-    pub api_key: String,
-    pub api_secret: String,
+    pub trader_id: i32,
 }
 
 impl Message for BalanceRequest {
@@ -88,11 +140,10 @@ impl Message for BalanceRequest {
 }
 
 impl BalanceRequest {
-    pub fn new(pair_id: impl Into<PairId>, api_key: impl Into<String>, api_secret: impl Into<String>) -> Self {
+    pub fn new(pair_id: impl Into<PairId>, trader_id: i32) -> Self {
         BalanceRequest {
             pair_id : pair_id.into(),
-            api_key: api_key.into(),
-            api_secret: api_secret.into(),
+            trader_id,
         }
     }
 }
@@ -107,12 +158,16 @@ pub struct BalanceResponse {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TradeRequest {
+    /// Idempotency key, stays the same when the request is redelivered
+    pub id: Uuid,
     pub exch: String,
-    pub api_key: String,
-    pub api_secret: String,
+    pub trader_id: i32,
     pub pair: TradePair,
     pub amount: f64,
     pub buy: bool,
+    /// Strategy revision whose decision resulted in the trade
+    #[serde(default)]
+    pub revision_id: Option<i32>,
 }
 
 impl Message for TradeRequest {
@@ -123,17 +178,21 @@ impl Message for TradeRequest {
 pub struct TradeResponse {
     pub amount: f64,
     pub price: f64,
+    /// Fee charged by the exchange, in quote currency
+    #[serde(default)]
+    pub fee: f64,
 }
 
 impl TradeRequest {
-    pub fn new(exch: impl Into<String>, api_key: impl Into<String>, api_secret: impl Into<String>, pair: TradePair, amount: f64, buy: bool) -> Self {
+    pub fn new(exch: impl Into<String>, trader_id: i32, pair: TradePair, amount: f64, buy: bool) -> Self {
         Self {
+            id: Uuid::new_v4(),
             exch: exch.into(),
-            api_key: api_key.into(),
-            api_secret: api_secret.into(),
+            trader_id,
             pair,
             amount,
             buy,
+            revision_id: None,
         }
     }
 }
@@ -190,5 +249,46 @@ impl OhlcUpdate {
     }
 }
 
+/// Strategy of an assignment was evaluated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvaluationLogged {
+    pub user_id: i32,
+    pub strategy_id: i32,
+    pub revision_id: i32,
+    pub pair: PairId,
+    pub period: OhlcPeriod,
+    /// Time of the evaluated candle, in seconds
+    pub time: i64,
+    pub decision: Option<TradingPosition>,
+    pub error: Option<String>,
+}
 
+impl Message for EvaluationLogged { type Result = (); }
 
+/// Trade of a trader was recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeLogged {
+    pub id: Uuid,
+    pub user_id: i32,
+    pub trader_id: i32,
+    pub pair: PairId,
+    /// Unix time in seconds
+    pub time: i64,
+    pub buy: bool,
+    pub amount: f64,
+    pub price: f64,
+    pub fee: f64,
+    pub status: bool,
+}
+
+impl Message for TradeLogged { type Result = (); }
+
+/// Assignments of the user, or strategies and traders they use, were changed through the web service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssignmentsChanged {
+    pub user_id: i32,
+}
+
+impl Message for AssignmentsChanged {
+    type Result = Result<(), ()>;
+}
@@ -0,0 +1,109 @@
+//! Checks of URLs supplied by users, which the services request on their behalf.
+use crate::prelude::*;
+use actix::actors::resolver::{Resolver, Resolve};
+use futures03::future::LocalBoxFuture;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Whether the address is reachable on the public internet, rather than on the host or its networks
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.segments() {
+            // IPv4 mapped addresses
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => is_public_v4(Ipv4Addr::from(((hi as u32) << 16) | lo as u32)),
+            _ => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0
+        // Shared address space of carrier grade NAT
+        || (a == 100 && b & 0xc0 == 64)
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking
+        || (a == 198 && b & 0xfe == 18)
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local
+        || first & 0xfe00 == 0xfc00
+        // Link local
+        || first & 0xffc0 == 0xfe80
+        // Documentation
+        || (first == 0x2001 && ip.segments()[1] == 0xdb8))
+}
+
+/// Checks that the URL uses http or https, and that all addresses of its host are public.
+/// The error describes the problem to the user.
+pub fn check_public_url(url: &str) -> LocalBoxFuture<'static, StdResult<(), String>> {
+    let url = match Url::parse(url) {
+        Ok(ref url) if url.scheme() == "http" || url.scheme() == "https" => url.clone(),
+        _ => return futures03::future::ready(Err("URL has to use http or https.".to_string())).boxed_local(),
+    };
+    let port = url.port_or_known_default().unwrap_or(80);
+    let host = url.host().map(|h| h.to_owned());
+
+    async move {
+        let addrs = match host {
+            Some(url::Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+            Some(url::Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+            Some(url::Host::Domain(domain)) => {
+                Resolver::from_registry()
+                    .send(Resolve::host_and_port(&domain, port))
+                    .compat().await
+                    .map_err(|e| format!("Could not resolve {} : {}", domain, e))?
+                    .map_err(|e| format!("Could not resolve {} : {}", domain, e))?
+                    .into_iter()
+                    .map(|addr| addr.ip())
+                    .collect()
+            }
+            None => vec![],
+        };
+        if addrs.is_empty() {
+            return Err("URL has no reachable host.".to_string());
+        }
+        if addrs.into_iter().any(|ip| !is_public(ip)) {
+            return Err("URL has to point to a public address.".to_string());
+        }
+        Ok(())
+    }.boxed_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(addr: &str) -> bool {
+        is_public(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn private_addresses_are_rejected() {
+        for addr in &["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "0.0.0.0",
+            "100.64.0.1", "255.255.255.255", "224.0.0.1", "::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254"] {
+            assert!(!public(addr), "{} is not public", addr);
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        for addr in &["1.1.1.1", "8.8.8.8", "100.128.0.1", "172.32.0.1", "2606:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(public(addr), "{} is public", addr);
+        }
+    }
+}
@@ -220,6 +220,16 @@ use crate::types::TradePair;
 use actix::dev::ToEnvelope;
 
 
+pub fn hmac_sha256(secret: &str, data: &str) -> Vec<u8> {
+    use hmac::Mac;
+
+    let mut hmac = ::hmac::Hmac::<::sha2::Sha256>::new_varkey(secret.as_bytes()).unwrap();
+    hmac.input(data.as_bytes());
+
+    Vec::from(hmac.result().code().as_slice())
+}
+
+
 pub fn hmac_sha384(secret: &str, data: &str) -> Vec<u8> {
     use hmac::Mac;
 
@@ -4,6 +4,7 @@ pub mod auth;
 
 mod ohlc;
 mod spec;
+mod params;
 
 pub mod ticker;
 
@@ -42,3 +43,4 @@ impl FromStr for TradingPosition {
 
 pub use self::ohlc::*;
 pub use self::spec::*;
+pub use self::params::*;
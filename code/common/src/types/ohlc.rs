@@ -30,6 +30,8 @@ impl Ohlc {
             if i == 0 {
                 time = v.time;
                 open = v.open;
+                high = v.high;
+                low = v.low;
             }
             close = v.close;
             high = f64::max(high, v.high);
@@ -46,6 +48,7 @@ impl Ohlc {
         };
     }
 
+    /// Fills missing candles between the values with flat candles at the previous close
     pub fn backfill(values: impl Iterator<Item=Ohlc>, period: OhlcPeriod) -> Vec<Ohlc> {
         let mut last = None::<Ohlc>;
         let mut res = vec![];
@@ -53,7 +56,7 @@ impl Ohlc {
         for v in values {
             if let Some(last) = last {
                 if last.time != v.time - period.seconds() {
-                    for i in 0..(last.time - v.time) / period.seconds() {
+                    for i in 1..(v.time - last.time) / period.seconds() {
                         res.push(Ohlc {
                             time: last.time + i * secs,
                             open: last.close,
@@ -72,12 +75,12 @@ impl Ohlc {
         return res;
     }
 
+    /// Combines values ordered by time into candles of the period, starting at multiples of its length
     pub fn rescale(values: impl Iterator<Item=Ohlc>, period: OhlcPeriod) -> Vec<Ohlc> {
-        let count = period.seconds() / 60;
-        let chunks = values.chunks(count as usize);
+        let groups = values.group_by(|v| period.clamp_time(v.time));
 
-        chunks.into_iter().map(|c| {
-            Ohlc::combine(c)
+        groups.into_iter().map(|(time, c)| {
+            Ohlc::combine_with_time(time, c)
         }).collect()
     }
 }
@@ -221,4 +224,74 @@ impl ::std::default::Default for OhlcPeriod {
     fn default() -> OhlcPeriod {
         OhlcPeriod::Min1
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candle(time: i64, open: f64, high: f64, low: f64, close: f64, vol: f64) -> Ohlc {
+        Ohlc { time, open, high, low, close, vol }
+    }
+
+    fn flat(time: i64, price: f64) -> Ohlc {
+        candle(time, price, price, price, price, 1.0)
+    }
+
+    #[test]
+    fn combine_keeps_extremes_of_all_values() {
+        let combined = Ohlc::combine(vec![
+            candle(60, 10.0, 12.0, 9.0, 11.0, 1.0),
+            candle(120, 11.0, 11.5, 10.5, 11.0, 2.0),
+            candle(180, 11.0, 13.0, 10.0, 12.0, 3.0),
+        ].into_iter());
+        assert_eq!(combined, candle(60, 10.0, 13.0, 9.0, 12.0, 6.0));
+
+        // The low isn't pulled down to zero, nor the high up from it
+        let single = Ohlc::combine(vec![candle(60, 10.0, 12.0, 9.0, 11.0, 1.0)].into_iter());
+        assert_eq!(single, candle(60, 10.0, 12.0, 9.0, 11.0, 1.0));
+        let negative = Ohlc::combine(vec![candle(60, -2.0, -1.0, -3.0, -2.0, 1.0)].into_iter());
+        assert_eq!(negative.high, -1.0);
+    }
+
+    #[test]
+    fn rescale_groups_by_period_start() {
+        let minutes = vec![
+            flat(240, 1.0),
+            flat(300, 2.0),
+            flat(360, 3.0),
+            // Minutes 420 to 540 are missing
+            flat(600, 4.0),
+            flat(660, 5.0),
+        ];
+        let rescaled = Ohlc::rescale(minutes.into_iter(), OhlcPeriod::Min5);
+        assert_eq!(rescaled, vec![
+            candle(0, 1.0, 1.0, 1.0, 1.0, 1.0),
+            candle(300, 2.0, 3.0, 2.0, 3.0, 2.0),
+            candle(600, 4.0, 5.0, 4.0, 5.0, 2.0),
+        ]);
+
+        assert_eq!(Ohlc::rescale(vec![flat(120, 1.0)].into_iter(), OhlcPeriod::Min1), vec![flat(120, 1.0)]);
+        assert!(Ohlc::rescale(vec![].into_iter(), OhlcPeriod::Hour1).is_empty());
+    }
+
+    #[test]
+    fn backfill_fills_missing_candles_with_previous_close() {
+        let candles = vec![
+            candle(0, 1.0, 2.0, 0.5, 1.5, 3.0),
+            candle(300, 1.5, 1.5, 1.5, 1.5, 1.0),
+            candle(1200, 2.0, 2.0, 2.0, 2.0, 1.0),
+        ];
+        let filled = Ohlc::backfill(candles.into_iter(), OhlcPeriod::Min5);
+        assert_eq!(filled.iter().map(|c| c.time).collect::<Vec<_>>(), vec![0, 300, 600, 900, 1200]);
+        assert_eq!(filled[2], candle(600, 1.5, 1.5, 1.5, 1.5, 0.0));
+        assert_eq!(filled[3], candle(900, 1.5, 1.5, 1.5, 1.5, 0.0));
+        assert_eq!(filled[4], candle(1200, 2.0, 2.0, 2.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn backfill_keeps_contiguous_candles() {
+        let candles = vec![flat(0, 1.0), flat(60, 2.0), flat(120, 3.0)];
+        assert_eq!(Ohlc::backfill(candles.clone().into_iter(), OhlcPeriod::Min1), candles);
+        assert!(Ohlc::backfill(vec![].into_iter(), OhlcPeriod::Min1).is_empty());
+    }
+}
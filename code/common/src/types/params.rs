@@ -0,0 +1,304 @@
+use crate::prelude::*;
+use crate::types::OhlcPeriod;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    Integer,
+    Float,
+    Bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+}
+
+impl ParamValue {
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            ParamValue::Integer(i) => Some(i as f64),
+            ParamValue::Float(f) => Some(f),
+            ParamValue::Bool(_) => None,
+        }
+    }
+}
+
+/// Values of strategy parameters by name
+pub type ParamValues = BTreeMap<String, ParamValue>;
+
+/// Parameter declared by a strategy, available to its code as `params.<name>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParamSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ParamType,
+    pub default: ParamValue,
+    /// Inclusive range of numeric parameters
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl ParamSpec {
+    /// Converts the value to the declared type, and checks that it lies in the declared range
+    pub fn check(&self, value: ParamValue) -> Result<ParamValue, String> {
+        let value = match (self.kind, value) {
+            (ParamType::Bool, v @ ParamValue::Bool(_)) => return Ok(v),
+            (ParamType::Integer, v @ ParamValue::Integer(_)) => v,
+            (ParamType::Float, ParamValue::Integer(i)) => ParamValue::Float(i as f64),
+            (ParamType::Float, v @ ParamValue::Float(_)) => v,
+            (kind, v) => return Err(format!("Parameter `{}` expects {:?}, got {:?}", self.name, kind, v)),
+        };
+
+        let num = value.as_f64().unwrap();
+        if self.min.map_or(false, |min| num < min) || self.max.map_or(false, |max| num > max) {
+            return Err(format!("Parameter `{}` must be between {:?} and {:?}", self.name, self.min, self.max));
+        }
+        Ok(value)
+    }
+
+    /// Checks that declarations are unique, their ranges are not empty and their defaults are valid
+    pub fn validate_all(specs: &[ParamSpec]) -> Result<(), String> {
+        for (i, spec) in specs.iter().enumerate() {
+            if spec.name.is_empty() || !spec.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("Invalid parameter name `{}`", spec.name));
+            }
+            if specs[..i].iter().any(|s| s.name == spec.name) {
+                return Err(format!("Parameter `{}` is declared twice", spec.name));
+            }
+            if let (Some(min), Some(max)) = (spec.min, spec.max) {
+                if !(min <= max) {
+                    return Err(format!("Parameter `{}` has a minimum above its maximum", spec.name));
+                }
+            }
+            spec.check(spec.default)?;
+        }
+        Ok(())
+    }
+
+    /// Combines values set on an assignment with defaults of the declared parameters
+    pub fn resolve(specs: &[ParamSpec], values: &ParamValues) -> Result<ParamValues, String> {
+        if let Some(name) = values.keys().find(|name| !specs.iter().any(|s| &s.name == *name)) {
+            return Err(format!("Unknown parameter `{}`", name));
+        }
+        specs.iter()
+            .map(|spec| {
+                let value = match values.get(&spec.name) {
+                    Some(v) => spec.check(*v)?,
+                    None => spec.default,
+                };
+                Ok((spec.name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+/// Largest number of parameter combinations evaluated by a single optimization
+pub const MAX_CANDIDATES: usize = 200;
+
+/// How parameter combinations are chosen by the optimizer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ParamSearch {
+    /// Every combination of the listed values, parameters that are not listed keep their defaults
+    Grid { values: BTreeMap<String, Vec<ParamValue>> },
+    /// Values drawn uniformly from declared ranges, every numeric parameter needs a range
+    Random { samples: usize },
+}
+
+impl ParamSearch {
+    /// Checks the search against declared parameters and limits its size
+    pub fn validate(&self, specs: &[ParamSpec]) -> Result<(), String> {
+        let size = match self {
+            ParamSearch::Grid { values } => {
+                for (name, values) in values.iter() {
+                    let spec = specs.iter().find(|s| &s.name == name)
+                        .ok_or_else(|| format!("Unknown parameter `{}`", name))?;
+                    if values.is_empty() {
+                        return Err(format!("No values listed for parameter `{}`", name));
+                    }
+                    for v in values.iter() {
+                        spec.check(*v)?;
+                    }
+                }
+                // Stops counting once the limit is passed, so the product can't overflow
+                values.values()
+                    .try_fold(1usize, |size, v| size.checked_mul(v.len()).filter(|s| *s <= MAX_CANDIDATES))
+                    .unwrap_or(MAX_CANDIDATES + 1)
+            }
+            ParamSearch::Random { samples } => {
+                if let Some(spec) = specs.iter().find(|s| s.kind != ParamType::Bool && (s.min.is_none() || s.max.is_none())) {
+                    return Err(format!("Parameter `{}` needs a minimum and a maximum for random search", spec.name));
+                }
+                *samples
+            }
+        };
+        if size == 0 || size > MAX_CANDIDATES {
+            return Err(format!("Search has to contain between 1 and {} combinations", MAX_CANDIDATES));
+        }
+        Ok(())
+    }
+}
+
+/// Largest number of walk-forward folds of a single optimization
+pub const MAX_FOLDS: i32 = 10;
+/// Largest number of candles covered by a single optimization
+pub const MAX_CANDLES: i64 = 20_000;
+/// Fewest candles in each walk-forward window
+pub const MIN_WINDOW_CANDLES: i64 = 10;
+
+/// Checks that the time range can be split into `folds + 1` walk-forward windows of whole candles
+pub fn validate_walk_forward(period: OhlcPeriod, from: i64, to: i64, folds: i32) -> Result<(), String> {
+    if folds < 1 || folds > MAX_FOLDS {
+        return Err(format!("Number of folds has to be between 1 and {}", MAX_FOLDS));
+    }
+    let min = MIN_WINDOW_CANDLES * (folds as i64 + 1);
+    let candles = to.saturating_sub(from) / period.seconds();
+    if candles < min || candles > MAX_CANDLES {
+        return Err(format!("Time range has to cover between {} and {} candles", min, MAX_CANDLES));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(name: &str, kind: ParamType, default: ParamValue, min: Option<f64>, max: Option<f64>) -> ParamSpec {
+        ParamSpec { name: name.into(), kind, default, min, max }
+    }
+
+    fn specs() -> Vec<ParamSpec> {
+        vec![
+            spec("period", ParamType::Integer, ParamValue::Integer(14), Some(2.), Some(100.)),
+            spec("threshold", ParamType::Float, ParamValue::Float(0.5), Some(0.), None),
+            spec("short", ParamType::Bool, ParamValue::Bool(false), None, None),
+        ]
+    }
+
+    #[test]
+    fn check_converts_and_limits() {
+        let specs = specs();
+        assert_eq!(specs[0].check(ParamValue::Integer(2)), Ok(ParamValue::Integer(2)));
+        assert_eq!(specs[0].check(ParamValue::Integer(100)), Ok(ParamValue::Integer(100)));
+        assert!(specs[0].check(ParamValue::Integer(1)).is_err());
+        assert!(specs[0].check(ParamValue::Integer(101)).is_err());
+        assert!(specs[0].check(ParamValue::Float(14.)).is_err());
+        assert!(specs[0].check(ParamValue::Bool(true)).is_err());
+
+        assert_eq!(specs[1].check(ParamValue::Integer(3)), Ok(ParamValue::Float(3.)));
+        assert_eq!(specs[1].check(ParamValue::Float(1e9)), Ok(ParamValue::Float(1e9)));
+        assert!(specs[1].check(ParamValue::Float(-0.1)).is_err());
+
+        assert_eq!(specs[2].check(ParamValue::Bool(true)), Ok(ParamValue::Bool(true)));
+        assert!(specs[2].check(ParamValue::Integer(1)).is_err());
+    }
+
+    #[test]
+    fn resolve_fills_defaults() {
+        let mut values = ParamValues::new();
+        values.insert("threshold".into(), ParamValue::Integer(2));
+
+        let resolved = ParamSpec::resolve(&specs(), &values).unwrap();
+        assert_eq!(resolved.len(), 3);
+        assert_eq!(resolved["period"], ParamValue::Integer(14));
+        assert_eq!(resolved["threshold"], ParamValue::Float(2.));
+        assert_eq!(resolved["short"], ParamValue::Bool(false));
+
+        assert_eq!(ParamSpec::resolve(&[], &ParamValues::new()), Ok(ParamValues::new()));
+    }
+
+    #[test]
+    fn resolve_rejects_invalid_values() {
+        let mut values = ParamValues::new();
+        values.insert("unknown".into(), ParamValue::Integer(1));
+        assert!(ParamSpec::resolve(&specs(), &values).is_err());
+
+        let mut values = ParamValues::new();
+        values.insert("period".into(), ParamValue::Integer(1000));
+        assert!(ParamSpec::resolve(&specs(), &values).is_err());
+    }
+
+    #[test]
+    fn validate_all_checks_declarations() {
+        assert!(ParamSpec::validate_all(&specs()).is_ok());
+
+        let mut twice = specs();
+        twice.push(spec("period", ParamType::Integer, ParamValue::Integer(3), None, None));
+        assert!(ParamSpec::validate_all(&twice).is_err());
+
+        let bad_name = vec![spec("a b", ParamType::Bool, ParamValue::Bool(true), None, None)];
+        assert!(ParamSpec::validate_all(&bad_name).is_err());
+
+        let bad_default = vec![spec("a", ParamType::Integer, ParamValue::Integer(0), Some(1.), Some(2.))];
+        assert!(ParamSpec::validate_all(&bad_default).is_err());
+
+        let empty_range = vec![spec("a", ParamType::Float, ParamValue::Float(1.), Some(2.), Some(1.))];
+        assert!(ParamSpec::validate_all(&empty_range).is_err());
+        let nan_range = vec![spec("a", ParamType::Float, ParamValue::Float(1.), Some(std::f64::NAN), Some(1.))];
+        assert!(ParamSpec::validate_all(&nan_range).is_err());
+    }
+
+    #[test]
+    fn grid_search_size() {
+        let specs = specs();
+        let grid = |values: Vec<(&str, Vec<ParamValue>)>| ParamSearch::Grid {
+            values: values.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+        };
+
+        let ok = grid(vec![
+            ("period", (2..=11).map(ParamValue::Integer).collect()),
+            ("short", vec![ParamValue::Bool(false), ParamValue::Bool(true)]),
+        ]);
+        assert!(ok.validate(&specs).is_ok());
+
+        let too_large = grid(vec![
+            ("period", (2..=100).map(ParamValue::Integer).collect()),
+            ("threshold", (0..10).map(|i| ParamValue::Float(i as f64)).collect()),
+        ]);
+        assert!(too_large.validate(&specs).is_err());
+
+        // Sizes that overflow usize when multiplied are rejected rather than wrapping around
+        let mut values = BTreeMap::new();
+        for i in 0..40 {
+            values.insert(format!("p{}", i), vec![ParamValue::Integer(0); 4]);
+        }
+        let many = (0..40)
+            .map(|i| spec(&format!("p{}", i), ParamType::Integer, ParamValue::Integer(0), None, None))
+            .collect::<Vec<_>>();
+        assert!(ParamSearch::Grid { values }.validate(&many).is_err());
+
+        assert!(grid(vec![("period", vec![])]).validate(&specs).is_err());
+        assert!(grid(vec![("unknown", vec![ParamValue::Integer(1)])]).validate(&specs).is_err());
+        assert!(grid(vec![("period", vec![ParamValue::Integer(1)])]).validate(&specs).is_err());
+    }
+
+    #[test]
+    fn random_search_needs_ranges() {
+        let bounded = vec![
+            spec("period", ParamType::Integer, ParamValue::Integer(14), Some(2.), Some(100.)),
+            spec("short", ParamType::Bool, ParamValue::Bool(false), None, None),
+        ];
+        assert!(ParamSearch::Random { samples: 10 }.validate(&bounded).is_ok());
+        assert!(ParamSearch::Random { samples: 0 }.validate(&bounded).is_err());
+        assert!(ParamSearch::Random { samples: MAX_CANDIDATES + 1 }.validate(&bounded).is_err());
+
+        // `threshold` has no maximum
+        assert!(ParamSearch::Random { samples: 10 }.validate(&specs()).is_err());
+    }
+
+    #[test]
+    fn walk_forward_range() {
+        let hour = 3600;
+        assert!(validate_walk_forward(OhlcPeriod::Hour1, 0, 40 * hour, 3).is_ok());
+        assert!(validate_walk_forward(OhlcPeriod::Hour1, 0, 39 * hour, 3).is_err());
+        assert!(validate_walk_forward(OhlcPeriod::Hour1, 0, 40 * hour, 0).is_err());
+        assert!(validate_walk_forward(OhlcPeriod::Hour1, 0, 40 * hour, MAX_FOLDS + 1).is_err());
+        assert!(validate_walk_forward(OhlcPeriod::Hour1, 40 * hour, 0, 3).is_err());
+        assert!(validate_walk_forward(OhlcPeriod::Hour1, 0, (MAX_CANDLES + 1) * hour, 3).is_err());
+        assert!(validate_walk_forward(OhlcPeriod::Hour1, std::i64::MIN, std::i64::MAX, 3).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+//! Actors and messages shared by the tests of the crate.
+use actix::prelude::*;
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Double(pub u32);
+
+impl Message for Double { type Result = u32; }
+
+pub struct Doubler;
+
+impl Actor for Doubler { type Context = Context<Self>; }
+
+impl Handler<Double> for Doubler {
+    type Result = u32;
+
+    fn handle(&mut self, msg: Double, _ctx: &mut Self::Context) -> u32 {
+        msg.0 * 2
+    }
+}
@@ -13,6 +13,12 @@ use futures03::{TryFutureExt, FutureExt};
 use futures::future::Future as _;
 use futures::stream::Stream as _;
 
+mod local;
+#[cfg(test)]
+mod fixtures;
+
+use crate::local::LocalBroker;
+
 pub async fn connect(name: impl Into<String>, addr: impl Into<String>) -> Arc<NatsClient> {
     let options = NatsClientOptions::builder()
         .cluster_uris(vec!(addr.into()))
@@ -30,6 +36,51 @@ pub trait RemoteMessage = Message + 'static + Serialize + DeserializeOwned + Sen
     where <Self as Message>::Result: DeserializeOwned + Serialize + Send + Sync + 'static;
 
 
+/// Tag byte prepended to binary payloads, json payloads can never start with it
+const BINARY_TAG: u8 = 0;
+
+/// Wire format of the messages. Binary payloads are tagged, so the receiver can decode
+/// any of them, and replies are always encoded in the format of the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    Binary,
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Json(json::Error),
+    Binary(bincode::Error),
+}
+
+impl Codec {
+    pub fn detect(payload: &[u8]) -> Codec {
+        match payload.first() {
+            Some(&BINARY_TAG) => Codec::Binary,
+            _ => Codec::Json,
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, data: &T) -> Vec<u8> {
+        match self {
+            Codec::Json => json::to_vec(data).expect("Msg serialization"),
+            Codec::Binary => {
+                let mut buf = vec![BINARY_TAG];
+                bincode::serialize_into(&mut buf, data).expect("Msg serialization");
+                buf
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Codec::Json => json::from_slice(payload).map_err(CodecError::Json),
+            Codec::Binary => bincode::deserialize(&payload[1..]).map_err(CodecError::Binary),
+        }
+    }
+}
+
+
 pub(crate) struct Subscribe<T: RemoteMessage> {
     name: String,
     group: Option<String>,
@@ -57,6 +108,7 @@ pub struct Publish<M: RemoteMessage> {
     data: M,
     subject: String,
     is_req: bool,
+    codec: Codec,
 }
 
 impl<M: RemoteMessage> Message for Publish<M> {
@@ -64,6 +116,9 @@ impl<M: RemoteMessage> Message for Publish<M> {
 }
 
 
+/// Ends the subscription stream once its recipient is stopped
+const SUBSCRIBER_CLOSED: &str = "Subscriber closed";
+
 pub(crate) struct ClientWorker {
     client: Arc<NatsClient>,
     subs: HashMap<String, SpawnHandle>,
@@ -88,16 +143,17 @@ impl<T: RemoteMessage> Handler<Subscribe<T>> for ClientWorker {
             .queue_group(msg.group)
             .build().unwrap();
 
-        use futures::future::ok;
+        use futures::future::{ok, err};
 
 
         let folder = move |(client, rec): (Arc<NatsClient>, Recipient<T>), i: nats::ops::Message| -> Box<futures::future::Future<Item=_,Error=_>> {
-            let req = json::from_slice(&i.payload).expect("Msg deserialization");
+            let codec = Codec::detect(&i.payload);
+            let req = codec.decode(&i.payload).expect("Msg deserialization");
             //println!("Sub recvd : {:?} => {:?} ", i, req);
             if let Some(reply_to) = i.reply_to {
                 let res = rec.send(req);
-                box res.map_err(|e| nats::error::RatsioError::GenericError("Mailbox".to_string())).and_then(|reply_data| {
-                    let data = json::to_vec(&reply_data).expect("Msg serialization");
+                box res.map_err(|e| nats::error::RatsioError::GenericError("Mailbox".to_string())).and_then(move |reply_data| {
+                    let data = codec.encode(&reply_data);
 
                     let pub_reply = nats::ops::Publish::builder()
                         .subject(reply_to)
@@ -108,8 +164,10 @@ impl<T: RemoteMessage> Handler<Subscribe<T>> for ClientWorker {
                         .map(|_| (client, rec))
                 })
             } else {
-                rec.do_send(req).unwrap();
-                box ok((client, rec))
+                match rec.do_send(req) {
+                    Ok(()) => box ok((client, rec)),
+                    Err(_) => box err(nats::error::RatsioError::GenericError(SUBSCRIBER_CLOSED.to_string())),
+                }
             }
         };
 
@@ -118,7 +176,10 @@ impl<T: RemoteMessage> Handler<Subscribe<T>> for ClientWorker {
                 stream.fold((client, recipient), folder)
             })
             .map(|_| ())
-            .map_err(|e| panic!("{:?}", e))
+            .map_err(|e| match e {
+                nats::error::RatsioError::GenericError(ref msg) if msg == SUBSCRIBER_CLOSED => (),
+                e => panic!("{:?}", e),
+            })
             .into_actor(self));
 
         self.subs.insert(sid.clone(), handle);
@@ -134,7 +195,7 @@ impl<T: RemoteMessage> Handler<Publish<T>> for ClientWorker {
         Box::new(async move {
             let subject = msg.subject.parse().unwrap();
             let rep = format!("{}-{}", subject, nuid::next());
-            let data = json::to_vec(&msg.data).unwrap();
+            let data = msg.codec.encode(&msg.data);
             let sid = nuid::next();
 
             let publish = nats::ops::Publish::builder()
@@ -161,7 +222,7 @@ impl<T: RemoteMessage> Handler<Publish<T>> for ClientWorker {
                 match futures::stream::Stream::into_future(stream).compat().await {
                     Ok((Some(reply), _)) => {
                         //println!("Returning value");
-                        let reply: T::Result = json::from_slice(&reply.payload).unwrap();
+                        let reply: T::Result = msg.codec.decode(&reply.payload).unwrap();
                         Ok(reply)
                     }
                     Ok((None, _)) => {
@@ -184,15 +245,24 @@ impl<T: RemoteMessage> Handler<Publish<T>> for ClientWorker {
 }
 
 
+#[derive(Clone)]
+enum Transport {
+    Nats(Addr<ClientWorker>),
+    Local(Addr<LocalBroker>),
+}
+
 #[derive(Clone)]
 pub struct Client {
-    addr: Addr<ClientWorker>
+    transport: Transport,
+    codec: Codec,
 }
 
 use futures::{
     BoxFuture,
-    future::result,
+    future::{result, loop_fn, Loop},
 };
+use tokio::util::FutureExt as _;
+use std::time::Duration;
 
 impl Client {
     pub async fn new(addr: impl Into<String>) -> Self {
@@ -203,29 +273,140 @@ impl Client {
                 subs: HashMap::new(),
             }
         });
-        Client { addr }
+        Client { transport: Transport::Nats(addr), codec: Codec::Json }
+    }
+
+    /// Creates a client backed by an in-process broker instead of the NATS server,
+    /// all clones of the returned client are connected to the same broker.
+    pub fn in_memory() -> Self {
+        let addr = LocalBroker::default().start();
+        Client { transport: Transport::Local(addr), codec: Codec::Json }
+    }
+
+    /// Selects the format of published messages, subscriptions accept all of them
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
     }
 
     pub fn subscribe<T>(&self, topic: impl Into<String>, queue: impl Into<Option<String>>, addr: Recipient<T>)
         where
             T: RemoteMessage + Send
     {
-        let _ = self.addr.do_send(Subscribe::new(topic, queue, addr));
+        let sub = Subscribe::new(topic, queue, addr);
+        let _ = match self.transport {
+            Transport::Nats(ref worker) => worker.do_send(sub),
+            Transport::Local(ref broker) => broker.do_send(sub),
+        };
     }
 
     pub fn publish<T>(&self, topic: impl Into<String>, data: T)
         where T: RemoteMessage
     {
-        let _ = self.addr.do_send(Publish { data, subject: topic.into(), is_req: false });
+        let publish = Publish { data, subject: topic.into(), is_req: false, codec: self.codec };
+        let _ = match self.transport {
+            Transport::Nats(ref worker) => worker.do_send(publish),
+            Transport::Local(ref broker) => broker.do_send(publish),
+        };
     }
 
     pub fn request<T>(&self, topic: impl Into<String>, data: T) -> Box<dyn futures::future::Future<Item=T::Result, Error=MailboxError>>
         where T: RemoteMessage
     {
-        let addr = self.addr.clone();
         let topic = topic.into();
-        let sent: BoxFuture<Result<T::Result, MailboxError>, MailboxError> = box addr.send(Publish { data, subject: topic, is_req: true });
+        let publish = Publish { data, subject: topic, is_req: true, codec: self.codec };
+        let sent: BoxFuture<Result<T::Result, MailboxError>, MailboxError> = match self.transport {
+            Transport::Nats(ref worker) => box worker.send(publish),
+            Transport::Local(ref broker) => box broker.send(publish),
+        };
 
         box sent.and_then(|r| result(r))
     }
-}
\ No newline at end of file
+
+    /// Sends a request and retries it until the receiver acknowledges it by replying.
+    /// Each attempt waits at most `timeout` for the reply, so receivers have to be idempotent,
+    /// a slow reply results in the same message being delivered again.
+    pub fn deliver<T>(&self, topic: impl Into<String>, data: T, attempts: usize, timeout: Duration) -> Box<dyn futures::future::Future<Item=T::Result, Error=MailboxError>>
+        where T: RemoteMessage + Clone
+    {
+        let client = self.clone();
+        let topic = topic.into();
+
+        box loop_fn(attempts, move |remaining| {
+            client.request(topic.clone(), data.clone())
+                .timeout(timeout)
+                .then(move |res| match res {
+                    Ok(reply) => Ok(Loop::Break(reply)),
+                    Err(_) if remaining > 1 => Ok(Loop::Continue(remaining - 1)),
+                    Err(_) => Err(MailboxError::Timeout),
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Double, Doubler};
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Sample {
+        id: u32,
+        name: String,
+        values: Vec<f64>,
+        flag: Option<bool>,
+    }
+
+    fn sample() -> Sample {
+        Sample { id: 7, name: "BTC:USD".into(), values: vec![1.5, -2.25], flag: None }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let payload = Codec::Json.encode(&sample());
+        assert_eq!(Codec::detect(&payload), Codec::Json);
+        assert_eq!(Codec::Json.decode::<Sample>(&payload).unwrap(), sample());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let payload = Codec::Binary.encode(&sample());
+        assert_eq!(payload[0], BINARY_TAG);
+        assert_eq!(Codec::detect(&payload), Codec::Binary);
+        assert_eq!(Codec::Binary.decode::<Sample>(&payload).unwrap(), sample());
+    }
+
+    #[test]
+    fn json_is_never_detected_as_binary() {
+        let payloads = vec![
+            Codec::Json.encode(&0u32),
+            Codec::Json.encode(&""),
+            Codec::Json.encode(&Option::<u32>::None),
+            Codec::Json.encode(&Vec::<u8>::new()),
+            Codec::Json.encode(&sample()),
+        ];
+        for payload in payloads {
+            assert_eq!(Codec::detect(&payload), Codec::Json);
+        }
+        assert_eq!(Codec::detect(&[]), Codec::Json);
+    }
+
+    #[test]
+    fn mismatched_codec_fails() {
+        assert!(Codec::Json.decode::<Sample>(&Codec::Binary.encode(&sample())).is_err());
+        assert!(Codec::Binary.decode::<Sample>(&Codec::Json.encode(&sample())).is_err());
+    }
+
+    #[test]
+    fn requests_in_both_codecs() {
+        let mut sys = System::new("test");
+        let doubler = Doubler.start();
+
+        for &codec in [Codec::Json, Codec::Binary].iter() {
+            let client = Client::in_memory().with_codec(codec);
+            client.subscribe("double", None, doubler.clone().recipient());
+            assert_eq!(sys.block_on(client.request("double", Double(21))).unwrap(), 42);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::{HashMap, BTreeMap};
+
+use actix::prelude::*;
+use futures::future::{self, Future as _};
+
+use crate::{Codec, Publish, RemoteMessage, Subscribe};
+
+type LocalReply = Box<dyn futures::future::Future<Item=Option<Vec<u8>>, Error=()>>;
+/// Returns `None` once the recipient is stopped, so the broker can drop the subscription
+type LocalHandler = Box<dyn Fn(&[u8], bool) -> Option<LocalReply>>;
+
+struct LocalSubscription {
+    group: Option<String>,
+    handler: LocalHandler,
+}
+
+/// In-process stand-in for the NATS server. Messages are routed by exact subject match,
+/// and go through the same encoding as they would on the wire.
+#[derive(Default)]
+pub(crate) struct LocalBroker {
+    subs: HashMap<String, Vec<LocalSubscription>>,
+    counter: usize,
+}
+
+impl Actor for LocalBroker {
+    type Context = Context<Self>;
+}
+
+impl LocalBroker {
+    /// Selects the receivers of a message, every subscription without a queue group
+    /// and a single member of each queue group, picked in round robin fashion.
+    fn route(&mut self, subject: &str) -> Vec<usize> {
+        self.counter += 1;
+        let counter = self.counter;
+
+        let subs = match self.subs.get(subject) {
+            Some(subs) => subs,
+            None => return vec![],
+        };
+
+        let mut res = vec![];
+        let mut groups: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (idx, sub) in subs.iter().enumerate() {
+            match sub.group {
+                Some(ref group) => groups.entry(group.as_str()).or_insert_with(Vec::new).push(idx),
+                None => res.push(idx),
+            }
+        }
+        for (_, members) in groups {
+            res.push(members[counter % members.len()]);
+        }
+        res
+    }
+}
+
+/// Decodes messages for the recipient, replies are encoded in the format of the request
+fn handler<T: RemoteMessage>(rec: Recipient<T>) -> LocalHandler {
+    Box::new(move |payload: &[u8], is_req: bool| -> Option<LocalReply> {
+        let codec = Codec::detect(payload);
+        let req: T = codec.decode(payload).expect("Msg deserialization");
+        if is_req {
+            Some(Box::new(rec.send(req)
+                .map(move |reply| Some(codec.encode(&reply)))
+                .map_err(|_| ())))
+        } else {
+            match rec.do_send(req) {
+                Ok(()) => Some(Box::new(future::ok(None))),
+                Err(_) => None,
+            }
+        }
+    })
+}
+
+impl<T: RemoteMessage> Handler<Subscribe<T>> for LocalBroker {
+    type Result = Result<String, ()>;
+
+    fn handle(&mut self, msg: Subscribe<T>, _ctx: &mut Self::Context) -> Self::Result {
+        let sid = nuid::next();
+        self.subs.entry(msg.name).or_insert_with(Vec::new).push(LocalSubscription {
+            group: msg.group,
+            handler: handler(msg.rec),
+        });
+        Ok(sid)
+    }
+}
+
+impl<T: RemoteMessage> Handler<Publish<T>> for LocalBroker {
+    type Result = ResponseFuture<T::Result, MailboxError>;
+
+    fn handle(&mut self, msg: Publish<T>, _ctx: &mut Self::Context) -> Self::Result {
+        let codec = msg.codec;
+        let payload = codec.encode(&msg.data);
+        let is_req = msg.is_req;
+
+        let mut replies = vec![];
+        let mut closed = vec![];
+        for idx in self.route(&msg.subject) {
+            match (self.subs[&msg.subject][idx].handler)(&payload, is_req) {
+                Some(reply) => replies.push(reply),
+                None => closed.push(idx),
+            }
+        }
+        if let Some(subs) = self.subs.get_mut(&msg.subject) {
+            for idx in closed.into_iter().rev() {
+                subs.remove(idx);
+            }
+        }
+
+        // Same as with the real server, nobody replies to notifications,
+        // and a request nobody is subscribed to does not receive a reply
+        if !is_req || replies.is_empty() {
+            return Box::new(future::err(MailboxError::Closed));
+        }
+
+        let replies = replies.into_iter().map(|r| r.and_then(|r| r.ok_or(())));
+        Box::new(future::select_ok(replies)
+            .map_err(|_| MailboxError::Closed)
+            .and_then(move |(reply, _)| {
+                codec.decode(&reply).map_err(|_| MailboxError::Closed)
+            }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BINARY_TAG;
+    use crate::fixtures::{Double, Doubler};
+
+    #[test]
+    fn replies_use_codec_of_request() {
+        let mut sys = System::new("test");
+        let handler = handler(Doubler.start().recipient::<Double>());
+
+        let reply = sys.block_on(handler(&Codec::Json.encode(&Double(21)), true).unwrap()).unwrap().unwrap();
+        assert_eq!(Codec::detect(&reply), Codec::Json);
+        assert_eq!(Codec::Json.decode::<u32>(&reply).unwrap(), 42);
+
+        let reply = sys.block_on(handler(&Codec::Binary.encode(&Double(21)), true).unwrap()).unwrap().unwrap();
+        assert_eq!(reply[0], BINARY_TAG);
+        assert_eq!(Codec::Binary.decode::<u32>(&reply).unwrap(), 42);
+    }
+}
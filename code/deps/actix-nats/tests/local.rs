@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use actix_nats::{Client, Codec};
+use futures::Future;
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Ping(u32);
+
+impl Message for Ping { type Result = u32; }
+
+struct Counter(Arc<AtomicUsize>);
+
+impl Actor for Counter { type Context = Context<Self>; }
+
+impl Handler<Ping> for Counter {
+    type Result = u32;
+
+    fn handle(&mut self, msg: Ping, _ctx: &mut Self::Context) -> Self::Result {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        msg.0 * 2
+    }
+}
+
+fn counter(client: &Client, group: Option<&str>) -> Arc<AtomicUsize> {
+    let count = Arc::new(AtomicUsize::new(0));
+    let addr = Counter(count.clone()).start();
+    client.subscribe("ping", group.map(|g| g.to_string()), addr.recipient::<Ping>());
+    count
+}
+
+fn run<F>(f: impl FnOnce() -> F + 'static)
+    where F: Future<Item=(), Error=()> + 'static
+{
+    System::run(move || {
+        let fut = f().then(|_| {
+            System::current().stop();
+            Ok(())
+        });
+        actix::spawn(fut);
+    });
+}
+
+fn settle() -> impl Future<Item=(), Error=()> {
+    tokio::timer::Delay::new(Instant::now() + Duration::from_millis(100)).map_err(|_| ())
+}
+
+#[test]
+fn queue_group_receives_each_message_once() {
+    let counts = Arc::new(std::sync::Mutex::new(vec![]));
+    let res = counts.clone();
+
+    run(move || {
+        let client = Client::in_memory();
+        let all = counter(&client, None);
+        let first = counter(&client, Some("workers"));
+        let second = counter(&client, Some("workers"));
+
+        for i in 0..10 {
+            client.publish("ping", Ping(i));
+        }
+
+        settle().map(move |_| {
+            *res.lock().unwrap() = vec![all.load(Ordering::SeqCst), first.load(Ordering::SeqCst), second.load(Ordering::SeqCst)];
+        })
+    });
+
+    let counts = counts.lock().unwrap();
+    assert_eq!(counts[0], 10);
+    assert_eq!(counts[1] + counts[2], 10);
+    assert!(counts[1] > 0 && counts[2] > 0);
+}
+
+#[test]
+fn request_is_answered_in_both_codecs() {
+    let replies = Arc::new(std::sync::Mutex::new(vec![]));
+    let res = replies.clone();
+
+    run(move || {
+        let client = Client::in_memory();
+        counter(&client, Some("workers"));
+
+        let json = client.request("ping", Ping(2));
+        let binary = client.clone().with_codec(Codec::Binary).request("ping", Ping(21));
+
+        json.join(binary)
+            .map(move |(a, b)| *res.lock().unwrap() = vec![a, b])
+            .map_err(|e| panic!("Request failed {:?}", e))
+    });
+
+    assert_eq!(*replies.lock().unwrap(), vec![4, 42]);
+}
+
+#[test]
+fn request_without_subscribers_fails() {
+    let failed = Arc::new(AtomicUsize::new(0));
+    let res = failed.clone();
+
+    run(move || {
+        Client::in_memory().request("ping", Ping(1))
+            .then(move |r| {
+                if r.is_err() {
+                    res.fetch_add(1, Ordering::SeqCst);
+                }
+                Ok(())
+            })
+    });
+
+    assert_eq!(failed.load(Ordering::SeqCst), 1);
+}
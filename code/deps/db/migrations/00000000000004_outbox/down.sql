@@ -0,0 +1,3 @@
+drop table if exists inbox;
+
+drop table if exists outbox;
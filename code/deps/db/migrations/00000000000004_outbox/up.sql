@@ -0,0 +1,25 @@
+create table if not exists outbox
+(
+    id           uuid                     not null default gen_random_uuid(),
+    subject      text                     not null,
+    payload      text                     not null,
+
+    created      timestamp with time zone not null default now(),
+    attempts     integer                  not null default 0,
+    next_attempt timestamp with time zone not null default now(),
+    delivered    timestamp with time zone,
+
+    primary key (id)
+);
+
+create index if not exists outbox_pending on outbox (next_attempt) where delivered is null;
+
+-- Ids of messages that were already acted upon, redelivered messages are ignored
+create table if not exists inbox
+(
+    id       uuid                     not null,
+    subject  text                     not null,
+    received timestamp with time zone not null default now(),
+
+    primary key (id)
+);
@@ -0,0 +1,12 @@
+-- Encrypted credentials can not be recovered in SQL, they have to be entered again
+update traders
+set api_key    = '',
+    api_secret = ''
+where data_key is not null;
+
+alter table traders
+    alter column api_key type text using convert_from(api_key, 'UTF8'),
+    alter column api_secret type text using convert_from(api_secret, 'UTF8');
+
+alter table traders
+    drop column if exists data_key;
@@ -0,0 +1,8 @@
+-- Exchange credentials are encrypted by a per-trader data key, which is encrypted by the master key.
+-- Existing credentials keep their plaintext bytes with no data key, until they are encrypted on startup.
+alter table traders
+    add column if not exists data_key bytea;
+
+alter table traders
+    alter column api_key type bytea using convert_to(api_key, 'UTF8'),
+    alter column api_secret type bytea using convert_to(api_secret, 'UTF8');
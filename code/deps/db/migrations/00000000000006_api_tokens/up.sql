@@ -0,0 +1,13 @@
+-- Personal tokens for programmatic access, only the SHA-256 hash of the token is stored
+create table if not exists api_tokens
+(
+    id         integer generated by default as identity primary key,
+    user_id    integer                  not null,
+    name       text                     not null,
+    scope      text                     not null,
+    token_hash bytea                    not null unique,
+    created    timestamp with time zone not null default now(),
+    last_used  timestamp with time zone,
+    revoked    timestamp with time zone,
+    foreign key (user_id) references users (id) on delete cascade
+);
@@ -0,0 +1,12 @@
+-- Single-use tokens sent by email, for address verification and password reset
+create table if not exists user_tokens
+(
+    id         integer generated by default as identity primary key,
+    user_id    integer                  not null,
+    purpose    text                     not null,
+    token_hash bytea                    not null unique,
+    created    timestamp with time zone not null default now(),
+    expires    timestamp with time zone not null,
+    used       timestamp with time zone,
+    foreign key (user_id) references users (id) on delete cascade
+);
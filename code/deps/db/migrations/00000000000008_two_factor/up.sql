@@ -0,0 +1,17 @@
+-- TOTP secret is encrypted by the master key, it becomes active once the user confirms a code.
+-- Last accepted time step is kept so that a code can't be used twice.
+alter table users
+    add column if not exists totp_secret    bytea,
+    add column if not exists totp_enabled   bool not null default false,
+    add column if not exists totp_last_step bigint;
+
+create table if not exists recovery_codes
+(
+    id        integer generated by default as identity primary key,
+    user_id   integer not null,
+    code_hash bytea   not null,
+    used      timestamp with time zone,
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+create index if not exists recovery_codes_user on recovery_codes (user_id);
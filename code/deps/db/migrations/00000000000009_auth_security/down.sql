@@ -0,0 +1,5 @@
+drop table if exists auth_events;
+
+alter table users
+    drop column if exists failed_logins,
+    drop column if exists locked_until;
@@ -0,0 +1,17 @@
+-- Consecutive failed logins, the account is locked for a while once they reach a limit
+alter table users
+    add column if not exists failed_logins integer not null default 0,
+    add column if not exists locked_until  timestamp with time zone;
+
+create table if not exists auth_events
+(
+    id      bigint generated by default as identity primary key,
+    user_id integer,
+    email   text,
+    event   text                     not null,
+    ip      text,
+    created timestamp with time zone not null default now(),
+    foreign key (user_id) references users (id) on delete set null
+);
+
+create index if not exists auth_events_user on auth_events (user_id, created);
@@ -0,0 +1,4 @@
+-- Disabled users can't sign in, and their assignments are not evaluated
+alter table users
+    add column if not exists role     text not null default 'user',
+    add column if not exists disabled bool not null default false;
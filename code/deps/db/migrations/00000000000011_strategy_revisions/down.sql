@@ -0,0 +1,13 @@
+alter table trades
+    drop column if exists revision_id;
+
+alter table evaluations
+    drop column if exists revision_id;
+
+alter table assignments
+    drop column if exists revision_id;
+
+alter table strategies
+    drop column if exists revision_id;
+
+drop table if exists strategy_revisions;
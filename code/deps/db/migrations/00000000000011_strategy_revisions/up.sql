@@ -0,0 +1,52 @@
+-- Strategy code is never overwritten, every change is stored as a new revision
+create table if not exists strategy_revisions
+(
+    id          integer generated by default as identity primary key,
+    strategy_id integer                  not null,
+    number      integer                  not null,
+    body        text                     not null,
+    created     timestamp with time zone not null default now(),
+
+    unique (strategy_id, number),
+    foreign key (strategy_id) references strategies (id) on delete cascade
+);
+
+insert into strategy_revisions (strategy_id, number, body, created)
+select id, 1, body, updated
+from strategies;
+
+-- Current revision, body of the strategy always mirrors it
+alter table strategies
+    add column if not exists revision_id integer references strategy_revisions (id);
+
+update strategies
+set revision_id = strategy_revisions.id
+from strategy_revisions
+where strategy_revisions.strategy_id = strategies.id;
+
+-- Revision evaluated by the assignment, follows the current revision of the strategy
+alter table assignments
+    add column if not exists revision_id integer references strategy_revisions (id) on delete cascade;
+
+update assignments
+set revision_id = strategies.revision_id
+from strategies
+where strategies.id = assignments.strategy_id;
+
+alter table assignments
+    alter column revision_id set not null;
+
+alter table evaluations
+    add column if not exists revision_id integer references strategy_revisions (id) on delete cascade;
+
+update evaluations
+set revision_id = strategies.revision_id
+from strategies
+where strategies.id = evaluations.strategy_id;
+
+alter table evaluations
+    alter column revision_id set not null;
+
+-- Trades placed without a strategy have no revision
+alter table trades
+    add column if not exists revision_id integer references strategy_revisions (id) on delete set null;
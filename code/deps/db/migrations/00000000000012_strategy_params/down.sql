@@ -0,0 +1,8 @@
+alter table assignments
+    drop column if exists params;
+
+alter table strategies
+    drop column if exists params;
+
+alter table strategy_revisions
+    drop column if exists params;
@@ -0,0 +1,9 @@
+-- Parameter declarations and values are stored as JSON, declarations are a part of the revision
+alter table strategy_revisions
+    add column if not exists params text not null default '[]';
+
+alter table strategies
+    add column if not exists params text not null default '[]';
+
+alter table assignments
+    add column if not exists params text not null default '{}';
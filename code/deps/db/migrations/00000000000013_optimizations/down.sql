@@ -0,0 +1,2 @@
+drop table if exists optimization_results;
+drop table if exists optimizations;
@@ -0,0 +1,44 @@
+-- Parameter searches, picked up by the optimizer of the app service
+create table if not exists optimizations
+(
+    id          integer generated by default as identity primary key,
+    user_id     integer                  not null,
+    strategy_id integer                  not null,
+    revision_id integer                  not null,
+    pair_id     integer                  not null,
+    period      text                     not null,
+
+    from_time   bigint                   not null,
+    to_time     bigint                   not null,
+    search      text                     not null,
+    folds       integer                  not null,
+
+    status      text                     not null default 'pending',
+    error       text,
+    created     timestamp with time zone not null default now(),
+    started     timestamp with time zone,
+    finished    timestamp with time zone,
+
+    foreign key (user_id) references users (id) on delete cascade,
+    foreign key (strategy_id) references strategies (id) on delete cascade,
+    foreign key (revision_id) references strategy_revisions (id) on delete cascade,
+    foreign key (pair_id) references pairs (id) on delete cascade
+);
+
+create index if not exists optimizations_status on optimizations (status, created);
+
+-- Parameter combinations ranked by their out-of-sample return
+create table if not exists optimization_results
+(
+    id              integer generated by default as identity primary key,
+    optimization_id integer          not null,
+    rank            integer          not null,
+    params          text             not null,
+
+    in_sample       double precision not null,
+    out_of_sample   double precision not null,
+    max_drawdown    double precision not null,
+    trades          integer          not null,
+
+    foreign key (optimization_id) references optimizations (id) on delete cascade
+);
@@ -0,0 +1,21 @@
+drop index if exists assignments_user_idx;
+
+-- Only the oldest assignment of every user and pair can be kept
+delete
+from assignments a
+    using assignments b
+where a.pair_id = b.pair_id
+  and a.user_id = b.user_id
+  and a.id > b.id;
+
+alter table assignments
+    drop column if exists created;
+
+alter table assignments
+    drop column if exists enabled;
+
+alter table assignments
+    drop column if exists id;
+
+alter table assignments
+    add primary key (pair_id, user_id);
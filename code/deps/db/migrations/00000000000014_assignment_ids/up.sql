@@ -0,0 +1,14 @@
+-- Assignments get their own identity, so a user can run several strategies and periods on the same pair
+alter table assignments
+    drop constraint if exists assignments_pkey;
+
+alter table assignments
+    add column if not exists id integer generated by default as identity primary key;
+
+alter table assignments
+    add column if not exists enabled boolean not null default true;
+
+alter table assignments
+    add column if not exists created timestamp with time zone not null default now();
+
+create index if not exists assignments_user_idx on assignments (user_id);
@@ -0,0 +1,2 @@
+drop table if exists risk_decisions;
+drop table if exists risk_limits;
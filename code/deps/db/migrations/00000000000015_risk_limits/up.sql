@@ -0,0 +1,38 @@
+-- Per-user limits enforced by the risk manager of the app service, missing limits are not enforced
+create table if not exists risk_limits
+(
+    user_id             integer primary key,
+    -- Summed size of open positions on pairs trading the same currency
+    max_exposure        double precision,
+    -- Loss of the current UTC day, in quote currency
+    max_daily_loss      double precision,
+    max_orders_per_hour integer,
+    -- Only allows closing positions
+    kill_switch         boolean                  not null default false,
+    updated             timestamp with time zone not null default now(),
+
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+-- Position requests which changed a position, or were rejected
+create table if not exists risk_decisions
+(
+    -- Id of the position request
+    id        uuid primary key,
+    time      timestamp with time zone not null default now(),
+    user_id   integer                  not null,
+    trader_id integer                  not null,
+    pair_id   integer                  not null,
+    position  text                     not null,
+    requested double precision         not null,
+    -- Size sent to the trader, null when the request was rejected
+    approved  double precision,
+    reason    text,
+
+    foreign key (user_id) references users (id) on delete cascade,
+    foreign key (trader_id) references traders (id) on delete cascade,
+    foreign key (pair_id) references pairs (id) on delete cascade
+);
+
+create index if not exists risk_decisions_user on risk_decisions (user_id, time);
+create index if not exists risk_decisions_position on risk_decisions (trader_id, pair_id, time);
@@ -0,0 +1,4 @@
+drop index if exists trades_trader_time;
+
+alter table trades
+    drop column if exists fee;
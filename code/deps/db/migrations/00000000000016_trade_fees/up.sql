@@ -0,0 +1,5 @@
+-- Fee charged by the exchange for the trade, in quote currency of the pair
+alter table trades
+    add column if not exists fee double precision not null default 0;
+
+create index if not exists trades_trader_time on trades (trader_id, time);
@@ -0,0 +1,3 @@
+drop table if exists notifications;
+drop table if exists notification_rules;
+drop table if exists notification_channels;
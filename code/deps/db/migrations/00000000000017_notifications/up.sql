@@ -0,0 +1,52 @@
+-- Destinations notifications can be sent to
+create table if not exists notification_channels
+(
+    id      integer generated by default as identity primary key,
+    user_id integer                  not null,
+    -- One of email, webhook, bot
+    kind    text                     not null,
+    -- Email address, webhook URL or chat id of the bot API
+    target  text                     not null,
+    -- Token of the bot API
+    token   text,
+    enabled boolean                  not null default true,
+    created timestamp with time zone not null default now(),
+
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+-- Events the user subscribed to, and the channels they are sent to
+create table if not exists notification_rules
+(
+    id         integer generated by default as identity primary key,
+    user_id    integer not null,
+    event      text    not null,
+    channel_id integer not null,
+
+    unique (event, channel_id),
+    foreign key (user_id) references users (id) on delete cascade,
+    foreign key (channel_id) references notification_channels (id) on delete cascade
+);
+
+-- Single delivery of an event to one channel, picked up by the notifier of the web service
+create table if not exists notifications
+(
+    id           uuid                     not null default gen_random_uuid() primary key,
+    user_id      integer                  not null,
+    channel_id   integer                  not null,
+    event        text                     not null,
+    subject      text                     not null,
+    body         text                     not null,
+
+    created      timestamp with time zone not null default now(),
+    attempts     integer                  not null default 0,
+    next_attempt timestamp with time zone not null default now(),
+    delivered    timestamp with time zone,
+    error        text,
+
+    foreign key (user_id) references users (id) on delete cascade,
+    foreign key (channel_id) references notification_channels (id) on delete cascade
+);
+
+create index if not exists notifications_pending on notifications (next_attempt) where delivered is null;
+create index if not exists notifications_user on notifications (user_id, created);
@@ -0,0 +1,5 @@
+drop table if exists signal_deliveries;
+
+alter table assignments
+    drop column if exists signal_url,
+    drop column if exists signal_secret;
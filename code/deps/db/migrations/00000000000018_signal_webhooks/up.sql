@@ -0,0 +1,28 @@
+-- Webhook receiving decisions of the assignment, payloads are signed with the secret
+alter table assignments
+    add column if not exists signal_url    text,
+    add column if not exists signal_secret text;
+
+-- Single decision posted to the signal target of an assignment, sent by the app service
+create table if not exists signal_deliveries
+(
+    id            uuid                     not null default gen_random_uuid() primary key,
+    assignment_id integer                  not null,
+    user_id       integer                  not null,
+    url           text                     not null,
+    payload       text                     not null,
+
+    created       timestamp with time zone not null default now(),
+    attempts      integer                  not null default 0,
+    next_attempt  timestamp with time zone not null default now(),
+    delivered     timestamp with time zone,
+    -- HTTP status of the last response
+    status        integer,
+    error         text,
+
+    foreign key (assignment_id) references assignments (id) on delete cascade,
+    foreign key (user_id) references users (id) on delete cascade
+);
+
+create index if not exists signal_deliveries_pending on signal_deliveries (next_attempt) where delivered is null;
+create index if not exists signal_deliveries_assignment on signal_deliveries (assignment_id, created);
@@ -0,0 +1 @@
+drop table if exists signal_hooks;
@@ -0,0 +1,18 @@
+-- Inbound webhooks turning external signals into position requests of a trader.
+-- The token is part of the URL, only its SHA-256 hash is stored.
+create table if not exists signal_hooks
+(
+    id         integer generated by default as identity primary key,
+    user_id    integer                  not null,
+    trader_id  integer                  not null,
+    name       text                     not null,
+    token_hash bytea                    not null unique,
+    created    timestamp with time zone not null default now(),
+    last_used  timestamp with time zone,
+    revoked    timestamp with time zone,
+
+    foreign key (user_id) references users (id) on delete cascade,
+    foreign key (trader_id) references traders (id) on delete cascade
+);
+
+create index if not exists signal_hooks_user on signal_hooks (user_id);
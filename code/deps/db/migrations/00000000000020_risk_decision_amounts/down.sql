@@ -0,0 +1,2 @@
+alter table risk_decisions
+    drop column if exists amount;
@@ -0,0 +1,3 @@
+-- Approved size converted to an amount of the target currency, from the balance of the trader at the time of the review
+alter table risk_decisions
+    add column if not exists amount double precision;
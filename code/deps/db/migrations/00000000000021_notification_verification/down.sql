@@ -0,0 +1,5 @@
+drop table if exists stale_feeds;
+
+alter table notification_channels
+    drop column if exists verify_hash,
+    drop column if exists verified;
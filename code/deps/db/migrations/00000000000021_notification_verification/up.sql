@@ -0,0 +1,22 @@
+-- Email channels receive notifications only after their address is confirmed through an emailed link,
+-- `verify_hash` holds the hash of the pending link token
+alter table notification_channels
+    add column if not exists verified boolean not null default true,
+    add column if not exists verify_hash bytea;
+
+-- Existing email channels stay usable only when they send to the verified address of their owner
+update notification_channels
+set verified = false
+where kind = 'email'
+  and not exists(select 1
+                 from users
+                 where users.id = notification_channels.user_id
+                   and users.email = notification_channels.target
+                   and users.has_verified_email);
+
+-- Pairs whose feed was reported as stale, cleared when candles arrive again
+create table if not exists stale_feeds
+(
+    pair_id  int primary key references pairs (id) on delete cascade,
+    reported timestamptz not null default now()
+);
@@ -0,0 +1,5 @@
+drop index if exists inbox_received;
+drop index if exists outbox_delivered;
+
+alter table inbox
+    drop column if exists response;
@@ -0,0 +1,7 @@
+-- Response to a received message, returned again when the message is redelivered
+alter table inbox
+    add column if not exists response text;
+
+-- Delivered and received messages are purged after a retention period
+create index if not exists outbox_delivered on outbox (delivered) where delivered is not null;
+create index if not exists inbox_received on inbox (received);
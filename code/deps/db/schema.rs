@@ -1,10 +1,41 @@
 table! {
-    assignments (pair_id, user_id) {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        scope -> Text,
+        token_hash -> Bytea,
+        created -> Timestamptz,
+        last_used -> Nullable<Timestamptz>,
+        revoked -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    assignments (id) {
         pair_id -> Int4,
         user_id -> Int4,
         period -> Text,
         strategy_id -> Int4,
         trader_id -> Nullable<Int4>,
+        revision_id -> Int4,
+        params -> Text,
+        id -> Int4,
+        enabled -> Bool,
+        created -> Timestamptz,
+        signal_url -> Nullable<Text>,
+        signal_secret -> Nullable<Text>,
+    }
+}
+
+table! {
+    auth_events (id) {
+        id -> Int8,
+        user_id -> Nullable<Int4>,
+        email -> Nullable<Text>,
+        event -> Text,
+        ip -> Nullable<Text>,
+        created -> Timestamptz,
     }
 }
 
@@ -20,6 +51,55 @@ table! {
         duration -> Int8,
         ok -> Nullable<Text>,
         error -> Nullable<Text>,
+        revision_id -> Int4,
+    }
+}
+
+table! {
+    inbox (id) {
+        id -> Uuid,
+        subject -> Text,
+        received -> Timestamptz,
+        response -> Nullable<Text>,
+    }
+}
+
+table! {
+    notification_channels (id) {
+        id -> Int4,
+        user_id -> Int4,
+        kind -> Text,
+        target -> Text,
+        token -> Nullable<Text>,
+        enabled -> Bool,
+        created -> Timestamptz,
+        verified -> Bool,
+        verify_hash -> Nullable<Bytea>,
+    }
+}
+
+table! {
+    notification_rules (id) {
+        id -> Int4,
+        user_id -> Int4,
+        event -> Text,
+        channel_id -> Int4,
+    }
+}
+
+table! {
+    notifications (id) {
+        id -> Uuid,
+        user_id -> Int4,
+        channel_id -> Int4,
+        event -> Text,
+        subject -> Text,
+        body -> Text,
+        created -> Timestamptz,
+        attempts -> Int4,
+        next_attempt -> Timestamptz,
+        delivered -> Nullable<Timestamptz>,
+        error -> Nullable<Text>,
     }
 }
 
@@ -35,6 +115,51 @@ table! {
     }
 }
 
+table! {
+    optimization_results (id) {
+        id -> Int4,
+        optimization_id -> Int4,
+        rank -> Int4,
+        params -> Text,
+        in_sample -> Float8,
+        out_of_sample -> Float8,
+        max_drawdown -> Float8,
+        trades -> Int4,
+    }
+}
+
+table! {
+    optimizations (id) {
+        id -> Int4,
+        user_id -> Int4,
+        strategy_id -> Int4,
+        revision_id -> Int4,
+        pair_id -> Int4,
+        period -> Text,
+        from_time -> Int8,
+        to_time -> Int8,
+        search -> Text,
+        folds -> Int4,
+        status -> Text,
+        error -> Nullable<Text>,
+        created -> Timestamptz,
+        started -> Nullable<Timestamptz>,
+        finished -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    outbox (id) {
+        id -> Uuid,
+        subject -> Text,
+        payload -> Text,
+        created -> Timestamptz,
+        attempts -> Int4,
+        next_attempt -> Timestamptz,
+        delivered -> Nullable<Timestamptz>,
+    }
+}
+
 table! {
     pairs (id) {
         id -> Int4,
@@ -43,6 +168,77 @@ table! {
     }
 }
 
+table! {
+    recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Bytea,
+        used -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    risk_decisions (id) {
+        id -> Uuid,
+        time -> Timestamptz,
+        user_id -> Int4,
+        trader_id -> Int4,
+        pair_id -> Int4,
+        position -> Text,
+        requested -> Float8,
+        approved -> Nullable<Float8>,
+        reason -> Nullable<Text>,
+        amount -> Nullable<Float8>,
+    }
+}
+
+table! {
+    risk_limits (user_id) {
+        user_id -> Int4,
+        max_exposure -> Nullable<Float8>,
+        max_daily_loss -> Nullable<Float8>,
+        max_orders_per_hour -> Nullable<Int4>,
+        kill_switch -> Bool,
+        updated -> Timestamptz,
+    }
+}
+
+table! {
+    signal_deliveries (id) {
+        id -> Uuid,
+        assignment_id -> Int4,
+        user_id -> Int4,
+        url -> Text,
+        payload -> Text,
+        created -> Timestamptz,
+        attempts -> Int4,
+        next_attempt -> Timestamptz,
+        delivered -> Nullable<Timestamptz>,
+        status -> Nullable<Int4>,
+        error -> Nullable<Text>,
+    }
+}
+
+table! {
+    signal_hooks (id) {
+        id -> Int4,
+        user_id -> Int4,
+        trader_id -> Int4,
+        name -> Text,
+        token_hash -> Bytea,
+        created -> Timestamptz,
+        last_used -> Nullable<Timestamptz>,
+        revoked -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    stale_feeds (pair_id) {
+        pair_id -> Int4,
+        reported -> Timestamptz,
+    }
+}
+
 table! {
     strategies (id) {
         id -> Int4,
@@ -51,6 +247,19 @@ table! {
         body -> Text,
         created -> Timestamptz,
         updated -> Timestamptz,
+        revision_id -> Nullable<Int4>,
+        params -> Text,
+    }
+}
+
+table! {
+    strategy_revisions (id) {
+        id -> Int4,
+        strategy_id -> Int4,
+        number -> Int4,
+        body -> Text,
+        created -> Timestamptz,
+        params -> Text,
     }
 }
 
@@ -60,8 +269,9 @@ table! {
         user_id -> Int4,
         name -> Text,
         exchange -> Text,
-        api_key -> Text,
-        api_secret -> Text,
+        api_key -> Bytea,
+        api_secret -> Bytea,
+        data_key -> Nullable<Bytea>,
     }
 }
 
@@ -78,6 +288,20 @@ table! {
         status -> Bool,
         ok -> Nullable<Text>,
         error -> Nullable<Text>,
+        revision_id -> Nullable<Int4>,
+        fee -> Float8,
+    }
+}
+
+table! {
+    user_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        purpose -> Text,
+        token_hash -> Bytea,
+        created -> Timestamptz,
+        expires -> Timestamptz,
+        used -> Nullable<Timestamptz>,
     }
 }
 
@@ -92,30 +316,81 @@ table! {
         has_verified_email -> Bool,
         created -> Timestamptz,
         updated -> Timestamptz,
+        totp_secret -> Nullable<Bytea>,
+        totp_enabled -> Bool,
+        totp_last_step -> Nullable<Int8>,
+        failed_logins -> Int4,
+        locked_until -> Nullable<Timestamptz>,
+        role -> Text,
+        disabled -> Bool,
     }
 }
 
+joinable!(api_tokens -> users (user_id));
 joinable!(assignments -> pairs (pair_id));
 joinable!(assignments -> strategies (strategy_id));
+joinable!(assignments -> strategy_revisions (revision_id));
 joinable!(assignments -> traders (trader_id));
 joinable!(assignments -> users (user_id));
+joinable!(auth_events -> users (user_id));
 joinable!(evaluations -> pairs (pair_id));
 joinable!(evaluations -> strategies (strategy_id));
+joinable!(evaluations -> strategy_revisions (revision_id));
 joinable!(evaluations -> users (user_id));
+joinable!(notification_channels -> users (user_id));
+joinable!(notification_rules -> notification_channels (channel_id));
+joinable!(notification_rules -> users (user_id));
+joinable!(notifications -> notification_channels (channel_id));
+joinable!(notifications -> users (user_id));
 joinable!(ohlc -> pairs (pair_id));
+joinable!(optimization_results -> optimizations (optimization_id));
+joinable!(optimizations -> pairs (pair_id));
+joinable!(optimizations -> strategies (strategy_id));
+joinable!(optimizations -> strategy_revisions (revision_id));
+joinable!(optimizations -> users (user_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(risk_decisions -> pairs (pair_id));
+joinable!(risk_decisions -> traders (trader_id));
+joinable!(risk_decisions -> users (user_id));
+joinable!(risk_limits -> users (user_id));
+joinable!(signal_deliveries -> assignments (assignment_id));
+joinable!(signal_deliveries -> users (user_id));
+joinable!(signal_hooks -> traders (trader_id));
+joinable!(signal_hooks -> users (user_id));
+joinable!(stale_feeds -> pairs (pair_id));
 joinable!(strategies -> users (user_id));
+joinable!(strategy_revisions -> strategies (strategy_id));
 joinable!(traders -> users (user_id));
 joinable!(trades -> pairs (pair_id));
+joinable!(trades -> strategy_revisions (revision_id));
 joinable!(trades -> traders (trader_id));
 joinable!(trades -> users (user_id));
+joinable!(user_tokens -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    api_tokens,
     assignments,
+    auth_events,
     evaluations,
+    inbox,
+    notification_channels,
+    notification_rules,
+    notifications,
     ohlc,
+    optimization_results,
+    optimizations,
+    outbox,
     pairs,
+    recovery_codes,
+    risk_decisions,
+    risk_limits,
+    signal_deliveries,
+    signal_hooks,
+    stale_feeds,
     strategies,
+    strategy_revisions,
     traders,
     trades,
+    user_tokens,
     users,
 );
@@ -0,0 +1,369 @@
+use crate::prelude::*;
+use crate::ohlc::LoadOhlc;
+use crate::schema::{trades, traders, pairs, Pair, Trade};
+use diesel::sql_types::{Array, Int4};
+use std::collections::VecDeque;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Closing candle of every day for the listed pairs
+const DAILY_CLOSES_Q: &'static str = r##"
+select distinct on (pair_id, time / 86400) *
+from ohlc
+where pair_id = any($1) and time >= $2
+order by pair_id, time / 86400, time desc
+"##;
+
+/// How the cost of sold amounts is determined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CostMethod {
+    /// Sales use up the oldest purchases first
+    Fifo,
+    /// Sales are valued at the average price of all held purchases
+    Average,
+}
+
+impl Default for CostMethod {
+    fn default() -> Self { CostMethod::Fifo }
+}
+
+/// Amount of a currency bought through a single pair, with values in the quote currency of the pair
+#[derive(Debug, Clone, Serialize)]
+pub struct Holding {
+    pub exchange: String,
+    pub pair: String,
+    pub currency: String,
+    pub quote: String,
+    pub amount: f64,
+    /// Price paid for the held amount
+    pub cost_basis: f64,
+    /// Latest close price, `None` if the pair has no candles
+    pub price: Option<f64>,
+    pub value: Option<f64>,
+    pub realized: f64,
+    pub unrealized: Option<f64>,
+    pub fees: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PnlTotals {
+    pub realized: f64,
+    pub unrealized: f64,
+    pub fees: f64,
+    /// Realized and unrealized result after fees
+    pub pnl: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Portfolio {
+    pub trader_id: i32,
+    pub method: CostMethod,
+    pub holdings: Vec<Holding>,
+    /// Totals by quote currency
+    pub totals: BTreeMap<String, PnlTotals>,
+}
+
+/// Result of a single day in one quote currency
+#[derive(Debug, Clone, Serialize)]
+pub struct DailyPnl {
+    pub day: chrono::NaiveDate,
+    pub quote: String,
+    /// Change of the result after fees during the day
+    pub pnl: f64,
+    /// Results accumulated up to the end of the day
+    #[serde(flatten)]
+    pub total: PnlTotals,
+}
+
+/// Position of a single pair rebuilt from its trades
+#[derive(Debug, Clone, Default)]
+struct Ledger {
+    /// Held purchases as (amount, price), oldest first. Average cost keeps a single merged lot.
+    lots: VecDeque<(f64, f64)>,
+    realized: f64,
+    fees: f64,
+}
+
+impl Ledger {
+    fn apply(&mut self, trade: &Trade, method: CostMethod) {
+        self.fees += trade.fee;
+        if trade.buy {
+            match (method, self.lots.front_mut()) {
+                (CostMethod::Average, Some(lot)) => {
+                    let amount = lot.0 + trade.amount;
+                    lot.1 = (lot.0 * lot.1 + trade.amount * trade.price) / amount;
+                    lot.0 = amount;
+                }
+                _ => self.lots.push_back((trade.amount, trade.price)),
+            }
+            return;
+        }
+
+        // Amounts sold above the tracked holdings were bought outside of the trader, and are not accounted
+        let mut left = trade.amount;
+        while left > 0.0 {
+            let lot = match self.lots.front_mut() {
+                Some(lot) => lot,
+                None => break,
+            };
+            let used = left.min(lot.0);
+            self.realized += used * (trade.price - lot.1);
+            lot.0 -= used;
+            left -= used;
+            if lot.0 <= std::f64::EPSILON {
+                self.lots.pop_front();
+            }
+        }
+    }
+
+    fn amount(&self) -> f64 {
+        self.lots.iter().map(|l| l.0).sum()
+    }
+
+    fn cost(&self) -> f64 {
+        self.lots.iter().map(|l| l.0 * l.1).sum()
+    }
+
+    fn unrealized(&self, price: f64) -> f64 {
+        self.amount() * price - self.cost()
+    }
+}
+
+fn add_totals(totals: &mut BTreeMap<String, PnlTotals>, quote: &str, ledger: &Ledger, unrealized: f64) {
+    let total = totals.entry(quote.to_string()).or_default();
+    total.realized += ledger.realized;
+    total.unrealized += unrealized;
+    total.fees += ledger.fees;
+    total.pnl = total.realized + total.unrealized - total.fees;
+}
+
+fn day_of(time: i64) -> chrono::NaiveDate {
+    chrono::NaiveDateTime::from_timestamp(time.div_euclid(SECONDS_PER_DAY) * SECONDS_PER_DAY, 0).date()
+}
+
+impl crate::Database {
+    /// Successful trades of a trader owned by the user, oldest first
+    pub async fn trader_trades(&self, uid: i32, tid: i32) -> Result<Vec<(Trade, Pair)>> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            traders::table
+                .filter(traders::id.eq(tid))
+                .filter(traders::user_id.eq(uid))
+                .select(traders::id)
+                .get_result::<i32>(&conn)?;
+
+            trades::table
+                .inner_join(pairs::table)
+                .filter(trades::trader_id.eq(tid))
+                .filter(trades::status.eq(true))
+                .order_by(trades::time.asc())
+                .load(&conn)
+        }).await
+    }
+
+    /// Close prices of the listed pairs by pair id and day, starting with the day containing `since`
+    pub async fn daily_closes(&self, pair_ids: Vec<i32>, since: i64) -> Result<BTreeMap<(i32, chrono::NaiveDate), f64>> {
+        self.0.invoke(move |this, ctx| {
+            let since = since.div_euclid(SECONDS_PER_DAY) * SECONDS_PER_DAY;
+            let closes: Vec<LoadOhlc> = diesel::sql_query(DAILY_CLOSES_Q)
+                .bind::<Array<Int4>, _>(pair_ids)
+                .bind::<BigInt, _>(since)
+                .load(&this.conn())?;
+            Ok(closes.into_iter().map(|c| ((c.pair_id, day_of(c.time)), c.close)).collect())
+        }).await
+    }
+
+    /// Holdings of a trader rebuilt from its trades, valued at the latest close prices
+    pub async fn portfolio(&self, uid: i32, tid: i32, method: CostMethod) -> Result<Portfolio> {
+        let trades = self.trader_trades(uid, tid).await?;
+        let prices = self.ohlc_lasts().await?;
+
+        let mut ledgers: BTreeMap<i32, (Pair, Ledger)> = BTreeMap::new();
+        for (trade, pair) in trades.iter() {
+            ledgers.entry(pair.id)
+                .or_insert_with(|| (pair.clone(), Ledger::default()))
+                .1
+                .apply(trade, method);
+        }
+
+        let mut holdings = vec![];
+        let mut totals = BTreeMap::new();
+        for (_, (pair, ledger)) in ledgers.into_iter() {
+            let id: PairId = pair.clone().into();
+            let price = prices.get(&id).map(|o| o.close);
+            let unrealized = price.map(|p| ledger.unrealized(p));
+            add_totals(&mut totals, id.src_currency(), &ledger, unrealized.unwrap_or(0.0));
+
+            holdings.push(Holding {
+                exchange: pair.exchange,
+                pair: pair.pair,
+                currency: id.tar_currency().to_string(),
+                quote: id.src_currency().to_string(),
+                amount: ledger.amount(),
+                cost_basis: ledger.cost(),
+                price,
+                value: price.map(|p| p * ledger.amount()),
+                realized: ledger.realized,
+                unrealized,
+                fees: ledger.fees,
+            });
+        }
+
+        Ok(Portfolio { trader_id: tid, method, holdings, totals })
+    }
+
+    /// Results of a trader for each of the last `days` days, holdings are valued at daily close prices
+    pub async fn daily_pnl(&self, uid: i32, tid: i32, method: CostMethod, days: i64) -> Result<Vec<DailyPnl>> {
+        let trades = self.trader_trades(uid, tid).await?;
+        let first = match trades.first() {
+            Some((trade, _)) => trade.time.timestamp(),
+            None => return Ok(vec![]),
+        };
+        let today = chrono::Utc::today().naive_utc();
+        let start = std::cmp::max(day_of(first), today - chrono::Duration::days(days - 1));
+        // Day before the reported ones is needed for the change of the first reported day
+        let since = (start - chrono::Duration::days(1)).and_hms(0, 0, 0).timestamp();
+
+        let pair_ids = trades.iter().map(|(_, p)| p.id).unique().collect::<Vec<_>>();
+        let closes = self.daily_closes(pair_ids, since).await?;
+
+        let mut trades = trades.into_iter().peekable();
+        let mut ledgers: BTreeMap<i32, (PairId, Ledger)> = BTreeMap::new();
+        let mut last_close: BTreeMap<i32, f64> = BTreeMap::new();
+        let mut previous: BTreeMap<String, f64> = BTreeMap::new();
+        let mut out = vec![];
+
+        let mut day = day_of(first);
+        while day <= today {
+            while let Some((trade, pair)) = trades.peek() {
+                if day_of(trade.time.timestamp()) > day {
+                    break;
+                }
+                let (trade, pair) = (trade.clone(), pair.clone());
+                ledgers.entry(pair.id)
+                    .or_insert_with(|| (pair.clone().into(), Ledger::default()))
+                    .1
+                    .apply(&trade, method);
+                last_close.entry(pair.id).or_insert(trade.price);
+                trades.next();
+            }
+
+            let mut totals = BTreeMap::new();
+            for (pid, (pair, ledger)) in ledgers.iter() {
+                if let Some(close) = closes.get(&(*pid, day)) {
+                    last_close.insert(*pid, *close);
+                }
+                let unrealized = ledger.unrealized(last_close[pid]);
+                add_totals(&mut totals, pair.src_currency(), ledger, unrealized);
+            }
+
+            for (quote, total) in totals.into_iter() {
+                let before = previous.insert(quote.clone(), total.pnl).unwrap_or(0.0);
+                if day >= start {
+                    out.push(DailyPnl {
+                        day,
+                        quote,
+                        pnl: total.pnl - before,
+                        total,
+                    });
+                }
+            }
+            day = day.succ();
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn trade(buy: bool, amount: f64, price: f64, fee: f64) -> Trade {
+        Trade {
+            id: Uuid::new_v4(),
+            time: chrono::Utc::now(),
+            user_id: 1,
+            trader_id: 1,
+            pair_id: 1,
+            buy,
+            amount,
+            price,
+            status: true,
+            ok: None,
+            error: None,
+            revision_id: None,
+            fee,
+        }
+    }
+
+    fn ledger(method: CostMethod, trades: &[Trade]) -> Ledger {
+        let mut ledger = Ledger::default();
+        for t in trades {
+            ledger.apply(t, method);
+        }
+        ledger
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn fifo_sells_oldest_lots_first() {
+        let ledger = ledger(CostMethod::Fifo, &[
+            trade(true, 1.0, 10.0, 0.1),
+            trade(true, 1.0, 20.0, 0.1),
+            trade(false, 1.5, 30.0, 0.2),
+        ]);
+        // 1 at 10 and 0.5 at 20 sold at 30
+        assert!(close(ledger.realized, 20.0 + 5.0));
+        assert!(close(ledger.amount(), 0.5));
+        assert!(close(ledger.cost(), 10.0));
+        assert!(close(ledger.unrealized(40.0), 10.0));
+        assert!(close(ledger.fees, 0.4));
+    }
+
+    #[test]
+    fn average_merges_lots() {
+        let ledger = ledger(CostMethod::Average, &[
+            trade(true, 1.0, 10.0, 0.0),
+            trade(true, 1.0, 20.0, 0.0),
+            trade(false, 1.5, 30.0, 0.0),
+        ]);
+        // Average price of 15
+        assert_eq!(ledger.lots.len(), 1);
+        assert!(close(ledger.realized, 1.5 * 15.0));
+        assert!(close(ledger.amount(), 0.5));
+        assert!(close(ledger.cost(), 7.5));
+        assert!(close(ledger.unrealized(15.0), 0.0));
+    }
+
+    #[test]
+    fn sales_above_holdings_are_not_accounted() {
+        for method in [CostMethod::Fifo, CostMethod::Average].iter() {
+            let mut ledger = ledger(*method, &[
+                trade(true, 1.0, 10.0, 0.0),
+                trade(false, 3.0, 12.0, 0.5),
+            ]);
+            assert!(close(ledger.realized, 2.0), "{:?}", method);
+            assert!(close(ledger.amount(), 0.0), "{:?}", method);
+            assert!(ledger.lots.is_empty(), "{:?}", method);
+            assert!(close(ledger.fees, 0.5), "{:?}", method);
+
+            // Later purchases start from an empty position
+            ledger.apply(&trade(true, 2.0, 5.0, 0.0), *method);
+            assert!(close(ledger.amount(), 2.0), "{:?}", method);
+            assert!(close(ledger.cost(), 10.0), "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn selling_without_holdings() {
+        let ledger = ledger(CostMethod::Fifo, &[trade(false, 1.0, 10.0, 0.1)]);
+        assert!(close(ledger.realized, 0.0));
+        assert!(close(ledger.amount(), 0.0));
+        assert!(close(ledger.fees, 0.1));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::prelude::*;
+use crate::schema::{users, assignments, strategies, traders, evaluations};
+use diesel::sql_types::{Integer, BigInt, Text, Nullable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    /// Can see and manage data of all users through `/api/admin`
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s {
+            "user" => Ok(Role::User),
+            "admin" => Ok(Role::Admin),
+            _ => bail!("Invalid role: {}", s),
+        }
+    }
+}
+
+impl User {
+    pub fn role(&self) -> Role {
+        self.role.parse().unwrap_or(Role::User)
+    }
+    pub fn is_admin(&self) -> bool {
+        self.role() == Role::Admin
+    }
+}
+
+/// Changes made to a user by an administrator
+#[derive(AsChangeset, Debug, Default)]
+#[table_name = "users"]
+pub struct UserUpdate {
+    pub role: Option<String>,
+    pub disabled: Option<bool>,
+}
+
+/// Latest stored candle of a pair
+#[derive(Debug, Clone, Serialize, QueryableByName)]
+pub struct IngestStatus {
+    #[sql_type = "Integer"]
+    pub pair_id: i32,
+    #[sql_type = "Text"]
+    pub exchange: String,
+    #[sql_type = "Text"]
+    pub pair: String,
+    #[sql_type = "Nullable<BigInt>"]
+    pub last: Option<i64>,
+}
+
+const INGEST_STATUS_Q: &str = r##"
+select pairs.id as pair_id, pairs.exchange, pairs.pair, last.time as last
+from pairs
+         left join lateral (
+    select time
+    from ohlc
+    where ohlc.pair_id = pairs.id
+    order by time desc
+    limit 1
+    ) last on true
+order by pairs.exchange, pairs.pair
+"##;
+
+impl crate::Database {
+    pub async fn all_users(&self) -> Result<Vec<User>> {
+        self.0.invoke(move |this, ctx| {
+            users::table.order_by(users::id.asc()).load(&this.conn())
+        }).await
+    }
+
+    pub async fn update_user(&self, uid: i32, update: UserUpdate) -> Result<User> {
+        self.0.invoke(move |this, ctx| {
+            diesel::update(users::table.filter(users::id.eq(uid)))
+                .set(&update)
+                .get_result(&this.conn())
+        }).await
+    }
+
+    pub async fn all_strategies(&self) -> Result<Vec<Strategy>> {
+        self.0.invoke(move |this, ctx| {
+            strategies::table.order_by(strategies::id.asc()).load(&this.conn())
+        }).await
+    }
+
+    /// Stops evaluation of a strategy by disabling all of its enabled assignments, returns their number.
+    /// Assignments are kept, so the owner can inspect and re-enable them.
+    pub async fn kill_strategy(&self, sid: i32) -> Result<usize> {
+        self.0.invoke(move |this, ctx| {
+            diesel::update(assignments::table
+                .filter(assignments::strategy_id.eq(sid))
+                .filter(assignments::enabled.eq(true)))
+                .set(assignments::enabled.eq(false))
+                .execute(&this.conn())
+        }).await
+    }
+
+    pub async fn all_assignments(&self) -> Result<Vec<Assignment>> {
+        self.0.invoke(move |this, ctx| {
+            assignments::table.load(&this.conn())
+        }).await
+    }
+
+    pub async fn all_traders(&self) -> Result<Vec<Trader>> {
+        self.0.invoke(move |this, ctx| {
+            traders::table.order_by(traders::id.asc()).load(&this.conn())
+        }).await
+    }
+
+    pub async fn recent_evaluations(&self, limit: i64) -> Result<Vec<Evaluation>> {
+        self.0.invoke(move |this, ctx| {
+            evaluations::table
+                .order_by(evaluations::time.desc())
+                .limit(limit)
+                .load(&this.conn())
+        }).await
+    }
+
+    pub async fn ingest_status(&self) -> Result<Vec<IngestStatus>> {
+        self.0.invoke(move |this, ctx| {
+            diesel::sql_query(INGEST_STATUS_Q).load(&this.conn())
+        }).await
+    }
+}
+
+/// Assigns a role to the user with the given email, used to create the first administrator
+pub fn set_role(mail: &str, role: Role) -> Result<usize, failure::Error> {
+    let conn = PgConnection::establish(&crate::db_url())?;
+    let updated = diesel::update(users::table.filter(users::email.eq(mail)))
+        .set(users::role.eq(role.as_str()))
+        .execute(&conn)?;
+    Ok(updated)
+}
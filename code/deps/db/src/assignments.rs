@@ -1,60 +1,134 @@
 use crate::prelude::*;
 use crate::Database;
-use common::types::Exchange;
+use crate::schema::Pair;
+use common::types::{Exchange, ParamValues};
+
+/// Assignment requested by the user, the evaluated revision is filled in when saving
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssignmentData {
+    pub pair_id: i32,
+    pub user_id: i32,
+    pub period: String,
+    pub strategy_id: i32,
+    pub trader_id: Option<i32>,
+    pub params: ParamValues,
+    pub enabled: bool,
+    pub signal_url: Option<String>,
+    /// Kept unchanged on update when missing, removed together with the URL
+    pub signal_secret: Option<String>,
+}
 
 impl Database {
-    pub fn all_assignments_with_traders(&self) -> LocalBoxFuture<'static, Result<Vec<(Assignment, Option<Trader>)>>> {
+    /// Enabled assignments of active users with their traders and pairs, limited to a single user if given
+    pub fn active_assignments(&self, uid: Option<i32>) -> LocalBoxFuture<'static, Result<Vec<(Assignment, Option<Trader>, Pair)>>> {
         self.0.invoke(move |this, ctx| {
-            use crate::schema::assignments;
-            use crate::schema::traders;
+            use crate::schema::{assignments, traders, pairs, users};
 
             let conn: &ConnType = &this.pool.get().unwrap();
 
-            let res = assignments::table
+            // Assignments of disabled users are not evaluated
+            let active = users::table.filter(users::disabled.eq(false)).select(users::id);
+            let mut q = assignments::table
                 .left_outer_join(traders::table.on(assignments::trader_id.eq(traders::id.nullable())))
-                .load(conn)?;
+                .inner_join(pairs::table)
+                .filter(assignments::user_id.eq_any(active))
+                .filter(assignments::enabled.eq(true))
+                .into_boxed();
+            if let Some(uid) = uid {
+                q = q.filter(assignments::user_id.eq(uid));
+            }
 
-            Ok(res)
+            q.load(conn)
         })
     }
 
     pub async fn assignments(&self, uid: i32) -> Result<Vec<Assignment>> {
         self.0.invoke(move |this, ctx| {
-            referenced_by::<Assignment, User, _>(&uid).load(&this.conn())
+            use crate::schema::assignments;
+
+            referenced_by::<Assignment, User, _>(&uid)
+                .order_by(assignments::created.asc())
+                .load(&this.conn())
         }).await
     }
 
-    pub async fn save_assignment(&self, req: Assignment) -> Result<Assignment> {
+    /// Assigns a strategy owned by the user, pinned to its current revision
+    pub async fn create_assignment(&self, req: AssignmentData) -> Result<Assignment> {
         self.0.invoke(move |this, ctx| {
             use schema::assignments::dsl::*;
             let conn: &ConnType = &this.pool.get().unwrap();
-            let s = diesel::insert_into(assignments)
-                .values(&req)
-                .on_conflict((pair_id, user_id))
-                .do_update()
-                .set((strategy_id.eq(&req.strategy_id), period.eq(&req.period)));
 
-            s.execute(conn)?;
+            let revision = current_revision(conn, req.user_id, req.strategy_id)?;
+            diesel::insert_into(assignments)
+                .values((
+                    pair_id.eq(req.pair_id),
+                    user_id.eq(req.user_id),
+                    period.eq(req.period),
+                    strategy_id.eq(req.strategy_id),
+                    trader_id.eq(req.trader_id),
+                    revision_id.eq(revision),
+                    params.eq(json::to_string(&req.params).expect("Parameter serialization")),
+                    enabled.eq(req.enabled),
+                    signal_url.eq(req.signal_url),
+                    signal_secret.eq(req.signal_secret),
+                ))
+                .get_result(conn)
+        }).await
+    }
 
-            let res = schema::assignments::table.load::<Assignment>(conn)?;
+    /// Replaces the settings of an assignment owned by the user, NotFound if there is no such assignment
+    pub async fn update_assignment(&self, aid: i32, req: AssignmentData) -> Result<Assignment> {
+        self.0.invoke(move |this, ctx| {
+            use schema::assignments::dsl::*;
+            let conn: &ConnType = &this.pool.get().unwrap();
 
-            Ok(req)
+            let revision = current_revision(conn, req.user_id, req.strategy_id)?;
+            let target = assignments.filter(id.eq(aid)).filter(user_id.eq(req.user_id));
+            conn.transaction(|| {
+                let secret = match (&req.signal_url, &req.signal_secret) {
+                    (None, _) => None,
+                    (Some(_), Some(secret)) => Some(secret.clone()),
+                    (Some(_), None) => target.select(signal_secret).get_result::<Option<String>>(conn)?,
+                };
+                diesel::update(target)
+                    .set((
+                        pair_id.eq(req.pair_id),
+                        period.eq(req.period),
+                        strategy_id.eq(req.strategy_id),
+                        trader_id.eq(req.trader_id),
+                        revision_id.eq(revision),
+                        params.eq(json::to_string(&req.params).expect("Parameter serialization")),
+                        enabled.eq(req.enabled),
+                        signal_url.eq(req.signal_url),
+                        signal_secret.eq(secret),
+                    ))
+                    .get_result(conn)
+            })
         }).await
     }
 
-    pub fn delete_assignment(&self, pair: PairId, uid: i32) -> LocalBoxFuture<'static,Result<()>> {
+    /// Removes an assignment owned by the user, returns false if there was no such assignment
+    pub fn delete_assignment(&self, uid: i32, aid: i32) -> LocalBoxFuture<'static, Result<bool>> {
         self.0.invoke(move |this, ctx| {
             use schema::assignments::dsl::*;
             let conn: &ConnType = &this.pool.get().unwrap();
 
-            let pid = schema::pair_id(pair.exch().to_string(), pair.pair().to_string());
-
-            diesel::delete(assignments)
-                .filter(pair_id.eq(pid))
-                .filter(user_id.eq(user_id))
+            let removed = diesel::delete(assignments)
+                .filter(id.eq(aid))
+                .filter(user_id.eq(uid))
                 .execute(conn)?;
 
-            Ok(())
+            Ok(removed > 0)
         })
     }
-}
\ No newline at end of file
+}
+
+/// Current revision of a strategy owned by the user
+fn current_revision(conn: &ConnType, uid: i32, sid: i32) -> Result<i32> {
+    schema::strategies::table
+        .filter(schema::strategies::id.eq(sid))
+        .filter(schema::strategies::user_id.eq(uid))
+        .select(schema::strategies::revision_id)
+        .get_result::<Option<i32>>(conn)?
+        .ok_or(diesel::result::Error::NotFound)
+}
@@ -0,0 +1,116 @@
+use crate::prelude::*;
+use crate::schema::auth_events;
+
+/// Security relevant events, stored in `auth_events`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthEvent {
+    LoginSucceeded,
+    LoginFailed,
+    LoginLocked,
+    LoginDisabled,
+    AccountLocked,
+    RateLimited,
+    SignedUp,
+    SignupRejected,
+    EmailVerified,
+    PasswordResetRequested,
+    PasswordReset,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    TwoFactorFailed,
+    TokenCreated,
+    TokenRevoked,
+    /// Role or disabled flag changed by an administrator
+    UserUpdated,
+    StrategyKilled,
+    /// Candles imported from an uploaded archive
+    OhlcImported,
+}
+
+impl AuthEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthEvent::LoginSucceeded => "login_succeeded",
+            AuthEvent::LoginFailed => "login_failed",
+            AuthEvent::LoginLocked => "login_locked",
+            AuthEvent::LoginDisabled => "login_disabled",
+            AuthEvent::AccountLocked => "account_locked",
+            AuthEvent::RateLimited => "rate_limited",
+            AuthEvent::SignedUp => "signed_up",
+            AuthEvent::SignupRejected => "signup_rejected",
+            AuthEvent::EmailVerified => "email_verified",
+            AuthEvent::PasswordResetRequested => "password_reset_requested",
+            AuthEvent::PasswordReset => "password_reset",
+            AuthEvent::TwoFactorEnabled => "two_factor_enabled",
+            AuthEvent::TwoFactorDisabled => "two_factor_disabled",
+            AuthEvent::TwoFactorFailed => "two_factor_failed",
+            AuthEvent::TokenCreated => "token_created",
+            AuthEvent::TokenRevoked => "token_revoked",
+            AuthEvent::UserUpdated => "user_updated",
+            AuthEvent::StrategyKilled => "strategy_killed",
+            AuthEvent::OhlcImported => "ohlc_imported",
+        }
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "auth_events"]
+struct NewAuthEvent {
+    user_id: Option<i32>,
+    email: Option<String>,
+    event: String,
+    ip: Option<String>,
+}
+
+impl crate::Database {
+    pub async fn log_auth_event(&self, event: AuthEvent, uid: Option<i32>, email: Option<String>, ip: Option<String>) -> Result<()> {
+        let data = NewAuthEvent {
+            user_id: uid,
+            email,
+            event: event.as_str().to_string(),
+            ip,
+        };
+        self.0.invoke(move |this, ctx| {
+            diesel::insert_into(auth_events::table)
+                .values(&data)
+                .execute(&this.conn())?;
+            Ok(())
+        }).await
+    }
+
+    /// Counts a failed login, and locks the account once `max_failures` is reached.
+    /// Returns the end of the lockout if the account got locked by this failure.
+    pub async fn record_failed_login(&self, uid: i32, max_failures: i32, lockout: chrono::Duration) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::users::dsl::*;
+
+            let conn = this.conn();
+            conn.transaction(|| {
+                let failures = diesel::update(users.filter(id.eq(uid)))
+                    .set(failed_logins.eq(failed_logins + 1))
+                    .returning(failed_logins)
+                    .get_result::<i32>(&conn)?;
+
+                if failures < max_failures {
+                    return Ok(None);
+                }
+                let until = chrono::Utc::now() + lockout;
+                diesel::update(users.filter(id.eq(uid)))
+                    .set((failed_logins.eq(0), locked_until.eq(until)))
+                    .execute(&conn)?;
+                Ok(Some(until))
+            })
+        }).await
+    }
+
+    pub async fn reset_failed_logins(&self, uid: i32) -> Result<()> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::users::dsl::*;
+
+            diesel::update(users.filter(id.eq(uid)))
+                .set((failed_logins.eq(0), locked_until.eq(None::<chrono::DateTime<chrono::Utc>>)))
+                .execute(&this.conn())?;
+            Ok(())
+        }).await
+    }
+}
@@ -0,0 +1,247 @@
+//! Exports stored candles for offline research, as CSV files or Parquet datasets.
+//!
+//! Usage: `export_ohlc <directory> [options]`, e.g.
+//! `export_ohlc ./data --pair bitfinex/BTC:USD --period 1h --period 1d --format parquet --incremental`
+use common::prelude::*;
+use common::types::{Ohlc, OhlcPeriod};
+use db::{OhlcReader, Pair};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+mod parquet;
+
+const USAGE: &str = "Usage: export_ohlc <directory> [options]
+
+Options:
+    --pair <exchange/pair>   Pair to export, e.g. bitfinex/BTC:USD, can be repeated, all pairs by default
+    --period <period>        Period of exported candles, can be repeated, 1m by default
+    --from <time>            Unix time in seconds, the start of stored history by default
+    --to <time>              Unix time in seconds, exclusive, now by default
+    --format <format>        csv or parquet, csv by default
+    --backfill               Fill missing candles with flat ones at the previous close
+    --incremental            Only export candles following the previous export into the directory";
+
+/// Candles are loaded in windows of this length, rounded up to whole periods
+const LOAD_WINDOW: i64 = 30 * 24 * 60 * 60;
+/// Records the last exported candle of each dataset in the directory
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Csv,
+    Parquet,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Exported {
+    format: Format,
+    backfill: bool,
+    /// Time of the last exported candle
+    last: i64,
+}
+
+struct Args {
+    dir: PathBuf,
+    pairs: Vec<String>,
+    periods: Vec<OhlcPeriod>,
+    from: Option<i64>,
+    to: Option<i64>,
+    format: Format,
+    backfill: bool,
+    incremental: bool,
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args> {
+    let mut dir = None;
+    let mut parsed = Args {
+        dir: PathBuf::new(),
+        pairs: vec![],
+        periods: vec![],
+        from: None,
+        to: None,
+        format: Format::Csv,
+        backfill: false,
+        incremental: false,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format_err!("Missing value of {}", arg));
+        match arg.as_str() {
+            "--pair" => {
+                let pair = value()?;
+                if !parsed.pairs.contains(&pair) {
+                    parsed.pairs.push(pair);
+                }
+            }
+            "--period" => {
+                let period = value()?;
+                let period = OhlcPeriod::from_str(&period)
+                    .map_err(|_| format_err!("Unknown period {}, supported are {}", period, OhlcPeriod::NAMES.join(", ")))?;
+                if !parsed.periods.contains(&period) {
+                    parsed.periods.push(period);
+                }
+            }
+            "--from" => parsed.from = Some(value()?.parse().map_err(|_| format_err!("From must be a unix time"))?),
+            "--to" => parsed.to = Some(value()?.parse().map_err(|_| format_err!("To must be a unix time"))?),
+            "--format" => parsed.format = match value()?.as_str() {
+                "csv" => Format::Csv,
+                "parquet" => Format::Parquet,
+                other => bail!("Unknown format {}", other),
+            },
+            "--backfill" => parsed.backfill = true,
+            "--incremental" => parsed.incremental = true,
+            flag if flag.starts_with("--") => bail!("Unknown option {}", flag),
+            _ if dir.is_none() => dir = Some(PathBuf::from(arg.clone())),
+            _ => bail!("Unexpected argument {}", arg),
+        }
+    }
+    parsed.dir = dir.ok_or_else(|| format_err!("Missing the export directory"))?;
+    if parsed.periods.is_empty() {
+        parsed.periods.push(OhlcPeriod::Min1);
+    }
+    Ok(parsed)
+}
+
+/// Name of the files of a pair and period, e.g. `bitfinex_BTCUSD_1h`
+fn dataset_name(pair: &Pair, period: OhlcPeriod) -> String {
+    format!("{}_{}_{}", pair.exchange, pair.pair.replace(':', ""), period.to_string())
+}
+
+fn load_manifest(dir: &Path) -> Result<BTreeMap<String, Exported>> {
+    match fs::read_to_string(dir.join(MANIFEST)) {
+        Ok(data) => Ok(json::from_str(&data)?),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_manifest(dir: &Path, manifest: &BTreeMap<String, Exported>) -> Result<()> {
+    let tmp = dir.join(format!("{}.tmp", MANIFEST));
+    fs::write(&tmp, json::to_string_pretty(manifest)?)?;
+    fs::rename(tmp, dir.join(MANIFEST))?;
+    Ok(())
+}
+
+/// Loads candles of the pair rescaled to the period, with times from `from` up to `to`
+fn load(reader: &OhlcReader, pair: &Pair, period: OhlcPeriod, from: i64, to: i64, backfill: bool) -> Result<Vec<Ohlc>> {
+    let window = (LOAD_WINDOW + period.seconds() - 1) / period.seconds() * period.seconds();
+    let mut candles = vec![];
+    let mut start = from;
+    while start < to {
+        let end = i64::min(start + window, to);
+        let minutes = reader.minutes(pair.id, start, end)?;
+        candles.extend(Ohlc::rescale(minutes.into_iter(), period));
+        start = end;
+    }
+    if backfill {
+        candles = Ohlc::backfill(candles.into_iter(), period);
+    }
+    Ok(candles)
+}
+
+fn write_csv(path: &Path, candles: &[Ohlc], append: bool) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(path)?;
+    let mut out = String::new();
+    if !append {
+        out.push_str("time,open,high,low,close,vol\n");
+    }
+    for c in candles {
+        out.push_str(&format!("{},{},{},{},{},{}\n", c.time, c.open, c.high, c.low, c.close, c.vol));
+    }
+    file.write_all(out.as_bytes())?;
+    Ok(())
+}
+
+/// Parquet datasets are directories of part files, increments are added as new parts
+fn write_parquet(dir: &Path, candles: &[Ohlc], append: bool) -> Result<()> {
+    if !append && dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::create_dir_all(dir)?;
+    let mut file = fs::File::create(dir.join(format!("part-{}.parquet", candles[0].time)))?;
+    parquet::write(&mut file, candles)?;
+    Ok(())
+}
+
+fn export(args: &Args, reader: &OhlcReader, pair: &Pair, period: OhlcPeriod, manifest: &mut BTreeMap<String, Exported>) -> Result<()> {
+    let name = dataset_name(pair, period);
+    let path = match args.format {
+        Format::Csv => args.dir.join(format!("{}.csv", name)),
+        Format::Parquet => args.dir.join(&name),
+    };
+
+    let previous = match manifest.get(&name) {
+        Some(prev) if args.incremental && path.exists() => {
+            if prev.format != args.format || prev.backfill != args.backfill {
+                bail!("{} was exported with other format or backfilling, export it again without --incremental", name);
+            }
+            Some(prev.last)
+        }
+        _ => None,
+    };
+
+    let (first, last) = match reader.bounds(pair.id)? {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            println!("{} : no stored candles", name);
+            return Ok(());
+        }
+    };
+    // The last exported candle is loaded again, so backfilling can continue from it
+    let from = period.clamp_time(previous.unwrap_or(i64::max(args.from.unwrap_or(first), first)));
+    // Only whole periods are exported, the current one is left for the next increment
+    let now = chrono::Utc::now().timestamp();
+    let to = period.clamp_time(i64::min(args.to.unwrap_or(now), i64::min(last + 60, now)));
+
+    let mut candles = load(reader, pair, period, from, to, args.backfill)?;
+    if let Some(previous) = previous {
+        candles.retain(|c| c.time > previous);
+    }
+    if candles.is_empty() {
+        println!("{} : up to date", name);
+        return Ok(());
+    }
+
+    let append = previous.is_some();
+    match args.format {
+        Format::Csv => write_csv(&path, &candles, append)?,
+        Format::Parquet => write_parquet(&path, &candles, append)?,
+    }
+    let last = candles[candles.len() - 1].time;
+    println!("{} : exported {} candles up to {}", name, candles.len(), chrono::NaiveDateTime::from_timestamp(last, 0));
+
+    manifest.insert(name, Exported { format: args.format, backfill: args.backfill, last });
+    save_manifest(&args.dir, manifest)
+}
+
+fn main() -> Result<(), failure::Error> {
+    common::init();
+    let args = match parse_args(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => bail!("{}\n\n{}", e, USAGE),
+    };
+    fs::create_dir_all(&args.dir)?;
+
+    let reader = OhlcReader::connect()?;
+    let pairs = reader.pairs()?.into_iter()
+        .filter(|p| args.pairs.is_empty() || args.pairs.contains(&format!("{}/{}", p.exchange, p.pair)))
+        .collect::<Vec<_>>();
+    let missing = args.pairs.iter()
+        .filter(|name| !pairs.iter().any(|p| format!("{}/{}", p.exchange, p.pair) == **name))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        bail!("Pairs {} are not stored, known pairs are listed by /api/pairs", missing.join(", "));
+    }
+
+    let mut manifest = load_manifest(&args.dir)?;
+    for pair in pairs.iter() {
+        for &period in args.periods.iter() {
+            export(&args, &reader, pair, period, &mut manifest)?;
+        }
+    }
+    Ok(())
+}
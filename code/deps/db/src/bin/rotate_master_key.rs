@@ -0,0 +1,26 @@
+//! Re-encrypts data keys of all traders with a new master key.
+//! Current key is taken from the configuration, the new one from `NEW_MASTER_KEY` or `NEW_MASTER_KEY_FILE`.
+use common::prelude::*;
+use db::MasterKey;
+
+fn new_key() -> Result<MasterKey, failure::Error> {
+    let encoded = match env::var("NEW_MASTER_KEY") {
+        Ok(key) => key,
+        Err(_) => {
+            let path = env::var("NEW_MASTER_KEY_FILE")
+                .map_err(|_| format_err!("NEW_MASTER_KEY or NEW_MASTER_KEY_FILE is required"))?;
+            std::fs::read_to_string(path)?
+        }
+    };
+    MasterKey::from_base64(&encoded)
+}
+
+fn main() -> Result<(), failure::Error> {
+    common::init();
+    let old = MasterKey::configured()?;
+    let new = new_key()?;
+
+    let count = db::rotate_master_key(&old, &new)?;
+    println!("Rotated master key of {} traders", count);
+    Ok(())
+}
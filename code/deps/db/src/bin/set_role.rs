@@ -0,0 +1,18 @@
+//! Assigns a role to a user, e.g. `set_role admin@example.com admin` to create the first administrator.
+use common::prelude::*;
+use db::Role;
+
+fn main() -> Result<(), failure::Error> {
+    common::init();
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() != 2 {
+        bail!("Usage: set_role <email> <user|admin>");
+    }
+
+    let role = args[1].parse::<Role>()?;
+    match db::set_role(&args[0], role)? {
+        0 => bail!("No user with email {}", args[0]),
+        _ => println!("{} is now {}", args[0], role.as_str()),
+    }
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use crate::prelude::*;
+use crate::schema::traders;
+use common::types::auth::AuthInfo;
+use ring::{aead, rand::{SecureRandom, SystemRandom}};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Key encrypting the data keys of traders, configured in `database.master_key`
+#[derive(Clone)]
+pub struct MasterKey([u8; KEY_LEN]);
+
+impl MasterKey {
+    pub fn from_base64(encoded: &str) -> Result<Self, failure::Error> {
+        let bytes = base64::decode(encoded.trim())?;
+        if bytes.len() != KEY_LEN {
+            bail!("Master key must be {} bytes long, got {}", KEY_LEN, bytes.len());
+        }
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(&bytes);
+        Ok(MasterKey(key))
+    }
+
+    pub fn configured() -> Result<Self, failure::Error> {
+        let key = &common::config().database.master_key;
+        if key.is_empty() {
+            bail!("database.master_key is not configured");
+        }
+        Self::from_base64(key.expose())
+    }
+
+    /// Encrypts a small value directly by the master key, for secrets that are not exchange credentials
+    pub(crate) fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, failure::Error> {
+        seal(&self.0, data)
+    }
+
+    pub(crate) fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, failure::Error> {
+        open(&self.0, data)
+    }
+}
+
+impl Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MasterKey(***)")
+    }
+}
+
+/// Exchange credentials encrypted by a random data key, which is itself encrypted by the master key.
+/// Rotating the master key only requires re-encrypting the data key.
+#[derive(Insertable, AsChangeset, Debug, Clone)]
+#[table_name = "traders"]
+pub(crate) struct SealedCredentials {
+    pub data_key: Option<Vec<u8>>,
+    pub api_key: Vec<u8>,
+    pub api_secret: Vec<u8>,
+}
+
+impl SealedCredentials {
+    pub fn seal(master: &MasterKey, info: &AuthInfo) -> Result<Self, failure::Error> {
+        let mut data_key = [0u8; KEY_LEN];
+        SystemRandom::new().fill(&mut data_key).map_err(|_| format_err!("Could not generate data key"))?;
+
+        Ok(SealedCredentials {
+            data_key: Some(seal(&master.0, &data_key)?),
+            api_key: seal(&data_key, info.key.as_bytes())?,
+            api_secret: seal(&data_key, info.secret.as_bytes())?,
+        })
+    }
+
+    pub fn open(&self, master: &MasterKey) -> Result<AuthInfo, failure::Error> {
+        let data_key = self.data_key.as_ref().ok_or_else(|| format_err!("Credentials are not encrypted"))?;
+        let data_key = open(&master.0, data_key)?;
+
+        Ok(AuthInfo {
+            key: String::from_utf8(open(&data_key, &self.api_key)?)?,
+            secret: String::from_utf8(open(&data_key, &self.api_secret)?)?,
+        })
+    }
+
+    /// Re-encrypts the data key with a new master key, the credentials themselves are unchanged
+    pub fn rewrap(&mut self, old: &MasterKey, new: &MasterKey) -> Result<(), failure::Error> {
+        let data_key = self.data_key.as_ref().ok_or_else(|| format_err!("Credentials are not encrypted"))?;
+        let data_key = open(&old.0, data_key)?;
+        self.data_key = Some(seal(&new.0, &data_key)?);
+        Ok(())
+    }
+}
+
+impl From<Trader> for SealedCredentials {
+    fn from(trader: Trader) -> Self {
+        SealedCredentials {
+            data_key: trader.data_key,
+            api_key: trader.api_key,
+            api_secret: trader.api_secret,
+        }
+    }
+}
+
+/// Encrypts data with AES-256-GCM, the random nonce is prepended to the ciphertext
+fn seal(key: &[u8], data: &[u8]) -> Result<Vec<u8>, failure::Error> {
+    let key = aead::SealingKey::new(&aead::AES_256_GCM, key).map_err(|_| format_err!("Invalid key"))?;
+
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|_| format_err!("Could not generate nonce"))?;
+
+    let mut buf = data.to_vec();
+    buf.extend_from_slice(&[0u8; aead::MAX_TAG_LEN]);
+    let len = aead::seal_in_place(&key, &nonce, &[], &mut buf, aead::MAX_TAG_LEN)
+        .map_err(|_| format_err!("Encryption failed"))?;
+    buf.truncate(len);
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(buf);
+    Ok(sealed)
+}
+
+fn open(key: &[u8], sealed: &[u8]) -> Result<Vec<u8>, failure::Error> {
+    let key = aead::OpeningKey::new(&aead::AES_256_GCM, key).map_err(|_| format_err!("Invalid key"))?;
+    if sealed.len() < NONCE_LEN + aead::MAX_TAG_LEN {
+        bail!("Encrypted value is too short");
+    }
+
+    let (nonce, data) = sealed.split_at(NONCE_LEN);
+    let mut buf = data.to_vec();
+    let plain = aead::open_in_place(&key, nonce, &[], 0, &mut buf)
+        .map_err(|_| format_err!("Decryption failed, wrong key or corrupted value"))?;
+    Ok(plain.to_vec())
+}
@@ -30,6 +30,18 @@ mod users;
 mod traders;
 mod strategies;
 mod assignments;
+mod outbox;
+mod crypto;
+mod tokens;
+mod two_factor;
+mod audit;
+mod admin;
+mod optimizations;
+mod risk;
+mod accounting;
+mod notifications;
+mod signals;
+mod signal_hooks;
 
 use crate::prelude::*;
 
@@ -42,9 +54,20 @@ pub use crate::users::*;
 pub use crate::traders::*;
 pub use crate::strategies::*;
 pub use crate::assignments::*;
+pub use crate::outbox::*;
+pub use crate::crypto::MasterKey;
+pub use crate::tokens::*;
+pub use crate::audit::*;
+pub use crate::admin::*;
+pub use crate::optimizations::*;
+pub use crate::risk::*;
+pub use crate::accounting::*;
+pub use crate::notifications::*;
+pub use crate::signals::*;
+pub use crate::signal_hooks::*;
 
 fn db_url() -> String {
-    format!("postgres://{}:{}@postgres.default.svc:5432/{}", "trader", "trader", "trader")
+    common::config().database.url()
 }
 
 pub fn init_store() {
@@ -55,6 +78,12 @@ pub fn init_store() {
 
     embedded_migrations::run(&connection).unwrap();
     info!("Migrations performed");
+
+    match traders::seal_plaintext_credentials(&connection) {
+        Ok(0) => {}
+        Ok(count) => info!("Encrypted credentials of {} traders", count),
+        Err(e) => warn!("Could not encrypt stored credentials: {}", e),
+    }
 }
 
 use diesel::r2d2::{PooledConnection, Pool, ConnectionManager};
@@ -73,7 +102,7 @@ impl DbWorker {
 }
 
 pub fn start() -> Database {
-    with_size(8)
+    with_size(common::config().database.pool_size)
 }
 
 fn with_size(count: usize) -> Database {
@@ -0,0 +1,361 @@
+use crate::prelude::*;
+use crate::schema::{notification_channels, notification_rules, notifications, assignments, traders, users, stale_feeds};
+use crate::tokens::{generate_token, hash_token};
+
+/// Claimed delivery is handed to another sender if not finished in this time
+pub(crate) const CLAIM_SECONDS: i64 = 120;
+/// Longest delay between two delivery attempts, in seconds
+const MAX_BACKOFF: i64 = 3600;
+/// Delivery is given up after this many failed attempts
+pub const MAX_NOTIFY_ATTEMPTS: i32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    TradeExecuted,
+    EvaluationError,
+    TraderAuthFailed,
+    /// No candles were received for a pair used by an assignment of the user
+    FeedStale,
+    /// Requested by the user to check a channel
+    Test,
+}
+
+impl NotifyEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotifyEvent::TradeExecuted => "trade_executed",
+            NotifyEvent::EvaluationError => "evaluation_error",
+            NotifyEvent::TraderAuthFailed => "trader_auth_failed",
+            NotifyEvent::FeedStale => "feed_stale",
+            NotifyEvent::Test => "test",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelKind {
+    Email,
+    Webhook,
+    /// Telegram style bot API, messages are sent to the chat in `target`
+    Bot,
+}
+
+impl ChannelKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelKind::Email => "email",
+            ChannelKind::Webhook => "webhook",
+            ChannelKind::Bot => "bot",
+        }
+    }
+}
+
+impl FromStr for ChannelKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> StdResult<Self, ()> {
+        match s {
+            "email" => Ok(ChannelKind::Email),
+            "webhook" => Ok(ChannelKind::Webhook),
+            "bot" => Ok(ChannelKind::Bot),
+            _ => Err(()),
+        }
+    }
+}
+
+impl NotificationChannel {
+    pub fn channel_kind(&self) -> Option<ChannelKind> {
+        ChannelKind::from_str(&self.kind).ok()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationChannelData {
+    pub kind: ChannelKind,
+    pub target: String,
+    pub token: Option<String>,
+}
+
+/// Delay before the next delivery attempt, doubled with every failed one
+pub(crate) fn retry_delay(attempts: i32) -> chrono::Duration {
+    chrono::Duration::seconds(i64::min(MAX_BACKOFF, 30 << attempts.max(0).min(16)))
+}
+
+/// Creates a delivery for every enabled channel the user routed the event to, returns their number
+pub(crate) fn notify_with(conn: &PgConnection, uid: i32, event: NotifyEvent, subject: &str, body: &str) -> Result<usize> {
+    let channels = notification_rules::table
+        .inner_join(notification_channels::table)
+        .filter(notification_rules::user_id.eq(uid))
+        .filter(notification_rules::event.eq(event.as_str()))
+        .filter(notification_channels::enabled.eq(true))
+        .filter(notification_channels::verified.eq(true))
+        .select(notification_channels::id)
+        .load::<i32>(conn)?;
+    if channels.is_empty() {
+        return Ok(0);
+    }
+
+    let rows = channels.into_iter()
+        .map(|cid| (
+            notifications::user_id.eq(uid),
+            notifications::channel_id.eq(cid),
+            notifications::event.eq(event.as_str()),
+            notifications::subject.eq(subject),
+            notifications::body.eq(body),
+        ))
+        .collect::<Vec<_>>();
+    diesel::insert_into(notifications::table)
+        .values(&rows)
+        .execute(conn)
+}
+
+impl crate::Database {
+    pub fn notify(&self, uid: i32, event: NotifyEvent, subject: impl Into<String>, body: impl Into<String>) -> LocalBoxFuture<'static, Result<usize>> {
+        let (subject, body) = (subject.into(), body.into());
+        self.0.invoke(move |this, ctx| {
+            notify_with(&this.conn(), uid, event, &subject, &body)
+        })
+    }
+
+    /// Notifies the owner of the trader
+    pub fn notify_trader_owner(&self, tid: i32, event: NotifyEvent, subject: impl Into<String>, body: impl Into<String>) -> LocalBoxFuture<'static, Result<usize>> {
+        let (subject, body) = (subject.into(), body.into());
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            let uid = traders::table
+                .find(tid)
+                .select(traders::user_id)
+                .get_result::<i32>(&conn)?;
+            notify_with(&conn, uid, event, &subject, &body)
+        })
+    }
+
+    /// Users with enabled assignments on the pair
+    pub fn pair_subscribers(&self, pid: i32) -> LocalBoxFuture<'static, Result<Vec<i32>>> {
+        self.0.invoke(move |this, ctx| {
+            assignments::table
+                .filter(assignments::pair_id.eq(pid))
+                .filter(assignments::enabled.eq(true))
+                .select(assignments::user_id)
+                .distinct()
+                .load(&this.conn())
+        })
+    }
+
+    pub async fn notification_channels(&self, uid: i32) -> Result<Vec<NotificationChannel>> {
+        self.0.invoke(move |this, ctx| {
+            notification_channels::table
+                .filter(notification_channels::user_id.eq(uid))
+                .order_by(notification_channels::created.asc())
+                .load(&this.conn())
+        }).await
+    }
+
+    /// Creates the channel, email channels sending to another address than the verified one of the user
+    /// also get a token, which has to be mailed to the address and passed to `verify_notification_channel`
+    pub async fn create_notification_channel(&self, uid: i32, data: NotificationChannelData) -> Result<(NotificationChannel, Option<String>)> {
+        let token = generate_token();
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            let token = if data.kind == ChannelKind::Email {
+                let (email, verified) = users::table
+                    .find(uid)
+                    .select((users::email, users::has_verified_email))
+                    .get_result::<(String, bool)>(&conn)?;
+                if verified && email.eq_ignore_ascii_case(data.target.trim()) { None } else { Some(token) }
+            } else {
+                None
+            };
+
+            let channel = diesel::insert_into(notification_channels::table)
+                .values((
+                    notification_channels::user_id.eq(uid),
+                    notification_channels::kind.eq(data.kind.as_str()),
+                    notification_channels::target.eq(data.target),
+                    notification_channels::token.eq(data.token),
+                    notification_channels::verified.eq(token.is_none()),
+                    notification_channels::verify_hash.eq(token.as_ref().map(|t| hash_token(t))),
+                ))
+                .get_result(&conn)?;
+            Ok((channel, token))
+        }).await
+    }
+
+    /// Marks the channel the token was sent for as verified, None if the token is not pending
+    pub async fn verify_notification_channel(&self, token: String) -> Result<Option<NotificationChannel>> {
+        self.0.invoke(move |this, ctx| {
+            diesel::update(notification_channels::table.filter(notification_channels::verify_hash.eq(hash_token(&token))))
+                .set((
+                    notification_channels::verified.eq(true),
+                    notification_channels::verify_hash.eq(None::<Vec<u8>>),
+                ))
+                .get_result(&this.conn())
+                .optional()
+        }).await
+    }
+
+    pub async fn delete_notification_channel(&self, uid: i32, cid: i32) -> Result<bool> {
+        self.0.invoke(move |this, ctx| {
+            let removed = diesel::delete(notification_channels::table)
+                .filter(notification_channels::id.eq(cid))
+                .filter(notification_channels::user_id.eq(uid))
+                .execute(&this.conn())?;
+            Ok(removed > 0)
+        }).await
+    }
+
+    pub async fn notification_rules(&self, uid: i32) -> Result<Vec<NotificationRule>> {
+        self.0.invoke(move |this, ctx| {
+            notification_rules::table
+                .filter(notification_rules::user_id.eq(uid))
+                .order_by(notification_rules::id.asc())
+                .load(&this.conn())
+        }).await
+    }
+
+    /// Routes the event to a channel of the user, NotFound if the user doesn't own the channel
+    pub async fn create_notification_rule(&self, uid: i32, event: NotifyEvent, cid: i32) -> Result<NotificationRule> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            notification_channels::table
+                .filter(notification_channels::id.eq(cid))
+                .filter(notification_channels::user_id.eq(uid))
+                .select(notification_channels::id)
+                .get_result::<i32>(&conn)?;
+
+            diesel::insert_into(notification_rules::table)
+                .values((
+                    notification_rules::user_id.eq(uid),
+                    notification_rules::event.eq(event.as_str()),
+                    notification_rules::channel_id.eq(cid),
+                ))
+                .on_conflict_do_nothing()
+                .execute(&conn)?;
+            notification_rules::table
+                .filter(notification_rules::event.eq(event.as_str()))
+                .filter(notification_rules::channel_id.eq(cid))
+                .get_result(&conn)
+        }).await
+    }
+
+    pub async fn delete_notification_rule(&self, uid: i32, rid: i32) -> Result<bool> {
+        self.0.invoke(move |this, ctx| {
+            let removed = diesel::delete(notification_rules::table)
+                .filter(notification_rules::id.eq(rid))
+                .filter(notification_rules::user_id.eq(uid))
+                .execute(&this.conn())?;
+            Ok(removed > 0)
+        }).await
+    }
+
+    /// Sends a test notification to a channel of the user, regardless of the rules.
+    /// None if the channel is not verified yet.
+    pub async fn test_notification_channel(&self, uid: i32, cid: i32) -> Result<Option<Notification>> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            let verified = notification_channels::table
+                .filter(notification_channels::id.eq(cid))
+                .filter(notification_channels::user_id.eq(uid))
+                .select(notification_channels::verified)
+                .get_result::<bool>(&conn)?;
+            if !verified {
+                return Ok(None);
+            }
+
+            diesel::insert_into(notifications::table)
+                .values((
+                    notifications::user_id.eq(uid),
+                    notifications::channel_id.eq(cid),
+                    notifications::event.eq(NotifyEvent::Test.as_str()),
+                    notifications::subject.eq("Test notification"),
+                    notifications::body.eq("Notifications sent to this channel are delivered."),
+                ))
+                .get_result(&conn)
+                .map(Some)
+        }).await
+    }
+
+    pub async fn notifications(&self, uid: i32, limit: i64) -> Result<Vec<Notification>> {
+        self.0.invoke(move |this, ctx| {
+            notifications::table
+                .filter(notifications::user_id.eq(uid))
+                .order_by(notifications::created.desc())
+                .limit(limit)
+                .load(&this.conn())
+        }).await
+    }
+
+    /// Pending notifications with their channels. They are hidden from other notifiers for a while,
+    /// and have to be marked as delivered or failed.
+    pub fn claim_notifications(&self, limit: i64) -> LocalBoxFuture<'static, Result<Vec<(Notification, NotificationChannel)>>> {
+        self.0.invoke(move |this, ctx| {
+            let conn: &ConnType = &this.pool.get().unwrap();
+            conn.transaction(|| {
+                let pending = notifications::table
+                    .inner_join(notification_channels::table)
+                    .filter(notifications::delivered.is_null())
+                    .filter(notifications::attempts.lt(MAX_NOTIFY_ATTEMPTS))
+                    .filter(notifications::next_attempt.le(diesel::dsl::now))
+                    .filter(notification_channels::verified.eq(true))
+                    .order_by(notifications::created.asc())
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .load::<(Notification, NotificationChannel)>(conn)?;
+
+                let ids = pending.iter().map(|(n, _)| n.id).collect::<Vec<_>>();
+                diesel::update(notifications::table.filter(notifications::id.eq_any(ids)))
+                    .set(notifications::next_attempt.eq(chrono::Utc::now() + chrono::Duration::seconds(CLAIM_SECONDS)))
+                    .execute(conn)?;
+                Ok(pending)
+            })
+        })
+    }
+
+    pub fn notification_delivered(&self, nid: uuid::Uuid) -> LocalBoxFuture<'static, Result<()>> {
+        self.0.invoke(move |this, ctx| {
+            diesel::update(notifications::table.find(nid))
+                .set((
+                    notifications::delivered.eq(chrono::Utc::now()),
+                    notifications::error.eq(None::<String>),
+                ))
+                .execute(&this.conn())?;
+            Ok(())
+        })
+    }
+
+    /// Records a failed attempt, the next one is delayed exponentially
+    pub fn notification_failed(&self, note: Notification, reason: String) -> LocalBoxFuture<'static, Result<()>> {
+        self.0.invoke(move |this, ctx| {
+            diesel::update(notifications::table.find(note.id))
+                .set((
+                    notifications::attempts.eq(note.attempts + 1),
+                    notifications::next_attempt.eq(chrono::Utc::now() + retry_delay(note.attempts)),
+                    notifications::error.eq(reason),
+                ))
+                .execute(&this.conn())?;
+            Ok(())
+        })
+    }
+
+    /// Records that the feed of the pair was reported as stale, false if it already was
+    pub fn report_stale_feed(&self, pid: i32) -> LocalBoxFuture<'static, Result<bool>> {
+        self.0.invoke(move |this, ctx| {
+            let inserted = diesel::insert_into(stale_feeds::table)
+                .values(stale_feeds::pair_id.eq(pid))
+                .on_conflict_do_nothing()
+                .execute(&this.conn())?;
+            Ok(inserted > 0)
+        })
+    }
+
+    /// Forgets reports of the pairs, whose feeds are receiving candles again
+    pub fn clear_stale_feeds(&self, pids: Vec<i32>) -> LocalBoxFuture<'static, Result<usize>> {
+        self.0.invoke(move |this, ctx| {
+            diesel::delete(stale_feeds::table.filter(stale_feeds::pair_id.eq_any(pids)))
+                .execute(&this.conn())
+        })
+    }
+}
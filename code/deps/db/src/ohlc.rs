@@ -1,7 +1,8 @@
 use crate::prelude::*;
-use crate::schema::{self, ohlc, Pair};
+use crate::schema::{self, ohlc, pairs, Pair};
 use common::types::Exchange;
 use diesel::select;
+use diesel::sql_types::Int4;
 
 
 #[derive(PartialEq, Debug, Clone, Queryable, QueryableByName)]
@@ -59,6 +60,106 @@ select * from ohlc join bound_vals
                         on ohlc.pair_id = bound_vals.pair_id and ohlc.time = bound_vals.time
 "##;
 
+/// Candles rescaled in the database, the open and close are taken from the first and last minute of each bucket
+const RANGE_Q: &'static str = r##"
+select pair_id,
+       time / $2 * $2                           as time,
+       (array_agg(open order by time))[1]       as open,
+       max(high)                                as high,
+       min(low)                                 as low,
+       (array_agg(close order by time desc))[1] as close,
+       sum(vol)                                 as vol
+from ohlc
+where pair_id = $1 and time >= $3 and time < $4
+group by 1, 2
+order by 2
+limit $5
+"##;
+
+const GAPS_Q: &'static str = r##"
+select time + 60 as first_missing, next - 60 as last_missing
+from (
+    select time, lead(time) over (order by time) as next
+    from ohlc
+    where pair_id = $1 and time >= $2 and time < $3
+) minutes
+where next - time > 60
+order by next - time desc
+limit $4
+"##;
+
+/// End of the first `buckets` buckets of the period starting at the one containing `from`, or `to` if it's earlier.
+/// Keeps range queries from aggregating more of the table than the limit can return.
+pub fn ohlc_range_end(period: OhlcPeriod, from: i64, to: i64, buckets: i64) -> i64 {
+    period.clamp_time(from)
+        .saturating_add(period.seconds().saturating_mul(buckets))
+        .min(to)
+}
+
+/// Minutes without a candle, both ends inclusive
+#[derive(Debug, Clone, Serialize, QueryableByName)]
+pub struct OhlcGap {
+    #[sql_type = "BigInt"]
+    pub first_missing: i64,
+    #[sql_type = "BigInt"]
+    pub last_missing: i64,
+}
+
+/// Stored history of a pair
+#[derive(Debug, Clone, Serialize)]
+pub struct PairCoverage {
+    pub pair_id: i32,
+    pub exchange: String,
+    pub pair: String,
+    /// Times of the first and last stored candles
+    pub first: Option<i64>,
+    pub last: Option<i64>,
+    /// Stored one minute candles
+    pub candles: i64,
+    /// Minutes between the first and last candle without one
+    pub missing: i64,
+    /// Range searched for gaps, end exclusive
+    pub gaps_from: i64,
+    pub gaps_to: i64,
+    /// Largest gaps in the searched range, ordered by time
+    pub gaps: Vec<OhlcGap>,
+}
+
+/// Direct access to stored candles for tools running outside of the services
+pub struct OhlcReader {
+    conn: PgConnection,
+}
+
+impl OhlcReader {
+    pub fn connect() -> Result<Self, failure::Error> {
+        Ok(OhlcReader { conn: PgConnection::establish(&crate::db_url())? })
+    }
+
+    pub fn pairs(&self) -> Result<Vec<Pair>, failure::Error> {
+        Ok(pairs::table.order_by(pairs::id.asc()).load(&self.conn)?)
+    }
+
+    /// Times of the first and last stored candles of the pair
+    pub fn bounds(&self, pair_id: i32) -> Result<(Option<i64>, Option<i64>), failure::Error> {
+        use diesel::dsl::{min, max};
+        Ok(ohlc::table
+            .filter(ohlc::pair_id.eq(pair_id))
+            .select((min(ohlc::time), max(ohlc::time)))
+            .get_result(&self.conn)?)
+    }
+
+    /// Stored one minute candles of the pair with times from `from` up to `to`, ordered by time
+    pub fn minutes(&self, pair_id: i32, from: i64, to: i64) -> Result<Vec<Ohlc>, failure::Error> {
+        let rows = ohlc::table
+            .filter(ohlc::pair_id.eq(pair_id))
+            .filter(ohlc::time.ge(from))
+            .filter(ohlc::time.lt(to))
+            .order_by(ohlc::time.asc())
+            .load::<LoadOhlc>(&self.conn)?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+}
+
 
 impl crate::Database {
     pub fn pair_id(&self, pair_id: PairId) -> LocalBoxFuture<'static, Result<i32>> {
@@ -81,6 +182,64 @@ impl crate::Database {
         }).await
     }
 
+    /// Candles of the pair rescaled to the period, from the bucket containing `from` up to `to`.
+    /// Buckets without stored candles are skipped, and only the first `limit` buckets of the range are read,
+    /// so at most `limit` candles are returned. `ohlc_range_end` is the end of the buckets that were read.
+    pub async fn ohlc_range(&self, pair_id: i32, period: OhlcPeriod, from: i64, to: i64, limit: i64) -> Result<Vec<Ohlc>> {
+        self.0.invoke(move |this, ctx| {
+            let to = ohlc_range_end(period, from, to, limit);
+            let from = period.clamp_time(from);
+            let rows: Vec<LoadOhlc> = diesel::sql_query(RANGE_Q)
+                .bind::<Int4, _>(pair_id)
+                .bind::<BigInt, _>(period.seconds())
+                .bind::<BigInt, _>(from)
+                .bind::<BigInt, _>(to)
+                .bind::<BigInt, _>(limit)
+                .load(&this.conn())?;
+            Ok(rows.into_iter().map(Into::into).collect())
+        }).await
+    }
+
+    /// Extent of stored candles of the pair with at most `max_gaps` of its largest gaps in `from..to`,
+    /// NotFound if there is no such pair
+    pub async fn pair_coverage(&self, pair_id: i32, from: i64, to: i64, max_gaps: i64) -> Result<PairCoverage> {
+        self.0.invoke(move |this, ctx| {
+            use diesel::dsl::{count_star, min, max};
+            let conn = this.conn();
+
+            let pair: Pair = pairs::table.find(pair_id).get_result(&conn)?;
+            let (candles, first, last) = ohlc::table
+                .filter(ohlc::pair_id.eq(pair_id))
+                .select((count_star(), min(ohlc::time), max(ohlc::time)))
+                .get_result::<(i64, Option<i64>, Option<i64>)>(&conn)?;
+
+            let mut gaps: Vec<OhlcGap> = diesel::sql_query(GAPS_Q)
+                .bind::<Int4, _>(pair_id)
+                .bind::<BigInt, _>(from)
+                .bind::<BigInt, _>(to)
+                .bind::<BigInt, _>(max_gaps)
+                .load(&conn)?;
+            gaps.sort_by_key(|g| g.first_missing);
+
+            let missing = match (first, last) {
+                (Some(first), Some(last)) => (last - first) / 60 + 1 - candles,
+                _ => 0,
+            };
+            Ok(PairCoverage {
+                pair_id,
+                exchange: pair.exchange,
+                pair: pair.pair,
+                first,
+                last,
+                candles,
+                missing,
+                gaps_from: from,
+                gaps_to: to,
+                gaps,
+            })
+        }).await
+    }
+
     async fn ohlcs_from_query(&self, query: &'static str) -> Result<BTreeMap<PairId, common::types::Ohlc>> {
         let pairs = self.pairs().await?;
         self.0.invoke(move |this, ctx| {
@@ -0,0 +1,192 @@
+use crate::prelude::*;
+use crate::schema::{optimizations, optimization_results};
+use common::types::{ParamSearch, ParamValues};
+
+/// Optimization still marked as running after this long is assumed to be abandoned by a stopped optimizer
+const ABANDONED_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptimizationStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+impl OptimizationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OptimizationStatus::Pending => "pending",
+            OptimizationStatus::Running => "running",
+            OptimizationStatus::Done => "done",
+            OptimizationStatus::Failed => "failed",
+        }
+    }
+}
+
+impl Optimization {
+    pub fn search(&self) -> StdResult<ParamSearch, json::Error> {
+        json::from_str(&self.search)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OptimizationData {
+    pub user_id: i32,
+    pub strategy_id: i32,
+    pub revision_id: i32,
+    pub pair_id: i32,
+    pub period: String,
+    pub from_time: i64,
+    pub to_time: i64,
+    pub search: ParamSearch,
+    pub folds: i32,
+}
+
+/// Scores of a single parameter combination, ranked when the optimization is finished
+#[derive(Debug, Clone)]
+pub struct CandidateScore {
+    pub params: ParamValues,
+    pub in_sample: f64,
+    pub out_of_sample: f64,
+    pub max_drawdown: f64,
+    pub trades: i32,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "optimization_results"]
+struct NewOptimizationResult {
+    optimization_id: i32,
+    rank: i32,
+    params: String,
+    in_sample: f64,
+    out_of_sample: f64,
+    max_drawdown: f64,
+    trades: i32,
+}
+
+impl crate::Database {
+    pub async fn create_optimization(&self, data: OptimizationData) -> Result<Optimization> {
+        self.0.invoke(move |this, ctx| {
+            diesel::insert_into(optimizations::table)
+                .values((
+                    optimizations::user_id.eq(data.user_id),
+                    optimizations::strategy_id.eq(data.strategy_id),
+                    optimizations::revision_id.eq(data.revision_id),
+                    optimizations::pair_id.eq(data.pair_id),
+                    optimizations::period.eq(data.period),
+                    optimizations::from_time.eq(data.from_time),
+                    optimizations::to_time.eq(data.to_time),
+                    optimizations::search.eq(json::to_string(&data.search).expect("Search serialization")),
+                    optimizations::folds.eq(data.folds),
+                ))
+                .get_result(&this.conn())
+        }).await
+    }
+
+    /// Marks the oldest pending optimization as running and returns it
+    pub fn claim_optimization(&self) -> LocalBoxFuture<'static, Result<Option<Optimization>>> {
+        self.0.invoke(move |this, ctx| {
+            let conn: &ConnType = &this.pool.get().unwrap();
+            let abandoned = chrono::Utc::now() - chrono::Duration::minutes(ABANDONED_MINUTES);
+
+            conn.transaction(|| {
+                let job = optimizations::table
+                    .filter(optimizations::status.eq(OptimizationStatus::Pending.as_str())
+                        .or(optimizations::status.eq(OptimizationStatus::Running.as_str())
+                            .and(optimizations::started.lt(abandoned))))
+                    .order_by(optimizations::created.asc())
+                    .for_update()
+                    .skip_locked()
+                    .first::<Optimization>(conn)
+                    .optional()?;
+
+                match job {
+                    Some(job) => diesel::update(&job)
+                        .set((
+                            optimizations::status.eq(OptimizationStatus::Running.as_str()),
+                            optimizations::started.eq(chrono::Utc::now()),
+                        ))
+                        .get_result(conn)
+                        .map(Some),
+                    None => Ok(None),
+                }
+            })
+        })
+    }
+
+    /// Stores results ranked by their out-of-sample return, and marks the optimization as done
+    pub fn finish_optimization(&self, oid: i32, mut scores: Vec<CandidateScore>) -> LocalBoxFuture<'static, Result<()>> {
+        scores.sort_by(|a, b| b.out_of_sample.partial_cmp(&a.out_of_sample).unwrap_or(std::cmp::Ordering::Equal));
+        let results = scores.into_iter().enumerate()
+            .map(|(i, s)| NewOptimizationResult {
+                optimization_id: oid,
+                rank: i as i32 + 1,
+                params: json::to_string(&s.params).expect("Parameter serialization"),
+                in_sample: s.in_sample,
+                out_of_sample: s.out_of_sample,
+                max_drawdown: s.max_drawdown,
+                trades: s.trades,
+            })
+            .collect::<Vec<_>>();
+
+        self.0.invoke(move |this, ctx| {
+            let conn: &ConnType = &this.pool.get().unwrap();
+            conn.transaction(|| {
+                diesel::delete(optimization_results::table.filter(optimization_results::optimization_id.eq(oid)))
+                    .execute(conn)?;
+                diesel::insert_into(optimization_results::table)
+                    .values(&results)
+                    .execute(conn)?;
+                diesel::update(optimizations::table.find(oid))
+                    .set((
+                        optimizations::status.eq(OptimizationStatus::Done.as_str()),
+                        optimizations::finished.eq(chrono::Utc::now()),
+                    ))
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    pub fn fail_optimization(&self, oid: i32, reason: String) -> LocalBoxFuture<'static, Result<()>> {
+        self.0.invoke(move |this, ctx| {
+            diesel::update(optimizations::table.find(oid))
+                .set((
+                    optimizations::status.eq(OptimizationStatus::Failed.as_str()),
+                    optimizations::error.eq(reason),
+                    optimizations::finished.eq(chrono::Utc::now()),
+                ))
+                .execute(&this.conn())?;
+            Ok(())
+        })
+    }
+
+    pub async fn user_optimizations(&self, uid: i32, sid: Option<i32>) -> Result<Vec<Optimization>> {
+        self.0.invoke(move |this, ctx| {
+            let mut q = optimizations::table
+                .filter(optimizations::user_id.eq(uid))
+                .into_boxed();
+            if let Some(sid) = sid {
+                q = q.filter(optimizations::strategy_id.eq(sid));
+            }
+            q.order_by(optimizations::created.desc()).load(&this.conn())
+        }).await
+    }
+
+    /// Optimization owned by the user, with its results in ranked order
+    pub async fn optimization(&self, uid: i32, oid: i32) -> Result<(Optimization, Vec<OptimizationResult>)> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            let job = optimizations::table
+                .filter(optimizations::id.eq(oid))
+                .filter(optimizations::user_id.eq(uid))
+                .get_result::<Optimization>(&conn)?;
+            let results = OptimizationResult::belonging_to(&job)
+                .order_by(optimization_results::rank.asc())
+                .load(&conn)?;
+            Ok((job, results))
+        }).await
+    }
+}
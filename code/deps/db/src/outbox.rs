@@ -0,0 +1,163 @@
+use crate::prelude::*;
+use crate::schema::{outbox, inbox};
+use uuid::Uuid;
+
+/// Longest delay between two delivery attempts of a single message, in seconds
+const MAX_BACKOFF: i64 = 60;
+
+#[derive(Insertable, Debug)]
+#[table_name = "outbox"]
+pub(crate) struct NewOutboxMessage {
+    subject: String,
+    payload: String,
+}
+
+impl NewOutboxMessage {
+    pub(crate) fn new<M: Serialize>(subject: impl Into<String>, msg: &M) -> Self {
+        NewOutboxMessage {
+            subject: subject.into(),
+            payload: json::to_string(msg).expect("Outbox message serialization"),
+        }
+    }
+
+    /// Inserts the message, can be a part of a larger transaction
+    pub(crate) fn insert(&self, conn: &PgConnection) -> Result<OutboxMessage> {
+        diesel::insert_into(outbox::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+impl OutboxMessage {
+    pub fn decode<T: DeserializeOwned>(&self) -> StdResult<T, json::Error> {
+        json::from_str(&self.payload)
+    }
+}
+
+impl crate::Database {
+    /// Stores a message that has to reach its subscriber at least once.
+    /// It stays in the outbox until it is acknowledged through `outbox_delivered`.
+    pub fn enqueue<M: Serialize>(&self, subject: impl Into<String>, msg: &M) -> LocalBoxFuture<'static, Result<OutboxMessage>> {
+        let msg = NewOutboxMessage::new(subject, msg);
+        self.0.invoke(move |this, ctx| {
+            msg.insert(&this.conn())
+        })
+    }
+
+    pub fn outbox_pending(&self, limit: i64) -> LocalBoxFuture<'static, Result<Vec<OutboxMessage>>> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::outbox::dsl::*;
+
+            outbox
+                .filter(delivered.is_null())
+                .filter(next_attempt.le(diesel::dsl::now))
+                .order_by(created.asc())
+                .limit(limit)
+                .load(&this.conn())
+        })
+    }
+
+    pub fn outbox_delivered(&self, mid: Uuid) -> LocalBoxFuture<'static, Result<()>> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::outbox::dsl::*;
+
+            diesel::update(outbox.filter(id.eq(mid)))
+                .set(delivered.eq(chrono::Utc::now()))
+                .execute(&this.conn())?;
+            Ok(())
+        })
+    }
+
+    /// Records a failed delivery attempt, the next one is delayed by one second per previous attempt
+    pub fn outbox_failed(&self, msg: OutboxMessage) -> LocalBoxFuture<'static, Result<()>> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::outbox::dsl::*;
+
+            let backoff = i64::min(MAX_BACKOFF, msg.attempts as i64 + 1);
+            diesel::update(outbox.filter(id.eq(msg.id)))
+                .set((attempts.eq(msg.attempts + 1),
+                      next_attempt.eq(chrono::Utc::now() + chrono::Duration::seconds(backoff))))
+                .execute(&this.conn())?;
+            Ok(())
+        })
+    }
+
+    /// Response stored when the message was received, `None` if it was not received yet
+    pub async fn received_response<R: DeserializeOwned>(&self, mid: Uuid) -> Result<Option<R>> {
+        let stored = self.0.invoke(move |this, ctx| {
+            use crate::schema::inbox::dsl::*;
+
+            inbox.find(mid)
+                .select(response)
+                .get_result::<Option<String>>(&this.conn())
+                .optional()
+        }).await?;
+
+        match stored.and_then(|r| r) {
+            Some(stored) => Ok(Some(json::from_str(&stored).map_err(|e| diesel::result::Error::DeserializationError(box e))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Marks the message as received along with the response to it. When it was already received,
+    /// the earlier response is returned instead, along with false.
+    pub async fn respond_once<R>(&self, mid: Uuid, sub: &'static str, reply: R) -> Result<(R, bool)>
+        where R: Serialize + DeserializeOwned
+    {
+        self.respond_and_enqueue(mid, sub, reply, None).await
+    }
+
+    /// Same as `respond_once`, the forwarded message is enqueued in the same transaction,
+    /// and only when the message was not received before
+    pub async fn respond_once_with<R, M>(&self, mid: Uuid, sub: &'static str, reply: R, forward: (&'static str, M)) -> Result<(R, bool)>
+        where R: Serialize + DeserializeOwned,
+              M: Serialize
+    {
+        let (to, msg) = forward;
+        self.respond_and_enqueue(mid, sub, reply, Some(NewOutboxMessage::new(to, &msg))).await
+    }
+
+    async fn respond_and_enqueue<R>(&self, mid: Uuid, sub: &'static str, reply: R, forward: Option<NewOutboxMessage>) -> Result<(R, bool)>
+        where R: Serialize + DeserializeOwned
+    {
+        let encoded = json::to_string(&reply).map_err(|e| diesel::result::Error::SerializationError(box e))?;
+        let (first, stored) = self.0.invoke(move |this, ctx| {
+            use crate::schema::inbox::dsl::*;
+            let conn: &ConnType = &this.pool.get().unwrap();
+
+            conn.transaction(|| {
+                let inserted = diesel::insert_into(inbox)
+                    .values((id.eq(mid), subject.eq(sub), response.eq(Some(encoded))))
+                    .on_conflict(id)
+                    .do_nothing()
+                    .execute(conn)?;
+                if inserted == 0 {
+                    return Ok((false, inbox.find(mid).select(response).get_result::<Option<String>>(conn)?));
+                }
+                if let Some(msg) = forward {
+                    msg.insert(conn)?;
+                }
+                Ok((true, None))
+            })
+        }).await?;
+
+        match stored {
+            Some(stored) => Ok((json::from_str(&stored).map_err(|e| diesel::result::Error::DeserializationError(box e))?, first)),
+            None => Ok((reply, first)),
+        }
+    }
+
+    /// Removes outbox messages delivered before `before`, and received inbox messages older than it.
+    /// Returns the number of removed outbox and inbox messages.
+    pub fn purge_messages(&self, before: chrono::DateTime<chrono::Utc>) -> LocalBoxFuture<'static, Result<(usize, usize)>> {
+        self.0.invoke(move |this, ctx| {
+            let conn: &ConnType = &this.pool.get().unwrap();
+
+            let sent = diesel::delete(outbox::table.filter(outbox::delivered.lt(before)))
+                .execute(conn)?;
+            let received = diesel::delete(inbox::table.filter(inbox::received.lt(before)))
+                .execute(conn)?;
+            Ok((sent, received))
+        })
+    }
+}
@@ -13,7 +13,7 @@ pub(crate) use diesel::sql_types::{Text, BigInt};
 
 pub(crate) use crate::{DbWorker, ConnType, schema};
 
-pub use schema::{User, Strategy, Assignment, Evaluation, Trader};
+pub use schema::{User, Strategy, StrategyRevision, Assignment, Evaluation, Trader, OutboxMessage, ApiToken, Optimization, OptimizationResult, RiskLimits, RiskDecision, NotificationChannel, NotificationRule, Notification, SignalDelivery, SignalHook};
 
 pub use common::futures03::future::LocalBoxFuture;
 pub use common::futures03::future::BoxFuture;
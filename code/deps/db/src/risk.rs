@@ -0,0 +1,190 @@
+use crate::prelude::*;
+use crate::schema::{risk_limits, risk_decisions, trades, traders, pairs, Pair, Trade};
+use crate::outbox::NewOutboxMessage;
+use diesel::sql_types::{Integer, Double};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RiskLimitsData {
+    pub max_exposure: Option<f64>,
+    pub max_daily_loss: Option<f64>,
+    pub max_orders_per_hour: Option<i32>,
+    #[serde(default)]
+    pub kill_switch: bool,
+}
+
+/// Everything the risk manager needs to know about the user owning a trader
+#[derive(Debug, Clone)]
+pub struct RiskState {
+    pub user_id: i32,
+    pub limits: Option<RiskLimits>,
+    /// Latest approved decision for every trader and pair of the user
+    pub positions: Vec<(RiskDecision, PairId)>,
+    /// What the traders of the user hold after their executed trades
+    pub holdings: Vec<HeldAmount>,
+    /// Approved decisions in the last hour
+    pub recent_orders: i64,
+    /// Successful trades of the current UTC day
+    pub trades_today: Vec<(Trade, PairId)>,
+}
+
+/// Net amount of the target currency bought by a trader on a pair, negative for short positions
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeldAmount {
+    pub trader_id: i32,
+    pub pair: PairId,
+    pub amount: f64,
+}
+
+#[derive(Debug, QueryableByName)]
+struct LoadHeldAmount {
+    #[sql_type = "Integer"]
+    trader_id: i32,
+    #[sql_type = "Integer"]
+    pair_id: i32,
+    #[sql_type = "Double"]
+    amount: f64,
+}
+
+const HOLDINGS_Q: &str = r##"
+select trader_id, pair_id, sum(case when buy then amount else -amount end) as amount
+from trades
+where user_id = $1
+  and status
+group by trader_id, pair_id
+"##;
+
+impl crate::Database {
+    pub async fn risk_limits(&self, uid: i32) -> Result<Option<RiskLimits>> {
+        self.0.invoke(move |this, ctx| {
+            risk_limits::table.find(uid).get_result(&this.conn()).optional()
+        }).await
+    }
+
+    pub async fn save_risk_limits(&self, uid: i32, data: RiskLimitsData) -> Result<RiskLimits> {
+        self.0.invoke(move |this, ctx| {
+            let limits = RiskLimits {
+                user_id: uid,
+                max_exposure: data.max_exposure,
+                max_daily_loss: data.max_daily_loss,
+                max_orders_per_hour: data.max_orders_per_hour,
+                kill_switch: data.kill_switch,
+                updated: chrono::Utc::now(),
+            };
+            diesel::insert_into(risk_limits::table)
+                .values(&limits)
+                .on_conflict(risk_limits::user_id)
+                .do_update()
+                .set(&limits)
+                .get_result(&this.conn())
+        }).await
+    }
+
+    pub async fn risk_decisions(&self, uid: i32, limit: i64) -> Result<Vec<RiskDecision>> {
+        self.0.invoke(move |this, ctx| {
+            risk_decisions::table
+                .filter(risk_decisions::user_id.eq(uid))
+                .order_by(risk_decisions::time.desc())
+                .limit(limit)
+                .load(&this.conn())
+        }).await
+    }
+
+    pub fn risk_state(&self, tid: i32) -> LocalBoxFuture<'static, Result<RiskState>> {
+        self.0.invoke(move |this, ctx| {
+            load_risk_state(&this.conn(), tid)
+        })
+    }
+
+    /// Reviews a position request and records the decision, the approved request is enqueued for the trader
+    /// in the same transaction. Limits of the user stay locked until then, so that concurrent reviews of the user
+    /// see each other's decisions. `review` returns `None` when the request doesn't need a decision.
+    /// Returns the decision, and false if the request was already decided before.
+    pub fn record_risk_decision<F, M>(&self, tid: i32, review: F) -> LocalBoxFuture<'static, Result<Option<(RiskDecision, bool)>>>
+        where F: FnOnce(&RiskState) -> Option<(RiskDecision, Option<(&'static str, M)>)> + Send + 'static,
+              M: Serialize
+    {
+        self.0.invoke(move |this, ctx| {
+            let conn: &ConnType = &this.pool.get().unwrap();
+            conn.transaction(|| {
+                let uid = traders::table
+                    .find(tid)
+                    .select(traders::user_id)
+                    .get_result::<i32>(conn)?;
+                risk_limits::table.find(uid).for_update().get_result::<RiskLimits>(conn).optional()?;
+
+                let state = load_risk_state(conn, tid)?;
+                let (decision, forward) = match review(&state) {
+                    Some(res) => res,
+                    None => return Ok(None),
+                };
+                let inserted = diesel::insert_into(risk_decisions::table)
+                    .values(&decision)
+                    .on_conflict(risk_decisions::id)
+                    .do_nothing()
+                    .execute(conn)?;
+                if inserted == 0 {
+                    return Ok(Some((decision, false)));
+                }
+                if let Some((subject, msg)) = forward {
+                    NewOutboxMessage::new(subject, &msg).insert(conn)?;
+                }
+                Ok(Some((decision, true)))
+            })
+        })
+    }
+}
+
+fn load_risk_state(conn: &PgConnection, tid: i32) -> Result<RiskState> {
+    let uid = traders::table
+        .find(tid)
+        .select(traders::user_id)
+        .get_result::<i32>(conn)?;
+    let limits = risk_limits::table.find(uid).get_result(conn).optional()?;
+
+    let positions = risk_decisions::table
+        .inner_join(pairs::table)
+        .filter(risk_decisions::user_id.eq(uid))
+        .filter(risk_decisions::approved.is_not_null())
+        .distinct_on((risk_decisions::trader_id, risk_decisions::pair_id))
+        .order_by((risk_decisions::trader_id, risk_decisions::pair_id, risk_decisions::time.desc()))
+        .load::<(RiskDecision, Pair)>(conn)?;
+
+    let held: Vec<LoadHeldAmount> = diesel::sql_query(HOLDINGS_Q)
+        .bind::<Integer, _>(uid)
+        .load(conn)?;
+    let held_pairs = pairs::table
+        .filter(pairs::id.eq_any(held.iter().map(|h| h.pair_id).collect::<Vec<_>>()))
+        .load::<Pair>(conn)?;
+    let holdings = held.into_iter()
+        .filter_map(|h| held_pairs.iter().find(|p| p.id == h.pair_id).map(|p| HeldAmount {
+            trader_id: h.trader_id,
+            pair: p.clone().into(),
+            amount: h.amount,
+        }))
+        .collect();
+
+    let hour_ago = chrono::Utc::now() - chrono::Duration::hours(1);
+    let recent_orders = risk_decisions::table
+        .filter(risk_decisions::user_id.eq(uid))
+        .filter(risk_decisions::approved.is_not_null())
+        .filter(risk_decisions::time.gt(hour_ago))
+        .count()
+        .get_result(conn)?;
+
+    let midnight = chrono::Utc::today().and_hms(0, 0, 0);
+    let trades_today = trades::table
+        .inner_join(pairs::table)
+        .filter(trades::user_id.eq(uid))
+        .filter(trades::status.eq(true))
+        .filter(trades::time.ge(midnight))
+        .load::<(Trade, Pair)>(conn)?;
+
+    Ok(RiskState {
+        user_id: uid,
+        limits,
+        positions: positions.into_iter().map(|(d, p)| (d, p.into())).collect(),
+        holdings,
+        recent_orders,
+        trades_today: trades_today.into_iter().map(|(t, p)| (t, p.into())).collect(),
+    })
+}
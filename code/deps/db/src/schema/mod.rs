@@ -1,15 +1,61 @@
 use super::*;
 use ::std::result::Result as Result;
 use uuid::Uuid;
-use common::types::Exchange;
+use common::types::{Exchange, ParamSpec, ParamValues};
+
+/// Serializes JSON stored in text columns as a nested value instead of a string
+mod json_text {
+    use common::prelude::*;
+
+    pub fn serialize<S: Serializer>(text: &str, ser: S) -> StdResult<S::Ok, S::Error> {
+        let value: json::Value = json::from_str(text).map_err(::serde::ser::Error::custom)?;
+        value.serialize(ser)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> StdResult<String, D::Error> {
+        let value = json::Value::deserialize(de)?;
+        Ok(value.to_string())
+    }
+}
+
+table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        scope -> Text,
+        token_hash -> Bytea,
+        created -> Timestamptz,
+        last_used -> Nullable<Timestamptz>,
+        revoked -> Nullable<Timestamptz>,
+    }
+}
 
 table! {
-    assignments (pair_id, user_id) {
+    assignments (id) {
         pair_id -> Int4,
         user_id -> Int4,
         period -> Text,
         strategy_id -> Int4,
         trader_id -> Nullable<Int4>,
+        revision_id -> Int4,
+        params -> Text,
+        id -> Int4,
+        enabled -> Bool,
+        created -> Timestamptz,
+        signal_url -> Nullable<Text>,
+        signal_secret -> Nullable<Text>,
+    }
+}
+
+table! {
+    auth_events (id) {
+        id -> Int8,
+        user_id -> Nullable<Int4>,
+        email -> Nullable<Text>,
+        event -> Text,
+        ip -> Nullable<Text>,
+        created -> Timestamptz,
     }
 }
 
@@ -25,6 +71,55 @@ table! {
         duration -> Int8,
         ok -> Nullable<Text>,
         error -> Nullable<Text>,
+        revision_id -> Int4,
+    }
+}
+
+table! {
+    inbox (id) {
+        id -> Uuid,
+        subject -> Text,
+        received -> Timestamptz,
+        response -> Nullable<Text>,
+    }
+}
+
+table! {
+    notification_channels (id) {
+        id -> Int4,
+        user_id -> Int4,
+        kind -> Text,
+        target -> Text,
+        token -> Nullable<Text>,
+        enabled -> Bool,
+        created -> Timestamptz,
+        verified -> Bool,
+        verify_hash -> Nullable<Bytea>,
+    }
+}
+
+table! {
+    notification_rules (id) {
+        id -> Int4,
+        user_id -> Int4,
+        event -> Text,
+        channel_id -> Int4,
+    }
+}
+
+table! {
+    notifications (id) {
+        id -> Uuid,
+        user_id -> Int4,
+        channel_id -> Int4,
+        event -> Text,
+        subject -> Text,
+        body -> Text,
+        created -> Timestamptz,
+        attempts -> Int4,
+        next_attempt -> Timestamptz,
+        delivered -> Nullable<Timestamptz>,
+        error -> Nullable<Text>,
     }
 }
 
@@ -40,6 +135,51 @@ table! {
     }
 }
 
+table! {
+    optimization_results (id) {
+        id -> Int4,
+        optimization_id -> Int4,
+        rank -> Int4,
+        params -> Text,
+        in_sample -> Float8,
+        out_of_sample -> Float8,
+        max_drawdown -> Float8,
+        trades -> Int4,
+    }
+}
+
+table! {
+    optimizations (id) {
+        id -> Int4,
+        user_id -> Int4,
+        strategy_id -> Int4,
+        revision_id -> Int4,
+        pair_id -> Int4,
+        period -> Text,
+        from_time -> Int8,
+        to_time -> Int8,
+        search -> Text,
+        folds -> Int4,
+        status -> Text,
+        error -> Nullable<Text>,
+        created -> Timestamptz,
+        started -> Nullable<Timestamptz>,
+        finished -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    outbox (id) {
+        id -> Uuid,
+        subject -> Text,
+        payload -> Text,
+        created -> Timestamptz,
+        attempts -> Int4,
+        next_attempt -> Timestamptz,
+        delivered -> Nullable<Timestamptz>,
+    }
+}
+
 table! {
     pairs (id) {
         id -> Int4,
@@ -48,6 +188,77 @@ table! {
     }
 }
 
+table! {
+    recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Bytea,
+        used -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    risk_decisions (id) {
+        id -> Uuid,
+        time -> Timestamptz,
+        user_id -> Int4,
+        trader_id -> Int4,
+        pair_id -> Int4,
+        position -> Text,
+        requested -> Float8,
+        approved -> Nullable<Float8>,
+        reason -> Nullable<Text>,
+        amount -> Nullable<Float8>,
+    }
+}
+
+table! {
+    risk_limits (user_id) {
+        user_id -> Int4,
+        max_exposure -> Nullable<Float8>,
+        max_daily_loss -> Nullable<Float8>,
+        max_orders_per_hour -> Nullable<Int4>,
+        kill_switch -> Bool,
+        updated -> Timestamptz,
+    }
+}
+
+table! {
+    signal_deliveries (id) {
+        id -> Uuid,
+        assignment_id -> Int4,
+        user_id -> Int4,
+        url -> Text,
+        payload -> Text,
+        created -> Timestamptz,
+        attempts -> Int4,
+        next_attempt -> Timestamptz,
+        delivered -> Nullable<Timestamptz>,
+        status -> Nullable<Int4>,
+        error -> Nullable<Text>,
+    }
+}
+
+table! {
+    signal_hooks (id) {
+        id -> Int4,
+        user_id -> Int4,
+        trader_id -> Int4,
+        name -> Text,
+        token_hash -> Bytea,
+        created -> Timestamptz,
+        last_used -> Nullable<Timestamptz>,
+        revoked -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    stale_feeds (pair_id) {
+        pair_id -> Int4,
+        reported -> Timestamptz,
+    }
+}
+
 table! {
     strategies (id) {
         id -> Int4,
@@ -56,6 +267,19 @@ table! {
         body -> Text,
         created -> Timestamptz,
         updated -> Timestamptz,
+        revision_id -> Nullable<Int4>,
+        params -> Text,
+    }
+}
+
+table! {
+    strategy_revisions (id) {
+        id -> Int4,
+        strategy_id -> Int4,
+        number -> Int4,
+        body -> Text,
+        created -> Timestamptz,
+        params -> Text,
     }
 }
 
@@ -65,8 +289,9 @@ table! {
         user_id -> Int4,
         name -> Text,
         exchange -> Text,
-        api_key -> Text,
-        api_secret -> Text,
+        api_key -> Bytea,
+        api_secret -> Bytea,
+        data_key -> Nullable<Bytea>,
     }
 }
 
@@ -83,6 +308,20 @@ table! {
         status -> Bool,
         ok -> Nullable<Text>,
         error -> Nullable<Text>,
+        revision_id -> Nullable<Int4>,
+        fee -> Float8,
+    }
+}
+
+table! {
+    user_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        purpose -> Text,
+        token_hash -> Bytea,
+        created -> Timestamptz,
+        expires -> Timestamptz,
+        used -> Nullable<Timestamptz>,
     }
 }
 
@@ -97,31 +336,82 @@ table! {
         has_verified_email -> Bool,
         created -> Timestamptz,
         updated -> Timestamptz,
+        totp_secret -> Nullable<Bytea>,
+        totp_enabled -> Bool,
+        totp_last_step -> Nullable<Int8>,
+        failed_logins -> Int4,
+        locked_until -> Nullable<Timestamptz>,
+        role -> Text,
+        disabled -> Bool,
     }
 }
 
+joinable!(api_tokens -> users (user_id));
 joinable!(assignments -> pairs (pair_id));
 joinable!(assignments -> strategies (strategy_id));
+joinable!(assignments -> strategy_revisions (revision_id));
 joinable!(assignments -> traders (trader_id));
 joinable!(assignments -> users (user_id));
+joinable!(auth_events -> users (user_id));
 joinable!(evaluations -> pairs (pair_id));
 joinable!(evaluations -> strategies (strategy_id));
+joinable!(evaluations -> strategy_revisions (revision_id));
 joinable!(evaluations -> users (user_id));
+joinable!(notification_channels -> users (user_id));
+joinable!(notification_rules -> notification_channels (channel_id));
+joinable!(notification_rules -> users (user_id));
+joinable!(notifications -> notification_channels (channel_id));
+joinable!(notifications -> users (user_id));
 joinable!(ohlc -> pairs (pair_id));
+joinable!(optimization_results -> optimizations (optimization_id));
+joinable!(optimizations -> pairs (pair_id));
+joinable!(optimizations -> strategies (strategy_id));
+joinable!(optimizations -> strategy_revisions (revision_id));
+joinable!(optimizations -> users (user_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(risk_decisions -> pairs (pair_id));
+joinable!(risk_decisions -> traders (trader_id));
+joinable!(risk_decisions -> users (user_id));
+joinable!(risk_limits -> users (user_id));
+joinable!(signal_deliveries -> assignments (assignment_id));
+joinable!(signal_deliveries -> users (user_id));
+joinable!(signal_hooks -> traders (trader_id));
+joinable!(signal_hooks -> users (user_id));
+joinable!(stale_feeds -> pairs (pair_id));
 joinable!(strategies -> users (user_id));
+joinable!(strategy_revisions -> strategies (strategy_id));
 joinable!(traders -> users (user_id));
 joinable!(trades -> pairs (pair_id));
+joinable!(trades -> strategy_revisions (revision_id));
 joinable!(trades -> traders (trader_id));
 joinable!(trades -> users (user_id));
+joinable!(user_tokens -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    api_tokens,
     assignments,
+    auth_events,
     evaluations,
+    inbox,
+    notification_channels,
+    notification_rules,
+    notifications,
     ohlc,
+    optimization_results,
+    optimizations,
+    outbox,
     pairs,
+    recovery_codes,
+    risk_decisions,
+    risk_limits,
+    signal_deliveries,
+    signal_hooks,
+    stale_feeds,
     strategies,
+    strategy_revisions,
     traders,
     trades,
+    user_tokens,
     users,
 );
 
@@ -129,6 +419,26 @@ sql_function!(fn pair_id(exch : Text, pair : Text) -> Int4);
 sql_function!(fn make_pair_id(exch : Text, pair : Text) -> Int4);
 
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Identifiable, Queryable, Associations)]
+#[table_name = "signal_hooks"]
+#[primary_key(id)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[belongs_to(Trader, foreign_key = "trader_id")]
+pub struct SignalHook {
+    pub id: i32,
+    pub user_id: i32,
+    /// Trader receiving the position requests
+    pub trader_id: i32,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: Vec<u8>,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub last_used: Option<chrono::DateTime<chrono::Utc>>,
+    pub revoked: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+
 #[derive(Debug, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
 #[derive(Identifiable, Queryable, Insertable, AsChangeset, Associations, QueryableByName)]
 #[table_name = "ohlc"]
@@ -179,12 +489,24 @@ pub struct User {
     pub id: i32,
     pub name: Option<String>,
     pub email: String,
+    #[serde(skip)]
     pub password: String,
     pub avatar: Option<String>,
     pub is_verified: bool,
     pub has_verified_email: bool,
     pub created: chrono::DateTime<chrono::Utc>,
     pub updated: chrono::DateTime<chrono::Utc>,
+    /// Encrypted by the master key, read through `totp_secret`
+    #[serde(skip)]
+    pub totp_secret: Option<Vec<u8>>,
+    pub totp_enabled: bool,
+    #[serde(skip)]
+    pub totp_last_step: Option<i64>,
+    #[serde(skip)]
+    pub failed_logins: i32,
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+    pub role: String,
+    pub disabled: bool,
 }
 
 #[derive(Debug, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
@@ -198,8 +520,31 @@ pub struct Trader {
     pub name: String,
 
     pub exchange: String,
-    pub api_key: String,
-    pub api_secret: String,
+    /// Encrypted credentials, decrypted only by the exchange connector through `trader_credentials`
+    #[serde(skip)]
+    pub api_key: Vec<u8>,
+    #[serde(skip)]
+    pub api_secret: Vec<u8>,
+    #[serde(skip)]
+    pub data_key: Option<Vec<u8>>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Identifiable, Queryable, Associations)]
+#[table_name = "api_tokens"]
+#[primary_key(id)]
+#[belongs_to(User, foreign_key = "user_id")]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub scope: String,
+    #[serde(skip)]
+    pub token_hash: Vec<u8>,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub last_used: Option<chrono::DateTime<chrono::Utc>>,
+    pub revoked: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 
@@ -215,15 +560,47 @@ pub struct Strategy {
     pub body: String,
     pub created: chrono::DateTime<chrono::Utc>,
     pub updated: chrono::DateTime<chrono::Utc>,
+    /// Current revision, `body` and `params` are copies of its code and parameter declarations
+    pub revision_id: Option<i32>,
+    #[serde(with = "json_text")]
+    pub params: String,
+}
+
+impl Strategy {
+    pub fn param_specs(&self) -> Vec<ParamSpec> {
+        json::from_str(&self.params).unwrap_or_default()
+    }
 }
 
 
 #[derive(Debug, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
-#[derive(Identifiable, Queryable, Insertable, AsChangeset, Associations, QueryableByName)]
+#[derive(Identifiable, Queryable, Associations)]
+#[table_name = "strategy_revisions"]
+#[primary_key(id)]
+#[belongs_to(Strategy, foreign_key = "strategy_id")]
+pub struct StrategyRevision {
+    pub id: i32,
+    pub strategy_id: i32,
+    /// Sequential per strategy, starting at 1
+    pub number: i32,
+    pub body: String,
+    pub created: chrono::DateTime<chrono::Utc>,
+    #[serde(with = "json_text")]
+    pub params: String,
+}
+
+impl StrategyRevision {
+    pub fn param_specs(&self) -> Vec<ParamSpec> {
+        json::from_str(&self.params).unwrap_or_default()
+    }
+}
+
+
+#[derive(Debug, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Associations, QueryableByName)]
 #[table_name = "assignments"]
-#[primary_key(pair_id, user_id)]
 #[belongs_to(User, foreign_key = "user_id")]
-#[belongs_to(Strategy, foreign_key = "user_id")]
+#[belongs_to(Strategy, foreign_key = "strategy_id")]
 #[belongs_to(Trader, foreign_key = "trader_id")]
 #[belongs_to(Pair, foreign_key = "pair_id")]
 pub struct Assignment {
@@ -234,6 +611,25 @@ pub struct Assignment {
     pub strategy_id: i32,
 
     pub trader_id: Option<i32>,
+    pub revision_id: i32,
+    /// Values of strategy parameters, defaults are used for missing ones
+    #[serde(with = "json_text")]
+    pub params: String,
+    pub id: i32,
+    /// Disabled assignments are kept, but not evaluated
+    pub enabled: bool,
+    pub created: chrono::DateTime<chrono::Utc>,
+    /// Webhook receiving every decision of the assignment
+    pub signal_url: Option<String>,
+    /// Key signing the payloads posted to `signal_url`
+    #[serde(skip_serializing)]
+    pub signal_secret: Option<String>,
+}
+
+impl Assignment {
+    pub fn param_values(&self) -> ParamValues {
+        json::from_str(&self.params).unwrap_or_default()
+    }
 }
 
 
@@ -257,7 +653,7 @@ pub struct Evaluation {
 
     pub ok: Option<String>,
     pub error: Option<String>,
-
+    pub revision_id: i32,
 }
 
 
@@ -282,6 +678,198 @@ pub struct Trade {
     pub status: bool,
     pub ok: Option<String>,
     pub error: Option<String>,
+    pub revision_id: Option<i32>,
+    /// Fee charged by the exchange, in quote currency
+    pub fee: f64,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Associations)]
+#[table_name = "optimizations"]
+#[primary_key(id)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[belongs_to(Strategy, foreign_key = "strategy_id")]
+pub struct Optimization {
+    pub id: i32,
+    pub user_id: i32,
+    pub strategy_id: i32,
+    pub revision_id: i32,
+    pub pair_id: i32,
+    pub period: String,
+
+    pub from_time: i64,
+    pub to_time: i64,
+    #[serde(with = "json_text")]
+    pub search: String,
+    pub folds: i32,
+
+    pub status: String,
+    pub error: Option<String>,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub started: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Associations)]
+#[table_name = "optimization_results"]
+#[primary_key(id)]
+#[belongs_to(Optimization, foreign_key = "optimization_id")]
+pub struct OptimizationResult {
+    pub id: i32,
+    pub optimization_id: i32,
+    /// Position by out-of-sample return, starting at 1
+    pub rank: i32,
+    #[serde(with = "json_text")]
+    pub params: String,
+
+    /// Mean return on training windows of the walk-forward folds
+    pub in_sample: f64,
+    /// Mean return on windows following the training ones
+    pub out_of_sample: f64,
+    pub max_drawdown: f64,
+    pub trades: i32,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Associations)]
+#[table_name = "notification_channels"]
+#[primary_key(id)]
+#[belongs_to(User, foreign_key = "user_id")]
+pub struct NotificationChannel {
+    pub id: i32,
+    pub user_id: i32,
+    pub kind: String,
+    /// Email address, webhook URL or chat id of the bot API
+    pub target: String,
+    #[serde(skip_serializing)]
+    pub token: Option<String>,
+    pub enabled: bool,
+    pub created: chrono::DateTime<chrono::Utc>,
+    /// Email channels receive notifications only after their address is confirmed
+    pub verified: bool,
+    #[serde(skip_serializing)]
+    pub verify_hash: Option<Vec<u8>>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Associations)]
+#[table_name = "notification_rules"]
+#[primary_key(id)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[belongs_to(NotificationChannel, foreign_key = "channel_id")]
+pub struct NotificationRule {
+    pub id: i32,
+    pub user_id: i32,
+    pub event: String,
+    pub channel_id: i32,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Associations)]
+#[table_name = "notifications"]
+#[primary_key(id)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[belongs_to(NotificationChannel, foreign_key = "channel_id")]
+pub struct Notification {
+    pub id: Uuid,
+    pub user_id: i32,
+    pub channel_id: i32,
+    pub event: String,
+    pub subject: String,
+    pub body: String,
+
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub attempts: i32,
+    pub next_attempt: chrono::DateTime<chrono::Utc>,
+    pub delivered: Option<chrono::DateTime<chrono::Utc>>,
+    pub error: Option<String>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Associations)]
+#[table_name = "signal_deliveries"]
+#[primary_key(id)]
+#[belongs_to(Assignment, foreign_key = "assignment_id")]
+#[belongs_to(User, foreign_key = "user_id")]
+pub struct SignalDelivery {
+    pub id: Uuid,
+    pub assignment_id: i32,
+    pub user_id: i32,
+    pub url: String,
+    #[serde(with = "json_text")]
+    pub payload: String,
+
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub attempts: i32,
+    pub next_attempt: chrono::DateTime<chrono::Utc>,
+    pub delivered: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: Option<i32>,
+    pub error: Option<String>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Associations)]
+#[table_name = "risk_limits"]
+#[primary_key(user_id)]
+#[changeset_options(treat_none_as_null = "true")]
+#[belongs_to(User, foreign_key = "user_id")]
+pub struct RiskLimits {
+    pub user_id: i32,
+    /// Largest summed amount of open positions on pairs trading the same target currency
+    pub max_exposure: Option<f64>,
+    /// Largest loss of the current UTC day, in quote currency, after which positions can only be closed
+    pub max_daily_loss: Option<f64>,
+    pub max_orders_per_hour: Option<i32>,
+    /// Rejects everything except closing positions
+    pub kill_switch: bool,
+    pub updated: chrono::DateTime<chrono::Utc>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Insertable, Associations)]
+#[table_name = "risk_decisions"]
+#[primary_key(id)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[belongs_to(Trader, foreign_key = "trader_id")]
+pub struct RiskDecision {
+    /// Id of the reviewed position request
+    pub id: Uuid,
+    pub time: chrono::DateTime<chrono::Utc>,
+    pub user_id: i32,
+    pub trader_id: i32,
+    pub pair_id: i32,
+    pub position: String,
+    pub requested: f64,
+    /// Size sent to the trader, `None` if the request was rejected
+    pub approved: Option<f64>,
+    pub reason: Option<String>,
+    /// Approved size as an amount of the target currency, known when the exposure was checked
+    pub amount: Option<f64>,
+}
+
+
+#[derive(Debug, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Associations, QueryableByName)]
+#[table_name = "outbox"]
+#[primary_key(id)]
+pub struct OutboxMessage {
+    pub id: Uuid,
+    pub subject: String,
+    pub payload: String,
+
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub attempts: i32,
+    pub next_attempt: chrono::DateTime<chrono::Utc>,
+    pub delivered: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 
@@ -0,0 +1,77 @@
+use crate::prelude::*;
+use crate::schema::{signal_hooks, traders, users};
+use crate::tokens::{generate_token, hash_token};
+
+impl crate::Database {
+    /// Creates a webhook for a trader owned by the user, NotFound if there is no such trader.
+    /// The returned plaintext token is not stored and can't be retrieved later.
+    pub async fn create_signal_hook(&self, uid: i32, tid: i32, name: String) -> Result<(SignalHook, String)> {
+        let token = generate_token();
+        let hash = hash_token(&token);
+
+        let created = self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            traders::table
+                .filter(traders::id.eq(tid))
+                .filter(traders::user_id.eq(uid))
+                .select(traders::id)
+                .get_result::<i32>(&conn)?;
+
+            diesel::insert_into(signal_hooks::table)
+                .values((
+                    signal_hooks::user_id.eq(uid),
+                    signal_hooks::trader_id.eq(tid),
+                    signal_hooks::name.eq(name),
+                    signal_hooks::token_hash.eq(hash),
+                ))
+                .get_result::<SignalHook>(&conn)
+        }).await?;
+        Ok((created, token))
+    }
+
+    pub async fn signal_hooks(&self, uid: i32) -> Result<Vec<SignalHook>> {
+        self.0.invoke(move |this, ctx| {
+            signal_hooks::table
+                .filter(signal_hooks::user_id.eq(uid))
+                .filter(signal_hooks::revoked.is_null())
+                .order_by(signal_hooks::created.desc())
+                .load(&this.conn())
+        }).await
+    }
+
+    pub async fn revoke_signal_hook(&self, uid: i32, hid: i32) -> Result<bool> {
+        self.0.invoke(move |this, ctx| {
+            let q = diesel::update(signal_hooks::table)
+                .filter(signal_hooks::user_id.eq(uid))
+                .filter(signal_hooks::id.eq(hid))
+                .filter(signal_hooks::revoked.is_null())
+                .set(signal_hooks::revoked.eq(chrono::Utc::now()));
+
+            Ok(q.execute(&this.conn())? > 0)
+        }).await
+    }
+
+    /// Finds the active webhook with the token and its trader, and records that the webhook was used.
+    /// Webhooks of disabled users are not found.
+    pub async fn authenticate_signal_hook(&self, token: String) -> Result<Option<(SignalHook, Trader)>> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            let found = signal_hooks::table
+                .inner_join(traders::table)
+                .inner_join(users::table)
+                .filter(signal_hooks::token_hash.eq(hash_token(&token)))
+                .filter(signal_hooks::revoked.is_null())
+                .filter(users::disabled.eq(false))
+                .select((signal_hooks::all_columns, traders::all_columns))
+                .get_result::<(SignalHook, Trader)>(&conn)
+                .optional()?;
+
+            if let Some((ref hook, _)) = found {
+                diesel::update(signal_hooks::table.find(hook.id))
+                    .set(signal_hooks::last_used.eq(chrono::Utc::now()))
+                    .execute(&conn)?;
+            }
+            Ok(found)
+        }).await
+    }
+}
@@ -0,0 +1,114 @@
+use crate::prelude::*;
+use crate::schema::{signal_deliveries, assignments};
+use crate::notifications::{retry_delay, CLAIM_SECONDS};
+
+/// Delivery of a signal is given up after this many failed attempts
+pub const MAX_SIGNAL_ATTEMPTS: i32 = 10;
+
+/// Decision of an assignment, as posted to its signal target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signal {
+    /// Same for every attempt to deliver the signal, receivers can drop duplicates by it
+    pub id: uuid::Uuid,
+    pub assignment_id: i32,
+    pub strategy_id: i32,
+    pub revision_id: i32,
+    pub exchange: String,
+    pub pair: String,
+    pub period: String,
+    pub decision: String,
+    /// Time of the evaluated candle, in seconds
+    pub time: i64,
+}
+
+impl crate::Database {
+    /// Queues the signal for delivery to the signal target of its assignment
+    pub fn enqueue_signal(&self, uid: i32, url: String, signal: Signal) -> LocalBoxFuture<'static, Result<()>> {
+        let payload = json::to_string(&signal).expect("Signal serialization");
+        self.0.invoke(move |this, ctx| {
+            diesel::insert_into(signal_deliveries::table)
+                .values((
+                    signal_deliveries::id.eq(signal.id),
+                    signal_deliveries::assignment_id.eq(signal.assignment_id),
+                    signal_deliveries::user_id.eq(uid),
+                    signal_deliveries::url.eq(url),
+                    signal_deliveries::payload.eq(payload),
+                ))
+                .execute(&this.conn())?;
+            Ok(())
+        })
+    }
+
+    /// Latest deliveries of an assignment owned by the user, NotFound if there is no such assignment
+    pub async fn signal_deliveries(&self, uid: i32, aid: i32, limit: i64) -> Result<Vec<SignalDelivery>> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            assignments::table
+                .filter(assignments::id.eq(aid))
+                .filter(assignments::user_id.eq(uid))
+                .select(assignments::id)
+                .get_result::<i32>(&conn)?;
+
+            signal_deliveries::table
+                .filter(signal_deliveries::assignment_id.eq(aid))
+                .order_by(signal_deliveries::created.desc())
+                .limit(limit)
+                .load(&conn)
+        }).await
+    }
+
+    /// Pending deliveries with the current secrets of their assignments. They are hidden from other
+    /// senders for a while, and have to be marked as delivered or failed.
+    pub fn claim_signal_deliveries(&self, limit: i64) -> LocalBoxFuture<'static, Result<Vec<(SignalDelivery, Option<String>)>>> {
+        self.0.invoke(move |this, ctx| {
+            let conn: &ConnType = &this.pool.get().unwrap();
+            conn.transaction(|| {
+                let pending = signal_deliveries::table
+                    .inner_join(assignments::table)
+                    .filter(signal_deliveries::delivered.is_null())
+                    .filter(signal_deliveries::attempts.lt(MAX_SIGNAL_ATTEMPTS))
+                    .filter(signal_deliveries::next_attempt.le(diesel::dsl::now))
+                    .order_by(signal_deliveries::created.asc())
+                    .limit(limit)
+                    .select((signal_deliveries::all_columns, assignments::signal_secret))
+                    .for_update()
+                    .skip_locked()
+                    .load::<(SignalDelivery, Option<String>)>(conn)?;
+
+                let ids = pending.iter().map(|(d, _)| d.id).collect::<Vec<_>>();
+                diesel::update(signal_deliveries::table.filter(signal_deliveries::id.eq_any(ids)))
+                    .set(signal_deliveries::next_attempt.eq(chrono::Utc::now() + chrono::Duration::seconds(CLAIM_SECONDS)))
+                    .execute(conn)?;
+                Ok(pending)
+            })
+        })
+    }
+
+    pub fn signal_delivered(&self, did: uuid::Uuid, status: i32) -> LocalBoxFuture<'static, Result<()>> {
+        self.0.invoke(move |this, ctx| {
+            diesel::update(signal_deliveries::table.find(did))
+                .set((
+                    signal_deliveries::delivered.eq(chrono::Utc::now()),
+                    signal_deliveries::status.eq(status),
+                    signal_deliveries::error.eq(None::<String>),
+                ))
+                .execute(&this.conn())?;
+            Ok(())
+        })
+    }
+
+    /// Records a failed attempt, the next one is delayed exponentially
+    pub fn signal_failed(&self, delivery: SignalDelivery, status: Option<i32>, reason: String) -> LocalBoxFuture<'static, Result<()>> {
+        self.0.invoke(move |this, ctx| {
+            diesel::update(signal_deliveries::table.find(delivery.id))
+                .set((
+                    signal_deliveries::attempts.eq(delivery.attempts + 1),
+                    signal_deliveries::next_attempt.eq(chrono::Utc::now() + retry_delay(delivery.attempts)),
+                    signal_deliveries::status.eq(status),
+                    signal_deliveries::error.eq(reason),
+                ))
+                .execute(&this.conn())?;
+            Ok(())
+        })
+    }
+}
@@ -1,8 +1,8 @@
 use crate::prelude::*;
-use schema::strategies;
+use schema::{strategies, strategy_revisions, assignments};
+use common::types::ParamSpec;
 
-#[derive(Insertable, AsChangeset, Deserialize, Serialize, Debug)]
-#[table_name = "strategies"]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct StrategyData {
     #[serde(skip_serializing, skip_deserializing)]
     pub id: Option<i32>,
@@ -11,8 +11,23 @@ pub struct StrategyData {
 
     pub name: String,
     pub body: String,
+    #[serde(default)]
+    pub params: Vec<ParamSpec>,
 }
 
+/// Reasons a strategy revision could not become current
+#[derive(Debug)]
+pub enum RevisionError {
+    Db(diesel::result::Error),
+    /// Assignments with parameter values the revision does not accept, nothing was saved
+    IncompatibleParams(Vec<String>),
+}
+
+impl From<diesel::result::Error> for RevisionError {
+    fn from(e: diesel::result::Error) -> Self {
+        RevisionError::Db(e)
+    }
+}
 
 impl crate::Database {
     pub fn strategy_data(&self, sid: i32) -> LocalBoxFuture<Result<(crate::Strategy, crate::User)>> {
@@ -37,21 +52,88 @@ impl crate::Database {
         }).await
     }
 
-    pub async fn save_strategy(&self, data: StrategyData) -> Result<crate::Strategy> {
+    /// Creates a strategy or updates one owned by `data.user_id`, changed code or parameters are stored as a new revision
+    pub async fn save_strategy(&self, data: StrategyData) -> Result<crate::Strategy, RevisionError> {
         ActorExt::invoke(self.0.clone(), move |this, ctx| {
-            use schema::strategies::dsl::*;
-            use schema::users::dsl::*;
-
             let conn: &ConnType = &this.pool.get().unwrap();
+            let params = json::to_string(&data.params).expect("Parameter serialization");
+
+            conn.transaction(|| {
+                let current = match data.id {
+                    Some(sid) => strategies::table
+                        .filter(strategies::id.eq(sid))
+                        .filter(strategies::user_id.eq(data.user_id))
+                        .for_update()
+                        .get_result::<Strategy>(conn)?,
+                    None => diesel::insert_into(strategies::table)
+                        .values((
+                            strategies::user_id.eq(data.user_id),
+                            strategies::name.eq(&data.name),
+                            strategies::body.eq(&data.body),
+                        ))
+                        .get_result::<Strategy>(conn)?,
+                };
+
+                if current.revision_id.is_some() && current.body == data.body && current.param_specs() == data.params {
+                    return Ok(diesel::update(&current)
+                        .set(strategies::name.eq(&data.name))
+                        .get_result(conn)?);
+                }
+
+                let number = strategy_revisions::table
+                    .filter(strategy_revisions::strategy_id.eq(current.id))
+                    .select(diesel::dsl::max(strategy_revisions::number))
+                    .get_result::<Option<i32>>(conn)?
+                    .unwrap_or(0) + 1;
+
+                let revision = diesel::insert_into(strategy_revisions::table)
+                    .values((
+                        strategy_revisions::strategy_id.eq(current.id),
+                        strategy_revisions::number.eq(number),
+                        strategy_revisions::body.eq(&data.body),
+                        strategy_revisions::params.eq(&params),
+                    ))
+                    .get_result::<StrategyRevision>(conn)?;
+
+                diesel::update(&current)
+                    .set(strategies::name.eq(&data.name))
+                    .execute(conn)?;
+                activate_revision(conn, &revision)
+            })
+        }).await
+    }
+
+    pub async fn strategy_revisions(&self, sid: i32) -> Result<Vec<StrategyRevision>> {
+        self.0.invoke(move |this, ctx| {
+            strategy_revisions::table
+                .filter(strategy_revisions::strategy_id.eq(sid))
+                .order_by(strategy_revisions::number.desc())
+                .load(&this.conn())
+        }).await
+    }
+
+    pub async fn strategy_revision(&self, rid: i32) -> Result<StrategyRevision> {
+        self.0.invoke(move |this, ctx| {
+            strategy_revisions::table.find(rid).get_result(&this.conn())
+        }).await
+    }
 
-            let s = diesel::insert_into(strategies)
-                .values(&data)
-                .on_conflict(schema::strategies::id)
-                .do_update()
-                .set(&data)
-                .get_result(conn)?;
+    /// Makes an older revision current again, assignments of the strategy switch to it
+    pub async fn rollback_strategy(&self, uid: i32, sid: i32, rid: i32) -> Result<crate::Strategy, RevisionError> {
+        self.0.invoke(move |this, ctx| {
+            let conn: &ConnType = &this.pool.get().unwrap();
 
-            return Ok(s);
+            conn.transaction(|| {
+                let revision = strategy_revisions::table
+                    .inner_join(strategies::table)
+                    .filter(strategy_revisions::id.eq(rid))
+                    .filter(strategies::id.eq(sid))
+                    .filter(strategies::user_id.eq(uid))
+                    .select(strategy_revisions::all_columns)
+                    .get_result::<StrategyRevision>(conn)?;
+
+                activate_revision(conn, &revision)
+            })
         }).await
     }
 
@@ -70,19 +152,15 @@ impl crate::Database {
     }
 
 
-    pub async fn log_eval(&self, res: Evaluation) -> Result<Evaluation> {
-        ActorExt::invoke(self.0.clone(), move |this, ctx| {
+    pub fn log_eval(&self, res: Evaluation) -> LocalBoxFuture<'static, Result<Evaluation>> {
+        self.0.invoke(move |this, ctx| {
             use schema::evaluations::dsl::*;
             let conn: &ConnType = &this.pool.get().unwrap();
 
-            let res = diesel::insert_into(evaluations)
+            diesel::insert_into(evaluations)
                 .values(&res)
-
-                .get_result(conn)?;
-
-
-            Ok(res)
-        }).await
+                .get_result(conn)
+        })
     }
     pub async fn user_evals(&self, uid: i32) -> Result<Vec<Evaluation>> {
         self.0.invoke(move |this, _| {
@@ -102,4 +180,34 @@ impl crate::Database {
                 .get_results(&this.conn())
         }).await
     }
-}
\ No newline at end of file
+}
+
+/// Points the strategy and its assignments to the revision, fails when the parameter values
+/// of an assignment are not valid for the parameters declared by the revision
+fn activate_revision(conn: &PgConnection, revision: &StrategyRevision) -> Result<Strategy, RevisionError> {
+    let specs = revision.param_specs();
+    let assigned = assignments::table
+        .filter(assignments::strategy_id.eq(revision.strategy_id))
+        .for_update()
+        .load::<Assignment>(conn)?;
+
+    let errors = assigned.iter()
+        .filter_map(|a| ParamSpec::resolve(&specs, &a.param_values()).err()
+            .map(|e| format!("Assignment {} : {}", a.id, e)))
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(RevisionError::IncompatibleParams(errors));
+    }
+
+    diesel::update(assignments::table.filter(assignments::strategy_id.eq(revision.strategy_id)))
+        .set(assignments::revision_id.eq(revision.id))
+        .execute(conn)?;
+
+    Ok(diesel::update(strategies::table.find(revision.strategy_id))
+        .set((
+            strategies::body.eq(&revision.body),
+            strategies::params.eq(&revision.params),
+            strategies::revision_id.eq(revision.id),
+        ))
+        .get_result(conn)?)
+}
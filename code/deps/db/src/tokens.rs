@@ -0,0 +1,130 @@
+use crate::prelude::*;
+use crate::schema::{api_tokens, users};
+use ring::{digest, rand::{SecureRandom, SystemRandom}};
+
+/// Prefix making the tokens easy to recognize, e.g. by secret scanners
+const TOKEN_PREFIX: &str = "tk_";
+const TOKEN_LEN: usize = 32;
+
+/// Operations allowed to a holder of an API token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Only requests that do not modify any data
+    Read,
+    /// Everything a signed in user can do, except for managing tokens
+    Trade,
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Trade => "trade",
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s {
+            "read" => Ok(TokenScope::Read),
+            "trade" => Ok(TokenScope::Trade),
+            _ => bail!("Invalid token scope: {}", s),
+        }
+    }
+}
+
+impl ApiToken {
+    pub fn scope(&self) -> TokenScope {
+        self.scope.parse().unwrap_or(TokenScope::Read)
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "api_tokens"]
+struct NewApiToken {
+    user_id: i32,
+    name: String,
+    scope: String,
+    token_hash: Vec<u8>,
+}
+
+pub(crate) fn hash_token(token: &str) -> Vec<u8> {
+    digest::digest(&digest::SHA256, token.as_bytes()).as_ref().to_vec()
+}
+
+pub(crate) fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_LEN];
+    SystemRandom::new().fill(&mut bytes).expect("Token generation");
+    format!("{}{}", TOKEN_PREFIX, base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+impl crate::Database {
+    /// Creates a new token, the returned plaintext value is not stored and can't be retrieved later
+    pub async fn create_api_token(&self, uid: i32, name: String, scope: TokenScope) -> Result<(ApiToken, String)> {
+        let token = generate_token();
+        let data = NewApiToken {
+            user_id: uid,
+            name,
+            scope: scope.as_str().to_string(),
+            token_hash: hash_token(&token),
+        };
+
+        let created = self.0.invoke(move |this, ctx| {
+            diesel::insert_into(api_tokens::table)
+                .values(&data)
+                .get_result::<ApiToken>(&this.conn())
+        }).await?;
+        Ok((created, token))
+    }
+
+    pub async fn user_api_tokens(&self, uid: i32) -> Result<Vec<ApiToken>> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::api_tokens::dsl::*;
+
+            api_tokens
+                .filter(user_id.eq(uid))
+                .filter(revoked.is_null())
+                .order_by(created.desc())
+                .load(&this.conn())
+        }).await
+    }
+
+    pub async fn revoke_api_token(&self, uid: i32, tid: i32) -> Result<bool> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::api_tokens::dsl::*;
+
+            let q = diesel::update(api_tokens)
+                .filter(user_id.eq(uid))
+                .filter(id.eq(tid))
+                .filter(revoked.is_null())
+                .set(revoked.eq(chrono::Utc::now()));
+
+            Ok(q.execute(&this.conn())? > 0)
+        }).await
+    }
+
+    /// Finds the owner of an active token, and records that the token was used
+    pub async fn authenticate_token(&self, token: String) -> Result<Option<(ApiToken, User)>> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::api_tokens::dsl::*;
+
+            let found = api_tokens
+                .inner_join(users::table)
+                .filter(token_hash.eq(hash_token(&token)))
+                .filter(revoked.is_null())
+                .get_result::<(ApiToken, User)>(&this.conn())
+                .optional()?;
+
+            if let Some((ref active, _)) = found {
+                diesel::update(api_tokens.filter(id.eq(active.id)))
+                    .set(last_used.eq(chrono::Utc::now()))
+                    .execute(&this.conn())?;
+            }
+            Ok(found)
+        }).await
+    }
+}
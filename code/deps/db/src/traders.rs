@@ -5,10 +5,15 @@ use crate::{
     DbWorker,
     ConnType,
     schema::{self, users, ohlc, traders, User, Trader, trades, Trade},
+    crypto::{MasterKey, SealedCredentials},
+    outbox::NewOutboxMessage,
 };
+use common::types::auth::AuthInfo;
+use common::msgs::{TradeRequest, TradeResponse, ExchangeError};
+use uuid::Uuid;
 
-#[derive(Insertable, AsChangeset, Deserialize, Serialize, Debug)]
-#[table_name = "traders"]
+/// Trader as submitted by the user, credentials are encrypted before being stored
+#[derive(Deserialize, Serialize, Debug)]
 pub struct TraderData {
     #[serde(skip_deserializing, skip_serializing)]
     pub id: Option<i32>,
@@ -20,10 +25,39 @@ pub struct TraderData {
     pub api_secret: String,
 }
 
+#[derive(Insertable, AsChangeset, Debug)]
+#[table_name = "traders"]
+struct SealedTraderData {
+    id: Option<i32>,
+    user_id: i32,
+    name: String,
+    exchange: String,
+    api_key: Vec<u8>,
+    api_secret: Vec<u8>,
+    data_key: Option<Vec<u8>>,
+}
+
+impl SealedTraderData {
+    fn seal(data: TraderData, master: &MasterKey) -> Result<Self, failure::Error> {
+        let sealed = SealedCredentials::seal(master, &AuthInfo::new(data.api_key, data.api_secret))?;
+        Ok(SealedTraderData {
+            id: data.id,
+            user_id: data.user_id,
+            name: data.name,
+            exchange: data.exchange,
+            api_key: sealed.api_key,
+            api_secret: sealed.api_secret,
+            data_key: sealed.data_key,
+        })
+    }
+}
+
 #[derive(PartialEq, Deserialize, Serialize, Debug, Clone)]
 #[derive(Insertable, AsChangeset)]
 #[table_name = "trades"]
 pub struct NewTradeData {
+    /// Id of the trade request, a trade is stored once even when its result is relayed again
+    pub id: Uuid,
     #[serde(skip_deserializing, skip_serializing)]
     pub user_id: i32,
     pub trader_id: i32,
@@ -37,6 +71,10 @@ pub struct NewTradeData {
     pub status: bool,
     pub ok: Option<String>,
     pub error: Option<String>,
+    /// Strategy revision whose decision resulted in the trade
+    pub revision_id: Option<i32>,
+    /// Fee charged by the exchange, in quote currency
+    pub fee: f64,
 }
 
 
@@ -47,18 +85,36 @@ impl crate::Database {
         }).await
     }
 
-    pub async fn save_trader(&self, trader: TraderData) -> Result<Trader> {
-        self.0.invoke(move |this, ctx| {
+    /// Creates the trader when it has no id, otherwise updates it.
+    /// Returns None when the trader to update does not exist or belongs to another user.
+    pub async fn save_trader(&self, trader: TraderData) -> Result<Option<Trader>, failure::Error> {
+        let trader = SealedTraderData::seal(trader, &MasterKey::configured()?)?;
+        let saved = self.0.invoke(move |this, ctx| {
             use crate::schema::traders::dsl::*;
             let conn: &ConnType = &this.pool.get().unwrap();
-            let q = diesel::insert_into(traders)
-                .values(&trader)
-                .on_conflict(id)
-                .do_update()
-                .set(&trader)
-                .get_result::<Trader>(conn)?;
-            Ok(q)
-        }).await
+            match trader.id {
+                Some(tid) => diesel::update(traders.filter(id.eq(tid)).filter(user_id.eq(trader.user_id)))
+                    .set(&trader)
+                    .get_result::<Trader>(conn)
+                    .optional(),
+                None => diesel::insert_into(traders)
+                    .values(&trader)
+                    .get_result::<Trader>(conn)
+                    .map(Some),
+            }
+        }).await?;
+        Ok(saved)
+    }
+
+    /// Decrypts exchange credentials of a trader, these should never leave the exchange connector
+    pub async fn trader_credentials(&self, tid: i32) -> Result<AuthInfo, failure::Error> {
+        let master = MasterKey::configured()?;
+        let trader = self.0.invoke(move |this, ctx| {
+            use crate::schema::traders::dsl::*;
+            traders.filter(id.eq(tid)).get_result::<Trader>(&this.conn())
+        }).await?;
+
+        SealedCredentials::from(trader).open(&master)
     }
 
     pub async fn delete_trader(&self, uid: i32, tid: i32) -> Result<bool> {
@@ -73,15 +129,45 @@ impl crate::Database {
         }).await
     }
 
+    /// Stores the trade, publishes it to browsers of the user and notifies the user if it was successful
     pub async fn log_trade(&self, trade: NewTradeData) -> Result<Trade> {
         self.0.invoke(move |this, ctx| {
-            use self::trades::dsl::*;
+            let conn: &ConnType = &this.pool.get().unwrap();
+            conn.transaction(|| insert_trade(conn, &trade))
+        }).await
+    }
+
+    /// Logs the result of a trade request, as returned by the exchange connector
+    pub async fn log_trade_result(&self, req: TradeRequest, result: StdResult<TradeResponse, ExchangeError>) -> Result<Trade> {
+        self.0.invoke(move |this, ctx| {
+            let conn: &ConnType = &this.pool.get().unwrap();
+            conn.transaction(|| {
+                let uid = traders::table
+                    .find(req.trader_id)
+                    .select(traders::user_id)
+                    .get_result::<i32>(conn)?;
+                let pid = diesel::select(schema::pair_id(req.exch.clone(), req.pair.to_string()))
+                    .get_result::<i32>(conn)?;
 
-            diesel::insert_into(trades)
-                .values(&trade)
-                .on_conflict(id)
-                .do_nothing()
-                .get_result::<Trade>(&this.conn())
+                let (amount, price, fee, error) = match result {
+                    Ok(resp) => (resp.amount, resp.price, resp.fee, None),
+                    Err(e) => (req.amount, 0.0, 0.0, Some(e.to_string())),
+                };
+                insert_trade(conn, &NewTradeData {
+                    id: req.id,
+                    user_id: uid,
+                    trader_id: req.trader_id,
+                    pair_id: pid,
+                    buy: req.buy,
+                    amount,
+                    price,
+                    status: error.is_none(),
+                    ok: None,
+                    error,
+                    revision_id: req.revision_id,
+                    fee,
+                })
+            })
         }).await
     }
 
@@ -93,4 +179,88 @@ impl crate::Database {
                 .load(&this.conn())
         }).await
     }
-}
\ No newline at end of file
+}
+/// Stores the trade unless it was already stored, has to run in a transaction.
+/// A new trade is published to browsers of the user, and the user is notified if it was successful.
+fn insert_trade(conn: &PgConnection, trade: &NewTradeData) -> Result<Trade> {
+    use self::trades::dsl::*;
+
+    let inserted = diesel::insert_into(trades)
+        .values(trade)
+        .on_conflict(id)
+        .do_nothing()
+        .get_result::<Trade>(conn)
+        .optional()?;
+    let logged = match inserted {
+        Some(logged) => logged,
+        None => return trades.find(trade.id).get_result::<Trade>(conn),
+    };
+
+    let pair = schema::pairs::table.find(logged.pair_id).get_result::<schema::Pair>(conn)?;
+    let event = common::msgs::TradeLogged {
+        id: logged.id,
+        user_id: logged.user_id,
+        trader_id: logged.trader_id,
+        pair: pair.clone().into(),
+        time: logged.time.timestamp(),
+        buy: logged.buy,
+        amount: logged.amount,
+        price: logged.price,
+        fee: logged.fee,
+        status: logged.status,
+    };
+    NewOutboxMessage::new(common::CHANNEL_TRADES, &event).insert(conn)?;
+    if !logged.status {
+        return Ok(logged);
+    }
+
+    let side = if logged.buy { "Bought" } else { "Sold" };
+    crate::notifications::notify_with(
+        conn,
+        logged.user_id,
+        crate::NotifyEvent::TradeExecuted,
+        &format!("Trade executed on {} {}", pair.exchange, pair.pair),
+        &format!("{} {} at {}, fee {}", side, logged.amount, logged.price, logged.fee),
+    )?;
+    Ok(logged)
+}
+
+/// Encrypts credentials stored before encryption was introduced, returns number of updated traders
+pub(crate) fn seal_plaintext_credentials(conn: &PgConnection) -> Result<usize, failure::Error> {
+    use crate::schema::traders::dsl::*;
+
+    conn.transaction(|| {
+        let plain = traders.filter(data_key.is_null()).for_update().load::<Trader>(conn)?;
+        if plain.is_empty() {
+            return Ok(0);
+        }
+
+        let master = MasterKey::configured()?;
+        for trader in plain.iter() {
+            let info = AuthInfo::new(String::from_utf8(trader.api_key.clone())?, String::from_utf8(trader.api_secret.clone())?);
+            diesel::update(traders.filter(id.eq(trader.id)))
+                .set(&SealedCredentials::seal(&master, &info)?)
+                .execute(conn)?;
+        }
+        Ok(plain.len())
+    })
+}
+
+/// Re-encrypts data keys of all traders with a new master key, returns number of updated traders
+pub fn rotate_master_key(old: &MasterKey, new: &MasterKey) -> Result<usize, failure::Error> {
+    use crate::schema::traders::dsl::*;
+
+    let conn = PgConnection::establish(&crate::db_url())?;
+    conn.transaction(|| {
+        let sealed = traders.filter(data_key.is_not_null()).for_update().load::<Trader>(&conn)?;
+        for trader in sealed.iter() {
+            let mut credentials = SealedCredentials::from(trader.clone());
+            credentials.rewrap(old, new).map_err(|e| format_err!("Trader {}: {}", trader.id, e))?;
+            diesel::update(traders.filter(id.eq(trader.id)))
+                .set(data_key.eq(credentials.data_key))
+                .execute(&conn)?;
+        }
+        crate::two_factor::rotate_totp_secrets(&conn, old, new)?;
+        Ok(sealed.len())
+    })
+}
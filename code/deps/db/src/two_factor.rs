@@ -0,0 +1,145 @@
+use crate::prelude::*;
+use crate::schema::{users, recovery_codes};
+use crate::crypto::MasterKey;
+use crate::tokens::hash_token;
+use ring::rand::{SecureRandom, SystemRandom};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+fn generate_recovery_codes() -> Vec<String> {
+    let rng = SystemRandom::new();
+    (0..RECOVERY_CODE_COUNT).map(|_| {
+        let mut bytes = [0u8; 10];
+        rng.fill(&mut bytes).expect("Recovery code generation");
+        let chars = bytes.iter()
+            .map(|b| RECOVERY_CODE_ALPHABET[*b as usize % RECOVERY_CODE_ALPHABET.len()] as char)
+            .collect::<String>();
+        format!("{}-{}", &chars[..5], &chars[5..])
+    }).collect()
+}
+
+/// Codes are compared case insensitively, and with the separator being optional
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase()
+}
+
+fn store_recovery_codes(conn: &PgConnection, uid: i32) -> Result<Vec<String>> {
+    let codes = generate_recovery_codes();
+    diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(uid))).execute(conn)?;
+
+    let rows = codes.iter()
+        .map(|c| (recovery_codes::user_id.eq(uid), recovery_codes::code_hash.eq(hash_token(&normalize_recovery_code(c)))))
+        .collect::<Vec<_>>();
+    diesel::insert_into(recovery_codes::table).values(&rows).execute(conn)?;
+    Ok(codes)
+}
+
+/// Re-encrypts TOTP secrets during master key rotation
+pub(crate) fn rotate_totp_secrets(conn: &PgConnection, old: &MasterKey, new: &MasterKey) -> Result<usize, failure::Error> {
+    use crate::schema::users::dsl::*;
+
+    let secrets = users
+        .filter(totp_secret.is_not_null())
+        .select((id, totp_secret))
+        .for_update()
+        .load::<(i32, Option<Vec<u8>>)>(conn)?;
+
+    for (uid, secret) in secrets.iter() {
+        if let Some(secret) = secret {
+            let reencrypted = new.encrypt(&old.decrypt(secret)?)?;
+            diesel::update(users.filter(id.eq(uid)))
+                .set(totp_secret.eq(reencrypted))
+                .execute(conn)?;
+        }
+    }
+    Ok(secrets.len())
+}
+
+impl crate::Database {
+    /// Stores a new secret which is not enabled until `enable_totp` is called after the user proves they can use it
+    pub async fn set_pending_totp(&self, uid: i32, secret: Vec<u8>) -> Result<(), failure::Error> {
+        let encrypted = MasterKey::configured()?.encrypt(&secret)?;
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::users::dsl::*;
+
+            diesel::update(users.filter(id.eq(uid)).filter(totp_enabled.eq(false)))
+                .set((totp_secret.eq(encrypted), totp_last_step.eq(None::<i64>)))
+                .execute(&this.conn())
+        }).await?;
+        Ok(())
+    }
+
+    pub async fn totp_secret(&self, uid: i32) -> Result<Option<Vec<u8>>, failure::Error> {
+        let encrypted = self.0.invoke(move |this, ctx| {
+            use crate::schema::users::dsl::*;
+            users.filter(id.eq(uid)).select(totp_secret).get_result::<Option<Vec<u8>>>(&this.conn())
+        }).await?;
+
+        match encrypted {
+            Some(encrypted) => Ok(Some(MasterKey::configured()?.decrypt(&encrypted)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the time step of an accepted code, returns false if this or a later step was already used
+    pub async fn record_totp_step(&self, uid: i32, step: i64) -> Result<bool> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::users::dsl::*;
+
+            let updated = diesel::update(users.filter(id.eq(uid)).filter(totp_last_step.is_null().or(totp_last_step.lt(step))))
+                .set(totp_last_step.eq(step))
+                .execute(&this.conn())?;
+            Ok(updated > 0)
+        }).await
+    }
+
+    /// Enables the pending secret, and returns a fresh set of recovery codes
+    pub async fn enable_totp(&self, uid: i32) -> Result<Vec<String>> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            conn.transaction(|| {
+                diesel::update(users::table.filter(users::id.eq(uid)))
+                    .set(users::totp_enabled.eq(true))
+                    .execute(&conn)?;
+                store_recovery_codes(&conn, uid)
+            })
+        }).await
+    }
+
+    pub async fn disable_totp(&self, uid: i32) -> Result<()> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            conn.transaction(|| {
+                diesel::update(users::table.filter(users::id.eq(uid)))
+                    .set((users::totp_enabled.eq(false), users::totp_secret.eq(None::<Vec<u8>>), users::totp_last_step.eq(None::<i64>)))
+                    .execute(&conn)?;
+                diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(uid))).execute(&conn)?;
+                Ok(())
+            })
+        }).await
+    }
+
+    /// Replaces all recovery codes of the user, the old ones stop working
+    pub async fn regenerate_recovery_codes(&self, uid: i32) -> Result<Vec<String>> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            conn.transaction(|| store_recovery_codes(&conn, uid))
+        }).await
+    }
+
+    /// Marks a recovery code as used, returns false if it does not exist or was already used
+    pub async fn use_recovery_code(&self, uid: i32, code: String) -> Result<bool> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::recovery_codes::dsl::*;
+
+            let updated = diesel::update(recovery_codes)
+                .filter(user_id.eq(uid))
+                .filter(code_hash.eq(hash_token(&normalize_recovery_code(&code))))
+                .filter(used.is_null())
+                .set(used.eq(chrono::Utc::now()))
+                .execute(&this.conn())?;
+            Ok(updated > 0)
+        }).await
+    }
+}
@@ -4,7 +4,8 @@ use diesel::prelude::*;
 use crate::{
     DbWorker,
     ConnType,
-    schema::{self, users, ohlc, traders, User, Trader},
+    schema::{self, users, ohlc, traders, user_tokens, User, Trader},
+    tokens::{generate_token, hash_token},
 };
 use crate::schema::Trade;
 
@@ -19,16 +20,40 @@ pub struct UserAuthInfo {
     pub password: String,
 }
 
+/// Password chosen at signup or through a reset, passwords are not checked on login,
+/// so accounts created before the rule still work
+#[derive(Validate, Debug)]
+pub struct NewPassword {
+    #[validate(length(min = 8, max = 256, message = "Password must have at least 8 characters"))]
+    pub password: String,
+}
+
+/// What a token sent by email can be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
 
-impl Into<common::types::auth::AuthInfo> for Trader {
-    fn into(self) -> common::types::auth::AuthInfo {
-        common::types::auth::AuthInfo {
-            key: self.api_key,
-            secret: self.api_secret,
+impl UserTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserTokenPurpose::VerifyEmail => "verify_email",
+            UserTokenPurpose::ResetPassword => "reset_password",
         }
     }
 }
 
+#[derive(Insertable, Debug)]
+#[table_name = "user_tokens"]
+struct NewUserToken {
+    user_id: i32,
+    purpose: String,
+    token_hash: Vec<u8>,
+    expires: chrono::DateTime<chrono::Utc>,
+}
+
+
 impl crate::Database {
     pub async fn get_user(&self, uid: i32) -> Result<User, diesel::result::Error> {
         ActorExt::invoke(self.0.clone(), move |this, ctx| {
@@ -55,4 +80,77 @@ impl crate::Database {
             Ok(r)
         }).await
     }
-}
\ No newline at end of file
+
+    pub async fn user_by_email(&self, mail: String) -> Result<Option<User>> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::users::dsl::*;
+            users.filter(email.eq(mail)).get_result::<User>(&this.conn()).optional()
+        }).await
+    }
+
+    /// Creates a single-use token valid for a limited time, only its hash is stored
+    pub async fn create_user_token(&self, uid: i32, purpose: UserTokenPurpose, valid_for: chrono::Duration) -> Result<String> {
+        let token = generate_token();
+        let data = NewUserToken {
+            user_id: uid,
+            purpose: purpose.as_str().to_string(),
+            token_hash: hash_token(&token),
+            expires: chrono::Utc::now() + valid_for,
+        };
+
+        self.0.invoke(move |this, ctx| {
+            diesel::insert_into(user_tokens::table)
+                .values(&data)
+                .execute(&this.conn())
+        }).await?;
+        Ok(token)
+    }
+
+    /// Marks the token as used, returns id of its user if it was valid, unused and not expired
+    pub async fn consume_user_token(&self, token: String, purpose: UserTokenPurpose) -> Result<Option<i32>> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::user_tokens::dsl;
+
+            diesel::update(dsl::user_tokens)
+                .filter(dsl::token_hash.eq(hash_token(&token)))
+                .filter(dsl::purpose.eq(purpose.as_str()))
+                .filter(dsl::used.is_null())
+                .filter(dsl::expires.gt(diesel::dsl::now))
+                .set(dsl::used.eq(chrono::Utc::now()))
+                .returning(dsl::user_id)
+                .get_result::<i32>(&this.conn())
+                .optional()
+        }).await
+    }
+
+    pub async fn set_email_verified(&self, uid: i32) -> Result<()> {
+        self.0.invoke(move |this, ctx| {
+            use crate::schema::users::dsl::*;
+
+            diesel::update(users.filter(id.eq(uid)))
+                .set((has_verified_email.eq(true), is_verified.eq(true)))
+                .execute(&this.conn())?;
+            Ok(())
+        }).await
+    }
+
+    /// Replaces the password hash, and invalidates all outstanding reset tokens of the user
+    pub async fn set_password(&self, uid: i32, hash: String) -> Result<()> {
+        self.0.invoke(move |this, ctx| {
+            let conn = this.conn();
+            conn.transaction(|| {
+                diesel::update(users::table.filter(users::id.eq(uid)))
+                    .set(users::password.eq(hash))
+                    .execute(&conn)?;
+
+                diesel::update(user_tokens::table)
+                    .filter(user_tokens::user_id.eq(uid))
+                    .filter(user_tokens::purpose.eq(UserTokenPurpose::ResetPassword.as_str()))
+                    .filter(user_tokens::used.is_null())
+                    .set(user_tokens::used.eq(chrono::Utc::now()))
+                    .execute(&conn)?;
+                Ok(())
+            })
+        }).await
+    }
+}
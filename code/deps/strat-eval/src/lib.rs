@@ -6,6 +6,8 @@ pub mod lua;
 
 pub struct StrategyInput {
     pub ohlc: BTreeMap<i64, Ohlc>,
+    /// Resolved parameter values, exposed to the strategy as `params`
+    pub params: ParamValues,
 
 }
 
@@ -14,11 +16,98 @@ pub trait TradingStrategy {
 }
 
 
-pub fn eval(ohlc : BTreeMap<i64,Ohlc>, strat : String) -> Result<TradingPosition, EvalError> {
-    let strat = lua::LuaStrategy::new(&strat).map_err(|e| EvalError::InvalidStrategy(e.to_string()))?;
+/// Syntax error in strategy source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyntaxError {
+    /// Line of the source where the error was found, if it could be determined
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl SyntaxError {
+    /// Parses messages in the `strategy:<line>: <message>` format produced by lua
+    fn from_message(msg: &str) -> Self {
+        let rest = msg.trim_start_matches("strategy:");
+        let mut parts = rest.splitn(2, ':');
+        match (parts.next().and_then(|l| l.parse().ok()), parts.next()) {
+            (Some(line), Some(message)) => SyntaxError { line: Some(line), message: message.trim().to_string() },
+            _ => SyntaxError { line: None, message: msg.to_string() },
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Checks that the strategy compiles, without executing it
+pub fn check_syntax(strat: &str) -> Result<(), SyntaxError> {
+    lua::LuaStrategy::check_syntax(strat)
+}
+
+/// Compiles the strategy once, so it can be evaluated on any number of inputs
+pub fn compile(strat: &str) -> Result<lua::LuaStrategy, SyntaxError> {
+    lua::LuaStrategy::new(strat)
+}
+
+pub fn eval(ohlc : BTreeMap<i64,Ohlc>, strat : String, params: ParamValues) -> Result<TradingPosition, EvalError> {
+    let strat = compile(&strat).map_err(|e| EvalError::InvalidStrategy(e.to_string()))?;
     let input = StrategyInput {
-        ohlc
+        ohlc,
+        params,
     };
 
     strat.decide(&input)
-}
\ No newline at end of file
+}
+
+/// Fee paid on every change of position, relative to the traded value
+const BACKTEST_FEE: f64 = 0.002;
+/// Number of candles visible to the strategy in each step, same as in live evaluation
+const BACKTEST_HISTORY: usize = 1000;
+
+/// Replays the strategy over candles, a position decided on a candle in `from..=to` is held until the next one
+pub fn backtest(ohlc: BTreeMap<i64, Ohlc>, strat: String, params: ParamValues, from: i64, to: i64) -> Result<BacktestResult, EvalError> {
+    let strat = compile(&strat).map_err(|e| EvalError::InvalidStrategy(e.to_string()))?;
+
+    let candles = ohlc.into_iter().map(|(_, c)| c).collect::<Vec<_>>();
+    let mut input = StrategyInput {
+        ohlc: BTreeMap::new(),
+        params,
+    };
+
+    let mut res = BacktestResult::default();
+    let (mut equity, mut peak, mut position) = (1.0f64, 1.0f64, 0.0f64);
+    for (i, candle) in candles.iter().enumerate() {
+        input.ohlc.insert(candle.time, candle.clone());
+        if input.ohlc.len() > BACKTEST_HISTORY {
+            input.ohlc.pop_first();
+        }
+        let next = match candles.get(i + 1) {
+            Some(next) if candle.time >= from && candle.time <= to && candle.close > 0.0 => next,
+            _ => continue,
+        };
+
+        let target = match strat.decide(&input)? {
+            TradingPosition::Long => 1.0,
+            TradingPosition::Short => -1.0,
+            TradingPosition::Indeterminate => 0.0,
+        };
+        if target != position {
+            equity *= 1.0 - BACKTEST_FEE * (target - position).abs();
+            position = target;
+            res.trades += 1;
+        }
+
+        equity *= 1.0 + position * (next.close / candle.close - 1.0);
+        peak = peak.max(equity);
+        res.max_drawdown = res.max_drawdown.max(1.0 - equity / peak);
+    }
+
+    res.ret = equity - 1.0;
+    Ok(res)
+}
@@ -3,53 +3,102 @@ use std::mem;
 use ta::{
     indicators::*,
 };
-use rlua::{self, Lua, UserData, UserDataMethods};
-use crate::{StrategyInput, TradingStrategy};
+use rlua::{self, Lua, UserData, UserDataMethods, HookTriggers};
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::{StrategyInput, TradingStrategy, SyntaxError};
+
+/// Name of the strategy chunk, errors are reported as `strategy:<line>: <message>`
+const CHUNK_NAME: &str = "=strategy";
+/// Instructions between two checks of the execution limit
+const HOOK_INTERVAL: u32 = 10_000;
+/// Strategy is stopped after executing `INSTRUCTION_LIMIT * HOOK_INTERVAL` instructions
+const INSTRUCTION_LIMIT: u32 = 1_000;
+/// Memory the lua state can allocate, including the indicators and candles
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
 
 
 pub struct LuaStrategy {
     lua: Box<Lua>,
-    src: String,
+    /// Compiled strategy chunk, run in the sandbox environment
+    chunk: rlua::RegistryKey,
+    steps: Arc<AtomicU32>,
 }
 
 
 impl LuaStrategy {
     pub fn from_file(path: &str) -> Result<LuaStrategy> {
-        return Self::new(&::std::fs::read_to_string(::std::path::Path::new(path))?);
+        let src = ::std::fs::read_to_string(::std::path::Path::new(path))?;
+        return Self::new(&src).map_err(|e| format_err!("{}", e));
     }
-    pub fn new(src: &str) -> Result<LuaStrategy> {
+
+    /// Compiles the strategy, it can then be executed any number of times
+    pub fn new(src: &str) -> Result<LuaStrategy, SyntaxError> {
         let lua = Box::new(Lua::new());
 
-        let x: Result<(), rlua::Error> = lua.context(|ctx| {
+        let chunk = lua.context(|ctx| {
             register_ta(ctx).unwrap();
             init_saferun(ctx).unwrap();
+            let env: rlua::Table = ctx.globals().get("sandbox_env")?;
+            let fun = ctx.load(src).set_name(CHUNK_NAME)?.set_environment(env)?.into_function()?;
+            ctx.create_registry_value(fun)
+        }).map_err(syntax_error)?;
+        lua.set_memory_limit(Some(MEMORY_LIMIT));
+
+        let steps = Arc::new(AtomicU32::new(0));
+        let counter = steps.clone();
+        lua.set_hook(HookTriggers { every_nth_instruction: Some(HOOK_INTERVAL), ..Default::default() }, move |_, _| {
+            if counter.fetch_add(1, Ordering::Relaxed) >= INSTRUCTION_LIMIT {
+                return Err(rlua::Error::RuntimeError("Script used too much time".into()));
+            }
             Ok(())
         });
 
-        let _ = x?;
-
         return Ok(LuaStrategy {
             lua,
-            src: src.into(),
+            chunk,
+            steps,
         });
     }
 
-    pub fn set_data(&self, data: &StrategyInput) {
+    /// Compiles the source without running it
+    pub fn check_syntax(src: &str) -> Result<(), SyntaxError> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            ctx.load(src).set_name(CHUNK_NAME)?.into_function().map(|_| ())
+        }).map_err(syntax_error)
+    }
+
+    /// Fails when the lua state runs out of memory, strategies can keep data around between runs
+    pub fn set_data(&self, data: &StrategyInput) -> Result<(), rlua::Error> {
         self.lua.context(|ctx| {
             ctx.globals()
                 .set("__ohlc",
                      data.ohlc
                          .iter()
                          .map(|(k, v)| { LuaOhlc(v.clone()) })
-                         .collect::<Vec<LuaOhlc>>()).unwrap();
-        });
+                         .collect::<Vec<LuaOhlc>>())?;
+
+            let params = ctx.create_table()?;
+            for (name, value) in data.params.iter() {
+                let value = match *value {
+                    ParamValue::Bool(b) => rlua::Value::Boolean(b),
+                    ParamValue::Integer(i) => rlua::Value::Integer(i),
+                    ParamValue::Float(f) => rlua::Value::Number(f),
+                };
+                params.set(name.as_str(), value)?;
+            }
+            let env: rlua::Table = ctx.globals().get("sandbox_env")?;
+            env.set("params", params)
+        })
     }
     pub fn execute(&self) -> Result<TradingPosition, EvalError> {
         return self.lua.context(|ctx| {
             debug!("Executing strategy");
+            self.steps.store(0, Ordering::Relaxed);
             let sandbox: rlua::Function = ctx.globals().get("safe_run").unwrap();
+            let chunk: rlua::Function = ctx.registry_value(&self.chunk).unwrap();
 
-            let res = sandbox.call::<_, (rlua::Value, rlua::Value)>(self.src.clone());
+            let res = sandbox.call::<_, (rlua::Value, rlua::Value)>(chunk);
 
             if let Err(e) = res {
                 return Err(EvalError::InvalidStrategy(format!("Could not launch strategy: {}", e)));
@@ -67,7 +116,7 @@ impl LuaStrategy {
                     v
                 }
                 (_, rlua::Value::String(ref s)) => {
-                    Err(EvalError::InvalidStrategy(format!("Invalid strategy output : {}", s.to_str().unwrap())))
+                    Err(EvalError::RuntimeError(s.to_str().unwrap_or("").to_string()))
                 }
                 (_, e) => {
                     Err(EvalError::InvalidStrategy(format!("Invalid strategy output : {:?}", e)))
@@ -77,9 +126,16 @@ impl LuaStrategy {
     }
 }
 
+fn syntax_error(e: rlua::Error) -> SyntaxError {
+    match e {
+        rlua::Error::SyntaxError { message, .. } => SyntaxError::from_message(&message),
+        e => SyntaxError { line: None, message: e.to_string() },
+    }
+}
+
 impl TradingStrategy for LuaStrategy {
     fn decide(&self, data: &StrategyInput) -> Result<TradingPosition, EvalError> {
-        self.set_data(data);
+        self.set_data(data).map_err(|e| EvalError::RuntimeError(e.to_string()))?;
         return self.execute();
     }
 }
@@ -133,7 +189,8 @@ fn init_saferun(lua: rlua::Context) -> Result<(), rlua::Error> {
 -- save a pointer to globals that would be unreachable in sandbox
 local e=_ENV
 
--- sample sandbox environment
+-- sample sandbox environment, without pcall and coroutines, which could catch the
+-- error raised by the instruction hook and keep the strategy running
 sandbox_env = {
   ta = ta,
   __ohlc = __ohlc,
@@ -141,14 +198,10 @@ sandbox_env = {
   ipairs = ipairs,
   next = next,
   pairs = pairs,
-  pcall = pcall,
   tonumber = tonumber,
   tostring = tostring,
   type = type,
   unpack = unpack,
-  coroutine = { create = coroutine.create, resume = coroutine.resume,
-      running = coroutine.running, status = coroutine.status,
-      wrap = coroutine.wrap },
   string = { byte = string.byte, char = string.char, find = string.find,
       format = string.format, gmatch = string.gmatch, gsub = string.gsub,
       len = string.len, lower = string.lower, match = string.match,
@@ -167,23 +220,11 @@ sandbox_env = {
   os = { clock = os.clock, difftime = os.difftime, time = os.time },
 }
 
-function run_sandbox(code, ...)
-    local instr_limit = 1e7
-    local instr_count = 0
-
-    local function debug_step()
-        instr_count = instr_count + 1
-        if instr_count > instr_limit then
-            error("Script used too much time")
-        end
-    end
-
-
-    local untrusted_fun, message = load(code,nil,'t',sandbox_env)
-    if not untrusted_fun then return nil, message end
-    -- debug.sethook(debug_step)
+-- the strategy is compiled from rust with sandbox_env as its environment,
+-- execution time is limited by an instruction hook and memory by the allocator
+function run_sandbox(untrusted_fun, ...)
     local stat, res = pcall(untrusted_fun, ...)
-    -- debug.sethook(nil)
+    if not stat then return nil, tostring(res) end
     return res, nil
 end
 return run_sandbox
@@ -267,3 +308,42 @@ fn register_ta(lua: rlua::Context) -> Result<()> {
     Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execute(src: &str) -> Result<TradingPosition, EvalError> {
+        LuaStrategy::new(src).unwrap().execute()
+    }
+
+    #[test]
+    fn infinite_loop_is_stopped() {
+        match execute("while true do end") {
+            Err(EvalError::RuntimeError(msg)) => assert!(msg.contains("too much time"), "{}", msg),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn limit_can_not_be_caught() {
+        let srcs = [
+            "while true do pcall(function() while true do end end) end",
+            "while true do coroutine.resume(coroutine.create(function() while true do end end)) end",
+        ];
+        for src in srcs.iter() {
+            assert!(match execute(src) {
+                Err(EvalError::RuntimeError(_)) => true,
+                _ => false,
+            }, "{} has to fail", src);
+        }
+    }
+
+    #[test]
+    fn limit_is_reset_between_runs() {
+        let strategy = LuaStrategy::new("local x = 0 for i = 1, 1000000 do x = x + i end return \"long\"").unwrap();
+        for _ in 0..3 {
+            assert_eq!(strategy.execute().unwrap(), TradingPosition::Long);
+        }
+    }
+}
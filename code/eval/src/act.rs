@@ -17,7 +17,8 @@ impl Actor for Evaluator {
 impl Evaluator {
     pub async fn new(client: anats::Client, db: Database) -> Addr<Self> {
         Actor::create(|ctx| {
-            client.subscribe(common::CHANNEL_EVAL_REQUESTS, common::GROUP_EVAL_WORKERS.to_string(), ctx.address().recipient());
+            client.subscribe(common::CHANNEL_EVAL_REQUESTS, common::GROUP_EVAL_WORKERS.to_string(), ctx.address().recipient::<EvalRequest>());
+            client.subscribe(common::CHANNEL_BACKTEST_REQUESTS, common::GROUP_EVAL_WORKERS.to_string(), ctx.address().recipient::<BacktestRequest>());
             Evaluator {
                 client,
                 db,
@@ -32,7 +33,9 @@ impl Handler<EvalRequest> for Evaluator {
     fn handle(&mut self, req: EvalRequest, _ctx: &mut Self::Context) -> Self::Result {
         let db = self.db.clone();
         Response::r#async(async move {
-            let strat = db.single_strategy(req.strat_id).await.unwrap();
+            let revision = db.strategy_revision(req.revision_id).await.unwrap();
+            let params = ParamSpec::resolve(&revision.param_specs(), &req.params)
+                .map_err(EvalError::InvalidParams)?;
 
             let since = req.last - (req.period.seconds() * 1000);
             // Thousand ohlc candles ought to be enough for everyone
@@ -44,7 +47,7 @@ impl Handler<EvalRequest> for Evaluator {
 
             error!("Starting Eval a");
 
-            let (res, time) = measure_time(|| strat_eval::eval(data, strat.body));
+            let (res, time) = measure_time(|| strat_eval::eval(data, revision.body, params));
 
             error!("Done Eval :{:?} in :{:?}", res, time);
             res
@@ -52,4 +55,29 @@ impl Handler<EvalRequest> for Evaluator {
     }
 }
 
+impl Handler<BacktestRequest> for Evaluator {
+    type Result = Response<BacktestResult, EvalError>;
 
+    fn handle(&mut self, req: BacktestRequest, _ctx: &mut Self::Context) -> Self::Result {
+        let db = self.db.clone();
+        Response::r#async(async move {
+            let revision = db.strategy_revision(req.revision_id).await
+                .map_err(|_| EvalError::MissingData)?;
+            let params = ParamSpec::resolve(&revision.param_specs(), &req.params)
+                .map_err(EvalError::InvalidParams)?;
+
+            // Strategies see the same amount of history as in live evaluation
+            let since = req.from - (req.period.seconds() * 1000);
+            let data = db.ohlc_history_backfilled(req.pair_id, req.period, since).await
+                .map_err(|_| EvalError::MissingData)?;
+            let data = data.into_iter()
+                .filter(|x| x.time <= req.to + req.period.seconds())
+                .map(|x| (x.time, x))
+                .collect();
+
+            let (res, time) = measure_time(|| strat_eval::backtest(data, revision.body, params, req.from, req.to));
+            debug!("Backtest of revision {} done in {:?} ms", req.revision_id, time);
+            res
+        }.boxed_local().compat())
+    }
+}
@@ -6,7 +6,7 @@ fn main() {
     common::init();
     println!("Starting eval");
     common::launch(|| async {
-        let client = anats::Client::new("nats://nats:4222").await;
+        let client = anats::Client::new(common::config().nats.url.clone()).await;
         let db = db::start();
 
         let _ = act::Evaluator::new(client,db).await;
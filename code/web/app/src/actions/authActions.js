@@ -26,6 +26,10 @@ export function redirectToHome() {
 export function signin(data) {
   return function (dispatch) {
     return api.signin(data).then(d => {
+      if (d.twoFactor) {
+        dispatch(goTo("/app/auth/2fa"));
+        return;
+      }
       dispatch({type: AUTH_OK});
       dispatch(redirectToHome())
     }).catch(e => {
@@ -21,9 +21,8 @@ export const TYPE_ASSIGNMENT = {
     field: "assignments",
     modelName: "Assignment",
     id: (e) => {
-        return `/${e.pair_id}/${e.period}`
+        return e.id
     },
-
 };
 
 export const TYPE_PAIR = {
@@ -128,10 +127,30 @@ export default class Api {
             if (response.status >= 400) {
                 throw response
             }
+            if (response.status == 202) {
+                return {twoFactor: true};
+            }
             return {}; //response.json();
         })
     }
 
+    static signinTwoFactor(data) {
+        return fetch("/api/signin/2fa", {
+            credentials: 'include',
+            method: 'post',
+            body: JSON.stringify(data),
+            headers: {
+                'Accept': 'application/json',
+                'Content-Type': 'application/json',
+            },
+        }).then(response => {
+            if (response.status >= 400) {
+                throw response
+            }
+            return {};
+        })
+    }
+
     static signup(data) {
         return fetch("/api/signup/", {
             credentials: 'include',
@@ -162,4 +181,55 @@ export default class Api {
             return {};
         })
     }
+
+    static resetPassword(data) {
+        return fetch("/api/password-reset/confirm", {
+            credentials: 'include',
+            method: 'post',
+            body: JSON.stringify(data),
+            headers: {
+                'Accept': 'application/json',
+                'Content-Type': 'application/json',
+            },
+        }).then(response => {
+            if (response.status >= 400) {
+                throw response
+            }
+            return {};
+        })
+    }
+
+    static verifyEmail(data) {
+        return fetch("/api/verify-email/", {
+            credentials: 'include',
+            method: 'post',
+            body: JSON.stringify(data),
+            headers: {
+                'Accept': 'application/json',
+                'Content-Type': 'application/json',
+            },
+        }).then(response => {
+            if (response.status >= 400) {
+                throw response
+            }
+            return {};
+        })
+    }
+
+    static verifyNotificationChannel(data) {
+        return fetch("/api/notifications/channels/verify", {
+            credentials: 'include',
+            method: 'post',
+            body: JSON.stringify(data),
+            headers: {
+                'Accept': 'application/json',
+                'Content-Type': 'application/json',
+            },
+        }).then(response => {
+            if (response.status >= 400) {
+                throw response
+            }
+            return {};
+        })
+    }
 }
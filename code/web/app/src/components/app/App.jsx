@@ -13,8 +13,11 @@ import StrategyDetail from "./../strategy/StrategyDetail";
 import AssignmentList from "../assignment/AssignmentList";
 import TraderList from "../traders/TraderList";
 import Login from "../util/Auth";
+import ResetPassword from "../util/ResetPassword";
+import VerifyEmail from "../util/VerifyEmail";
+import TwoFactor from "../util/TwoFactor";
 
-import {TYPE_PAIR, TYPE_PERIOD} from "../../api/baseApi";
+import api, {TYPE_PAIR, TYPE_PERIOD} from "../../api/baseApi";
 import Home from "./Home";
 import { createMuiTheme, MuiThemeProvider } from "@material-ui/core/styles";
 import Typography from "@material-ui/core/Typography";
@@ -42,7 +45,13 @@ const Evaluations = () => {
 
 function AppRoot(props) {
   const authRoutes = {
-    "/app/auth": () => (<Login/>)
+    "/app/auth": () => (<Login/>),
+    "/app/auth/2fa": () => (<TwoFactor/>),
+    "/app/auth/reset/:token": ({token}) => (<ResetPassword token={token}/>),
+    "/app/auth/verify/:token": ({token}) => (<VerifyEmail token={token}/>),
+    "/app/auth/verify-channel/:token": ({token}) => (
+      <VerifyEmail token={token} title="Verify your notification address" verify={api.verifyNotificationChannel}/>
+    )
   };
 
   const normalRoutes = {
@@ -98,7 +98,9 @@ function editorAttrs(pairs, periods, strategies, traders, setData) {
           trader_id: e ? e.id : null
         }))
       }
-    }
+    },
+    {name: "signal_url", title: "Signal webhook", type: "text"},
+    {name: "signal_secret", title: "Signal secret", type: "text"}
   ];
 }
 
@@ -112,7 +114,8 @@ function AssignmentList(props) {
     pair: null,
     period: null,
     strategy_id: null,
-    trader_id: null
+    trader_id: null,
+    enabled: true
   });
 
 
@@ -138,6 +141,8 @@ function AssignmentList(props) {
             <TableCell>Period</TableCell>
             <TableCell>Strategy</TableCell>
             <TableCell>Trader</TableCell>
+            <TableCell>Signals</TableCell>
+            <TableCell>Enabled</TableCell>
             <TableCell align="right">Actions</TableCell>
           </TableRow>
         </TableHead>
@@ -156,7 +161,17 @@ function AssignmentList(props) {
                 <TableCell>{row.period}</TableCell>
                 <TableCell>{row.strategy ? row.strategy.name : ""}</TableCell>
                 <TableCell>{row.trader ? row.trader.name : (<i>None</i>)}</TableCell>
+                <TableCell>{row.signal_url ? row.signal_url : (<i>None</i>)}</TableCell>
+                <TableCell>{row.enabled ? "Yes" : "No"}</TableCell>
                 <TableCell align="right">
+                  <Button color="primary" onClick={() => {
+                    dispatch(postOne(TYPE_ASSIGNMENT, {
+                      ...row.ref,
+                      exchange: row.pair.exchange,
+                      pair: row.pair.pair,
+                      enabled: !row.enabled
+                    }));
+                  }}>{row.enabled ? "Disable" : "Enable"}</Button>
                   <Button color="primary" onClick={() => {
                     dispatch(deleteOne(TYPE_ASSIGNMENT, row)).then(() => setOpen(false));
                   }}>Delete</Button>
@@ -177,11 +192,11 @@ function AssignmentList(props) {
         if (save) {
           dispatch(postOne(TYPE_ASSIGNMENT, newData)).then(() => {
             setOpen(false);
-            setNewData({});
+            setNewData({enabled: true});
           })
         } else {
           setOpen(false);
-          setNewData({});
+          setNewData({enabled: true});
         }
       }}
       onDelete={creating ? null : e => {
@@ -79,7 +79,6 @@ function TraderList(props) {
             <TableRow>
               <TableCell className={classes.cell}>Name</TableCell>
               <TableCell className={classes.cell}>Exchange</TableCell>
-              <TableCell className={classes.cell}>Key</TableCell>
               <TableCell className={classes.cell} style={{minWidth: '15em'}}
                          align='right'>Actions</TableCell>
             </TableRow>
@@ -96,7 +95,6 @@ function TraderList(props) {
                 <TableRow key={row.id}>
                   <TableCell>{row.name}</TableCell>
                   <TableCell>{row.exchange}</TableCell>
-                  <TableCell>{row.api_key}</TableCell>
                   <TableCell align="right">
                     <Button color="primary"
                             onClick={e => {
@@ -0,0 +1,73 @@
+import React, {useState} from 'react';
+import Button from '@material-ui/core/Button';
+import CssBaseline from '@material-ui/core/CssBaseline';
+import FormControl from '@material-ui/core/FormControl';
+import Input from '@material-ui/core/Input';
+import InputLabel from '@material-ui/core/InputLabel';
+import Paper from '@material-ui/core/Paper';
+import Typography from '@material-ui/core/Typography';
+import {navigate} from 'hookrouter';
+import {makeStyles} from "@material-ui/core";
+import api from "../../api/baseApi";
+
+const useStyle = makeStyles(theme => ({
+  main: {
+    width: 'auto',
+    display: 'block',
+    marginLeft: theme.spacing(3),
+    marginRight: theme.spacing(3),
+    [theme.breakpoints.up(400 + theme.spacing(3) * 2)]: {
+      width: 400,
+      marginLeft: 'auto',
+      marginRight: 'auto',
+    },
+  },
+  paper: {
+    marginTop: theme.spacing(8),
+    display: 'flex',
+    flexDirection: 'column',
+    alignItems: 'center',
+    padding: `${theme.spacing(2)}px ${theme.spacing(3)}px ${theme.spacing(3)}px`,
+  },
+  form: {
+    width: '100%',
+    marginTop: theme.spacing(1),
+  },
+  submit: {
+    marginTop: theme.spacing(3),
+  },
+}));
+
+function ResetPassword(props) {
+  let classes = useStyle();
+  let [error, setError] = useState(null);
+
+  return (
+    <main className={classes.main}>
+      <CssBaseline/>
+      <Paper className={classes.paper}>
+        <Typography component="h1" variant="h5">
+          Choose a new password
+        </Typography>
+        <form className={classes.form} method="post" onSubmit={e => {
+          e.preventDefault();
+          let password = new FormData(e.target).get("password");
+          api.resetPassword({token: props.token, password})
+            .then(() => navigate("/app/auth"))
+            .catch(() => setError("The reset link is invalid or has expired."));
+        }}>
+          <FormControl margin="dense" required fullWidth>
+            <InputLabel htmlFor="password">New password</InputLabel>
+            <Input name="password" type="password" id="password" autoComplete="new-password" autoFocus/>
+          </FormControl>
+          <Button type="submit" fullWidth variant="contained" color="primary" className={classes.submit}>
+            Set password
+          </Button>
+          {error && <Typography color="error">{error}</Typography>}
+        </form>
+      </Paper>
+    </main>
+  );
+}
+
+export default ResetPassword;
@@ -0,0 +1,61 @@
+import React, {useState} from 'react';
+import Button from '@material-ui/core/Button';
+import CssBaseline from '@material-ui/core/CssBaseline';
+import Paper from '@material-ui/core/Paper';
+import Typography from '@material-ui/core/Typography';
+import {navigate} from 'hookrouter';
+import {makeStyles} from "@material-ui/core";
+import api from "../../api/baseApi";
+
+const useStyle = makeStyles(theme => ({
+  main: {
+    width: 'auto',
+    display: 'block',
+    marginLeft: theme.spacing(3),
+    marginRight: theme.spacing(3),
+    [theme.breakpoints.up(400 + theme.spacing(3) * 2)]: {
+      width: 400,
+      marginLeft: 'auto',
+      marginRight: 'auto',
+    },
+  },
+  paper: {
+    marginTop: theme.spacing(8),
+    display: 'flex',
+    flexDirection: 'column',
+    alignItems: 'center',
+    padding: `${theme.spacing(2)}px ${theme.spacing(3)}px ${theme.spacing(3)}px`,
+  },
+  submit: {
+    marginTop: theme.spacing(3),
+  },
+}));
+
+// The token is only spent by the button, so link previews and scanners opening the page don't use it up.
+// Also confirms addresses of notification channels, through the `title` and `verify` props.
+function VerifyEmail(props) {
+  let verify = props.verify || api.verifyEmail;
+  let classes = useStyle();
+  let [error, setError] = useState(null);
+
+  return (
+    <main className={classes.main}>
+      <CssBaseline/>
+      <Paper className={classes.paper}>
+        <Typography component="h1" variant="h5">
+          {props.title || "Verify your email address"}
+        </Typography>
+        <Button fullWidth variant="contained" color="primary" className={classes.submit} onClick={() => {
+          verify({token: props.token})
+            .then(() => navigate("/app/"))
+            .catch(() => setError("The verification link is invalid or has expired."));
+        }}>
+          Verify
+        </Button>
+        {error && <Typography color="error">{error}</Typography>}
+      </Paper>
+    </main>
+  );
+}
+
+export default VerifyEmail;
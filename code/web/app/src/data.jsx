@@ -72,6 +72,9 @@ class Assignment extends Model {
       to: 'Trader',
       as: 'trader'
     }),
+    enabled: attr(),
+    created: attr(),
+    signal_url: attr(),
   };
 }
 
@@ -0,0 +1,170 @@
+use crate::prelude::*;
+use crate::State;
+use crate::utils::*;
+use crate::users::audit;
+use db::{Database, Role, AuthEvent};
+
+/// Number of evaluations returned by `/api/admin/evaluations`
+const RECENT_EVALUATIONS: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct UserChange {
+    pub role: Option<Role>,
+    pub disabled: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PairIngest {
+    #[serde(flatten)]
+    pub status: db::IngestStatus,
+    /// Seconds since the last stored candle
+    pub lag: Option<i64>,
+}
+
+pub async fn users(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let users = db.all_users().await?;
+    Ok(Json(users).respond_to(&req)?)
+}
+
+pub async fn update_user((req, id, data): (HttpRequest<State>, Path<i32>, Json<UserChange>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let (id, data) = (id.into_inner(), data.into_inner());
+    if data.role.is_none() && data.disabled.is_none() {
+        let resp: Json<Vec<String>> = Json(vec!["Nothing to update.".into()]);
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, resp));
+    }
+    if id == base.auth.uid {
+        let resp: Json<Vec<String>> = Json(vec!["Administrators can't change their own account.".into()]);
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, resp));
+    }
+
+    let update = db::UserUpdate {
+        role: data.role.map(|r| r.as_str().to_string()),
+        disabled: data.disabled,
+    };
+    let user = match db.update_user(id, update).await {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    if data.disabled.is_some() {
+        notify_assignments(&db, user.id).await;
+    }
+    audit(&req, AuthEvent::UserUpdated, Some(user.id), Some(base.auth.email.clone()));
+    Ok(Json(user).respond_to(&req)?)
+}
+
+pub async fn strategies(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let strategies = db.all_strategies().await?;
+    Ok(Json(strategies).respond_to(&req)?)
+}
+
+pub async fn kill_strategy((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let strategy = match db.single_strategy(id.into_inner()).await {
+        Ok(strategy) => strategy,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    let disabled = db.kill_strategy(strategy.id).await?;
+    notify_assignments(&db, strategy.user_id).await;
+    audit(&req, AuthEvent::StrategyKilled, Some(base.auth.uid), None);
+    Ok(Json(json!({ "disabled_assignments": disabled })).respond_to(&req)?)
+}
+
+pub async fn assignments(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let assignments = db.all_assignments().await?;
+    Ok(Json(assignments).respond_to(&req)?)
+}
+
+pub async fn traders(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let traders = db.all_traders().await?;
+    Ok(Json(traders).respond_to(&req)?)
+}
+
+pub async fn pairs(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let pairs = db.pairs().await?;
+    Ok(Json(pairs).respond_to(&req)?)
+}
+
+pub async fn evaluations(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let evaluations = db.recent_evaluations(RECENT_EVALUATIONS).await?;
+    Ok(Json(evaluations).respond_to(&req)?)
+}
+
+pub async fn ingest(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let now = unixtime();
+    let status = db.ingest_status().await?
+        .into_iter()
+        .map(|status| PairIngest {
+            lag: status.last.map(|last| now - last),
+            status,
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(status).respond_to(&req)?)
+}
+
+pub fn configure(application: App<State>) -> App<State> {
+    application
+        .resource("/api/admin/users", |r| {
+            r.method(Method::GET).with_async(compat(users));
+        })
+        .resource("/api/admin/users/{id}", |r| {
+            r.method(Method::POST).with_async(compat(update_user));
+        })
+        .resource("/api/admin/strategies", |r| {
+            r.method(Method::GET).with_async(compat(strategies));
+        })
+        .resource("/api/admin/strategies/{id}/kill", |r| {
+            r.method(Method::POST).with_async(compat(kill_strategy));
+        })
+        .resource("/api/admin/assignments", |r| {
+            r.method(Method::GET).with_async(compat(assignments));
+        })
+        .resource("/api/admin/traders", |r| {
+            r.method(Method::GET).with_async(compat(traders));
+        })
+        .resource("/api/admin/pairs", |r| {
+            r.method(Method::GET).with_async(compat(pairs));
+        })
+        .resource("/api/admin/evaluations", |r| {
+            r.method(Method::GET).with_async(compat(evaluations));
+        })
+        .resource("/api/admin/ingest", |r| {
+            r.method(Method::GET).with_async(compat(ingest));
+        })
+}
@@ -2,12 +2,16 @@ use crate::prelude::*;
 use crate::State;
 use crate::utils::*;
 use crate::users::middleware::UserAuthentication;
+use crate::users::two_factor::require_recent_confirmation;
 use std::string::ToString;
 use actix_web::Path;
-use common::types::{OhlcPeriod, Exchange, TradePair, PairId};
-use db::{Database, Assignment};
+use common::types::{OhlcPeriod, Exchange, TradePair, PairId, ParamSpec, ParamValues};
+use db::{Database, Assignment, AssignmentData};
 use actix_web::Json;
 
+/// Number of deliveries returned by `/api/assignments/{id}/signals`
+const RECENT_SIGNALS: i64 = 100;
+
 pub async fn list(req: HttpRequest<State>) -> Result<impl Responder> {
     let db: Database = req.state().db.clone();
     let base = BaseReqInfo::from_request(&req).await?;
@@ -20,51 +24,149 @@ pub async fn list(req: HttpRequest<State>) -> Result<impl Responder> {
     Ok(Json(assignments).respond_to(&req)?)
 }
 
-#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+fn default_enabled() -> bool { true }
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Assign {
+    pub exchange: Exchange,
+    pub pair: String,
+    pub period: OhlcPeriod,
     pub strategy_id: i32,
     pub trader_id: Option<i32>,
+    /// Values of strategy parameters, defaults are used for missing ones
+    #[serde(default)]
+    pub params: ParamValues,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Webhook receiving every decision of the assignment
+    pub signal_url: Option<String>,
+    /// Key signing the posted decisions, the current one is kept on update when missing
+    pub signal_secret: Option<String>,
 }
 
-pub async fn post((req, path, data): (HttpRequest<State>, Path<(Exchange, String, OhlcPeriod)>, Json<Assign>)) -> Result<impl Responder> {
-    let (exch, pair, period) = path.into_inner();
-    let data = data.into_inner();
-
+/// Checks the request against strategies and traders of the user, and converts it to stored form.
+/// `aid` is the id of the updated assignment.
+async fn assignment_data(req: &HttpRequest<State>, base: &BaseReqInfo, aid: Option<i32>, mut data: Assign) -> Result<AssignmentData> {
     let db: Database = req.state().db.clone();
-    let base = BaseReqInfo::from_request(&req).await?;
-    require_login!(base);
-    let pair_id = PairId::new(exch, TradePair::from_str(&pair).unwrap());
+    // Cleared form fields arrive as empty strings
+    data.signal_url = data.signal_url.filter(|url| !url.trim().is_empty());
+    data.signal_secret = data.signal_secret.filter(|secret| !secret.is_empty());
+    if data.trader_id.is_some() {
+        check_verified_email(&db, base.auth.uid).await?;
+        require_recent_confirmation(req, base).await?;
+    }
+    let pair = match TradePair::from_str(&data.pair) {
+        Ok(pair) => pair,
+        Err(_) => return Err(Error::from_resp(req, http::StatusCode::BAD_REQUEST, Json(vec!["Invalid pair"]))),
+    };
+    if let Some(ref url) = data.signal_url {
+        if let Err(e) = common::net::check_public_url(url).await {
+            return Err(Error::from_resp(req, http::StatusCode::BAD_REQUEST, Json(vec![format!("Signal target : {}", e)])));
+        }
+        let has_secret = match (&data.signal_secret, aid) {
+            (Some(_), _) => true,
+            (None, Some(aid)) => db.assignments(base.auth.uid).await?.iter().any(|a| a.id == aid && a.signal_secret.is_some()),
+            (None, None) => false,
+        };
+        if !has_secret {
+            return Err(Error::from_resp(req, http::StatusCode::BAD_REQUEST, Json(vec!["Signal target requires a secret"])));
+        }
+    }
 
-    let assign = Assignment {
-        pair_id : db.pair_id(pair_id).await?,
-        period: period.to_string(),
+    let strategy = db.single_strategy(data.strategy_id).await?;
+    if strategy.user_id != base.auth.uid {
+        return Err(actix_web::error::ErrorUnauthorized("").into());
+    }
+    if let Err(e) = ParamSpec::resolve(&strategy.param_specs(), &data.params) {
+        return Err(Error::from_resp(req, http::StatusCode::BAD_REQUEST, Json(vec![e])));
+    }
+    if let Some(tid) = data.trader_id {
+        let traders = db.user_traders(base.auth.uid).await?;
+        if !traders.iter().any(|t| t.id == tid) {
+            return Err(actix_web::error::ErrorUnauthorized("").into());
+        }
+    }
+
+    Ok(AssignmentData {
+        pair_id: db.pair_id(PairId::new(data.exchange, pair)).await?,
+        period: data.period.to_string(),
         user_id: base.auth.uid,
         strategy_id: data.strategy_id,
         trader_id: data.trader_id,
+        params: data.params,
+        enabled: data.enabled,
+        signal_url: data.signal_url,
+        signal_secret: data.signal_secret,
+    })
+}
+
+pub async fn post((req, data): (HttpRequest<State>, Json<Assign>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let assign = assignment_data(&req, &base, None, data.into_inner()).await?;
+    let res = match db.create_assignment(assign).await {
+        Ok(res) => res,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
     };
+    notify_assignments(&db, base.auth.uid).await;
+    return Ok(Json(res).respond_to(&req)?);
+}
 
-    let res = db.save_assignment(assign).await?;
-    return Ok(Json(res).respond_to(&req).unwrap());
+pub async fn update((req, id, data): (HttpRequest<State>, Path<i32>, Json<Assign>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
 
+    let id = id.into_inner();
+    let assign = assignment_data(&req, &base, Some(id), data.into_inner()).await?;
+    let res = match db.update_assignment(id, assign).await {
+        Ok(res) => res,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    notify_assignments(&db, base.auth.uid).await;
+    return Ok(Json(res).respond_to(&req)?);
 }
 
-pub async fn delete((req, path): (HttpRequest<State>, Path<(Exchange, String, OhlcPeriod)>)) -> Result<impl Responder> {
-    let (exch, pair, period) = path.into_inner();
+pub async fn delete((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
     let db: Database = req.state().db.clone();
     let base = BaseReqInfo::from_request(&req).await?;
     require_login!(base);
-    db.delete_assignment(PairId::new(exch, TradePair::from_str(&pair).unwrap()), base.auth.uid).await?;
+    if !db.delete_assignment(base.auth.uid, id.into_inner()).await? {
+        return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND));
+    }
+    notify_assignments(&db, base.auth.uid).await;
     return Ok(HttpResponse::new(http::StatusCode::OK));
 }
 
+/// Latest decisions posted to the signal target of the assignment, with their delivery state
+pub async fn signals((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let deliveries = match db.signal_deliveries(base.auth.uid, id.into_inner(), RECENT_SIGNALS).await {
+        Ok(deliveries) => deliveries,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Json(deliveries).respond_to(&req)?)
+}
+
 pub fn configure(application: App<State>) -> App<State> {
     application
-        .resource("/api/assignments/{exch}/{pair}/{period}", |r| {
-            r.method(Method::POST).with_async(compat(post));
+        .resource("/api/assignments/{id}", |r| {
+            r.method(Method::POST).with_async(compat(update));
             r.method(Method::DELETE).with_async(compat(delete));
         })
+        .resource("/api/assignments/{id}/signals", |r| {
+            r.method(Method::GET).with_async(compat(signals));
+        })
         .resource("/api/assignments", |r| {
             r.method(Method::GET).with_async(compat(list));
+            r.method(Method::POST).with_async(compat(post));
         })
 }
-
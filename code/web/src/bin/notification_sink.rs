@@ -0,0 +1,30 @@
+//! Local receiver for testing notification channels. Every request is logged and answered with success,
+//! so its address can be used as the URL of a webhook channel, or as `notify.bot_api_url`.
+//!
+//! Usage: `notification_sink [bind address]`, listens on 127.0.0.1:8090 by default.
+use common::prelude::*;
+use common::actix_web::{server, App, HttpRequest, HttpResponse, HttpMessage};
+
+const DEFAULT_BIND: &str = "127.0.0.1:8090";
+
+fn receive(req: &HttpRequest) -> Box<dyn Future<Item=HttpResponse, Error=actix_web::Error>> {
+    let (method, path) = (req.method().clone(), req.path().to_string());
+    Box::new(req.body().limit(common::BODY_LIMIT).from_err().map(move |body| {
+        info!("{} {} : {}", method, path, String::from_utf8_lossy(&body));
+        HttpResponse::Ok().json(json!({ "ok": true }))
+    }))
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    env_logger::init();
+    let bind = env::args().nth(1).unwrap_or_else(|| DEFAULT_BIND.to_string());
+
+    actix::System::run(move || {
+        server::new(|| App::new().default_resource(|r| r.f(receive)))
+            .bind(&bind)
+            .unwrap()
+            .start();
+        info!("Notification sink listening on {}", bind);
+    });
+}
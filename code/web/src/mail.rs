@@ -0,0 +1,115 @@
+//! Outgoing email, the transport is chosen by `mail.transport` in the configuration.
+use crate::prelude::*;
+use common::config::{MailConfig, MailTransport};
+use std::sync::{mpsc, Mutex};
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    /// Token contained in the body, left out when the mail is only logged
+    pub secret: Option<String>,
+}
+
+impl Mail {
+    /// RFC 5322 message, plain text only
+    fn to_message(&self, from: &str) -> String {
+        format!("From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}",
+                from, self.to, self.subject, chrono::Utc::now().to_rfc2822(), self.body)
+    }
+}
+
+pub trait MailSender: Send + Sync {
+    /// Must not block the calling arbiter
+    fn send(&self, mail: Mail) -> Result<(), failure::Error>;
+}
+
+pub fn from_config(config: &MailConfig) -> Arc<dyn MailSender> {
+    match config.transport {
+        MailTransport::Log => Arc::new(LogSender),
+        MailTransport::File => Arc::new(FileSender {
+            dir: config.directory.clone().into(),
+            from: config.from.clone(),
+        }),
+        MailTransport::Smtp => Arc::new(SmtpSender::start(config.clone())),
+    }
+}
+
+/// Only logs the mail, with tokens redacted since logs are kept and shipped elsewhere.
+/// The `file` transport keeps usable links for local development.
+pub struct LogSender;
+
+impl MailSender for LogSender {
+    fn send(&self, mail: Mail) -> Result<(), failure::Error> {
+        let body = match mail.secret {
+            Some(ref secret) if !secret.is_empty() => mail.body.replace(secret.as_str(), "[redacted]"),
+            _ => mail.body.clone(),
+        };
+        info!("Mail to {}: {}\n{}", mail.to, mail.subject, body);
+        Ok(())
+    }
+}
+
+pub struct FileSender {
+    dir: std::path::PathBuf,
+    from: String,
+}
+
+impl MailSender for FileSender {
+    fn send(&self, mail: Mail) -> Result<(), failure::Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        let name = format!("{}-{}.eml", chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f"), mail.to.replace('/', "_"));
+        std::fs::write(self.dir.join(name), mail.to_message(&self.from))?;
+        Ok(())
+    }
+}
+
+/// Hands mails over to a dedicated thread, so the SMTP conversation doesn't block request handling
+pub struct SmtpSender {
+    queue: Mutex<mpsc::Sender<Mail>>,
+}
+
+impl SmtpSender {
+    fn start(config: MailConfig) -> Self {
+        let (tx, rx) = mpsc::channel::<Mail>();
+        std::thread::spawn(move || {
+            use lettre::{Transport, SendableEmail, Envelope, EmailAddress, smtp::{SmtpClient, authentication::Credentials}};
+
+            let client = SmtpClient::new_simple(&config.smtp_host).expect("SMTP client");
+            let client = if config.smtp_user.is_empty() {
+                client
+            } else {
+                client.credentials(Credentials::new(config.smtp_user.clone(), config.smtp_password.expose().to_string()))
+            };
+            let mut transport = client.transport();
+
+            for mail in rx {
+                let envelope = EmailAddress::new(config.from.clone())
+                    .and_then(|from| Ok((from, EmailAddress::new(mail.to.clone())?)))
+                    .and_then(|(from, to)| Envelope::new(Some(from), vec![to]));
+                let envelope = match envelope {
+                    Ok(envelope) => envelope,
+                    Err(e) => {
+                        error!("Invalid mail address {:?}: {}", mail.to, e);
+                        continue;
+                    }
+                };
+
+                let email = SendableEmail::new(envelope, uuid::Uuid::new_v4().to_string(), mail.to_message(&config.from).into_bytes());
+                if let Err(e) = transport.send(email) {
+                    error!("Could not send mail to {:?}: {}", mail.to, e);
+                }
+            }
+        });
+
+        SmtpSender { queue: Mutex::new(tx) }
+    }
+}
+
+impl MailSender for SmtpSender {
+    fn send(&self, mail: Mail) -> Result<(), failure::Error> {
+        self.queue.lock().unwrap().send(mail).map_err(|_| format_err!("Mail thread has stopped"))?;
+        Ok(())
+    }
+}
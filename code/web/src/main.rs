@@ -6,11 +6,19 @@ pub mod prelude;
 
 #[macro_use]
 pub mod utils;
+pub mod mail;
 
 pub mod root;
 pub mod ohlc;
 pub mod users;
 pub mod traders;
+pub mod tokens;
+pub mod admin;
+pub mod optimizations;
+pub mod risk;
+pub mod notifications;
+pub mod signal_hooks;
+pub mod push;
 pub mod strategies;
 pub mod assignments;
 
@@ -26,6 +34,13 @@ use actix_web::http::header::ContentType;
 
 pub struct State {
     db: db::Database,
+    mail: Arc<dyn mail::MailSender>,
+    auth_limits: Arc<users::rate_limit::AuthLimits>,
+    push: Addr<push::Hub>,
+    dry_runs: Addr<strategies::DryRunner>,
+    imports: Addr<ohlc::ImportParser>,
+    channel_tests: Arc<users::rate_limit::SlidingWindow>,
+    hook_limits: Arc<users::rate_limit::SlidingWindow>,
 }
 
 fn check<S>(_: &HttpRequest<S>) -> impl Responder { format!("I'm UP") }
@@ -33,7 +48,7 @@ fn check<S>(_: &HttpRequest<S>) -> impl Responder { format!("I'm UP") }
 pub fn static_file_named(name: &str) -> Result<impl Responder> {
     warn!("Returning : {:?}", name);
     let name = name.replace("..", "").to_string();
-    let mut path = PathBuf::from(env::var("WEBAPP_ROOT").unwrap_or("./code/web/app/dist".to_string()));
+    let mut path = PathBuf::from(&common::config().web.webapp_root);
     path.push(&name);
     let mime = mime_guess::from_path(&path).first_or_octet_stream();
     if let Ok(file) = std::fs::read(path) {
@@ -52,13 +67,33 @@ pub fn static_file(req: HttpRequest<State>) -> Result<impl Responder> {
 
 fn main() {
     common::init();
-    common::launch(|| {
+    if common::config().web.session_key.is_empty() {
+        panic!("Session key is not configured, provide it through SESSION_KEY or SESSION_KEY_FILE");
+    }
+    common::launch(|| async {
+        let client = anats::Client::new(common::config().nats.url.clone()).await;
         let db = db::start();
+        let mail = mail::from_config(&common::config().mail);
+        let auth_limits = Arc::new(users::rate_limit::AuthLimits::default());
+        notifications::notifier::Notifier::start(db.clone(), mail.clone());
+        let push = push::Hub::start(client);
+        let dry_runs = strategies::DryRunner::start();
+        let imports = ohlc::ImportParser::start();
+        let channel_tests = Arc::new(notifications::test_limit());
+        let hook_limits = Arc::new(signal_hooks::receive_limit());
         server::new(move || {
             let mut app = App::with_state(State {
                 db: db.clone(),
+                mail: mail.clone(),
+                auth_limits: auth_limits.clone(),
+                push: push.clone(),
+                dry_runs: dry_runs.clone(),
+                imports: imports.clone(),
+                channel_tests: channel_tests.clone(),
+                hook_limits: hook_limits.clone(),
             });
             app = app.middleware(actix_web::middleware::Logger::default());
+            app = app.middleware(users::rate_limit::RateLimit);
 
             app = app.resource("/app/{tail:.*}", |r| r.method(http::Method::GET).with(|r: HttpRequest<State>| {
                 static_file_named("index.html")
@@ -73,6 +108,13 @@ fn main() {
             app = evaluations::configure(app);
             app = trades::configure(app);
             app = traders::configure(app);
+            app = tokens::configure(app);
+            app = admin::configure(app);
+            app = optimizations::configure(app);
+            app = risk::configure(app);
+            app = notifications::configure(app);
+            app = signal_hooks::configure(app);
+            app = push::configure(app);
 
 
             app
@@ -80,7 +122,6 @@ fn main() {
                 .resource("/ready", |r| r.method(http::Method::GET).f(check))
                 .resource("/static/{tail:.*}", |r| r.method(http::Method::GET).with(static_file))
                 .default_resource(|r| r.h(http::NormalizePath::default()))
-        }).bind("0.0.0.0:8000").unwrap().start();
-        async {}
+        }).bind(&common::config().web.bind).unwrap().start();
     });
 }
\ No newline at end of file
@@ -0,0 +1,210 @@
+use crate::prelude::*;
+use crate::State;
+use crate::utils::*;
+use crate::mail::Mail;
+use crate::users::{public_link, rate_limit::{SlidingWindow, too_many_requests}};
+use db::{Database, ChannelKind, NotificationChannelData, NotifyEvent};
+
+pub mod notifier;
+
+/// Number of deliveries returned by `/api/notifications`
+const RECENT_NOTIFICATIONS: i64 = 100;
+
+/// Test notifications a user can send in `TEST_WINDOW`, so the endpoint can't be used to flood a target
+const TEST_LIMIT: usize = 5;
+const TEST_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+pub fn test_limit() -> SlidingWindow {
+    SlidingWindow::new(TEST_LIMIT, TEST_WINDOW)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleData {
+    pub event: NotifyEvent,
+    pub channel_id: i32,
+}
+
+/// Reasons the channel can't be used, empty if it's valid
+fn channel_errors(data: &NotificationChannelData) -> Vec<String> {
+    let mut errors = vec![];
+    match data.kind {
+        ChannelKind::Email => if !data.target.contains('@') {
+            errors.push("Email channel requires an email address.".to_string());
+        },
+        ChannelKind::Webhook => match Url::parse(&data.target) {
+            Ok(ref url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            _ => errors.push("Webhook channel requires an http or https URL.".to_string()),
+        },
+        ChannelKind::Bot => {
+            if data.target.trim().is_empty() {
+                errors.push("Bot channel requires a chat id.".to_string());
+            }
+            if data.token.as_ref().map_or(true, |t| t.trim().is_empty()) {
+                errors.push("Bot channel requires a bot token.".to_string());
+            }
+        }
+    }
+    errors
+}
+
+pub async fn channels(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let channels = db.notification_channels(base.auth.uid).await?;
+    Ok(Json(channels).respond_to(&req)?)
+}
+
+pub async fn create_channel((req, data): (HttpRequest<State>, Json<NotificationChannelData>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let data = data.into_inner();
+    let errors = channel_errors(&data);
+    if !errors.is_empty() {
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(errors)));
+    }
+
+    if data.kind == ChannelKind::Webhook {
+        if let Err(e) = common::net::check_public_url(&data.target).await {
+            return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec![format!("Webhook channel : {}", e)])));
+        }
+    }
+
+    let (channel, token) = db.create_notification_channel(base.auth.uid, data).await?;
+    if let Some(token) = token {
+        req.state().mail.send(Mail {
+            to: channel.target.clone(),
+            subject: "Verify your notification address".into(),
+            body: format!("Open the following link to receive notifications at this address:\n\n{}\n",
+                          public_link(&format!("/app/auth/verify-channel/{}", token))),
+            secret: Some(token),
+        }).map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+    Ok(Json(channel).respond_to(&req)?)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChannelVerification {
+    pub token: String,
+}
+
+/// Posted by the page the emailed link opens, the token identifies the channel
+pub async fn verify_channel((req, data): (HttpRequest<State>, Json<ChannelVerification>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    match db.verify_notification_channel(data.into_inner().token).await? {
+        Some(_) => Ok(HttpResponse::Ok().finish()),
+        None => {
+            let resp: Json<Vec<String>> = Json(vec!["Verification link is invalid or was already used.".into()]);
+            Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, resp))
+        }
+    }
+}
+
+pub async fn delete_channel((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    if !db.delete_notification_channel(base.auth.uid, id.into_inner()).await? {
+        return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND));
+    }
+    return Ok(HttpResponse::new(http::StatusCode::OK));
+}
+
+pub async fn test_channel((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    if let Err(retry_after) = req.state().channel_tests.hit(&base.auth.uid.to_string()) {
+        return Ok(too_many_requests(retry_after));
+    }
+
+    let notification = match db.test_notification_channel(base.auth.uid, id.into_inner()).await {
+        Ok(Some(notification)) => notification,
+        Ok(None) => {
+            let resp: Json<Vec<String>> = Json(vec!["Channel address is not verified yet.".into()]);
+            return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, resp));
+        }
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Json(notification).respond_to(&req)?)
+}
+
+pub async fn rules(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let rules = db.notification_rules(base.auth.uid).await?;
+    Ok(Json(rules).respond_to(&req)?)
+}
+
+pub async fn create_rule((req, data): (HttpRequest<State>, Json<RuleData>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    if data.event == NotifyEvent::Test {
+        let resp: Json<Vec<String>> = Json(vec!["Test notifications can't be subscribed to.".into()]);
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, resp));
+    }
+
+    let rule = match db.create_notification_rule(base.auth.uid, data.event, data.channel_id).await {
+        Ok(rule) => rule,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Json(rule).respond_to(&req)?)
+}
+
+pub async fn delete_rule((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    if !db.delete_notification_rule(base.auth.uid, id.into_inner()).await? {
+        return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND));
+    }
+    return Ok(HttpResponse::new(http::StatusCode::OK));
+}
+
+pub async fn list(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let notifications = db.notifications(base.auth.uid, RECENT_NOTIFICATIONS).await?;
+    Ok(Json(notifications).respond_to(&req)?)
+}
+
+pub fn configure(application: App<State>) -> App<State> {
+    application
+        .resource("/api/notifications", |r| {
+            r.method(Method::GET).with_async(compat(list));
+        })
+        .resource("/api/notifications/channels", |r| {
+            r.method(Method::GET).with_async(compat(channels));
+            r.method(Method::POST).with_async(compat(create_channel));
+        })
+        .resource("/api/notifications/channels/verify", |r| {
+            r.method(Method::POST).with_async(compat(verify_channel));
+        })
+        .resource("/api/notifications/channels/{id}", |r| {
+            r.method(Method::DELETE).with_async(compat(delete_channel));
+        })
+        .resource("/api/notifications/channels/{id}/test", |r| {
+            r.method(Method::POST).with_async(compat(test_channel));
+        })
+        .resource("/api/notifications/rules", |r| {
+            r.method(Method::GET).with_async(compat(rules));
+            r.method(Method::POST).with_async(compat(create_rule));
+        })
+        .resource("/api/notifications/rules/{id}", |r| {
+            r.method(Method::DELETE).with_async(compat(delete_rule));
+        })
+}
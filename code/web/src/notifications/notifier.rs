@@ -0,0 +1,194 @@
+//! Delivery of queued notifications, and detection of stale exchange feeds.
+use crate::prelude::*;
+use crate::mail::{Mail, MailSender};
+use db::{Database, ChannelKind, Notification, NotificationChannel, NotifyEvent};
+
+/// How often the queue is checked for pending notifications
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the latest candles of all pairs are checked
+const FEED_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const BATCH_SIZE: i64 = 50;
+/// Webhook and bot requests taking longer are failed attempts
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Way of delivering notifications to a single kind of channel
+pub trait Channel {
+    fn deliver(&self, notification: &Notification, channel: &NotificationChannel) -> BoxFuture<()>;
+}
+
+pub struct EmailChannel {
+    mail: Arc<dyn MailSender>,
+}
+
+impl Channel for EmailChannel {
+    fn deliver(&self, notification: &Notification, channel: &NotificationChannel) -> BoxFuture<()> {
+        box future::result(self.mail.send(Mail {
+            to: channel.target.clone(),
+            subject: notification.subject.clone(),
+            body: notification.body.clone(),
+            secret: None,
+        }))
+    }
+}
+
+/// Posts the notification as JSON to the URL of the channel. The host is resolved again before
+/// every delivery, so a name changed to point to a private address after the channel was saved is refused.
+pub struct WebhookChannel;
+
+impl Channel for WebhookChannel {
+    fn deliver(&self, notification: &Notification, channel: &NotificationChannel) -> BoxFuture<()> {
+        let url = channel.target.clone();
+        let body = json!({
+            "id": notification.id,
+            "event": notification.event,
+            "subject": notification.subject,
+            "body": notification.body,
+            "created": notification.created,
+        });
+        box common::net::check_public_url(&url).compat()
+            .map_err(|e| format_err!("{}", e))
+            .and_then(move |()| post_json(&url, body))
+    }
+}
+
+/// Sends a message through a Telegram style bot API, the target of the channel is the chat id
+pub struct BotChannel {
+    api_url: String,
+}
+
+impl Channel for BotChannel {
+    fn deliver(&self, notification: &Notification, channel: &NotificationChannel) -> BoxFuture<()> {
+        let token = match channel.token {
+            Some(ref token) => token,
+            None => return box future::err(format_err!("Bot channel {} has no token", channel.id)),
+        };
+        let url = format!("{}/bot{}/sendMessage", self.api_url.trim_end_matches('/'), token);
+        post_json(&url, json!({
+            "chat_id": channel.target,
+            "text": format!("{}\n\n{}", notification.subject, notification.body),
+        }))
+    }
+}
+
+fn post_json(url: &str, body: json::Value) -> BoxFuture<()> {
+    let req = match actix_web::client::post(url).timeout(DELIVERY_TIMEOUT).json(body) {
+        Ok(req) => req,
+        Err(e) => return box future::err(format_err!("{}", e)),
+    };
+    box req.send()
+        .map_err(|e| format_err!("{}", e))
+        .and_then(|resp| {
+            if resp.status().is_success() {
+                Ok(())
+            } else {
+                Err(format_err!("Responded with {}", resp.status()))
+            }
+        })
+}
+
+/// Delivers notifications queued in the database. Several instances can run at once, claimed
+/// notifications are hidden from the others. Reported stale feeds are recorded in the database,
+/// so a feed is reported once when it stops receiving candles, regardless of restarts and instances.
+pub struct Notifier {
+    db: Database,
+    channels: HashMap<ChannelKind, Box<dyn Channel>>,
+    /// Batch of deliveries is in progress, the next one is claimed after it finishes
+    busy: bool,
+}
+
+impl Actor for Notifier { type Context = Context<Self>; }
+
+impl Notifier {
+    pub fn start(db: Database, mail: Arc<dyn MailSender>) -> Addr<Self> {
+        Arbiter::start(move |ctx: &mut Context<Self>| {
+            ctx.run_interval(POLL_INTERVAL, |this, ctx| this.deliver(ctx));
+            ctx.run_interval(FEED_CHECK_INTERVAL, |this, ctx| this.check_feeds(ctx));
+
+            let mut channels: HashMap<ChannelKind, Box<dyn Channel>> = HashMap::new();
+            channels.insert(ChannelKind::Email, box EmailChannel { mail });
+            channels.insert(ChannelKind::Webhook, box WebhookChannel);
+            channels.insert(ChannelKind::Bot, box BotChannel { api_url: common::config().notify.bot_api_url.clone() });
+            Notifier {
+                db,
+                channels,
+                busy: false,
+            }
+        })
+    }
+
+    fn deliver(&mut self, ctx: &mut Context<Self>) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+
+        let fut = wrap_future(self.db.claim_notifications(BATCH_SIZE).boxed_local().compat())
+            .map_err(|e, this: &mut Self, _| {
+                this.busy = false;
+                error!("Could not claim notifications : {:?}", e);
+            })
+            .and_then(|pending, this: &mut Self, _| {
+                let sends = pending.into_iter().map(|(notification, channel)| {
+                    let sent: BoxFuture<()> = match channel.channel_kind().and_then(|k| this.channels.get(&k)) {
+                        Some(sender) => sender.deliver(&notification, &channel),
+                        None => box future::err(format_err!("Unknown channel kind {}", channel.kind)),
+                    };
+                    let db = this.db.clone();
+                    sent.then(move |res| {
+                        let recorded = match res {
+                            Ok(()) => db.notification_delivered(notification.id),
+                            Err(e) => {
+                                warn!("Could not deliver notification {} to channel {} : {}", notification.id, channel.id, e);
+                                db.notification_failed(notification, e.to_string())
+                            }
+                        };
+                        recorded.compat().then(|res| {
+                            if let Err(e) = res {
+                                error!("Could not record notification delivery : {:?}", e);
+                            }
+                            Ok::<_, ()>(())
+                        })
+                    })
+                }).collect::<Vec<_>>();
+
+                wrap_future(future::join_all(sends)).map(|_, this: &mut Self, _| this.busy = false)
+            });
+        ctx.spawn(fut);
+    }
+
+    fn check_feeds(&mut self, ctx: &mut Context<Self>) {
+        let stale_after = common::config().notify.stale_after as i64;
+
+        let fut = wrap_future(self.db.ingest_status().boxed_local().compat())
+            .map_err(|e, _, _| error!("Could not load ingest status : {:?}", e))
+            .and_then(move |status, this: &mut Self, _| {
+                let now = unixtime();
+                let (stale, live): (Vec<_>, Vec<_>) = status.into_iter()
+                    .partition(|pair| pair.last.map_or(false, |last| now - last > stale_after));
+
+                let db = this.db.clone();
+                let notified = async move {
+                    db.clear_stale_feeds(live.into_iter().map(|pair| pair.pair_id).collect()).await?;
+                    for pair in stale.into_iter() {
+                        if !db.report_stale_feed(pair.pair_id).await? {
+                            continue;
+                        }
+                        let last = chrono::NaiveDateTime::from_timestamp(pair.last.unwrap_or(0), 0);
+                        warn!("Feed of {} {} is stale, last candle at {}", pair.exchange, pair.pair, last);
+                        for uid in db.pair_subscribers(pair.pair_id).await? {
+                            db.notify(
+                                uid,
+                                NotifyEvent::FeedStale,
+                                format!("No data from {} {}", pair.exchange, pair.pair),
+                                format!("Last candle of {} {} was received at {} UTC", pair.exchange, pair.pair, last),
+                            ).await?;
+                        }
+                    }
+                    Ok::<_, diesel::result::Error>(())
+                };
+                wrap_future(notified.boxed_local().compat())
+                    .map_err(|e, _, _| error!("Could not notify about stale feeds : {:?}", e))
+            });
+        ctx.spawn(fut);
+    }
+}
@@ -1,15 +1,13 @@
 use crate::prelude::*;
 
 
-use actix_web::Query;
+use actix_web::{Query, HttpMessage};
 use common::types::{TradePair, Ohlc, PairId, OhlcPeriod, Exchange};
+use common::import::{CsvOptions, CsvImport};
+use db::AuthEvent;
+use crate::users::audit;
 use serde::de::Visitor;
 
-#[derive(Debug, Deserialize)]
-pub struct SinceQuery {
-    since: i64
-}
-
 fn pair_from_str<'de, D>(data: D) -> Result<TradePair, D::Error>
     where
         D: Deserializer<'de>,
@@ -32,18 +30,348 @@ fn pair_from_str<'de, D>(data: D) -> Result<TradePair, D::Error>
 #[derive(Debug, Deserialize)]
 pub struct PairStr(#[serde(deserialize_with = "pair_from_str")] TradePair);
 
-pub async fn get_ohlc((req, path, since): (HttpRequest<State>, Path<(Exchange, PairStr, Option<OhlcPeriod>)>, Query<SinceQuery>)) -> Result<Json<Vec<Ohlc>>> {
+/// Candles returned by a single request when no `limit` is given
+const DEFAULT_LIMIT: i64 = 500;
+const MAX_LIMIT: i64 = 5000;
+/// Gaps listed by the coverage endpoint
+const MAX_GAPS: i64 = 100;
+/// Range searched for gaps when none is given, and the longest accepted one
+const GAPS_RANGE: i64 = 30 * 24 * 60 * 60;
+const MAX_GAPS_RANGE: i64 = 366 * 24 * 60 * 60;
+/// Cache lifetime of ranges that ended before the current candle, and of ranges that may still change
+const CACHE_COMPLETE: u64 = 3600;
+const CACHE_LIVE: u64 = 5;
+/// Largest accepted archive upload
+const UPLOAD_LIMIT: usize = 64 * 1024 * 1024;
+/// Candles saved at once, progress is logged after each batch
+const IMPORT_BATCH: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+pub struct RangeQuery {
+    /// Start of the range in seconds, rounded down to the period
+    from: Option<i64>,
+    /// Start of the range for polling clients, only the most recent `limit` periods up to `to` are returned
+    since: Option<i64>,
+    /// End of the range in seconds, exclusive, the current time by default
+    to: Option<i64>,
+    limit: Option<i64>,
+    /// Continuation returned with the previous page, takes precedence over `from`
+    cursor: Option<String>,
+    /// `json` (default), `csv` or `columnar`
+    format: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Array of candle objects
+    Json,
+    Csv,
+    /// Object of arrays, one for each field, in the layout of columnar formats like Arrow
+    Columnar,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> StdResult<Self, ()> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "columnar" => Ok(Format::Columnar),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Format {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Columnar => "columnar",
+        }
+    }
+}
+
+/// Cursors are opaque to clients, they carry the period so they can't be reused for another one
+fn encode_cursor(period: OhlcPeriod, time: i64) -> String {
+    base64::encode_config(&format!("{}:{}", period.to_string(), time), base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_cursor(cursor: &str, period: OhlcPeriod) -> Option<i64> {
+    let decoded = String::from_utf8(base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+    if parts.next()? != period.to_string() {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+fn render(data: &[Ohlc], format: Format) -> String {
+    match format {
+        Format::Json => json::to_string(data).expect("Candle serialization"),
+        Format::Csv => {
+            let mut out = String::from("time,open,high,low,close,vol\n");
+            for c in data {
+                out.push_str(&format!("{},{},{},{},{},{}\n", c.time, c.open, c.high, c.low, c.close, c.vol));
+            }
+            out
+        }
+        Format::Columnar => json::to_string(&json!({
+            "time": data.iter().map(|c| c.time).collect::<Vec<_>>(),
+            "open": data.iter().map(|c| c.open).collect::<Vec<_>>(),
+            "high": data.iter().map(|c| c.high).collect::<Vec<_>>(),
+            "low": data.iter().map(|c| c.low).collect::<Vec<_>>(),
+            "close": data.iter().map(|c| c.close).collect::<Vec<_>>(),
+            "vol": data.iter().map(|c| c.vol).collect::<Vec<_>>(),
+        })).expect("Candle serialization"),
+    }
+}
+
+/// Start of the last `limit` periods before `to`
+fn latest_start(period: OhlcPeriod, to: i64, limit: i64) -> i64 {
+    period.clamp_time(to - 1) - (limit - 1) * period.seconds()
+}
+
+/// Whether a tag listed in `If-None-Match` matches the tag of the response.
+/// Tags are compared weakly as required for `GET` requests, so the `W/` prefix is ignored.
+fn etag_matches(header: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    header.trim() == "*" || header.split(',').any(|tag| opaque(tag) == opaque(etag))
+}
+
+fn accepts_gzip(req: &HttpRequest<State>) -> bool {
+    req.headers().get(http::header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.contains("gzip"))
+}
+
+/// Candles of a pair in the requested period. Pages are linked through the `Link` header and
+/// `X-Next-Cursor`, which are missing on the last page.
+pub async fn get_ohlc((req, path, query): (HttpRequest<State>, Path<(Exchange, PairStr, String)>, Query<RangeQuery>)) -> Result<HttpResponse> {
+    let db = req.state().db.clone();
     let (exch, pair, period) = path.into_inner();
-    let pair = pair.0;
-    let db = &req.state().db;
-    let data = db.ohlc_history(PairId::new(exch, pair), since.into_inner().since).await.unwrap();
+    let pair = PairId::new(exch, pair.0);
+    let query = query.into_inner();
+
+    let period = match OhlcPeriod::from_str(&period) {
+        Ok(period) => period,
+        Err(_) => return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec![
+            format!("Unknown period, supported are {}", OhlcPeriod::NAMES.join(", "))
+        ]))),
+    };
+    let format = match query.format.as_ref().map(|f| Format::from_str(f)).unwrap_or(Ok(Format::Json)) {
+        Ok(format) => format,
+        Err(_) => return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec!["Format must be json, csv or columnar"]))),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit < 1 || limit > MAX_LIMIT {
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec![format!("Limit must be between 1 and {}", MAX_LIMIT)])));
+    }
+    let now = chrono::Utc::now().timestamp();
+    let to = query.to.unwrap_or(now);
+    let from = match (query.cursor.as_ref(), query.from, query.since) {
+        (Some(cursor), _, _) => match decode_cursor(cursor, period) {
+            Some(from) => from,
+            None => return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec!["Invalid cursor"]))),
+        },
+        (None, Some(from), _) => from,
+        // Polling clients pass their last candle as `since` and expect to catch up to now, instead of paging
+        (None, None, Some(since)) => i64::max(since, latest_start(period, to, limit)),
+        (None, None, None) => 0,
+    };
+    if to <= from {
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec!["The range must end after it starts"])));
+    }
+
+    let pairs: Vec<db::Pair> = db.pairs().await?;
+    let pair_id = match pairs.into_iter().find(|p| p.exchange == pair.exch().to_string() && p.pair == pair.pair().to_string()) {
+        Some(p) => p.id,
+        None => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+    };
+
+    // Only `limit + 1` buckets are read, the next page starts after them when they hold fewer candles
+    let mut data = db.ohlc_range(pair_id, period, from, to, limit + 1).await?;
+    let read_to = db::ohlc_range_end(period, from, to, limit + 1);
+    let next = if data.len() as i64 > limit {
+        let next = data[limit as usize].time;
+        data.truncate(limit as usize);
+        Some(encode_cursor(period, next))
+    } else if read_to < to {
+        Some(encode_cursor(period, read_to))
+    } else {
+        None
+    };
+
+    let body = render(&data, format);
+    let etag = {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        body.hash(&mut hasher);
+        format!("\"{:016x}\"", hasher.finish())
+    };
+    let cache = if to <= period.clamp_time(now) { CACHE_COMPLETE } else { CACHE_LIVE };
 
-    let data = Ohlc::rescale(data.into_iter().map(|(_, v)| v), period.unwrap_or(OhlcPeriod::Min1));
-    Ok(Json(data))
+    let not_modified = req.headers().get(http::header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| etag_matches(v, &etag));
+    let mut resp = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    resp.header(http::header::ETAG, etag.clone())
+        .header(http::header::CACHE_CONTROL, format!("public, max-age={}", cache))
+        .header(http::header::VARY, "Accept-Encoding");
+    if let Some(ref next) = next {
+        let url = format!("{}?cursor={}&to={}&limit={}&format={}", req.path(), next, to, limit, format.as_str());
+        resp.header(http::header::LINK, format!("<{}>; rel=\"next\"", url))
+            .header("X-Next-Cursor", next.as_str());
+    }
+    if accepts_gzip(&req) {
+        resp.content_encoding(http::ContentEncoding::Gzip);
+    }
+    if not_modified {
+        return Ok(resp.finish());
+    }
+
+    let content_type = match format {
+        Format::Csv => "text/csv; charset=utf-8",
+        Format::Json | Format::Columnar => "application/json",
+    };
+    Ok(resp.content_type(content_type).body(body))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CoverageQuery {
+    /// Range searched for gaps in seconds, end exclusive, the last 30 days by default
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+/// Extent of the stored one minute candles of a pair, and gaps in a range of them
+pub async fn coverage((req, id, query): (HttpRequest<State>, Path<i32>, Query<CoverageQuery>)) -> Result<impl Responder> {
+    let db = req.state().db.clone();
+    let to = query.to.unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let from = query.from.unwrap_or(to - GAPS_RANGE);
+    if to <= from || to - from > MAX_GAPS_RANGE {
+        let resp = Json(vec![format!("Gaps are searched in ranges of up to {} days", MAX_GAPS_RANGE / (24 * 60 * 60))]);
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, resp));
+    }
+    match db.pair_coverage(id.into_inner(), from, to, MAX_GAPS).await {
+        Ok(coverage) => Ok(Json(coverage).respond_to(&req)?),
+        Err(diesel::result::Error::NotFound) => Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportResult {
+    #[serde(flatten)]
+    pub parsed: CsvImport,
+    pub imported: usize,
+    /// Times of the first and last imported candles
+    pub first: Option<i64>,
+    pub last: Option<i64>,
+}
+
+/// Threads parsing uploaded archives
+pub const IMPORT_THREADS: usize = 1;
+
+/// Parses uploaded archives on its own threads, so large files don't block the server workers
+pub struct ImportParser;
+
+impl ImportParser {
+    pub fn start() -> Addr<ImportParser> {
+        SyncArbiter::start(IMPORT_THREADS, || ImportParser)
+    }
+}
+
+impl Actor for ImportParser { type Context = SyncContext<Self>; }
+
+struct ParseImport {
+    body: Bytes,
+    options: CsvOptions,
+}
+
+impl Message for ParseImport { type Result = StdResult<CsvImport, String>; }
+
+impl Handler<ParseImport> for ImportParser {
+    type Result = StdResult<CsvImport, String>;
+
+    fn handle(&mut self, msg: ParseImport, _ctx: &mut Self::Context) -> Self::Result {
+        let body = std::str::from_utf8(&msg.body).map_err(|_| "The file must be UTF-8 encoded".to_string())?;
+        msg.options.parse(body).map_err(|e| e.to_string())
+    }
+}
+
+/// Imports one minute candles of a pair from a CSV archive in the body, the layout is described by the query.
+/// Rows that fail validation are skipped and reported, existing candles with the same time are replaced.
+pub async fn import((req, path, options): (HttpRequest<State>, Path<(Exchange, PairStr)>, Query<CsvOptions>)) -> Result<HttpResponse> {
+    let db = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_admin!(base);
+
+    let (exch, pair) = path.into_inner();
+    let pair = PairId::new(exch, pair.0);
+    let body = req.body().limit(UPLOAD_LIMIT).compat().await.map_err(actix_web::Error::from)?;
+    let parsed = req.state().imports.send(ParseImport { body, options: options.into_inner() }).compat().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Archive parsing failed"))?;
+    let parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec![e]))),
+    };
+
+    let total = parsed.candles.len();
+    let mut imported = 0;
+    for batch in parsed.candles.chunks(IMPORT_BATCH) {
+        db.do_save_ohlc(pair.clone(), batch.to_vec()).await?;
+        imported += batch.len();
+        info!("Imported {}/{} candles of {}", imported, total, pair);
+    }
+    audit(&req, AuthEvent::OhlcImported, Some(base.auth.uid), None);
+
+    let result = ImportResult {
+        first: parsed.candles.first().map(|c| c.time),
+        last: parsed.candles.last().map(|c| c.time),
+        imported,
+        parsed,
+    };
+    Ok(Json(result).respond_to(&req)?)
 }
 
 pub fn configure(app: App<State>) -> App<State> {
     app.resource("/api/ohlc/{exch}/{pair}/{period}", |r| {
         r.get().with(compat(get_ohlc))
     })
-}
\ No newline at end of file
+        .resource("/api/ohlc/{exch}/{pair}", |r| {
+            r.method(Method::POST).with_async(compat(import));
+        })
+        .resource("/api/pairs/{id}/coverage", |r| {
+            r.method(Method::GET).with(compat(coverage));
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etags_are_matched_in_lists_and_weakly() {
+        let etag = "\"00000000000000ab\"";
+        assert!(etag_matches(etag, etag));
+        assert!(etag_matches("*", etag));
+        assert!(etag_matches("W/\"00000000000000ab\"", etag));
+        assert!(etag_matches("\"1\", W/\"00000000000000ab\" ,\"2\"", etag));
+        assert!(!etag_matches("\"1\", \"2\"", etag));
+        assert!(!etag_matches("", etag));
+    }
+
+    #[test]
+    fn polling_returns_the_latest_periods() {
+        let hour = OhlcPeriod::Hour1;
+        // Three hours before 10:30 start at 8:00, the last one is still open
+        assert_eq!(latest_start(hour, 10 * 3600 + 1800, 3), 8 * 3600);
+        assert_eq!(latest_start(hour, 10 * 3600, 3), 7 * 3600);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::prelude::*;
+use crate::State;
+use crate::utils::*;
+use actix_web::{Path, Query};
+use common::types::{OhlcPeriod, Exchange, TradePair, PairId, ParamSearch, validate_walk_forward};
+use db::{Database, OptimizationData};
+
+fn default_folds() -> i32 { 3 }
+
+#[derive(Debug, Deserialize)]
+pub struct OptimizationRequest {
+    pub strategy_id: i32,
+    pub exchange: Exchange,
+    pub pair: String,
+    pub period: OhlcPeriod,
+    /// Time range of candles used for the backtests
+    pub from: i64,
+    pub to: i64,
+    pub search: ParamSearch,
+    #[serde(default = "default_folds")]
+    pub folds: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub strategy_id: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OptimizationDetail {
+    #[serde(flatten)]
+    pub info: db::Optimization,
+    pub results: Vec<db::OptimizationResult>,
+}
+
+fn bad_request(req: &HttpRequest<State>, msg: impl Into<String>) -> Error {
+    let resp: Json<Vec<String>> = Json(vec![msg.into()]);
+    Error::from_resp(req, http::StatusCode::BAD_REQUEST, resp)
+}
+
+pub async fn list((req, query): (HttpRequest<State>, Query<ListQuery>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let jobs = db.user_optimizations(base.auth.uid, query.strategy_id).await?;
+    Ok(Json(jobs).respond_to(&req)?)
+}
+
+pub async fn get((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let (info, results) = match db.optimization(base.auth.uid, id.into_inner()).await {
+        Ok(res) => res,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Json(OptimizationDetail { info, results }).respond_to(&req)?)
+}
+
+pub async fn post((req, data): (HttpRequest<State>, Json<OptimizationRequest>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let data = data.into_inner();
+    let strategy = db.single_strategy(data.strategy_id).await?;
+    require_cond!(strategy.user_id == base.auth.uid);
+    let revision_id = match strategy.revision_id {
+        Some(id) => id,
+        None => return Err(bad_request(&req, "Strategy has no saved revision")),
+    };
+
+    if let Err(e) = data.search.validate(&strategy.param_specs()) {
+        return Err(bad_request(&req, e));
+    }
+    if let Err(e) = validate_walk_forward(data.period, data.from, data.to, data.folds) {
+        return Err(bad_request(&req, e));
+    }
+    let pair = match TradePair::from_str(&data.pair) {
+        Ok(pair) => pair,
+        Err(_) => return Err(bad_request(&req, "Invalid pair")),
+    };
+
+    let job = db.create_optimization(OptimizationData {
+        user_id: base.auth.uid,
+        strategy_id: strategy.id,
+        revision_id,
+        pair_id: db.pair_id(PairId::new(data.exchange, pair)).await?,
+        period: data.period.to_string(),
+        from_time: data.from,
+        to_time: data.to,
+        search: data.search,
+        folds: data.folds,
+    }).await?;
+    Ok(Json(job).respond_to(&req)?)
+}
+
+pub fn configure(application: App<State>) -> App<State> {
+    application
+        .resource("/api/optimizations", |r| {
+            r.method(Method::GET).with_async(compat(list));
+            r.method(Method::POST).with_async(compat(post));
+        })
+        .resource("/api/optimizations/{id}", |r| {
+            r.method(Method::GET).with_async(compat(get));
+        })
+}
@@ -0,0 +1,362 @@
+//! Real-time push of candles, evaluations and trades to browsers over websockets.
+use crate::prelude::*;
+use actix_web::{ws, Binary};
+use common::msgs::{OhlcUpdate, EvaluationLogged, TradeLogged};
+use common::types::{Exchange, TradePair, OhlcSpec, OhlcPeriod};
+
+/// Frames waiting for a connection, further frames are dropped until it catches up
+const MAILBOX_CAPACITY: usize = 64;
+/// Subscriptions a single connection can hold
+const MAX_SUBSCRIPTIONS: usize = 32;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// Connections not answering pings for this long are closed
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+/// Written bytes after which the connection waits for them to be flushed to the socket
+const FLUSH_AFTER: usize = 64 * 1024;
+/// Frames are dropped while more than this many written bytes are not flushed yet, so a client that
+/// doesn't read can't grow the write buffer
+const MAX_UNFLUSHED: usize = 256 * 1024;
+
+/// Frame for a single connection, along with the number of frames dropped since the last delivered one
+pub struct Push {
+    frame: Arc<String>,
+    dropped: u64,
+}
+
+impl Message for Push { type Result = (); }
+
+pub struct Connect {
+    user_id: i32,
+    addr: Recipient<Push>,
+}
+
+impl Message for Connect { type Result = usize; }
+
+pub struct Disconnect(usize);
+
+impl Message for Disconnect { type Result = (); }
+
+pub struct Subscribe(usize, OhlcSpec);
+
+impl Message for Subscribe { type Result = (); }
+
+pub struct Unsubscribe(usize, OhlcSpec);
+
+impl Message for Unsubscribe { type Result = (); }
+
+struct Connection {
+    user_id: i32,
+    addr: Recipient<Push>,
+    specs: HashSet<OhlcSpec>,
+    dropped: u64,
+}
+
+/// Receives events from NATS and fans them out to the connections they are relevant for.
+/// Candles go to every connection subscribed to their pair and period, evaluations only to the
+/// subscribed connections of their owner, and trades to all connections of their owner.
+pub struct Hub {
+    next_id: usize,
+    connections: HashMap<usize, Connection>,
+}
+
+impl Actor for Hub { type Context = Context<Self>; }
+
+impl Hub {
+    pub fn start(client: anats::Client) -> Addr<Self> {
+        Arbiter::start(move |ctx: &mut Context<Self>| {
+            client.subscribe(common::CHANNEL_OHLC_RESCALED, None, ctx.address().recipient::<OhlcUpdate>());
+            client.subscribe(common::CHANNEL_EVALUATIONS, None, ctx.address().recipient::<EvaluationLogged>());
+            client.subscribe(common::CHANNEL_TRADES, None, ctx.address().recipient::<TradeLogged>());
+            Hub {
+                next_id: 0,
+                connections: HashMap::new(),
+            }
+        })
+    }
+
+    /// Sends the frame to matching connections without waiting for full mailboxes. Frames that don't
+    /// fit are counted, so the connection can tell its client how much it missed.
+    fn push(&mut self, kind: &str, data: impl Serialize, filter: impl Fn(&Connection) -> bool) {
+        let mut frame = None;
+        let mut closed = vec![];
+
+        for (id, conn) in self.connections.iter_mut().filter(|(_, c)| filter(c)) {
+            let frame = frame.get_or_insert_with(|| {
+                Arc::new(json::to_string(&json!({ "type": kind, "data": data })).expect("Push serialization"))
+            });
+            match conn.addr.try_send(Push { frame: frame.clone(), dropped: conn.dropped }) {
+                Ok(()) => conn.dropped = 0,
+                Err(SendError::Full(_)) => conn.dropped += 1,
+                Err(SendError::Closed(_)) => closed.push(*id),
+            }
+        }
+        for id in closed {
+            self.connections.remove(&id);
+        }
+    }
+}
+
+impl Handler<Connect> for Hub {
+    type Result = usize;
+
+    fn handle(&mut self, msg: Connect, ctx: &mut Self::Context) -> Self::Result {
+        self.next_id += 1;
+        self.connections.insert(self.next_id, Connection {
+            user_id: msg.user_id,
+            addr: msg.addr,
+            specs: HashSet::new(),
+            dropped: 0,
+        });
+        self.next_id
+    }
+}
+
+impl Handler<Disconnect> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) -> Self::Result {
+        self.connections.remove(&msg.0);
+    }
+}
+
+impl Handler<Subscribe> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(conn) = self.connections.get_mut(&msg.0) {
+            conn.specs.insert(msg.1);
+        }
+    }
+}
+
+impl Handler<Unsubscribe> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Unsubscribe, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(conn) = self.connections.get_mut(&msg.0) {
+            conn.specs.remove(&msg.1);
+        }
+    }
+}
+
+impl Handler<OhlcUpdate> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, msg: OhlcUpdate, ctx: &mut Self::Context) -> Self::Result {
+        let spec = msg.spec.clone();
+        self.push("candle", msg, |c| c.specs.contains(&spec));
+    }
+}
+
+impl Handler<EvaluationLogged> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, msg: EvaluationLogged, ctx: &mut Self::Context) -> Self::Result {
+        let spec = OhlcSpec::from_pair(msg.pair.clone(), msg.period);
+        let uid = msg.user_id;
+        self.push("evaluation", msg, |c| c.user_id == uid && c.specs.contains(&spec));
+    }
+}
+
+impl Handler<TradeLogged> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, msg: TradeLogged, ctx: &mut Self::Context) -> Self::Result {
+        let uid = msg.user_id;
+        self.push("trade", msg, |c| c.user_id == uid);
+    }
+}
+
+/// Command sent by the browser
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Command {
+    Subscribe { exchange: Exchange, pair: String, period: OhlcPeriod },
+    Unsubscribe { exchange: Exchange, pair: String, period: OhlcPeriod },
+}
+
+/// Websocket connection of a single browser
+pub struct Session {
+    id: Option<usize>,
+    user_id: i32,
+    hub: Addr<Hub>,
+    specs: HashSet<OhlcSpec>,
+    heartbeat: Instant,
+    /// Bytes written to the response and not known to be flushed
+    unflushed: usize,
+    /// Bytes covered by the pending flush
+    flushing: Option<usize>,
+    /// Pushed frames dropped by this session since the last delivered one
+    dropped: u64,
+}
+
+impl Session {
+    /// Writes the frame, unless too much of the earlier ones is still waiting for the client
+    fn write(&mut self, ctx: &mut ws::WebsocketContext<Self, State>, text: impl Into<Binary>) -> bool {
+        if self.unflushed > MAX_UNFLUSHED {
+            return false;
+        }
+        let text = text.into();
+        self.unflushed += text.len();
+        ctx.text(text);
+
+        if self.unflushed > FLUSH_AFTER && self.flushing.is_none() {
+            self.flushing = Some(self.unflushed);
+            let flushed = ctx.drain()
+                .map(|(), this: &mut Self, _| {
+                    this.unflushed -= this.flushing.take().unwrap_or(0);
+                })
+                .map_err(|(), _, ctx| ctx.stop());
+            ctx.spawn(flushed);
+        }
+        true
+    }
+
+    fn reply(&mut self, ctx: &mut ws::WebsocketContext<Self, State>, kind: &str, data: json::Value) {
+        self.write(ctx, json::to_string(&json!({ "type": kind, "data": data })).expect("Push serialization"));
+    }
+
+    fn command(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self, State>) {
+        let id = match self.id {
+            Some(id) => id,
+            None => return self.reply(ctx, "error", json!("Connection is not registered yet")),
+        };
+        let (subscribe, exchange, pair, period) = match json::from_str(text) {
+            Ok(Command::Subscribe { exchange, pair, period }) => (true, exchange, pair, period),
+            Ok(Command::Unsubscribe { exchange, pair, period }) => (false, exchange, pair, period),
+            Err(e) => return self.reply(ctx, "error", json!(format!("Invalid command : {}", e))),
+        };
+        let pair = match TradePair::from_str(&pair) {
+            Ok(pair) => pair,
+            Err(_) => return self.reply(ctx, "error", json!("Invalid pair")),
+        };
+        let spec = OhlcSpec::new(exchange, pair, period);
+
+        if subscribe {
+            if !self.specs.contains(&spec) && self.specs.len() >= MAX_SUBSCRIPTIONS {
+                return self.reply(ctx, "error", json!(format!("At most {} subscriptions are allowed", MAX_SUBSCRIPTIONS)));
+            }
+            self.specs.insert(spec.clone());
+            self.hub.do_send(Subscribe(id, spec.clone()));
+            self.reply(ctx, "subscribed", json!(spec));
+        } else {
+            self.specs.remove(&spec);
+            self.hub.do_send(Unsubscribe(id, spec.clone()));
+            self.reply(ctx, "unsubscribed", json!(spec));
+        }
+    }
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self, State>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(MAILBOX_CAPACITY);
+        ctx.run_interval(HEARTBEAT_INTERVAL, |this, ctx| {
+            if Instant::now().duration_since(this.heartbeat) > CLIENT_TIMEOUT {
+                info!("Websocket of user {} timed out", this.user_id);
+                ctx.stop();
+                return;
+            }
+            ctx.ping("");
+        });
+
+        let connect = Connect { user_id: self.user_id, addr: ctx.address().recipient() };
+        let fut = wrap_future(self.hub.send(connect))
+            .map(|id, this: &mut Self, _| this.id = Some(id))
+            .map_err(|e, this: &mut Self, ctx| {
+                error!("Could not register websocket : {:?}", e);
+                ctx.stop();
+            });
+        ctx.wait(fut);
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if let Some(id) = self.id {
+            self.hub.do_send(Disconnect(id));
+        }
+    }
+}
+
+impl Handler<Push> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: Push, ctx: &mut Self::Context) -> Self::Result {
+        let dropped = self.dropped + msg.dropped;
+        if self.unflushed > MAX_UNFLUSHED {
+            self.dropped = dropped + 1;
+            return;
+        }
+        if dropped > 0 {
+            self.reply(ctx, "lagged", json!({ "dropped": dropped }));
+        }
+        self.dropped = 0;
+        self.write(ctx, msg.frame);
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for Session {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Ping(msg) => {
+                self.heartbeat = Instant::now();
+                ctx.pong(&msg);
+            }
+            ws::Message::Pong(_) => {
+                self.heartbeat = Instant::now();
+            }
+            ws::Message::Text(text) => {
+                self.heartbeat = Instant::now();
+                self.command(&text, ctx);
+            }
+            ws::Message::Binary(_) => {
+                self.reply(ctx, "error", json!("Binary frames are not supported"));
+            }
+            ws::Message::Close(_) => {
+                ctx.stop();
+            }
+        }
+    }
+}
+
+/// Whether the `Origin` header, sent by browsers, names the site itself. Websockets are not subject
+/// to the same origin policy, so this keeps other sites from connecting with the session cookie.
+fn same_origin(req: &HttpRequest<State>) -> bool {
+    let origin = match req.headers().get(http::header::ORIGIN) {
+        Some(origin) => origin,
+        None => return true,
+    };
+    match Url::parse(&common::config().web.public_url) {
+        Ok(url) => origin.to_str().ok() == Some(url.origin().ascii_serialization().as_str()),
+        Err(_) => false,
+    }
+}
+
+/// Upgrades the request of a signed in user to a websocket connection
+pub async fn connect(req: HttpRequest<State>) -> Result<HttpResponse> {
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    if !same_origin(&req) {
+        return Ok(HttpResponse::new(http::StatusCode::FORBIDDEN));
+    }
+
+    let session = Session {
+        id: None,
+        user_id: base.auth.uid,
+        hub: req.state().push.clone(),
+        specs: HashSet::new(),
+        heartbeat: Instant::now(),
+        unflushed: 0,
+        flushing: None,
+        dropped: 0,
+    };
+    Ok(ws::start(&req, session)?)
+}
+
+pub fn configure(application: App<State>) -> App<State> {
+    application
+        .resource("/api/ws", |r| {
+            r.method(Method::GET).with_async(compat(connect));
+        })
+}
@@ -0,0 +1,50 @@
+use crate::prelude::*;
+use crate::State;
+use crate::utils::*;
+use db::{Database, RiskLimitsData};
+
+/// Number of decisions returned by `/api/risk`
+const RECENT_DECISIONS: i64 = 100;
+
+#[derive(Debug, Serialize)]
+pub struct RiskOverview {
+    pub limits: Option<db::RiskLimits>,
+    /// Latest position requests which changed a position or were rejected
+    pub decisions: Vec<db::RiskDecision>,
+}
+
+pub async fn get(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let limits = db.risk_limits(base.auth.uid).await?;
+    let decisions = db.risk_decisions(base.auth.uid, RECENT_DECISIONS).await?;
+    Ok(Json(RiskOverview { limits, decisions }).respond_to(&req)?)
+}
+
+pub async fn post((req, data): (HttpRequest<State>, Json<RiskLimitsData>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let data = data.into_inner();
+    let negative = data.max_exposure.map_or(false, |v| !(v >= 0.0))
+        || data.max_daily_loss.map_or(false, |v| !(v >= 0.0))
+        || data.max_orders_per_hour.map_or(false, |v| v < 0);
+    if negative {
+        let resp: Json<Vec<String>> = Json(vec!["Limits can't be negative.".into()]);
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, resp));
+    }
+
+    let limits = db.save_risk_limits(base.auth.uid, data).await?;
+    Ok(Json(limits).respond_to(&req)?)
+}
+
+pub fn configure(application: App<State>) -> App<State> {
+    application
+        .resource("/api/risk", |r| {
+            r.method(Method::GET).with_async(compat(get));
+            r.method(Method::POST).with_async(compat(post));
+        })
+}
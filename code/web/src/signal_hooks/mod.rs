@@ -0,0 +1,168 @@
+use crate::prelude::*;
+use crate::State;
+use crate::utils::*;
+use crate::users::two_factor::require_recent_confirmation;
+use crate::users::rate_limit::{SlidingWindow, client_ip, too_many_requests};
+use common::msgs::PositionRequest;
+use common::types::{Exchange, TradePair, PairId, TradingPosition};
+use db::Database;
+
+/// Header carrying the token of the webhook, for senders which can set headers
+pub const TOKEN_HEADER: &str = "X-Hook-Token";
+
+/// Signals accepted from a single address in `RECEIVE_WINDOW`
+const RECEIVE_LIMIT: usize = 60;
+const RECEIVE_WINDOW: Duration = Duration::from_secs(60);
+
+pub fn receive_limit() -> SlidingWindow {
+    SlidingWindow::new(RECEIVE_LIMIT, RECEIVE_WINDOW)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HookData {
+    pub name: String,
+    pub trader_id: i32,
+}
+
+/// Newly created webhook, this is the only time its token is returned
+#[derive(Debug, Serialize)]
+pub struct CreatedHook {
+    #[serde(flatten)]
+    pub info: db::SignalHook,
+    pub token: String,
+    /// Address signals are posted to, with the token in the body or in `X-Hook-Token`
+    pub url: String,
+}
+
+/// Signal posted to a webhook, e.g. by a TradingView alert
+#[derive(Debug, Deserialize)]
+pub struct HookSignal {
+    /// Token of the webhook, when it's not sent in `X-Hook-Token`
+    pub token: Option<String>,
+    pub exchange: Exchange,
+    pub pair: String,
+    /// `long` or `buy`, `short` or `sell`, `neutral`, `flat` or `close`
+    pub position: String,
+    /// Fraction of the available funds, the whole amount by default
+    pub size: Option<f64>,
+}
+
+fn parse_position(position: &str) -> Option<TradingPosition> {
+    match position.to_lowercase().as_str() {
+        "long" | "buy" => Some(TradingPosition::Long),
+        "short" | "sell" => Some(TradingPosition::Short),
+        "neutral" | "flat" | "close" => Some(TradingPosition::Indeterminate),
+        _ => None,
+    }
+}
+
+fn hook_url() -> String {
+    format!("{}/api/hooks", common::config().web.public_url.trim_end_matches('/'))
+}
+
+pub async fn list(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+
+    let hooks = db.signal_hooks(base.auth.uid).await?;
+    Ok(Json(hooks).respond_to(&req)?)
+}
+
+pub async fn post((req, data): (HttpRequest<State>, Json<HookData>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+    check_verified_email(&db, base.auth.uid).await?;
+    require_recent_confirmation(&req, &base).await?;
+
+    let data = data.into_inner();
+    let (info, token) = match db.create_signal_hook(base.auth.uid, data.trader_id, data.name).await {
+        Ok(created) => created,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    let url = hook_url();
+    Ok(Json(CreatedHook { info, token, url }).respond_to(&req)?)
+}
+
+pub async fn delete((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+
+    if !db.revoke_signal_hook(base.auth.uid, id.into_inner()).await? {
+        return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND));
+    }
+    return Ok(HttpResponse::new(http::StatusCode::OK));
+}
+
+/// Accepts a signal authenticated by the token in `X-Hook-Token` or in the body, and queues a position request
+/// of the trader of the webhook. The token is kept out of the path, which ends up in access logs.
+/// The request passes through the risk manager like decisions of assignments.
+/// The body is parsed as JSON regardless of its content type, alert services often send it as plain text.
+pub async fn receive((req, body): (HttpRequest<State>, String)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+
+    if let Err(retry_after) = req.state().hook_limits.hit(&client_ip(&req).unwrap_or_default()) {
+        return Ok(too_many_requests(retry_after));
+    }
+
+    let signal: HookSignal = match json::from_str(&body) {
+        Ok(signal) => signal,
+        Err(e) => return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec![format!("Invalid signal : {}", e)]))),
+    };
+    let token = req.headers().get(TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| signal.token.clone());
+    let found = match token {
+        Some(token) => db.authenticate_signal_hook(token).await?,
+        None => None,
+    };
+    let (hook, trader) = match found {
+        Some(found) => found,
+        None => return Ok(HttpResponse::new(http::StatusCode::UNAUTHORIZED)),
+    };
+
+    let position = match parse_position(&signal.position) {
+        Some(position) => position,
+        None => return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec!["Unknown position"]))),
+    };
+    let pair = match TradePair::from_str(&signal.pair) {
+        Ok(pair) => pair,
+        Err(_) => return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec!["Invalid pair"]))),
+    };
+    if signal.exchange.to_string() != trader.exchange {
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec!["Trader of the webhook uses another exchange"])));
+    }
+    let size = signal.size.unwrap_or(1.0);
+    if !(size > 0.0 && size <= 1.0) {
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec!["Size must be above 0 and at most 1"])));
+    }
+
+    let request = PositionRequest {
+        size,
+        ..PositionRequest::new(trader.id, PairId::new(signal.exchange, pair), position, None)
+    };
+    db.enqueue(common::CHANNEL_POSITION_REQUESTS, &request).await?;
+    info!("Webhook {} queued position request {:?} of trader {}", hook.id, request.id, trader.id);
+
+    let mut resp = Json(json!({ "id": request.id })).respond_to(&req)?;
+    *resp.status_mut() = http::StatusCode::ACCEPTED;
+    Ok(resp)
+}
+
+pub fn configure(application: App<State>) -> App<State> {
+    application
+        .resource("/api/signal-hooks", |r| {
+            r.method(Method::GET).with_async(compat(list));
+            r.method(Method::POST).with_async(compat(post));
+        })
+        .resource("/api/signal-hooks/{id}", |r| {
+            r.method(Method::DELETE).with_async(compat(delete));
+        })
+        .resource("/api/hooks", |r| {
+            r.method(Method::POST).with_async(compat(receive));
+        })
+}
@@ -0,0 +1,203 @@
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", content = "line", rename_all = "lowercase")]
+pub enum DiffLine {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+/// Line based diff of two strategy sources, computed by the linear space variant of Myers' algorithm.
+/// Takes O((N+M)D) time for D differing lines and O(N+M) memory.
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let mut out = vec![];
+    diff_range(&old, &new, &mut out);
+    out
+}
+
+fn diff_range(old: &[&str], new: &[&str], out: &mut Vec<DiffLine>) {
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+
+    out.extend(old[..prefix].iter().map(|l| DiffLine::Same(l.to_string())));
+    let (old_mid, new_mid) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    if old_mid.is_empty() || new_mid.is_empty() {
+        out.extend(old_mid.iter().map(|l| DiffLine::Removed(l.to_string())));
+        out.extend(new_mid.iter().map(|l| DiffLine::Added(l.to_string())));
+    } else {
+        match middle_snake(old_mid, new_mid) {
+            Some((x, y)) => {
+                diff_range(&old_mid[..x], &new_mid[..y], out);
+                diff_range(&old_mid[x..], &new_mid[y..], out);
+            }
+            None => {
+                out.extend(old_mid.iter().map(|l| DiffLine::Removed(l.to_string())));
+                out.extend(new_mid.iter().map(|l| DiffLine::Added(l.to_string())));
+            }
+        }
+    }
+    out.extend(old[old.len() - suffix..].iter().map(|l| DiffLine::Same(l.to_string())));
+}
+
+/// Finds a point on an optimal edit path, by following paths from both ends until they meet.
+/// Returns `None` when the inputs have nothing in common.
+fn middle_snake(old: &[&str], new: &[&str]) -> Option<(usize, usize)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let len = 2 * max_d + 2;
+
+    // Furthest reaching x on every diagonal k = x - y, forward from the start and backward from the end
+    let mut forward = vec![-1isize; len as usize];
+    let mut backward = vec![-1isize; len as usize];
+    forward[(offset + 1) as usize] = 0;
+    backward[(offset + 1) as usize] = 0;
+
+    let delta = n - m;
+    // With odd delta the forward path is the one to detect the overlap
+    let front = delta % 2 != 0;
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let k1_offset = (offset + k1) as usize;
+            let mut x1 = if k1 == -d || (k1 != d && forward[k1_offset - 1] < forward[k1_offset + 1]) {
+                forward[k1_offset + 1]
+            } else {
+                forward[k1_offset - 1] + 1
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && old[x1 as usize] == new[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            forward[k1_offset] = x1;
+
+            if x1 > n {
+                k1_end += 2;
+            } else if y1 > m {
+                k1_start += 2;
+            } else if front {
+                let k2_offset = offset + delta - k1;
+                if k2_offset >= 0 && k2_offset < len && backward[k2_offset as usize] != -1 {
+                    let x2 = n - backward[k2_offset as usize];
+                    if x1 >= x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let k2_offset = (offset + k2) as usize;
+            let mut x2 = if k2 == -d || (k2 != d && backward[k2_offset - 1] < backward[k2_offset + 1]) {
+                backward[k2_offset + 1]
+            } else {
+                backward[k2_offset - 1] + 1
+            };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && old[(n - x2 - 1) as usize] == new[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            backward[k2_offset] = x2;
+
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !front {
+                let k1_offset = offset + delta - k2;
+                if k1_offset >= 0 && k1_offset < len && forward[k1_offset as usize] != -1 {
+                    let x1 = forward[k1_offset as usize];
+                    let y1 = offset + x1 - k1_offset;
+                    if x1 >= n - x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(old: &str, diff: &[DiffLine]) -> (String, String) {
+        let (mut from, mut to) = (vec![], vec![]);
+        for line in diff {
+            match line {
+                DiffLine::Same(l) => {
+                    from.push(l.as_str());
+                    to.push(l.as_str());
+                }
+                DiffLine::Removed(l) => from.push(l.as_str()),
+                DiffLine::Added(l) => to.push(l.as_str()),
+            }
+        }
+        assert_eq!(from.join("\n"), old.lines().collect::<Vec<_>>().join("\n"));
+        (from.join("\n"), to.join("\n"))
+    }
+
+    fn edits(diff: &[DiffLine]) -> usize {
+        diff.iter().filter(|l| match l { DiffLine::Same(_) => false, _ => true }).count()
+    }
+
+    #[test]
+    fn identical_and_empty() {
+        assert_eq!(diff_lines("", ""), vec![]);
+        assert_eq!(diff_lines("a\nb", "a\nb"), vec![DiffLine::Same("a".into()), DiffLine::Same("b".into())]);
+        assert_eq!(diff_lines("", "a"), vec![DiffLine::Added("a".into())]);
+        assert_eq!(diff_lines("a", ""), vec![DiffLine::Removed("a".into())]);
+    }
+
+    #[test]
+    fn single_change() {
+        assert_eq!(diff_lines("a\nb\nc", "a\nx\nc"), vec![
+            DiffLine::Same("a".into()),
+            DiffLine::Removed("b".into()),
+            DiffLine::Added("x".into()),
+            DiffLine::Same("c".into()),
+        ]);
+    }
+
+    #[test]
+    fn minimal_edits() {
+        // Myers' paper example, the shortest edit script has 5 edits
+        let (old, new) = ("a\nb\nc\na\nb\nb\na", "c\nb\na\nb\na\nc");
+        let diff = diff_lines(old, new);
+        assert_eq!(apply(old, &diff).1, new);
+        assert_eq!(edits(&diff), 5);
+
+        let (old, new) = ("x\na\nb\nc\ny", "a\nz\nb\nc");
+        let diff = diff_lines(old, new);
+        assert_eq!(apply(old, &diff).1, new);
+        assert_eq!(edits(&diff), 3);
+    }
+
+    #[test]
+    fn nothing_in_common() {
+        let diff = diff_lines("a\nb\nc", "x\ny");
+        assert_eq!(apply("a\nb\nc", &diff).1, "x\ny");
+        assert_eq!(edits(&diff), 5);
+    }
+
+    #[test]
+    fn large_inputs() {
+        let old = (0..20_000).map(|i| format!("line {}", i)).collect::<Vec<_>>().join("\n");
+        let new = (0..20_000).filter(|i| i % 1000 != 0).map(|i| format!("line {}", i)).collect::<Vec<_>>().join("\n");
+        let diff = diff_lines(&old, &new);
+        assert_eq!(apply(&old, &diff).1, new);
+        assert_eq!(edits(&diff), 20);
+    }
+}
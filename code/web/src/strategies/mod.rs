@@ -1,5 +1,7 @@
 use crate::prelude::*;
 
+mod diff;
+
 use common::futures01::Future;
 use actix_web::{http::Method, App, Json};
 use actix_web::{AsyncResponder, HttpRequest};
@@ -10,9 +12,11 @@ use crate::utils::*;
 use crate::users::middleware::UserAuthentication;
 
 use db::User;
-use db::Database;
-use actix_web::Path;
-use common::types::OhlcPeriod;
+use db::{Database, RevisionError};
+use actix_web::{Path, Query};
+use common::types::{Ohlc, OhlcPeriod, Exchange, TradePair, PairId, TradingPosition, ParamSpec, ParamValues};
+use strat_eval::{SyntaxError, StrategyInput, TradingStrategy};
+use self::diff::{diff_lines, DiffLine};
 
 
 async fn list(req: HttpRequest<State>) -> Result<impl Responder> {
@@ -43,22 +47,209 @@ async fn post((req, id, data): (HttpRequest<State>, Option<Path<i32>>, Json<db::
     require_login!(base);
 
     let mut data = data.into_inner();
+    if let Err(e) = strat_eval::check_syntax(&data.body) {
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(e)));
+    }
+    if let Err(e) = ParamSpec::validate_all(&data.params) {
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(vec![e])));
+    }
     data.user_id = base.auth.uid;
     if let Some(id) = id {
         data.id = Some(id.into_inner());
     }
 
-    let strat = db.save_strategy(data).await?;
+    let strat = match db.save_strategy(data).await {
+        Ok(strat) => strat,
+        Err(RevisionError::Db(diesel::result::Error::NotFound)) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(RevisionError::IncompatibleParams(e)) => return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(e))),
+        Err(RevisionError::Db(e)) => return Err(e.into()),
+    };
+    notify_assignments(&db, base.auth.uid).await;
+    Ok(Json(strat).respond_to(&req)?)
+}
+
+async fn revisions((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let (strat, _) = db.strategy_data(id.into_inner()).await?;
+    require_cond!(strat.user_id == base.auth.uid);
+
+    let revisions = db.strategy_revisions(strat.id).await?;
+    Ok(Json(revisions).respond_to(&req)?)
+}
+
+#[derive(Debug, Deserialize)]
+struct DiffQuery {
+    /// Revision to compare against, the current one by default
+    against: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+struct RevisionDiff {
+    from: i32,
+    to: i32,
+    lines: Vec<DiffLine>,
+}
+
+async fn diff((req, path, query): (HttpRequest<State>, Path<(i32, i32)>, Query<DiffQuery>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let (sid, rid) = path.into_inner();
+    let (strat, _) = db.strategy_data(sid).await?;
+    require_cond!(strat.user_id == base.auth.uid);
+
+    let against = query.against.or(strat.revision_id).unwrap_or(rid);
+    let from = db.strategy_revision(rid).await?;
+    let to = db.strategy_revision(against).await?;
+    if from.strategy_id != strat.id || to.strategy_id != strat.id {
+        return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND));
+    }
+
+    let diff = RevisionDiff {
+        from: from.number,
+        to: to.number,
+        lines: diff_lines(&from.body, &to.body),
+    };
+    Ok(Json(diff).respond_to(&req)?)
+}
+
+async fn rollback((req, path): (HttpRequest<State>, Path<(i32, i32)>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let (sid, rid) = path.into_inner();
+    let strat = match db.rollback_strategy(base.auth.uid, sid, rid).await {
+        Ok(strat) => strat,
+        Err(RevisionError::Db(diesel::result::Error::NotFound)) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(RevisionError::IncompatibleParams(e)) => return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, Json(e))),
+        Err(RevisionError::Db(e)) => return Err(e.into()),
+    };
+    notify_assignments(&db, base.auth.uid).await;
     Ok(Json(strat).respond_to(&req)?)
 }
 
 
+#[derive(Debug, Deserialize)]
+struct ValidateData {
+    body: String,
+    /// Market for the dry run, it is skipped when not provided
+    exchange: Option<Exchange>,
+    pair: Option<String>,
+    period: Option<OhlcPeriod>,
+    /// Declared parameters, and values overriding their defaults in the dry run
+    #[serde(default)]
+    params: Vec<ParamSpec>,
+    #[serde(default)]
+    values: ParamValues,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Validation {
+    syntax_error: Option<SyntaxError>,
+    params_error: Option<String>,
+    runtime_error: Option<String>,
+    decision: Option<TradingPosition>,
+    /// Duration of the dry run in milliseconds
+    duration: Option<i64>,
+}
+
+/// Number of threads running dry runs, further requests wait for a free one
+pub const DRY_RUN_THREADS: usize = 2;
+
+/// Runs dry runs on a fixed pool of threads, away from the server workers.
+/// The sandbox limits how long each run takes and how much memory it uses.
+pub struct DryRunner;
+
+impl DryRunner {
+    pub fn start() -> Addr<DryRunner> {
+        SyncArbiter::start(DRY_RUN_THREADS, || DryRunner)
+    }
+}
+
+impl Actor for DryRunner { type Context = SyncContext<Self>; }
+
+struct DryRun {
+    body: String,
+    params: StdResult<ParamValues, String>,
+    /// Candles to evaluate the strategy on, the strategy is only compiled when missing
+    candles: Option<BTreeMap<i64, Ohlc>>,
+}
+
+impl Message for DryRun { type Result = Validation; }
+
+impl Handler<DryRun> for DryRunner {
+    type Result = MessageResult<DryRun>;
+
+    fn handle(&mut self, msg: DryRun, _ctx: &mut Self::Context) -> Self::Result {
+        let mut res = Validation::default();
+        let strat = match strat_eval::compile(&msg.body) {
+            Ok(strat) => strat,
+            Err(e) => {
+                res.syntax_error = Some(e);
+                return MessageResult(res);
+            }
+        };
+        let params = match msg.params {
+            Ok(params) => params,
+            Err(e) => {
+                res.params_error = Some(e);
+                return MessageResult(res);
+            }
+        };
+        if let Some(ohlc) = msg.candles {
+            let (decision, duration) = measure_time(|| strat.decide(&StrategyInput { ohlc, params }));
+            res.duration = Some(duration);
+            match decision {
+                Ok(decision) => res.decision = Some(decision),
+                Err(e) => res.runtime_error = Some(e.to_string()),
+            }
+        }
+        MessageResult(res)
+    }
+}
+
+/// Compiles the strategy and runs it on recent candles, without saving it
+async fn validate((req, data): (HttpRequest<State>, Json<ValidateData>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let data = data.into_inner();
+    let params = ParamSpec::validate_all(&data.params).and_then(|_| ParamSpec::resolve(&data.params, &data.values));
+
+    let mut candles = None;
+    if let (Some(exchange), Some(pair), true) = (data.exchange, data.pair, params.is_ok()) {
+        let pair = match TradePair::from_str(&pair) {
+            Ok(pair) => pair,
+            Err(_) => return Ok(HttpResponse::new(http::StatusCode::BAD_REQUEST)),
+        };
+        let period = data.period.unwrap_or(OhlcPeriod::Min1);
+        let pair_id = db.pair_id(PairId::new(exchange, pair)).await?;
+        let since = unixtime() - period.seconds() * 1000;
+        candles = Some(db.ohlc_history_backfilled(pair_id, period, since).await?
+            .into_iter()
+            .map(|c| (c.time, c))
+            .collect());
+    }
+
+    let res = req.state().dry_runs.send(DryRun { body: data.body, params, candles }).compat().await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Strategy evaluation failed"))?;
+    Ok(Json(res).respond_to(&req)?)
+}
+
 async fn delete((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
     let base = BaseReqInfo::from_request(&req).await?;
     let db: Database = req.state().db.clone();
 
     require_login!(base);
-    let _ = db.delete_strategy(base.auth.uid,id.into_inner()).await?;
+    if db.delete_strategy(base.auth.uid,id.into_inner()).await? {
+        notify_assignments(&db, base.auth.uid).await;
+    }
     return Ok(HttpResponse::new(http::StatusCode::OK));
 }
 
@@ -69,10 +260,22 @@ pub fn configure(application: App<State>) -> App<State> {
             r.method(Method::GET).with_async(compat(list));
             r.method(Method::POST).with_async(compat(post));
         })
+        .resource("/api/strategies/validate", |r| {
+            r.method(Method::POST).with_async(compat(validate));
+        })
         .resource("/api/strategies/{id}", |r| {
             r.method(Method::GET).with_async(compat(get));
             r.method(Method::POST).with_async(compat(post));
             r.method(Method::DELETE).with_async(compat(delete));
         })
+        .resource("/api/strategies/{id}/revisions", |r| {
+            r.method(Method::GET).with_async(compat(revisions));
+        })
+        .resource("/api/strategies/{id}/revisions/{rev}/diff", |r| {
+            r.method(Method::GET).with_async(compat(diff));
+        })
+        .resource("/api/strategies/{id}/revisions/{rev}/rollback", |r| {
+            r.method(Method::POST).with_async(compat(rollback));
+        })
 }
 
@@ -0,0 +1,66 @@
+use crate::prelude::*;
+use crate::State;
+use crate::utils::*;
+use crate::users::{audit, two_factor::require_recent_confirmation};
+use db::{Database, TokenScope, AuthEvent};
+
+#[derive(Debug, Deserialize)]
+pub struct TokenData {
+    pub name: String,
+    pub scope: TokenScope,
+}
+
+/// Newly created token, this is the only time its value is returned
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    pub info: db::ApiToken,
+    pub token: String,
+}
+
+pub async fn list(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+
+    let tokens = db.user_api_tokens(base.auth.uid).await?;
+    Ok(Json(tokens).respond_to(&req)?)
+}
+
+pub async fn post((req, data): (HttpRequest<State>, Json<TokenData>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+
+    let data = data.into_inner();
+    if data.scope == TokenScope::Trade {
+        require_recent_confirmation(&req, &base).await?;
+    }
+    let (info, token) = db.create_api_token(base.auth.uid, data.name, data.scope).await?;
+    audit(&req, AuthEvent::TokenCreated, Some(base.auth.uid), None);
+    Ok(Json(CreatedToken { info, token }).respond_to(&req)?)
+}
+
+pub async fn delete((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+
+    if db.revoke_api_token(base.auth.uid, id.into_inner()).await? {
+        audit(&req, AuthEvent::TokenRevoked, Some(base.auth.uid), None);
+        Ok(HttpResponse::new(http::StatusCode::OK))
+    } else {
+        Ok(HttpResponse::new(http::StatusCode::NOT_FOUND))
+    }
+}
+
+pub fn configure(application: App<State>) -> App<State> {
+    application
+        .resource("/api/tokens", |r| {
+            r.method(Method::GET).with_async(compat(list));
+            r.method(Method::POST).with_async(compat(post));
+        })
+        .resource("/api/tokens/{id}", |r| {
+            r.method(Method::DELETE).with_async(compat(delete));
+        })
+}
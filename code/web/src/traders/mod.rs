@@ -2,9 +2,29 @@ use crate::prelude::*;
 use crate::State;
 use crate::utils::*;
 use crate::users::middleware::UserAuthentication;
+use crate::users::two_factor::require_recent_confirmation;
 use common::types::OhlcPeriod;
-use db::Database;
+use db::{Database, CostMethod};
+use actix_web::Query;
 
+/// Longest daily PnL history returned at once
+const MAX_PNL_DAYS: i64 = 366;
+
+fn default_days() -> i64 { 30 }
+
+#[derive(Debug, Deserialize)]
+pub struct PortfolioQuery {
+    #[serde(default)]
+    pub method: CostMethod,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PnlQuery {
+    #[serde(default)]
+    pub method: CostMethod,
+    #[serde(default = "default_days")]
+    pub days: i64,
+}
 
 pub async fn list(req: HttpRequest<State>) -> Result<impl Responder> {
     let db: Database = req.state().db.clone();
@@ -26,7 +46,13 @@ pub async fn post((req, id, form): (HttpRequest<State>, Option<Path<i32>>, Json<
         form.id = Some(id.into_inner());
     }
     require_login!(base);
-    let trader = db.save_trader(form).await?;
+    check_verified_email(&db, base.auth.uid).await?;
+    require_recent_confirmation(&req, &base).await?;
+    let trader = match db.save_trader(form).await? {
+        Some(trader) => trader,
+        None => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+    };
+    notify_assignments(&db, base.auth.uid).await;
     Ok(Json(trader).respond_to(&req)?)
 }
 
@@ -35,11 +61,43 @@ pub async fn delete((req, id): (HttpRequest<State>, Path<i32>)) -> Result<impl R
     let base = BaseReqInfo::from_request(&req).await?;
     require_login!(base);
 
-    db.delete_trader(base.auth.uid,id.into_inner()).await?;
+    if db.delete_trader(base.auth.uid,id.into_inner()).await? {
+        notify_assignments(&db, base.auth.uid).await;
+    }
 
     return Ok(HttpResponse::new(http::StatusCode::OK));
 }
 
+pub async fn portfolio((req, id, query): (HttpRequest<State>, Path<i32>, Query<PortfolioQuery>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    let portfolio = match db.portfolio(base.auth.uid, id.into_inner(), query.method).await {
+        Ok(portfolio) => portfolio,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Json(portfolio).respond_to(&req)?)
+}
+
+pub async fn pnl((req, id, query): (HttpRequest<State>, Path<i32>, Query<PnlQuery>)) -> Result<impl Responder> {
+    let db: Database = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_login!(base);
+
+    if query.days < 1 || query.days > MAX_PNL_DAYS {
+        let resp: Json<Vec<String>> = Json(vec![format!("Number of days has to be between 1 and {}", MAX_PNL_DAYS)]);
+        return Err(Error::from_resp(&req, http::StatusCode::BAD_REQUEST, resp));
+    }
+    let history = match db.daily_pnl(base.auth.uid, id.into_inner(), query.method, query.days).await {
+        Ok(history) => history,
+        Err(diesel::result::Error::NotFound) => return Ok(HttpResponse::new(http::StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Json(history).respond_to(&req)?)
+}
+
 pub fn configure(application: App<State>) -> App<State> {
     application
         .resource("/api/traders", |r| {
@@ -50,5 +108,11 @@ pub fn configure(application: App<State>) -> App<State> {
             r.method(Method::POST).with_async(compat(post));
             r.method(Method::DELETE).with_async(compat(delete));
         })
+        .resource("/api/traders/{id}/portfolio", |r| {
+            r.method(Method::GET).with_async(compat(portfolio));
+        })
+        .resource("/api/traders/{id}/pnl", |r| {
+            r.method(Method::GET).with_async(compat(pnl));
+        })
 }
 
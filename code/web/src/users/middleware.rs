@@ -1,12 +1,28 @@
 use crate::prelude::*;
 use db::{
-    User, UserAuthInfo,
+    User, UserAuthInfo, TokenScope, Role,
 };
 
 pub type UserAuthenticationResult<'a> = common::futures03::future::LocalBoxFuture<'a, Result<User, actix_web::Error>>;
+pub type IdentityResult<'a> = common::futures03::future::LocalBoxFuture<'a, Result<Option<Identity>, actix_web::Error>>;
+
+/// User on whose behalf the request is made
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub uid: i32,
+    pub email: String,
+    /// Scope of the API token used, `None` for cookie sessions
+    pub scope: Option<TokenScope>,
+    pub role: Role,
+}
 
 pub trait UserAuthentication {
+    /// Only checks the cookie session, use `identity` to also accept API tokens
     fn is_authenticated(&self) -> bool;
+    fn bearer_token(&self) -> Option<String>;
+    /// Resolves the `Authorization: Bearer` token if present, falls back to the cookie session.
+    /// Invalid or revoked token is an error, not an anonymous request.
+    fn identity(&self) -> IdentityResult;
     fn user(&self) -> UserAuthenticationResult;
 }
 
@@ -28,32 +44,70 @@ impl UserAuthentication for HttpRequest<State> {
         }
     }
 
+    fn bearer_token(&self) -> Option<String> {
+        let header = self.headers().get(http::header::AUTHORIZATION)?.to_str().ok()?;
+        let mut parts = header.splitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => Some(token.trim().to_string()),
+            _ => None,
+        }
+    }
+
+    fn identity(&self) -> IdentityResult {
+        let token = self.bearer_token();
+        let uid = self.session().get::<i32>("uid");
+        let db = self.state().db.clone();
+        async move {
+            let (user, scope) = if let Some(token) = token {
+                let found = db.authenticate_token(token).await
+                    .map_err(actix_web::error::ErrorInternalServerError)?;
+                match found {
+                    Some((token, user)) => (user, Some(token.scope())),
+                    None => return Err(actix_web::error::ErrorUnauthorized("Invalid API token")),
+                }
+            } else {
+                let uid = match uid? {
+                    Some(uid) => uid,
+                    None => return Ok(None),
+                };
+                match db.get_user(uid).await {
+                    Ok(user) => (user, None),
+                    Err(e) => {
+                        warn!("Session refers to a missing user {}: {}", uid, e);
+                        return Ok(None);
+                    }
+                }
+            };
+
+            if user.disabled {
+                return Err(actix_web::error::ErrorForbidden("Account is disabled"));
+            }
+            Ok(Some(Identity {
+                uid: user.id,
+                role: user.role(),
+                email: user.email,
+                scope,
+            }))
+        }.boxed_local()
+    }
+
     fn user(&self) -> UserAuthenticationResult {
-        let sess = self.session().get::<i32>("uid");
+        let identity = self.identity();
         let db = self.state().db.clone();
         async move {
-            match sess {
-                Ok(session) => {
-                    match session {
-                        Some(session_id) => {
-                            let res = db.get_user(session_id).await;
-                            match res {
-                                Ok(user) => Ok(user),
-                                Err(err) => {
-                                    let e = IoError::new(ErrorKind::NotFound, format!("{}", err));
-                                    Err(e.into())
-                                }
-                            }
-                        }
-                        None => {
-                            let e = IoError::new(ErrorKind::NotFound, "User has no session data.");
+            match identity.await? {
+                Some(identity) => {
+                    let res = db.get_user(identity.uid).await;
+                    match res {
+                        Ok(user) => Ok(user),
+                        Err(err) => {
+                            let e = IoError::new(ErrorKind::NotFound, format!("{}", err));
                             Err(e.into())
                         }
                     }
                 }
-
-                Err(e) => {
-                    error!("Error'd when attempting to fetch session data: {:?}", e);
+                None => {
+                    let e = IoError::new(ErrorKind::NotFound, "User has no session data.");
                     Err(e.into())
                 }
             }
@@ -1,6 +1,9 @@
 use crate::prelude::*;
 
 pub mod middleware;
+pub mod totp;
+pub mod two_factor;
+pub mod rate_limit;
 
 use actix_web::{
     http::Method, App,
@@ -12,8 +15,67 @@ use super::State;
 use crate::users::middleware::UserAuthentication;
 
 
-use db::UserAuthInfo;
+use db::{UserAuthInfo, NewPassword, UserTokenPurpose, AuthEvent};
 use db::validator::Validate;
+use db::diesel::result::{Error as DieselError, DatabaseErrorKind};
+use crate::mail::Mail;
+
+const VERIFY_EMAIL_VALIDITY_HOURS: i64 = 48;
+const RESET_PASSWORD_VALIDITY_MINUTES: i64 = 60;
+
+/// Consecutive failed logins after which the account is locked
+pub const MAX_FAILED_LOGINS: i32 = 5;
+pub const LOCKOUT_MINUTES: i64 = 15;
+
+/// Stores an auth event without delaying the response
+pub fn audit(req: &HttpRequest<State>, event: AuthEvent, uid: Option<i32>, email: Option<String>) {
+    let fut = req.state().db.log_auth_event(event, uid, email, rate_limit::client_ip(req));
+    actix::spawn(fut.boxed_local().compat().map_err(|e| error!("Audit log failed: {}", e)));
+}
+
+/// Counts a failed password or code, locking the account after too many of them
+pub async fn login_failed(req: &HttpRequest<State>, event: AuthEvent, user: &db::User) -> Result<()> {
+    audit(req, event, Some(user.id), Some(user.email.clone()));
+    let lockout = chrono::Duration::minutes(LOCKOUT_MINUTES);
+    if let Some(until) = req.state().db.record_failed_login(user.id, MAX_FAILED_LOGINS, lockout).await? {
+        warn!("Locking account {} until {}", user.id, until);
+        audit(req, AuthEvent::AccountLocked, Some(user.id), Some(user.email.clone()));
+    }
+    Ok(())
+}
+
+pub fn is_locked(user: &db::User) -> bool {
+    user.locked_until.map(|until| until > chrono::Utc::now()).unwrap_or(false)
+}
+
+fn locked_response(request: &HttpRequest<State>) -> Error {
+    let resp: Json<Vec<String>> = Json(vec!["Account is temporarily locked after too many failed attempts, try again later.".into()]);
+    crate::prelude::Error::from_resp(request, http::StatusCode::LOCKED, resp)
+}
+
+/// Checks a password chosen at signup or through a reset
+fn validate_new_password(request: &HttpRequest<State>, password: &str) -> Result<()> {
+    if let Err(e) = (NewPassword { password: password.to_string() }).validate() {
+        let resp = Json(collect_validation_errors(e));
+        return Err(crate::prelude::Error::from_resp(request, http::StatusCode::BAD_REQUEST, resp));
+    }
+    Ok(())
+}
+
+pub(crate) fn public_link(path: &str) -> String {
+    format!("{}{}", common::config().web.public_url.trim_end_matches('/'), path)
+}
+
+async fn send_verification(state: &State, user: &db::User) -> Result<(), failure::Error> {
+    let token = state.db.create_user_token(user.id, UserTokenPurpose::VerifyEmail, chrono::Duration::hours(VERIFY_EMAIL_VALIDITY_HOURS)).await?;
+    state.mail.send(Mail {
+        to: user.email.clone(),
+        subject: "Verify your email address".into(),
+        body: format!("Open the following link to verify your email address:\n\n{}\n\nThe link expires in {} hours.\n",
+                      public_link(&format!("/app/auth/verify/{}", token)), VERIFY_EMAIL_VALIDITY_HOURS),
+        secret: Some(token),
+    })
+}
 
 pub async fn login((request, login): (HttpRequest<State>, Json<UserAuthInfo>)) -> Result<HttpResponse> {
     error!("Login");
@@ -29,22 +91,50 @@ pub async fn login((request, login): (HttpRequest<State>, Json<UserAuthInfo>)) -
         return Err(crate::prelude::Error::from_resp(&request, http::StatusCode::FORBIDDEN, resp));
     }
 
-    let password = login.password.clone();
-    let res: Result<db::User, _> = request.state().db.login(login).await;
-
-
-    return match res {
-        Ok(ref user) if djangohashers::check_password(&password, &user.password) == Ok(true) => {
-            request.session().set("email", user.email.clone()).unwrap();
-            request.session().set("uid", user.id.clone()).unwrap();
-            homepage
-        }
+    if let Err(retry_after) = request.state().auth_limits.per_email.hit(&login.email.to_lowercase()) {
+        audit(&request, AuthEvent::RateLimited, None, Some(login.email.clone()));
+        return Ok(rate_limit::too_many_requests(retry_after));
+    }
 
-        Ok(_) | Err(_) => {
+    let password = login.password.clone();
+    let email = login.email.clone();
+    let user = match request.state().db.login(login).await {
+        Ok(user) => user,
+        Err(_) => {
+            audit(&request, AuthEvent::LoginFailed, None, Some(email));
             let resp: Json<Vec<String>> = Json(vec!["Email or password is incorrect.".into()]);
             return Err(crate::prelude::Error::from_resp(&request, http::StatusCode::FORBIDDEN, resp));
         }
     };
+
+    if is_locked(&user) {
+        audit(&request, AuthEvent::LoginLocked, Some(user.id), Some(user.email.clone()));
+        return Err(locked_response(&request));
+    }
+    if user.disabled {
+        audit(&request, AuthEvent::LoginDisabled, Some(user.id), Some(user.email.clone()));
+        let resp: Json<Vec<String>> = Json(vec!["Account is disabled.".into()]);
+        return Err(crate::prelude::Error::from_resp(&request, http::StatusCode::FORBIDDEN, resp));
+    }
+
+    if djangohashers::check_password(&password, &user.password) != Ok(true) {
+        login_failed(&request, AuthEvent::LoginFailed, &user).await?;
+        let resp: Json<Vec<String>> = Json(vec!["Email or password is incorrect.".into()]);
+        return Err(crate::prelude::Error::from_resp(&request, http::StatusCode::FORBIDDEN, resp));
+    }
+
+    if user.totp_enabled {
+        // Password is correct, the session is established once the code is provided to `two_factor::signin`
+        request.session().remove("uid");
+        request.session().set(two_factor::SESSION_PENDING, user.id).unwrap();
+        return Ok(HttpResponse::Accepted().finish());
+    }
+
+    request.state().db.reset_failed_logins(user.id).await?;
+    audit(&request, AuthEvent::LoginSucceeded, Some(user.id), Some(user.email.clone()));
+    request.session().set("email", user.email.clone()).unwrap();
+    request.session().set("uid", user.id.clone()).unwrap();
+    homepage
 }
 
 
@@ -56,21 +146,120 @@ pub async fn signup((request, user): (HttpRequest<State>, Json<UserAuthInfo>)) -
     let  base: BaseReqInfo = BaseReqInfo::from_request(&request).await?;
 
     let mut user = user.into_inner();
+    if let Err(e) = user.validate() {
+        let resp = Json(collect_validation_errors(e));
+        return Err(crate::prelude::Error::from_resp(&request, http::StatusCode::BAD_REQUEST, resp));
+    }
+    validate_new_password(&request, &user.password)?;
 
+    let email = user.email.clone();
     user.password = djangohashers::make_password(&user.password);
 
     match request.state().db.new_user(user).await {
         Ok(user) => {
+            audit(&request, AuthEvent::SignedUp, Some(user.id), Some(user.email.clone()));
+            if let Err(e) = send_verification(request.state(), &user).await {
+                error!("Could not send verification email: {}", e);
+            }
             request.session().set("email", user.email).unwrap();
             request.session().set("uid", user.id).unwrap();
 
             let url = request.url_for("homepage", &[""; 0]).unwrap();
             Ok(redirect(url.as_str()))
         }
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            audit(&request, AuthEvent::SignupRejected, None, Some(email));
+            let resp: Json<Vec<String>> = Json(vec!["Email is already registered.".into()]);
+            Err(crate::prelude::Error::from_resp(&request, http::StatusCode::CONFLICT, resp))
+        }
         Err(e) => {
             error!("Error creating new user: {:?}", e);
-            return Ok(redirect_to(request, "homepage"));
+            Err(actix_web::error::ErrorInternalServerError("Could not create user").into())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailVerification {
+    pub token: String,
+}
 
+/// Posted by the page the emailed link opens, so prefetching the link doesn't spend the token
+pub async fn verify_email((request, data): (HttpRequest<State>, Json<EmailVerification>)) -> Result<HttpResponse> {
+    let db = request.state().db.clone();
+    match db.consume_user_token(data.into_inner().token, UserTokenPurpose::VerifyEmail).await? {
+        Some(uid) => {
+            db.set_email_verified(uid).await?;
+            audit(&request, AuthEvent::EmailVerified, Some(uid), None);
+            Ok(HttpResponse::Ok().finish())
+        }
+        None => {
+            let resp: Json<Vec<String>> = Json(vec!["Verification link is invalid or has expired.".into()]);
+            Err(crate::prelude::Error::from_resp(&request, http::StatusCode::BAD_REQUEST, resp))
+        }
+    }
+}
+
+pub async fn resend_verification(request: HttpRequest<State>) -> Result<HttpResponse> {
+    let base = BaseReqInfo::from_request(&request).await?;
+    require_session!(base);
+
+    let user = request.state().db.get_user(base.auth.uid).await?;
+    if !user.has_verified_email {
+        send_verification(request.state(), &user).await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+/// Always succeeds, so the response can't be used to find out which emails are registered
+pub async fn request_password_reset((request, data): (HttpRequest<State>, Json<PasswordResetRequest>)) -> Result<HttpResponse> {
+    let state = request.state();
+    if let Some(user) = state.db.user_by_email(data.into_inner().email).await? {
+        audit(&request, AuthEvent::PasswordResetRequested, Some(user.id), Some(user.email.clone()));
+        let validity = chrono::Duration::minutes(RESET_PASSWORD_VALIDITY_MINUTES);
+        let token = state.db.create_user_token(user.id, UserTokenPurpose::ResetPassword, validity).await?;
+        let sent = state.mail.send(Mail {
+            to: user.email.clone(),
+            subject: "Password reset".into(),
+            body: format!("Open the following link to choose a new password:\n\n{}\n\nThe link expires in {} minutes. \
+                           If you did not request a password reset, you can ignore this email.\n",
+                          public_link(&format!("/app/auth/reset/{}", token)), RESET_PASSWORD_VALIDITY_MINUTES),
+            secret: Some(token),
+        });
+        if let Err(e) = sent {
+            error!("Could not send password reset email: {}", e);
+        }
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordReset {
+    pub token: String,
+    pub password: String,
+}
+
+pub async fn reset_password((request, data): (HttpRequest<State>, Json<PasswordReset>)) -> Result<HttpResponse> {
+    let data = data.into_inner();
+    validate_new_password(&request, &data.password)?;
+    let db = request.state().db.clone();
+    match db.consume_user_token(data.token, UserTokenPurpose::ResetPassword).await? {
+        Some(uid) => {
+            db.set_password(uid, djangohashers::make_password(&data.password)).await?;
+            // Resetting the password through email also lifts a lockout
+            db.reset_failed_logins(uid).await?;
+            audit(&request, AuthEvent::PasswordReset, Some(uid), None);
+            Ok(HttpResponse::Ok().finish())
+        }
+        None => {
+            let resp: Json<Vec<String>> = Json(vec!["Reset link is invalid or has expired.".into()]);
+            Err(crate::prelude::Error::from_resp(&request, http::StatusCode::BAD_REQUEST, resp))
         }
     }
 }
@@ -83,15 +272,36 @@ pub fn logout(request: HttpRequest<State>) -> HttpResponse {
 
 
 pub fn configure(app: App<State>) -> App<State> {
+    let key = &common::config().web.session_key;
     app.middleware(SessionStorage::new(
-        CookieSessionBackend::private(&[42; 32])
+        CookieSessionBackend::private(key.expose().as_bytes())
             .secure(false)
             .name("_TSESSION")
     )).resource("/api/signup/", |r| {
         r.method(Method::POST).with_async(compat(signup));
     }).resource("/api/signin/", |r| {
         r.method(Method::POST).with_async(compat(login));
+    }).resource("/api/signin/2fa", |r| {
+        r.method(Method::POST).with_async(compat(two_factor::signin));
+    }).resource("/api/2fa/enroll", |r| {
+        r.method(Method::POST).with_async(compat(two_factor::enroll));
+    }).resource("/api/2fa/activate", |r| {
+        r.method(Method::POST).with_async(compat(two_factor::activate));
+    }).resource("/api/2fa/confirm", |r| {
+        r.method(Method::POST).with_async(compat(two_factor::confirm));
+    }).resource("/api/2fa/disable", |r| {
+        r.method(Method::POST).with_async(compat(two_factor::disable));
+    }).resource("/api/2fa/recovery-codes", |r| {
+        r.method(Method::POST).with_async(compat(two_factor::regenerate_recovery_codes));
     }).resource("/api/logout/", |r| {
         r.method(Method::POST).with(logout);
+    }).resource("/api/verify-email/resend", |r| {
+        r.method(Method::POST).with_async(compat(resend_verification));
+    }).resource("/api/verify-email/", |r| {
+        r.method(Method::POST).with_async(compat(verify_email));
+    }).resource("/api/password-reset/", |r| {
+        r.method(Method::POST).with_async(compat(request_password_reset));
+    }).resource("/api/password-reset/confirm", |r| {
+        r.method(Method::POST).with_async(compat(reset_password));
     })
 }
\ No newline at end of file
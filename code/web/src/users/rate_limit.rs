@@ -0,0 +1,163 @@
+//! Sliding window rate limiting of authentication endpoints, kept in memory of the web service
+use crate::prelude::*;
+use actix_web::middleware::{Middleware, Started};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::net::IpAddr;
+
+/// Paths of endpoints that check passwords, codes or send mails
+const AUTH_PATHS: &[&str] = &["/api/signin", "/api/signup", "/api/password-reset", "/api/2fa"];
+
+/// Number of tracked keys above which expired entries are swept
+const SWEEP_THRESHOLD: usize = 10_000;
+
+pub struct SlidingWindow {
+    limit: usize,
+    window: Duration,
+    hits: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl SlidingWindow {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        SlidingWindow {
+            limit,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records an attempt, returns time until the next attempt is allowed if the limit was exceeded
+    pub fn hit(&self, key: &str) -> StdResult<(), Duration> {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().unwrap();
+
+        if hits.len() > SWEEP_THRESHOLD {
+            let window = self.window;
+            hits.retain(|_, times| times.back().map(|t| now.duration_since(*t) < window).unwrap_or(false));
+        }
+
+        let times = hits.entry(key.to_string()).or_insert_with(VecDeque::new);
+        while times.front().map(|t| now.duration_since(*t) >= self.window).unwrap_or(false) {
+            times.pop_front();
+        }
+        if times.len() >= self.limit {
+            let oldest = *times.front().unwrap();
+            return Err(self.window - now.duration_since(oldest));
+        }
+        times.push_back(now);
+        Ok(())
+    }
+}
+
+/// Limiters shared by the middleware and the handlers
+pub struct AuthLimits {
+    pub per_ip: SlidingWindow,
+    pub per_email: SlidingWindow,
+}
+
+impl Default for AuthLimits {
+    fn default() -> Self {
+        AuthLimits {
+            per_ip: SlidingWindow::new(30, Duration::from_secs(5 * 60)),
+            per_email: SlidingWindow::new(10, Duration::from_secs(15 * 60)),
+        }
+    }
+}
+
+pub fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .header(http::header::RETRY_AFTER, (retry_after.as_secs() + 1).to_string())
+        .finish()
+}
+
+/// Address of the client. `X-Forwarded-For` is only honoured on connections from `web.trusted_proxies`,
+/// since anyone else can put any address into it.
+pub fn client_ip<S>(req: &HttpRequest<S>) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let forwarded = req.headers().get_all("x-forwarded-for").iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>();
+    Some(resolve_client(peer, &forwarded, &common::config().web.trusted_proxies).to_string())
+}
+
+/// Walks the forwarded chain from the connected peer, the client is the first address
+/// that isn't a trusted proxy. Entries left of it were provided by the client itself.
+fn resolve_client(peer: IpAddr, forwarded: &[&str], trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+    let chain = forwarded.iter()
+        .flat_map(|header| header.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    let mut client = peer;
+    for addr in chain.into_iter().rev() {
+        match addr.parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted.contains(&ip) {
+                    break;
+                }
+            }
+            // Garbage in the chain can't be attributed to anyone, the last proxy is blamed instead
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// Limits requests to authentication endpoints per client address
+pub struct RateLimit;
+
+impl Middleware<State> for RateLimit {
+    fn start(&self, req: &HttpRequest<State>) -> actix_web::Result<Started> {
+        if req.method() != http::Method::POST || !AUTH_PATHS.iter().any(|p| req.path().starts_with(p)) {
+            return Ok(Started::Done);
+        }
+
+        let ip = client_ip(req).unwrap_or_default();
+        match req.state().auth_limits.per_ip.hit(&ip) {
+            Ok(()) => Ok(Started::Done),
+            Err(retry_after) => {
+                warn!("Rate limited authentication request from {}", ip);
+                super::audit(req, db::AuthEvent::RateLimited, None, None);
+                Ok(Started::Response(too_many_requests(retry_after)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_header_of_untrusted_peer_is_ignored() {
+        let peer = ip("203.0.113.7");
+        assert_eq!(resolve_client(peer, &["198.51.100.1"], &[]), peer);
+        assert_eq!(resolve_client(peer, &["198.51.100.1"], &[ip("10.0.0.1")]), peer);
+    }
+
+    #[test]
+    fn client_is_first_untrusted_address() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        assert_eq!(resolve_client(ip("10.0.0.1"), &["198.51.100.1"], &trusted), ip("198.51.100.1"));
+        // Spoofed entries on the left are skipped
+        assert_eq!(resolve_client(ip("10.0.0.1"), &["1.2.3.4, 198.51.100.1, 10.0.0.2"], &trusted), ip("198.51.100.1"));
+        assert_eq!(resolve_client(ip("10.0.0.1"), &["1.2.3.4", "198.51.100.1"], &trusted), ip("198.51.100.1"));
+        assert_eq!(resolve_client(ip("10.0.0.1"), &["2001:db8::1"], &trusted), ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn missing_or_invalid_chain_falls_back_to_proxy() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        assert_eq!(resolve_client(ip("10.0.0.1"), &[], &trusted), ip("10.0.0.1"));
+        assert_eq!(resolve_client(ip("10.0.0.1"), &["10.0.0.2"], &trusted), ip("10.0.0.2"));
+        assert_eq!(resolve_client(ip("10.0.0.1"), &["198.51.100.1, unknown"], &trusted), ip("10.0.0.1"));
+    }
+}
@@ -0,0 +1,142 @@
+//! Time-based one-time passwords as described in RFC 6238, compatible with common authenticator apps
+use ring::{digest, hmac, rand::{SecureRandom, SystemRandom}};
+
+const SECRET_LEN: usize = 20;
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Number of neighbouring time steps accepted, to tolerate clock drift
+const ALLOWED_SKEW: i64 = 1;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LEN];
+    SystemRandom::new().fill(&mut secret).expect("TOTP secret generation");
+    secret
+}
+
+/// Unpadded base32, the format authenticator apps expect
+pub fn encode_secret(secret: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in secret.chunks(5) {
+        let mut buf = [0u8; 5];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let bits = buf.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        let chars = (chunk.len() * 8 + 4) / 5;
+        for i in 0..chars {
+            let idx = (bits >> (35 - i * 5)) & 0x1f;
+            out.push(BASE32_ALPHABET[idx as usize] as char);
+        }
+    }
+    out
+}
+
+pub fn provisioning_url(secret: &[u8], account: &str) -> String {
+    format!("otpauth://totp/Trader:{}?secret={}&issuer=Trader&digits={}&period={}",
+            account, encode_secret(secret), DIGITS, STEP_SECONDS)
+}
+
+fn code_at(secret: &[u8], step: i64) -> u32 {
+    let key = hmac::SigningKey::new(&digest::SHA1, secret);
+    let sig = hmac::sign(&key, &(step as u64).to_be_bytes());
+    let sig = sig.as_ref();
+
+    let offset = (sig[sig.len() - 1] & 0x0f) as usize;
+    let value = ((sig[offset] as u32 & 0x7f) << 24)
+        | ((sig[offset + 1] as u32) << 16)
+        | ((sig[offset + 2] as u32) << 8)
+        | (sig[offset + 3] as u32);
+    value % 10u32.pow(DIGITS)
+}
+
+/// Returns the time step the code belongs to, if it is valid at the given time.
+/// Caller has to make sure the same step is not accepted twice.
+pub fn verify(secret: &[u8], code: &str, unixtime: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    let current = unixtime / STEP_SECONDS;
+    (current - ALLOWED_SKEW..=current + ALLOWED_SKEW).find(|step| code_at(secret, *step) == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shared secret of the SHA1 test vectors in RFC 6238 Appendix B
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn decode_secret(encoded: &str) -> Vec<u8> {
+        let mut out = vec![];
+        let (mut bits, mut count) = (0u64, 0);
+        for c in encoded.bytes() {
+            let idx = BASE32_ALPHABET.iter().position(|a| *a == c).expect("Base32 character");
+            bits = (bits << 5) | idx as u64;
+            count += 5;
+            if count >= 8 {
+                count -= 8;
+                out.push((bits >> count) as u8);
+                bits &= (1 << count) - 1;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn rfc6238_vectors() {
+        // Appendix B lists 8 digit codes, the last 6 digits are the 6 digit codes
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+        for &(time, code) in vectors.iter() {
+            assert_eq!(code_at(RFC_SECRET, time / STEP_SECONDS), code % 1_000_000, "Code at {}", time);
+        }
+    }
+
+    #[test]
+    fn verify_tolerates_skew() {
+        assert_eq!(verify(RFC_SECRET, "287082", 59), Some(1));
+        assert_eq!(verify(RFC_SECRET, " 287082 ", 89), Some(1));
+        assert_eq!(verify(RFC_SECRET, "287082", 29), Some(1));
+        assert_eq!(verify(RFC_SECRET, "287082", 119), None);
+        assert_eq!(verify(RFC_SECRET, "94287082", 59), None);
+        assert_eq!(verify(RFC_SECRET, "28708a", 59), None);
+    }
+
+    #[test]
+    fn base32_rfc4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+        for &(raw, encoded) in vectors.iter() {
+            assert_eq!(encode_secret(raw.as_bytes()), encoded);
+            assert_eq!(decode_secret(encoded), raw.as_bytes());
+        }
+        assert_eq!(encode_secret(RFC_SECRET), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        for len in 0..=SECRET_LEN {
+            let secret = (0..len).map(|i| (i * 37 + 11) as u8).collect::<Vec<_>>();
+            assert_eq!(decode_secret(&encode_secret(&secret)), secret);
+        }
+        let secret = generate_secret();
+        assert_eq!(encode_secret(&secret).len(), 32);
+        assert_eq!(decode_secret(&encode_secret(&secret)), secret);
+    }
+}
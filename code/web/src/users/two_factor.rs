@@ -0,0 +1,170 @@
+use crate::prelude::*;
+use crate::users::{totp, audit, login_failed, is_locked};
+use db::AuthEvent;
+use db::{Database, TokenScope};
+
+/// How long a confirmed code allows sensitive actions
+pub const CONFIRMATION_SECONDS: i64 = 10 * 60;
+
+const SESSION_CONFIRMED: &str = "2fa_confirmed";
+/// User who entered a correct password, but still has to provide a code
+pub(crate) const SESSION_PENDING: &str = "pending_uid";
+
+#[derive(Debug, Deserialize)]
+pub struct CodeData {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Enrollment {
+    pub secret: String,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Accepts either a current TOTP code, or an unused recovery code
+pub async fn check_code(db: &Database, uid: i32, code: &str) -> Result<bool> {
+    let secret = db.totp_secret(uid).await.map_err(actix_web::error::ErrorInternalServerError)?;
+    let secret = match secret {
+        Some(secret) => secret,
+        None => return Ok(false),
+    };
+
+    if let Some(step) = totp::verify(&secret, code, unixtime()) {
+        return Ok(db.record_totp_step(uid, step).await?);
+    }
+    Ok(db.use_recovery_code(uid, code.to_string()).await?)
+}
+
+pub(crate) fn mark_confirmed(req: &HttpRequest<State>) -> Result<()> {
+    req.session().set(SESSION_CONFIRMED, unixtime())?;
+    Ok(())
+}
+
+/// Sensitive actions require 2FA to be enabled, and a code confirmed within the last few minutes.
+/// Trade-scoped tokens pass, since creating them requires the same confirmation.
+pub async fn require_recent_confirmation(req: &HttpRequest<State>, base: &BaseReqInfo) -> Result<()> {
+    let user = req.state().db.get_user(base.auth.uid).await?;
+    if !user.totp_enabled {
+        return Err(actix_web::error::ErrorForbidden("Enable two-factor authentication to control live traders").into());
+    }
+    if base.auth.scope == Some(TokenScope::Trade) {
+        return Ok(());
+    }
+
+    let confirmed = req.session().get::<i64>(SESSION_CONFIRMED)?;
+    match confirmed {
+        Some(time) if unixtime() - time < CONFIRMATION_SECONDS => Ok(()),
+        _ => Err(actix_web::error::ErrorForbidden("Confirm this action with a two-factor code").into()),
+    }
+}
+
+fn invalid_code() -> Error {
+    actix_web::error::ErrorForbidden("Invalid two-factor code").into()
+}
+
+pub async fn enroll(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+
+    let user = db.get_user(base.auth.uid).await?;
+    if user.totp_enabled {
+        return Ok(HttpResponse::Conflict().body("Two-factor authentication is already enabled"));
+    }
+
+    let secret = totp::generate_secret();
+    db.set_pending_totp(user.id, secret.clone()).await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(Json(Enrollment {
+        secret: totp::encode_secret(&secret),
+        url: totp::provisioning_url(&secret, &user.email),
+    }).respond_to(&req)?)
+}
+
+pub async fn activate((req, data): (HttpRequest<State>, Json<CodeData>)) -> Result<impl Responder> {
+    let db = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+
+    let user = db.get_user(base.auth.uid).await?;
+    if user.totp_enabled {
+        return Ok(HttpResponse::Conflict().body("Two-factor authentication is already enabled"));
+    }
+    if !check_code(&db, user.id, &data.code).await? {
+        return Err(invalid_code());
+    }
+
+    let recovery_codes = db.enable_totp(user.id).await?;
+    audit(&req, AuthEvent::TwoFactorEnabled, Some(user.id), Some(user.email.clone()));
+    mark_confirmed(&req)?;
+    Ok(Json(RecoveryCodes { recovery_codes }).respond_to(&req)?)
+}
+
+pub async fn confirm((req, data): (HttpRequest<State>, Json<CodeData>)) -> Result<impl Responder> {
+    let db = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+
+    if !check_code(&db, base.auth.uid, &data.code).await? {
+        audit(&req, AuthEvent::TwoFactorFailed, Some(base.auth.uid), None);
+        return Err(invalid_code());
+    }
+    mark_confirmed(&req)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn disable((req, data): (HttpRequest<State>, Json<CodeData>)) -> Result<impl Responder> {
+    let db = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+
+    if !check_code(&db, base.auth.uid, &data.code).await? {
+        audit(&req, AuthEvent::TwoFactorFailed, Some(base.auth.uid), None);
+        return Err(invalid_code());
+    }
+    db.disable_totp(base.auth.uid).await?;
+    audit(&req, AuthEvent::TwoFactorDisabled, Some(base.auth.uid), None);
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn regenerate_recovery_codes(req: HttpRequest<State>) -> Result<impl Responder> {
+    let db = req.state().db.clone();
+    let base = BaseReqInfo::from_request(&req).await?;
+    require_session!(base);
+    require_recent_confirmation(&req, &base).await?;
+
+    let recovery_codes = db.regenerate_recovery_codes(base.auth.uid).await?;
+    Ok(Json(RecoveryCodes { recovery_codes }).respond_to(&req)?)
+}
+
+/// Second step of signing in, for users with 2FA enabled
+pub async fn signin((req, data): (HttpRequest<State>, Json<CodeData>)) -> Result<HttpResponse> {
+    let db = req.state().db.clone();
+    let uid = match req.session().get::<i32>(SESSION_PENDING)? {
+        Some(uid) => uid,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    let user = db.get_user(uid).await?;
+    if is_locked(&user) {
+        audit(&req, AuthEvent::LoginLocked, Some(user.id), Some(user.email.clone()));
+        return Err(actix_web::error::ErrorLocked("Account is temporarily locked after too many failed attempts").into());
+    }
+    if !check_code(&db, uid, &data.code).await? {
+        login_failed(&req, AuthEvent::TwoFactorFailed, &user).await?;
+        return Err(invalid_code());
+    }
+
+    db.reset_failed_logins(user.id).await?;
+    audit(&req, AuthEvent::LoginSucceeded, Some(user.id), Some(user.email.clone()));
+    req.session().remove(SESSION_PENDING);
+    req.session().set("email", user.email)?;
+    req.session().set("uid", user.id)?;
+    mark_confirmed(&req)?;
+    Ok(redirect_to(req, "homepage"))
+}
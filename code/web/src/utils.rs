@@ -6,7 +6,8 @@ use std::borrow::Borrow;
 use db::validator::ValidationErrors;
 use std::future::Future;
 use actix_web::{http, HttpRequest, HttpResponse};
-use crate::users::middleware::UserAuthentication;
+use crate::users::middleware::{UserAuthentication, Identity};
+use db::TokenScope;
 
 
 #[inline(always)]
@@ -23,6 +24,9 @@ pub struct AuthTemplateInfo {
     pub signed_in: bool,
     pub email: String,
     pub uid: i32,
+    /// Scope of the API token used, `None` for cookie sessions
+    pub scope: Option<TokenScope>,
+    pub admin: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -32,11 +36,31 @@ pub struct BaseReqInfo {
 
 impl BaseReqInfo {
     pub async fn from_request(req: &HttpRequest<super::State>) -> Result<Self> {
+        let identity = req.identity().await?;
+
+        // Read-only tokens are limited to requests which don't modify anything
+        if let Some(Identity { scope: Some(TokenScope::Read), .. }) = identity {
+            if req.method() != http::Method::GET && req.method() != http::Method::HEAD {
+                return Err(actix_web::error::ErrorForbidden("API token is read-only").into());
+            }
+        }
+
         Ok(BaseReqInfo {
-            auth: AuthTemplateInfo {
-                signed_in: req.is_authenticated(),
-                email: req.session().get("email").unwrap().unwrap_or("".into()),
-                uid: req.session().get("uid").unwrap().unwrap_or(0),
+            auth: match identity {
+                Some(identity) => AuthTemplateInfo {
+                    signed_in: true,
+                    email: identity.email,
+                    uid: identity.uid,
+                    scope: identity.scope,
+                    admin: identity.role == db::Role::Admin,
+                },
+                None => AuthTemplateInfo {
+                    signed_in: false,
+                    email: "".into(),
+                    uid: 0,
+                    scope: None,
+                    admin: false,
+                },
             },
         })
     }
@@ -83,6 +107,45 @@ macro_rules! require_login {
     };
 }
 
+/// Requires the signed in user to be an administrator, using a cookie session
+#[macro_export]
+macro_rules! require_admin {
+    ($base: expr) => {
+        require_session!($base);
+        if !$base.auth.admin {
+            return Ok(HttpResponse::Forbidden().finish());
+        }
+    };
+}
+
+/// Requires a cookie session, API tokens are not accepted
+#[macro_export]
+macro_rules! require_session {
+    ($base: expr) => {
+        require_login!($base);
+        if $base.auth.scope.is_some() {
+            return Ok(HttpResponse::Forbidden().finish());
+        }
+    };
+}
+
+
+/// Live traders can only be used by users who verified their email address
+pub async fn check_verified_email(db: &db::Database, uid: i32) -> Result<()> {
+    let user = db.get_user(uid).await?;
+    if !user.has_verified_email {
+        return Err(actix_web::error::ErrorForbidden("Verify your email address before connecting exchange accounts").into());
+    }
+    Ok(())
+}
+
+/// Lets the decider pick up changed assignments of the user, its periodic reload catches up if this fails
+pub async fn notify_assignments(db: &db::Database, uid: i32) {
+    let msg = common::msgs::AssignmentsChanged { user_id: uid };
+    if let Err(e) = db.enqueue(common::CHANNEL_ASSIGNMENT_UPDATES, &msg).await {
+        warn!("Could not publish assignment update of user {} : {:?}", uid, e);
+    }
+}
 
 pub fn exchanges() -> Vec<String> {
     vec!["bitfinex".to_string()]